@@ -304,6 +304,28 @@ impl<K: Eq + Hash, V> OrderedMap<K, V> {
         }
     }
 
+    /// The position of the specified key in the map. The position of a key stays the same until
+    /// the key is removed or the map is compacted, so it can be used to look up the key again
+    /// using `get_index`, as long as the key is checked.
+    pub fn get_index_of<Q: ?Sized>(&self, key: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        if self.lookup.is_empty() {
+            return None;
+        }
+        self.find(key).ok()
+    }
+
+    /// The key and value at the specified position, as returned by `get_index_of`.
+    pub fn get_index(&self, idx: usize) -> Option<(&K, &V)> {
+        match self.values.get(idx) {
+            Some(Element::Node(n)) => Some((&n.key, &n.value)),
+            _ => None,
+        }
+    }
+
     pub fn remove<Q: ?Sized>(&mut self, key: &Q) -> Option<V>
     where
         K: Borrow<Q>,
@@ -732,6 +754,22 @@ mod tests {
         assert_eq!(m.to_string(), "[1: a, 3: c, 2: b]");
     }
 
+    #[test]
+    fn test_index() {
+        let mut m = OrderedMap::new();
+        assert_eq!(m.get_index_of(&1), None);
+        m.insert(1, "a");
+        m.insert(3, "c");
+        m.insert(2, "b");
+        let idx = m.get_index_of(&3).unwrap();
+        assert_eq!(m.get_index(idx), Some((&3, &"c")));
+        m.insert(3, "d");
+        assert_eq!(m.get_index(idx), Some((&3, &"d")));
+        m.remove(&3);
+        assert_eq!(m.get_index_of(&3), None);
+        assert_eq!(m.get_index(idx), None);
+    }
+
     #[test]
     fn test_with_realloc() {
         let mut m = OrderedMap::new();
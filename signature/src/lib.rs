@@ -389,6 +389,7 @@ fn signature_real(metadata: TokenStream, input: TokenStream) -> SignatureResult<
                 let mut allowed_values = None;
                 let mut description = None;
                 let mut completion_command = quote! {None};
+                let mut completion_tag = quote! {None};
 
                 if !field.attrs.is_empty() {
                     for attr in &field.attrs {
@@ -405,6 +406,9 @@ fn signature_real(metadata: TokenStream, input: TokenStream) -> SignatureResult<
                         } else if call_is_named(attr, "custom_completion") {
                             let name = call_value(attr)?;
                             completion_command = quote! {Some(#name)};
+                        } else if call_is_named(attr, "completion_tag") {
+                            let tag = call_literal(attr)?;
+                            completion_tag = quote! {Some(#tag.to_string())};
                         } else if call_is_named(attr, "description") {
                             description = Some(unescape(&(call_literal(attr)?.to_string())));
                         }
@@ -490,6 +494,7 @@ fn signature_real(metadata: TokenStream, input: TokenStream) -> SignatureResult<
                         allowed: #allowed_values,
                         description: None,
                         complete: #completion_command,
+                        completion_tag: #completion_tag,
                         named: #is_named_target,
                         unnamed: #is_unnamed_target,
                         default: #default_value_node,
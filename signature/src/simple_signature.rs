@@ -154,6 +154,7 @@ impl SimpleSignature {
                     crate::lang::value::ValueType::File,
                     crate::lang::value::ValueType::Glob,
                     crate::lang::value::ValueType::Regex,
+                    crate::lang::value::ValueType::BinaryOutputStream,
                 ]
             )},
         }
@@ -182,7 +183,7 @@ impl SimpleSignature {
             SimpleSignature::BinaryInput => {
                 "one_of $file $string $binary $binary_input_stream $glob $re"
             }
-            SimpleSignature::Files => "one_of $file $glob $re",
+            SimpleSignature::Files => "one_of $file $glob $re $binary_output_stream",
         }
     }
 
@@ -2,16 +2,23 @@ use crate::CrushResult;
 use crate::lang::argument::{Argument, SwitchStyle};
 use crate::lang::command::OutputType::Known;
 use crate::lang::command_invocation::resolve_external_command;
-use crate::lang::errors::command_error;
+use crate::lang::errors::{command_error, exit_status_error};
+use crate::lang::interactive::terminal;
 use crate::lang::ordered_string_map::OrderedStringMap;
+use crate::lang::state::global_state::ExitStatus;
+use crate::lang::threads::current_job;
 use crate::lang::value::Value;
-use crate::lang::value::Value::{Binary, BinaryInputStream};
+use crate::lang::value::Value::{Binary, BinaryInputStream, BinaryOutputStream};
 use crate::lang::value::ValueType;
 use crate::state::contexts::CommandContext;
 use crate::util::file::cwd;
+use nix::libc;
+use nix::unistd::Pid;
 use signature::signature;
 use std::borrow::BorrowMut;
 use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
 use std::path::PathBuf;
 use std::process::Stdio;
 
@@ -19,6 +26,8 @@ use std::process::Stdio;
     control.cmd,
     short = "Execute an external command",
     long = "Globs are file-expanded. Argument and switch order is preserved.",
+    long = "Binary streams and binary output streams, like the two ends of a `binary_stream:pipe`, are passed to the command as a file name under `/dev/fd`, which the command can read from or write to respectively.",
+    long = "The exit status of the command is available as `crush:last_status`. If strict mode is enabled using `crush:strict:set`, a command that does not exit successfully is an error.",
     output = Known(ValueType::BinaryInputStream),
     can_block = true,
 )]
@@ -46,6 +55,113 @@ fn format_value(v: &Value) -> CrushResult<Vec<String>> {
         .collect())
 }
 
+/// Create an OS pipe for a binary stream argument and return the name of the end that is inherited
+/// by the child. A background thread copies data between the other end and the stream.
+fn pipe_argument(
+    context: &CommandContext,
+    value: Value,
+    child_fds: &mut Vec<OwnedFd>,
+) -> CrushResult<String> {
+    let (reader, writer) = os_pipe::pipe()?;
+    let child_fd: OwnedFd = match value {
+        BinaryInputStream(mut input) => {
+            let mut writer = writer;
+            context.spawn("cmd:argument", move || {
+                std::io::copy(input.as_mut(), &mut writer)?;
+                Ok(())
+            })?;
+            reader.into()
+        }
+        BinaryOutputStream(output) => {
+            let mut reader = reader;
+            context.spawn("cmd:argument", move || {
+                std::io::copy(&mut reader, &mut output.writer())?;
+                Ok(())
+            })?;
+            writer.into()
+        }
+        v => {
+            return command_error(format!(
+                "Expected a binary stream, got a value of type `{}`.",
+                v.value_type()
+            ));
+        }
+    };
+    let name = format!("/dev/fd/{}", child_fd.as_raw_fd());
+    child_fds.push(child_fd);
+    Ok(name)
+}
+
+fn command_line(cmd: &std::process::Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|s| s.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn record_status(
+    context: &CommandContext,
+    command: String,
+    status: std::process::ExitStatus,
+) -> CrushResult<()> {
+    let status = ExitStatus::new(command, status);
+    context.global_state.set_last_status(status.clone());
+    if !status.success() && context.global_state.strict() {
+        exit_status_error(status)
+    } else {
+        Ok(())
+    }
+}
+
+/// Start a child process. While it is running, the child is registered with the current job, so
+/// that signals sent to the job reach it.
+fn spawn(cmd: &mut std::process::Command) -> CrushResult<std::process::Child> {
+    match current_job() {
+        Some(job) => job.spawn(cmd),
+        None => Ok(cmd.spawn()?),
+    }
+}
+
+/// Wait for a child process to exit. If the child is stopped, e.g. because `^Z` was pressed while
+/// its job owned the terminal, the job is suspended. If it is killed by `^C`, the job is cancelled.
+fn wait(
+    context: &CommandContext,
+    child: &std::process::Child,
+) -> CrushResult<std::process::ExitStatus> {
+    let job = current_job();
+    let pid = Pid::from_raw(child.id() as i32);
+    let mut status = 0;
+    let res = loop {
+        // The child is reaped using waitpid instead of Child::wait, which doesn't report stopped
+        // children.
+        if unsafe { libc::waitpid(pid.as_raw(), &mut status, libc::WUNTRACED) } < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            break Err(err);
+        }
+        if !libc::WIFSTOPPED(status) {
+            break Ok(std::process::ExitStatus::from_raw(status));
+        }
+        if let Some(job) = &job {
+            job.suspend();
+            context.global_state.threads().wake();
+        }
+    };
+    if let Some(job) = &job {
+        job.remove_child(pid);
+        if terminal::enabled()
+            && libc::WIFSIGNALED(status)
+            && libc::WTERMSIG(status) == libc::SIGINT
+        {
+            job.cancel();
+        }
+    }
+    Ok(res?)
+}
+
 fn cmd_internal(
     context: CommandContext,
     file: PathBuf,
@@ -54,6 +170,14 @@ fn cmd_internal(
     let use_tty = !context.input.is_pipeline() && !context.output.is_pipeline();
     let mut cmd = std::process::Command::new(file.as_os_str());
 
+    for (name, value) in context.scope.environment()?.iter() {
+        match value {
+            Some(value) => cmd.env(name, value),
+            None => cmd.env_remove(name),
+        };
+    }
+
+    let mut child_fds = Vec::new();
     for a in arguments.drain(..) {
         match a.argument_type {
             None => match a.value {
@@ -64,6 +188,9 @@ fn cmd_internal(
                         cmd.arg(file);
                     }
                 }
+                BinaryInputStream(_) | BinaryOutputStream(_) => {
+                    cmd.arg(pipe_argument(&context, a.value, &mut child_fds)?);
+                }
                 _ => {
                     for s in format_value(&a.value)? {
                         cmd.arg(s);
@@ -99,6 +226,10 @@ fn cmd_internal(
                             ));
                         }
                     }
+                    BinaryInputStream(_) | BinaryOutputStream(_) => {
+                        let file = pipe_argument(&context, a.value, &mut child_fds)?;
+                        cmd.arg(format!("{}{}{}", switch, join_string, file));
+                    }
                     _ => {
                         for s in format_value(&a.value)? {
                             cmd.arg(format!("{}{}{}", switch, join_string, s));
@@ -109,13 +240,35 @@ fn cmd_internal(
         }
     }
 
+    let command = command_line(&cmd);
+
+    if !child_fds.is_empty() {
+        let fds = child_fds
+            .iter()
+            .map(|fd| fd.as_raw_fd())
+            .collect::<Vec<RawFd>>();
+        // Safety: the closure only calls fcntl, which is async-signal-safe.
+        unsafe {
+            cmd.pre_exec(move || {
+                for fd in &fds {
+                    if nix::libc::fcntl(*fd, nix::libc::F_SETFD, 0) < 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+
     if use_tty {
         cmd.stdin(Stdio::inherit())
             .stdout(Stdio::inherit())
             .stderr(Stdio::inherit());
 
-        cmd.spawn()?.wait()?;
-        Ok(())
+        let child = spawn(&mut cmd)?;
+        drop(child_fds);
+        let status = wait(&context, &child)?;
+        record_status(&context, command, status)
     } else {
         let input = context.input.recv()?;
 
@@ -126,7 +279,8 @@ fn cmd_internal(
         cmd.stdout(stdout_writer);
         cmd.stderr(stderr_writer);
 
-        let mut child = cmd.spawn()?;
+        let mut child = spawn(&mut cmd)?;
+        drop(child_fds);
         let mut stdin = child.stdin.take().ok_or("Expected stdin stream")?;
 
         match input {
@@ -166,9 +320,8 @@ fn cmd_internal(
             Ok(())
         })?;
 
-        child.wait()?;
-
-        Ok(())
+        let status = wait(&context, &child)?;
+        record_status(&context, command, status)
     }
 }
 
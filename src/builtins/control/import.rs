@@ -0,0 +1,179 @@
+use crate::lang::command::OutputType::Known;
+use crate::lang::errors::{CrushResult, command_error};
+use crate::lang::state::contexts::CommandContext;
+use crate::lang::state::scope::{Scope, ScopeType};
+use crate::lang::threads::{imports, set_imports};
+use crate::lang::value::{Value, ValueType};
+use crate::util::file::cwd;
+use signature::signature;
+use std::path::{Path, PathBuf};
+
+/// The file that is evaluated when importing a directory.
+const ENTRY_FILE: &str = "mod.crush";
+
+#[signature(
+    control.import,
+    can_block = true,
+    output = Known(ValueType::Empty),
+    short = "Evaluate a file into a namespace of its own",
+    long = "Unlike `source`, which evaluates a file into the current scope, `import` evaluates a module into a new namespace and binds that namespace to a name in the current scope. The variables and closures declared by the module become members of the namespace. Modules can see the global namespace, but not the scope they are imported from.",
+    long = "",
+    long = "A module is either a `.crush` file or a directory containing a `mod.crush` entry file. Modules specified by name, like `text:strings`, are searched for in the directories of the `$global:crush:module_path` list, which by default contains the `modules` directory of the Crush configuration directory. The name `text:strings` matches the file `text/strings.crush` and the directory `text/strings`. Modules can also be specified directly as a file or directory. Relative files imported by a module are resolved against the directory of that module.",
+    long = "",
+    long = "Every module is only evaluated once. Importing it again binds the namespace that was created the first time, unless `reload` is specified. Modules that directly or indirectly import themselves are an error.",
+    example = "# Import text/strings.crush from the module path, and bind it to the name `strings`",
+    example = "import text:strings",
+    example = "strings:capitalize hello",
+    example = "# Import a module from a file, and bind it to the name `util`",
+    example = "import ./scripts/helpers.crush name=util",
+)]
+pub struct Import {
+    #[description("the name of the module, or its file or directory.")]
+    module: Value,
+    #[description(
+        "the name to bind the namespace to. Defaults to the last component of the module name, or the name of the file without its extension."
+    )]
+    name: Option<String>,
+    #[description("evaluate the module again, even if it has already been imported.")]
+    #[default(false)]
+    reload: bool,
+}
+
+/// The entry file of a module in the specified file or directory, if it exists.
+fn entry_file(path: &Path) -> Option<PathBuf> {
+    if path.is_dir() {
+        let entry = path.join(ENTRY_FILE);
+        entry.is_file().then_some(entry)
+    } else {
+        path.is_file().then(|| path.to_path_buf())
+    }
+}
+
+fn module_path(scope: &Scope) -> CrushResult<Vec<PathBuf>> {
+    match scope.get_absolute_path(vec![
+        "global".to_string(),
+        "crush".to_string(),
+        "module_path".to_string(),
+    ])? {
+        Value::List(dirs) => Ok(dirs
+            .iter()
+            .filter_map(|dir| match dir {
+                Value::File(dir) => Some(dir.to_path_buf()),
+                Value::String(dir) => Some(PathBuf::from(dir.to_string())),
+                _ => None,
+            })
+            .collect()),
+        v => command_error(format!(
+            "Expected the module path to be a list, got a value of type `{}`.",
+            v.value_type()
+        )),
+    }
+}
+
+/// Find the entry file of a module specified by name, e.g. `text:strings`.
+fn find_module(name: &str, scope: &Scope) -> CrushResult<PathBuf> {
+    let relative = name.split(':').collect::<PathBuf>();
+    for dir in module_path(scope)? {
+        let mut file = dir.join(&relative).into_os_string();
+        file.push(".crush");
+        if let Some(entry) =
+            entry_file(Path::new(&file)).or_else(|| entry_file(&dir.join(&relative)))
+        {
+            return Ok(entry);
+        }
+    }
+    command_error(format!(
+        "Could not find the module `{}` in the module path.",
+        name
+    ))
+}
+
+/// Evaluate the module in the specified entry file into a new namespace.
+fn load(path: &Path, name: &str, context: &CommandContext) -> CrushResult<Scope> {
+    let root = match context
+        .scope
+        .get_absolute_path(vec!["global".to_string()])?
+    {
+        Value::Scope(root) => root,
+        _ => return command_error("Could not find the global namespace."),
+    };
+    let module = Scope::create(
+        Some(name.to_string()),
+        Some(format!("The module in {}", path.display())),
+        ScopeType::Namespace,
+        false,
+        false,
+    );
+    module.set_parent(Some(root.clone()));
+    module.set_calling(Some(root));
+
+    // The modules being imported are tracked per thread, and inherited by the threads a module
+    // spawns, so that imports in unrelated jobs are not mistaken for cycles.
+    let mut importing = imports();
+    if importing.iter().any(|p| p == path) {
+        let cycle = importing
+            .iter()
+            .skip_while(|p| *p != path)
+            .chain([&path.to_path_buf()])
+            .map(|p| p.to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        return command_error(format!("Import cycle: {}", cycle.join(" -> ")));
+    }
+    importing.push(path.to_path_buf());
+    let previous = set_imports(importing);
+    let res = crate::execute::module(&module, path, &context.global_state);
+    set_imports(previous);
+    res?;
+
+    context
+        .global_state
+        .set_module(path.to_path_buf(), module.clone());
+    Ok(module)
+}
+
+/// Relative files imported by a module are relative to the directory of that module.
+fn relative_to_module(file: &Path) -> PathBuf {
+    match imports().last().and_then(|module| module.parent()) {
+        Some(dir) => dir.join(file),
+        None => file.to_path_buf(),
+    }
+}
+
+fn import(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: Import = Import::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let (path, default_name) = match &cfg.module {
+        Value::String(name) => (
+            find_module(name, &context.scope)?,
+            name.rsplit(':').next().map(|n| n.to_string()),
+        ),
+        Value::File(file) => (
+            entry_file(&relative_to_module(file)).ok_or_else(|| {
+                format!(
+                    "Could not find a module in `{}`. Modules are `.crush` files or directories containing a `{}` file.",
+                    file.display(),
+                    ENTRY_FILE
+                )
+            })?,
+            file.file_stem().map(|n| n.to_string_lossy().to_string()),
+        ),
+        v => {
+            return command_error(format!(
+                "Expected a module name or a file, got a value of type `{}`.",
+                v.value_type()
+            ));
+        }
+    };
+    let name = cfg
+        .name
+        .or(default_name)
+        .ok_or("Could not determine the name of the module.")?;
+    // Make sure the same module is not loaded twice using different paths to it
+    let path = cwd()?.join(path).canonicalize()?;
+
+    let module = match context.global_state.module(&path) {
+        Some(module) if !cfg.reload => module,
+        _ => load(&path, &name, &context)?,
+    };
+    context.scope.redeclare(&name, Value::Scope(module))?;
+    context.output.send(Value::Empty)
+}
@@ -2,7 +2,7 @@ use crate::lang::ast::lexer::LanguageMode;
 use crate::lang::command::OutputType::Known;
 use crate::lang::command::OutputType::Unknown;
 use crate::lang::command_invocation::resolve_external_command;
-use crate::lang::errors::CrushResult;
+use crate::lang::errors::{CrushResult, user_error};
 use crate::lang::pipe::ValueReceiver;
 use crate::lang::signature::binary_input::BinaryInput;
 use crate::lang::signature::files::Files;
@@ -25,10 +25,12 @@ mod cmd;
 mod r#for;
 mod help;
 mod r#if;
+mod import;
 mod r#loop;
 mod schedule;
 mod timeit;
 mod timer;
+mod r#try;
 mod r#while;
 
 #[signature(
@@ -43,6 +45,24 @@ fn r#break(context: CommandContext) -> CrushResult<()> {
     context.output.empty()
 }
 
+#[signature(
+    control.raise,
+    can_block = false,
+    short = "Raise an error.",
+    long = "The error can be handled using `try`. Otherwise, it aborts the script, just like any other error.",
+    output = Known(ValueType::Empty),
+    example = "$positive := {|$n: $integer| if ($n < 0) {raise \"Expected a positive number\"} else {$n}}",
+)]
+struct Raise {
+    #[description("the error message.")]
+    message: String,
+}
+
+fn raise(mut context: CommandContext) -> CrushResult<()> {
+    let cfg = Raise::parse(context.remove_arguments(), context.global_state.printer())?;
+    user_error(cfg.message)
+}
+
 #[signature(
     control.r#return,
     can_block = false,
@@ -314,6 +334,8 @@ pub fn declare(root: &Scope) -> CrushResult<()> {
             r#while::While::declare(env)?;
             r#loop::Loop::declare(env)?;
             r#for::For::declare(env)?;
+            r#try::Try::declare(env)?;
+            Raise::declare(env)?;
             cmd::Cmd::declare(env)?;
             Break::declare(env)?;
             Return::declare(env)?;
@@ -326,6 +348,7 @@ pub fn declare(root: &Scope) -> CrushResult<()> {
             Fg::declare(env)?;
             help::HelpSignature::declare(env)?;
             Source::declare(env)?;
+            import::Import::declare(env)?;
             Which::declare(env)?;
             Ok(())
         }),
@@ -38,10 +38,7 @@ pub fn time_run(it: &Command, context: &CommandContext) -> CrushResult<Duration>
 
     let start_time = Local::now();
     it.eval(context.clone().with_args(vec![], None).with_output(sender))?;
-    context
-        .global_state
-        .threads()
-        .join_one(c, context.global_state.printer());
+    context.global_state.threads().join_one(c)?;
     let end_time = Local::now();
     Ok(end_time - start_time)
 }
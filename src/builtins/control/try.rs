@@ -0,0 +1,139 @@
+use crate::lang::argument::Argument;
+use crate::lang::command::Command;
+use crate::lang::command::OutputType::Unknown;
+use crate::lang::data::r#struct::Struct;
+use crate::lang::errors::{CrushError, CrushResult, command_error};
+use crate::lang::pipe::{black_hole, forwarding_pipe};
+use crate::lang::state::contexts::CommandContext;
+use crate::lang::state::scope::ScopeType;
+use crate::lang::value::Value;
+use signature::signature;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[signature(
+    control.r#try,
+    output = Unknown,
+    short = "Execute a command, and handle any error it raises.",
+    long = "If the command fails, the error handler is invoked with a single argument, a struct describing the error. It has the following fields:",
+    long = "* `message` the error message.",
+    long = "* `kind` the type of error, e.g. `invalid_argument`, `io`, `exit_status` or `user`.",
+    long = "* `command` the name of the command that failed, if known.",
+    long = "* `file` and `line` the location in the source code where the error happened, if known.",
+    long = "* `trace` the stack trace of the error, if any.",
+    long = "The output of the command, or of the error handler if the command failed before producing any output, is the output of `try`.",
+    example = "try {",
+    example = "  rm ./important_file",
+    example = "} catch |$err| {",
+    example = "  echo $err:message",
+    example = "}",
+)]
+pub struct Try {
+    #[description("the command to invoke.")]
+    body: Command,
+    #[default("catch")]
+    catch: String,
+    #[description("the command to invoke if the body fails.")]
+    handler: Command,
+}
+
+fn error_value(err: &CrushError) -> Value {
+    let source = err.source();
+    Value::from(Struct::new(
+        vec![
+            ("message", Value::from(err.message())),
+            ("kind", Value::from(err.kind())),
+            (
+                "command",
+                err.command()
+                    .as_ref()
+                    .map(Value::from)
+                    .unwrap_or(Value::Empty),
+            ),
+            (
+                "file",
+                source
+                    .as_ref()
+                    .and_then(|s| s.file())
+                    .map(Value::from)
+                    .unwrap_or(Value::Empty),
+            ),
+            (
+                "line",
+                source
+                    .as_ref()
+                    .map(|s| Value::from(s.line_number()))
+                    .unwrap_or(Value::Empty),
+            ),
+            (
+                "trace",
+                err.trace()
+                    .as_ref()
+                    .map(Value::from)
+                    .unwrap_or(Value::Empty),
+            ),
+        ],
+        None,
+    ))
+}
+
+fn r#try(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: Try = Try::parse(context.remove_arguments(), context.global_state.printer())?;
+    if cfg.catch != "catch" {
+        return command_error(format!(
+            "Unknown clause `{}`. Did you misspell catch?",
+            cfg.catch
+        ));
+    }
+
+    // The output of the body is passed on as soon as it is produced, so that a body that produces
+    // a stream doesn't have to finish before anyone reads from it. If the body fails before
+    // producing any output, the error handler produces the output of the command instead.
+    let (sender, receiver) = forwarding_pipe(&context.output);
+    let output = context.output.clone();
+    let forwarded = Arc::new(AtomicBool::new(false));
+    let forwarder = {
+        let forwarded = forwarded.clone();
+        context.spawn("try:output", move || {
+            if let Ok(value) = receiver.recv() {
+                forwarded.store(true, Ordering::Relaxed);
+                output.send(value)?;
+            }
+            Ok(())
+        })?
+    };
+
+    let env = context
+        .scope
+        .create_child(&context.scope, ScopeType::Conditional);
+    let res = cfg.body.eval(
+        context
+            .empty()
+            .with_scope(env)
+            .with_output(sender)
+            .catching_errors(),
+    );
+    context.global_state.threads().join_one(forwarder)?;
+    let forwarded = forwarded.load(Ordering::Relaxed);
+
+    match res {
+        Ok(()) if forwarded => Ok(()),
+        Ok(()) => context.output.send(Value::Empty),
+        Err(err) => {
+            let env = context
+                .scope
+                .create_child(&context.scope, ScopeType::Conditional);
+            let argument = Argument::unnamed(error_value(&err), &context.source);
+            cfg.handler.eval(
+                context
+                    .empty()
+                    .with_scope(env)
+                    .with_args(vec![argument], None)
+                    .with_output(match forwarded {
+                        true => black_hole(),
+                        false => context.output,
+                    }),
+            )
+        }
+    }
+}
@@ -3,8 +3,10 @@ use crate::lang::ast::lexer::LanguageMode;
 use crate::lang::command::Command;
 use crate::lang::command::OutputType::Known;
 use crate::lang::data::dict::Dict;
+use crate::lang::data::r#struct::Struct;
 use crate::lang::data::table::{ColumnType, Row};
-use crate::lang::errors::CrushResult;
+use crate::lang::errors::{CrushResult, command_error};
+use crate::lang::interactive::config_dir;
 use crate::lang::state::contexts::CommandContext;
 use crate::lang::state::global_state::RunMode;
 use crate::lang::state::scope::Scope;
@@ -14,6 +16,19 @@ use rustyline::history::{History, SearchDirection};
 use signature::signature;
 use std::env;
 
+/// Modules are looked up in the `modules` directory of the configuration directory by default.
+fn make_module_path() -> Value {
+    List::new(
+        ValueType::File,
+        config_dir()
+            .map(|dir| vec![Value::from(dir.join("modules"))])
+            .unwrap_or_default(),
+    )
+    .into()
+}
+
+/// The environment of the Crush process when it started, kept for scripts written before the `env`
+/// namespace existed. Unlike `env:list`, it does not include variables set using `env:set`.
 fn make_env() -> CrushResult<Value> {
     let e = Dict::new(ValueType::String, ValueType::String)?;
     for (key, value) in env::vars() {
@@ -75,6 +90,83 @@ fn exit(mut context: CommandContext) -> CrushResult<()> {
     context.output.send(Value::Empty)
 }
 
+#[signature(
+    crush.last_status,
+    can_block = false,
+    output = Known(ValueType::Any),
+    short = "The exit status of the last external command.",
+    long = "Returns a struct with the fields `command`, `code` and `signal`. `code` is empty if the command was terminated by a signal, and `signal` is empty otherwise. If no external command has been run yet, an empty value is returned.",
+    example = "git pull",
+    example = "if $(neq $(crush:last_status:code) 0) {echo \"Pull failed\"}",
+)]
+struct LastStatus {}
+
+fn last_status(context: CommandContext) -> CrushResult<()> {
+    context
+        .output
+        .send(match context.global_state.last_status() {
+            Some(status) => Value::Struct(Struct::new(
+                vec![
+                    ("command", Value::from(status.command)),
+                    (
+                        "code",
+                        status
+                            .code
+                            .map(|c| Value::Integer(c as i128))
+                            .unwrap_or(Value::Empty),
+                    ),
+                    (
+                        "signal",
+                        status
+                            .signal
+                            .map(|s| Value::Integer(s as i128))
+                            .unwrap_or(Value::Empty),
+                    ),
+                ],
+                None,
+            )),
+            None => Value::Empty,
+        })
+}
+
+mod strict {
+    use super::*;
+
+    #[signature(
+        crush.strict.set,
+        can_block = false,
+        short = "Enable or disable strict mode.",
+        long = "In strict mode, an external command that exits with a non-zero status or is killed by a signal is an error, which aborts the script unless it is caught.",
+        long = "Other errors also abort the script in strict mode. Outside of strict mode, they are printed and execution continues.",
+        output = Known(ValueType::Empty)
+    )]
+    pub struct Set {
+        #[default(true)]
+        #[description("whether strict mode is enabled.")]
+        strict: bool,
+    }
+
+    fn set(mut context: CommandContext) -> CrushResult<()> {
+        let cfg: Set = Set::parse(context.remove_arguments(), &context.global_state.printer())?;
+        context.global_state.set_strict(cfg.strict);
+        context.output.send(Value::Empty)
+    }
+
+    #[signature(
+        crush.strict.get,
+        can_block = false,
+        short = "Returns true if strict mode is enabled.",
+        output = Known(ValueType::Bool)
+    )]
+    pub struct Get {}
+
+    fn get(context: CommandContext) -> CrushResult<()> {
+        context
+            .output
+            .send(Value::Bool(context.global_state.strict()))
+    }
+}
+
 mod prompt {
     use super::*;
 
@@ -189,8 +281,9 @@ mod title {
     }
 }
 
-static JOB_OUTPUT_TYPE: [ColumnType; 2] = [
+static JOB_OUTPUT_TYPE: [ColumnType; 3] = [
     ColumnType::new("id", ValueType::Integer),
+    ColumnType::new("status", ValueType::String),
     ColumnType::new("description", ValueType::String),
 ];
 
@@ -199,20 +292,121 @@ static JOB_OUTPUT_TYPE: [ColumnType; 2] = [
     can_block = false,
     short = "List running jobs",
     output = Known(ValueType::table_input_stream(&JOB_OUTPUT_TYPE)),
-    long = "All currently running jobs")]
+    long = "All currently running jobs. The status of a job is either `running`, `suspended` or `cancelled`.")]
 struct Jobs {}
 
 fn jobs(context: CommandContext) -> CrushResult<()> {
     let output = context.output.initialize(&JOB_OUTPUT_TYPE)?;
     for job in context.global_state.jobs() {
+        let status = if job.control.is_cancelled() {
+            "cancelled"
+        } else if job.control.is_suspended() {
+            "suspended"
+        } else {
+            "running"
+        };
         output.send(Row::new(vec![
             Value::from(job.id),
+            Value::from(status),
             Value::from(job.description),
         ]))?;
     }
     Ok(())
 }
 
+mod job {
+    use super::*;
+    use crate::lang::state::global_state::{JobId, LiveJob};
+    use crate::lang::threads::current_job;
+    use nix::sys::signal::Signal;
+    use std::time::Duration;
+
+    fn job(context: &CommandContext, id: usize) -> CrushResult<LiveJob> {
+        match context.global_state.job(JobId::from(id)) {
+            Some(job) => Ok(job),
+            None => command_error(format!("No job with id {}.", id)),
+        }
+    }
+
+    #[signature(
+        crush.job.kill,
+        can_block = false,
+        short = "Cancel a job",
+        long = "Builtins and closures in the job stop executing the next time they check for cancellation, and all external commands started by the job are sent the `SIGTERM` signal.",
+        example = "crush:job:kill 3",
+        output = Known(ValueType::Empty))]
+    pub struct Kill {
+        #[description("the id of the job to cancel.")]
+        id: usize,
+    }
+
+    fn kill(mut context: CommandContext) -> CrushResult<()> {
+        let cfg: Kill = Kill::parse(context.remove_arguments(), &context.global_state.printer())?;
+        let job = job(&context, cfg.id)?;
+        job.control.cancel();
+        job.control.signal_children(Signal::SIGTERM);
+        job.control.resume();
+        context.output.empty()
+    }
+
+    #[signature(
+        crush.job.resume,
+        can_block = false,
+        short = "Resume a suspended job in the background",
+        long = "A job running in the foreground is suspended by pressing ^Z. Resuming it sends the `SIGCONT` signal to all external commands started by the job. The terminal stays with Crush.",
+        example = "crush:job:resume 3",
+        output = Known(ValueType::Empty))]
+    pub struct Resume {
+        #[description("the id of the job to resume.")]
+        id: usize,
+    }
+
+    fn resume(mut context: CommandContext) -> CrushResult<()> {
+        let cfg: Resume =
+            Resume::parse(context.remove_arguments(), &context.global_state.printer())?;
+        job(&context, cfg.id)?.control.resume();
+        context.output.empty()
+    }
+
+    #[signature(
+        crush.job.foreground,
+        can_block = true,
+        short = "Resume a job in the foreground",
+        long = "The terminal is handed over to the external commands of the job, which are sent the `SIGCONT` signal if they were suspended. Returns once the job is done or is suspended again by pressing ^Z.",
+        example = "crush:job:foreground 3",
+        output = Known(ValueType::Empty))]
+    pub struct Foreground {
+        #[description("the id of the job to resume.")]
+        id: usize,
+    }
+
+    fn foreground(mut context: CommandContext) -> CrushResult<()> {
+        let cfg: Foreground =
+            Foreground::parse(context.remove_arguments(), &context.global_state.printer())?;
+        let job = job(&context, cfg.id)?;
+        if current_job().is_some_and(|current| current.is_part_of(&job.control)) {
+            return command_error("A job can't move itself to the foreground.");
+        }
+        let previous = context.global_state.set_foreground(Some(job.id));
+        job.control.resume();
+        while context.global_state.job(job.id).is_some()
+            && !job.control.is_suspended()
+            && !context.scope.is_stopped()
+        {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        context.global_state.set_foreground(previous);
+        if job.control.is_suspended() {
+            context.global_state.printer().line(&format!(
+                "Suspended job {}: {}",
+                usize::from(job.id),
+                job.description
+            ));
+        }
+        context.output.empty()
+    }
+}
+
 static HISTORY_OUTPUT_TYPE: [ColumnType; 2] = [
     ColumnType::new("idx", ValueType::Integer),
     ColumnType::new("command", ValueType::String),
@@ -244,11 +438,170 @@ fn history(context: CommandContext) -> CrushResult<()> {
     Ok(())
 }
 
+mod completion {
+    use super::*;
+    use crate::lang::completion::tag;
+
+    static LIST_OUTPUT_TYPE: [ColumnType; 3] = [
+        ColumnType::new("tag", ValueType::String),
+        ColumnType::new("custom", ValueType::Bool),
+        ColumnType::new("description", ValueType::String),
+    ];
+
+    #[signature(
+        crush.completion.set,
+        can_block = false,
+        short = "Set the completion provider for a completion tag.",
+        long = "Completion tags name a kind of value, like `hostname`, `user` or `git/branch`. Arguments of builtins that accept such a value are tab completed using the provider of its tag, and external commands can be assigned a tag using `crush:completion:external`.",
+        long = "",
+        long = "The provider is a command that is called without arguments and returns a list of candidates, or a table or table stream, in which case the first column is used. A provider set using this command takes precedence over the builtin provider for the same tag. Providers are usually set in `config.crush`.",
+        example = "# Complete git remotes",
+        example = "crush:completion:set git/remote {git remote | lines:from}",
+        output = Known(ValueType::Empty)
+    )]
+    pub struct Set {
+        #[description("the completion tag.")]
+        tag: String,
+        #[description("the command that produces the candidates.")]
+        provider: Command,
+    }
+
+    fn set(mut context: CommandContext) -> CrushResult<()> {
+        let cfg: Set = Set::parse(context.remove_arguments(), &context.global_state.printer())?;
+        context
+            .global_state
+            .set_completion_provider(cfg.tag, Some(cfg.provider));
+        context.output.send(Value::Empty)
+    }
+
+    #[signature(
+        crush.completion.unset,
+        can_block = false,
+        short = "Remove the completion provider for a completion tag.",
+        long = "If there is a builtin provider for the tag, it is used again.",
+        output = Known(ValueType::Empty)
+    )]
+    pub struct Unset {
+        #[description("the completion tag.")]
+        tag: String,
+    }
+
+    fn unset(mut context: CommandContext) -> CrushResult<()> {
+        let cfg: Unset = Unset::parse(context.remove_arguments(), &context.global_state.printer())?;
+        context.global_state.set_completion_provider(cfg.tag, None);
+        context.output.send(Value::Empty)
+    }
+
+    #[signature(
+        crush.completion.list,
+        can_block = false,
+        short = "List all completion tags that have a provider.",
+        long = "The `custom` column is true for tags whose provider was set using `crush:completion:set`.",
+        output = Known(ValueType::table_input_stream(&LIST_OUTPUT_TYPE)),
+    )]
+    pub struct List {}
+
+    fn list(context: CommandContext) -> CrushResult<()> {
+        let output = context.output.initialize(&LIST_OUTPUT_TYPE)?;
+        let custom = context.global_state.completion_providers();
+        let mut rows = tag::builtin_providers()
+            .iter()
+            .filter(|p| !custom.iter().any(|(tag, _)| tag == p.tag))
+            .map(|p| (p.tag.to_string(), false, p.description.to_string()))
+            .chain(
+                custom
+                    .iter()
+                    .map(|(tag, provider)| (tag.clone(), true, provider.short_help())),
+            )
+            .collect::<Vec<_>>();
+        rows.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+        for (tag, custom, description) in rows {
+            output.send(Row::new(vec![
+                Value::from(tag),
+                Value::Bool(custom),
+                Value::from(description),
+            ]))?;
+        }
+        Ok(())
+    }
+
+    #[signature(
+        crush.completion.candidates,
+        can_block = true,
+        short = "Return all completion candidates for a completion tag.",
+        output = Known(ValueType::List(Box::from(ValueType::String))),
+        example = "crush:completion:candidates user",
+    )]
+    pub struct Candidates {
+        #[description("the completion tag.")]
+        tag: String,
+    }
+
+    fn candidates(mut context: CommandContext) -> CrushResult<()> {
+        let cfg: Candidates =
+            Candidates::parse(context.remove_arguments(), &context.global_state.printer())?;
+        let candidates = tag::candidates(&cfg.tag, &context.scope, &context.global_state)?;
+        context.output.send(
+            crate::data::list::List::new(
+                ValueType::String,
+                candidates.into_iter().map(Value::from).collect::<Vec<_>>(),
+            )
+            .into(),
+        )
+    }
+
+    #[signature(
+        crush.completion.external,
+        can_block = false,
+        short = "Set the completion tag for the arguments of an external command.",
+        long = "All arguments of the command are completed using the provider of the tag. If no tag is given, the completion tag of the command is removed.",
+        example = "crush:completion:external ssh hostname",
+        output = Known(ValueType::Empty)
+    )]
+    pub struct External {
+        #[description("the name of the external command.")]
+        command: String,
+        #[description("the completion tag.")]
+        tag: Option<String>,
+    }
+
+    fn external(mut context: CommandContext) -> CrushResult<()> {
+        let cfg: External =
+            External::parse(context.remove_arguments(), &context.global_state.printer())?;
+        context
+            .global_state
+            .set_external_completion(cfg.command, cfg.tag);
+        context.output.send(Value::Empty)
+    }
+
+    #[signature(
+        crush.completion.help,
+        can_block = false,
+        short = "Allow completing the switches of an external command using its help text.",
+        long = "Switches and subcommands of external commands are completed using installed fish completion files. Commands without such a file are not completed, unless they are allowed using this command, in which case they are run with the `--help` switch when tab completing them, and the output is parsed. Only allow commands that are safe to run with `--help`. This command is usually called in `config.crush`.",
+        example = "crush:completion:help cargo",
+        example = "crush:completion:help cargo enabled=$false",
+        output = Known(ValueType::Empty)
+    )]
+    pub struct Help {
+        #[description("the name of the external command.")]
+        command: String,
+        #[description("whether the command may be run with `--help`.")]
+        #[default(true)]
+        enabled: bool,
+    }
+
+    fn help(mut context: CommandContext) -> CrushResult<()> {
+        let cfg: Help = Help::parse(context.remove_arguments(), &context.global_state.printer())?;
+        context
+            .global_state
+            .set_help_completion(cfg.command, cfg.enabled);
+        context.output.send(Value::Empty)
+    }
+}
+
 mod locale {
     use super::*;
-    use crate::lang::completion::Completion;
-    use crate::lang::completion::parse::{LastArgument, PartialCommandResult};
-    use crate::util::escape::{escape, escape_without_quotes};
     use num_format::SystemLocale;
 
     static LIST_OUTPUT_TYPE: [ColumnType; 1] = [ColumnType::new("name", ValueType::String)];
@@ -270,40 +623,11 @@ mod locale {
         Ok(())
     }
 
-    fn locale_complete(
-        cmd: &PartialCommandResult,
-        _cursor: usize,
-        _scope: &Scope,
-        res: &mut Vec<Completion>,
-    ) -> CrushResult<()> {
-        for name in SystemLocale::available_names()? {
-            match &cmd.last_argument {
-                LastArgument::Unknown => res.push(Completion::new(escape(&name), name, 0)),
-
-                LastArgument::QuotedString(stripped_prefix) => {
-                    if name.starts_with(stripped_prefix) && name.len() > 0 {
-                        res.push(Completion::new(
-                            format!(
-                                "{}\" ",
-                                escape_without_quotes(&name[stripped_prefix.len()..])
-                            ),
-                            name,
-                            0,
-                        ));
-                    }
-                }
-
-                _ => {}
-            }
-        }
-        Ok(())
-    }
-
     #[signature(
         crush.locale.set, output = Known(ValueType::Empty), short = "Set the current locale."
     )]
     pub struct Set {
-        #[custom_completion(locale_complete)]
+        #[completion_tag("locale")]
         #[description("the new locale.")]
         locale: String,
     }
@@ -399,6 +723,7 @@ pub fn declare(root: &Scope) -> CrushResult<()> {
 
             crush.declare("env", make_env()?)?;
             crush.declare("arguments", make_arguments())?;
+            crush.declare("module_path", make_module_path())?;
 
             crush.create_namespace(
                 "prompt",
@@ -426,9 +751,45 @@ pub fn declare(root: &Scope) -> CrushResult<()> {
 
             Threads::declare(crush)?;
             Exit::declare(crush)?;
+            LastStatus::declare(crush)?;
+
+            crush.create_namespace(
+                "strict",
+                "Strict mode makes failing external commands an error",
+                Box::new(move |env| {
+                    strict::Set::declare(env)?;
+                    strict::Get::declare(env)?;
+                    Ok(())
+                }),
+            )?;
+
             Jobs::declare(crush)?;
+            crush.create_namespace(
+                "job",
+                "Control running jobs",
+                Box::new(move |env| {
+                    job::Kill::declare(env)?;
+                    job::Resume::declare(env)?;
+                    job::Foreground::declare(env)?;
+                    Ok(())
+                }),
+            )?;
             HistoryCommand::declare(crush)?;
 
+            crush.create_namespace(
+                "completion",
+                "Tab completion providers",
+                Box::new(move |env| {
+                    completion::Set::declare(env)?;
+                    completion::Unset::declare(env)?;
+                    completion::List::declare(env)?;
+                    completion::Candidates::declare(env)?;
+                    completion::External::declare(env)?;
+                    completion::Help::declare(env)?;
+                    Ok(())
+                }),
+            )?;
+
             crush.create_namespace(
                 "locale",
                 "Locale data for Crush",
@@ -1,5 +1,5 @@
 use crate::lang::argument::{Argument, column_names};
-use crate::lang::ast::location::Location;
+use crate::lang::ast::source::{Source, SourceType};
 use crate::lang::command::CrushCommand;
 use crate::lang::command::OutputType::*;
 use crate::lang::data::dict::Dict;
@@ -71,7 +71,7 @@ impl DBusThing {
             .drain(..)
             .zip(output_arguments.drain(..))
             // Fake location, only needed to call the column_names function
-            .map(|(value, arg)| Argument::new(arg.name.clone(), value, Location::new(0, 0)))
+            .map(|(value, arg)| Argument::new(arg.name.clone(), value, &Source::new(SourceType::Input, "".into())))
             .collect::<Vec<_>>();
 
         let mut names = column_names(&values_as_arguments);
@@ -96,7 +96,7 @@ impl DBusThing {
         queue.push("/".to_string());
         let mut res = Vec::new();
         while !queue.is_empty() {
-            let path = queue.pop()?;
+            let path = queue.pop().ok_or("Empty queue")?;
             let sub_proxy = self.proxy(service, &path);
             let (intro_xml,): (String,) = sub_proxy.method_call(
                 "org.freedesktop.DBus.Introspectable", //&name,
@@ -438,7 +438,7 @@ fn deserialize(iter: &mut dbus::arg::Iter) -> CrushResult<Value> {
         ArgType::UInt64 => Value::Integer(iter.get::<u64>().ok_or("Unexpected type")? as i128),
         ArgType::Double => Value::Float(iter.get::<f64>().ok_or("Unexpected type")?),
         ArgType::Array => {
-            let mut sub = iter.recurse(ArgType::Array)?;
+            let mut sub = iter.recurse(ArgType::Array).ok_or("Invalid DBUS message")?;
 
             if sub.arg_type() == ArgType::DictEntry {
                 let mut res = Vec::new();
@@ -528,7 +528,7 @@ fn deserialize(iter: &mut dbus::arg::Iter) -> CrushResult<Value> {
             }
         }
         ArgType::Variant => {
-            let mut sub = iter.recurse(ArgType::Variant)?;
+            let mut sub = iter.recurse(ArgType::Variant).ok_or("Invalid DBUS message")?;
             match deserialize(&mut sub) {
                 Ok(value) => {
                     sub.next();
@@ -547,7 +547,7 @@ fn deserialize(iter: &mut dbus::arg::Iter) -> CrushResult<Value> {
         ArgType::DictEntry => panic!("Invalid location for DictEntry"),
         ArgType::UnixFd => panic!("unimplemented"),
         ArgType::Struct => {
-            let mut sub = iter.recurse(ArgType::Struct)?;
+            let mut sub = iter.recurse(ArgType::Struct).ok_or("Invalid DBUS message")?;
             let mut res = Vec::new();
             loop {
                 match deserialize(&mut sub) {
@@ -630,7 +630,7 @@ struct ServiceCall {
 fn filter_object(mut input: Vec<DBusObject>, filter: Value) -> CrushResult<DBusObject> {
     let mut res: Vec<_>;
     match &filter {
-        Value::File(p) => res = input.drain(..).filter(|o| &o.path == p.to_str()?).collect(),
+        Value::File(p) => res = input.drain(..).filter(|o| Some(o.path.as_str()) == p.to_str()).collect(),
         Value::Glob(p) => res = input.drain(..).filter(|o| p.matches(&o.path)).collect(),
         Value::Regex(_, re) => res = input.drain(..).filter(|o| re.is_match(&o.path)).collect(),
         _ => return error("Invalid filter type"),
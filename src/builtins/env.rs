@@ -0,0 +1,204 @@
+use crate::lang::command::Command;
+use crate::lang::command::OutputType::{Known, Unknown};
+use crate::lang::data::table::{ColumnType, Row};
+use crate::lang::errors::{CrushResult, command_error};
+use crate::lang::ordered_string_map::OrderedStringMap;
+use crate::lang::state::contexts::CommandContext;
+use crate::lang::state::scope::{Scope, ScopeType};
+use crate::lang::value::{Value, ValueType};
+use signature::signature;
+use std::collections::BTreeMap;
+use std::env;
+use std::sync::Mutex;
+
+/// Serializes all modifications of the process environment made by Crush.
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+fn check_name(name: &str) -> CrushResult<()> {
+    if name.is_empty() || name.contains('=') || name.contains('\0') {
+        command_error(format!("Illegal environment variable name `{}`.", name))
+    } else {
+        Ok(())
+    }
+}
+
+fn to_env_value(name: &str, value: Value) -> CrushResult<String> {
+    let res = match value {
+        Value::String(s) => s.to_string(),
+        Value::File(_) | Value::Integer(_) | Value::Float(_) => value.to_string(),
+        v => {
+            return command_error(format!(
+                "Can't use a value of type `{}` as the value of the environment variable `{}`.",
+                v.value_type(),
+                name
+            ));
+        }
+    };
+    if res.contains('\0') {
+        return command_error(format!(
+            "The value of the environment variable `{}` contains a null character.",
+            name
+        ));
+    }
+    Ok(res)
+}
+
+/// Returns the environment that an external command started in the specified scope would see.
+pub fn effective_environment(scope: &Scope) -> CrushResult<BTreeMap<String, String>> {
+    let mut res = env::vars().collect::<BTreeMap<_, _>>();
+    for (name, value) in scope.environment()?.iter() {
+        match value {
+            Some(value) => res.insert(name.clone(), value.clone()),
+            None => res.remove(name),
+        };
+    }
+    Ok(res)
+}
+
+#[signature(
+    env.get,
+    can_block = false,
+    output = Known(ValueType::one_of(vec![ValueType::String, ValueType::Empty])),
+    short = "Returns the value of an environment variable.",
+    long = "If the variable is not set, an empty value is returned.",
+    example = "env:get HOME",
+)]
+struct Get {
+    #[description("the name of the environment variable.")]
+    name: String,
+}
+
+fn get(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: Get = Get::parse(context.remove_arguments(), context.global_state.printer())?;
+    let value = match context.scope.environment()?.get(&cfg.name) {
+        Some(value) => value.clone(),
+        None => env::var(&cfg.name).ok(),
+    };
+    context
+        .output
+        .send(value.map(Value::from).unwrap_or(Value::Empty))
+}
+
+#[signature(
+    env.set,
+    can_block = false,
+    output = Known(ValueType::Empty),
+    short = "Set environment variables.",
+    long = "The variables are set in the environment of the Crush process, and are inherited by all external commands started afterwards.",
+    example = "env:set RUST_LOG=debug AWS_PROFILE=staging",
+)]
+struct Set {
+    #[named()]
+    #[description("the environment variables to set.")]
+    variables: OrderedStringMap<Value>,
+}
+
+fn set(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: Set = Set::parse(context.remove_arguments(), context.global_state.printer())?;
+    let _lock = ENV_LOCK.lock()?;
+    for (name, value) in cfg.variables {
+        check_name(&name)?;
+        let value = to_env_value(&name, value)?;
+        // SAFETY: Crush itself only modifies the environment while holding ENV_LOCK, and the
+        // name and value have been checked for the characters that set_var rejects.
+        unsafe { env::set_var(&name, value) };
+    }
+    context.output.empty()
+}
+
+#[signature(
+    env.unset,
+    can_block = false,
+    output = Known(ValueType::Empty),
+    short = "Remove environment variables.",
+    long = "The variables are removed from the environment of the Crush process, and from the environment of all external commands started afterwards.",
+    example = "env:unset AWS_PROFILE",
+)]
+struct Unset {
+    #[unnamed()]
+    #[description("the names of the environment variables to remove.")]
+    names: Vec<String>,
+}
+
+fn unset(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: Unset = Unset::parse(context.remove_arguments(), context.global_state.printer())?;
+    let _lock = ENV_LOCK.lock()?;
+    for name in cfg.names {
+        check_name(&name)?;
+        // SAFETY: See env:set.
+        unsafe { env::remove_var(&name) };
+    }
+    context.output.empty()
+}
+
+static LIST_OUTPUT_TYPE: [ColumnType; 2] = [
+    ColumnType::new("name", ValueType::String),
+    ColumnType::new("value", ValueType::String),
+];
+
+#[signature(
+    env.list,
+    can_block = false,
+    output = Known(ValueType::table_input_stream(&LIST_OUTPUT_TYPE)),
+    short = "Returns a table of all environment variables, sorted by name.",
+    long = "Variables set using `env:with` in the current scope are included.",
+    example = "env:list | where {$name:starts_with AWS_}",
+)]
+struct List {}
+
+fn list(context: CommandContext) -> CrushResult<()> {
+    let output = context.output.initialize(&LIST_OUTPUT_TYPE)?;
+    for (name, value) in effective_environment(&context.scope)? {
+        output.send(Row::new(vec![Value::from(name), Value::from(value)]))?;
+    }
+    Ok(())
+}
+
+#[signature(
+    env.with,
+    output = Unknown,
+    short = "Run a command with additional environment variables.",
+    long = "The variables are only set for external commands started by the specified command. The environment of the Crush process is not modified.",
+    example = "env:with {cargo build} RUST_LOG=debug unset=CARGO_TARGET_DIR",
+)]
+struct With {
+    #[description("the command to run.")]
+    command: Command,
+    #[description("the names of environment variables to remove.")]
+    unset: Vec<String>,
+    #[named()]
+    #[description("the environment variables to set.")]
+    variables: OrderedStringMap<Value>,
+}
+
+fn with(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: With = With::parse(context.remove_arguments(), context.global_state.printer())?;
+    let scope = context.scope.create_child(&context.scope, ScopeType::Block);
+    for (name, value) in cfg.variables {
+        check_name(&name)?;
+        let value = to_env_value(&name, value)?;
+        scope.set_environment(&name, Some(value))?;
+    }
+    for name in cfg.unset {
+        check_name(&name)?;
+        scope.set_environment(&name, None)?;
+    }
+    cfg.command
+        .eval(context.with_args(vec![], None).with_scope(scope))
+}
+
+pub fn declare(root: &Scope) -> CrushResult<()> {
+    root.create_namespace(
+        "env",
+        "Environment variables of the Crush process and of the external commands it runs",
+        Box::new(move |ns| {
+            Get::declare(ns)?;
+            Set::declare(ns)?;
+            Unset::declare(ns)?;
+            List::declare(ns)?;
+            With::declare(ns)?;
+            Ok(())
+        }),
+    )?;
+    Ok(())
+}
@@ -1,35 +1,51 @@
 use crate::CrushResult;
-use crate::builtins::io::json::{json_to_value, value_to_json};
 use crate::data::r#struct::Struct;
-use crate::lang::any_str::AnyStr;
-use crate::lang::command::CrushCommand;
-use crate::lang::command::OutputType::Unknown;
-use crate::lang::data::list::List;
-use crate::lang::data::table::ColumnType;
-use crate::lang::data::table::{Row, Table};
-use crate::lang::errors::{command_error, error};
+use crate::lang::argument::ArgumentDefinition;
+use crate::lang::command::OutputType::Known;
+use crate::lang::command::{Command, CrushCommand, OutputType, Parameter};
+use crate::lang::errors::{CrushResultExtra, command_error, data_error};
+use crate::lang::help::Help;
+use crate::lang::serialization::SerializationState;
+use crate::lang::serialization::model::Element;
+use crate::lang::signature::files;
+use crate::lang::signature::files::Files;
 use crate::lang::signature::patterns::Patterns;
-use crate::lang::state::contexts::CommandContext;
+use crate::lang::state::contexts::{CommandContext, EvalContext};
 use crate::lang::state::scope::Scope;
-use crate::lang::state::this::This;
 use crate::lang::value::Value;
 use crate::lang::value::ValueType;
+use crate::util::protobuf;
 use chrono::Duration;
-use crossbeam::channel::bounded;
-use itertools::Itertools;
-use regex::Regex;
+use prost::Message;
+use prost_reflect::{DescriptorPool, DynamicMessage, MessageDescriptor, MethodDescriptor};
 use signature::signature;
-use std::collections::{HashMap, HashSet};
-use std::io::Read;
-use std::process;
-use std::process::Stdio;
-use std::sync::OnceLock;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::sync::{Arc, OnceLock};
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder};
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+use tonic::{Status, Streaming};
 
 #[signature(
     grpc.connect,
     can_block = true,
-    short = "Create a connection to a gRPC service)",
-    long = "This command currently uses grpcurl under the hood. It does not have a persistent gRPC connections and can therefore be slow."
+    output = Known(ValueType::Struct),
+    short = "Create a connection to a gRPC service.",
+    long = "Returns a struct with one member for every method of the matching services. Calling such a member performs the call. Request fields are passed in as named arguments, and the response message is returned as a struct. Methods that stream their responses return a table stream with one row per response message. Methods that stream their requests read them from the input, which should be a table stream or a list of structs.",
+    long = "",
+    long = "Unless one or more `.proto` files are specified, the available services are discovered using server reflection.",
+    long = "",
+    long = "Protobuf messages are converted into structs, with repeated fields becoming lists, map fields becoming dicts and enum values becoming the name of the value. `google.protobuf.Timestamp` and `google.protobuf.Duration` messages become times and durations. Unset message fields become `$empty`.",
+    example = "# Connect to all services on a server that supports reflection",
+    example = "$svc := $(grpc:connect \"localhost\" port=50051 --plaintext service=*)",
+    example = "# Call a method",
+    example = "$svc:SayHello name=\"Anna\"",
+    example = "# Connect using a protobuf definition instead of server reflection",
+    example = "$svc := $(grpc:connect \"example.com\" port=443 service=\"helloworld.Greeter\" proto=./helloworld.proto)",
 )]
 struct Connect {
     #[description("Host to connect to.")]
@@ -51,195 +67,298 @@ struct Connect {
     #[default(5990)]
     #[description("Port to connect to")]
     port: i128,
+
+    #[description(
+        "Protobuf definitions of the services. If none are given, server reflection is used to discover the services."
+    )]
+    proto: Vec<Files>,
 }
 
-struct Grpc {
-    host: String,
-    plaintext: bool,
-    timeout: Duration,
-    port: i128,
+/// The tokio runtime used to drive all gRPC calls.
+fn runtime() -> CrushResult<&'static Runtime> {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    if let Some(runtime) = RUNTIME.get() {
+        return Ok(runtime);
+    }
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .thread_name("grpc")
+        .enable_all()
+        .build()?;
+    Ok(RUNTIME.get_or_init(|| runtime))
 }
 
-impl Grpc {
-    fn new(s: Struct) -> CrushResult<Grpc> {
-        if let Some(Value::String(host)) = s.get("host") {
-            if let Some(Value::Bool(plaintext)) = s.get("plaintext") {
-                if let Some(Value::Duration(timeout)) = s.get("timeout") {
-                    if let Some(Value::Integer(port)) = s.get("port") {
-                        return Ok(Grpc {
-                            host: host.to_string(),
-                            plaintext,
-                            timeout,
-                            port,
-                        });
-                    }
-                }
+/// Run a future to completion, giving up if the job that this command belongs to is cancelled
+/// first, in which case `None` is returned.
+fn wait<F: Future>(scope: &Scope, future: F) -> CrushResult<Option<F::Output>> {
+    runtime()?.block_on(async {
+        let mut future = std::pin::pin!(future);
+        loop {
+            match tokio::time::timeout(std::time::Duration::from_millis(100), &mut future).await {
+                Ok(res) => return Ok(Some(res)),
+                Err(_) if scope.is_stopped() => return Ok(None),
+                Err(_) => {}
             }
         }
-        command_error("Invalid struct specification.")
+    })
+}
+
+fn uri(host: &str, port: i128, plaintext: bool) -> String {
+    format!(
+        "{}://{}:{}",
+        if plaintext { "http" } else { "https" },
+        host,
+        port
+    )
+}
+
+fn endpoint(uri: String, plaintext: bool, timeout: Duration) -> CrushResult<Endpoint> {
+    let timeout = timeout.to_std()?;
+    let endpoint = Endpoint::from_shared(uri)?
+        .timeout(timeout)
+        .connect_timeout(timeout);
+    if plaintext {
+        Ok(endpoint)
+    } else {
+        Ok(endpoint.tls_config(ClientTlsConfig::new().with_native_roots())?)
     }
+}
 
-    fn call<S: Into<String>>(
-        &self,
-        context: &CommandContext,
-        data: Option<String>,
-        mut args: Vec<S>,
-    ) -> CrushResult<String> {
-        let mut cmd = process::Command::new("grpcurl");
-
-        if self.plaintext {
-            cmd.arg("--plaintext");
-        }
+/// A codec that encodes and decodes messages described by runtime descriptors rather than
+/// generated types.
+#[derive(Clone)]
+struct DynamicCodec {
+    decode: MessageDescriptor,
+}
 
-        cmd.arg("--max-time")
-            .arg(self.timeout.num_seconds().to_string());
+struct DynamicEncoder;
 
-        if let Some(data) = data {
-            cmd.arg("-d").arg(data);
-        }
+struct DynamicDecoder(MessageDescriptor);
 
-        cmd.arg(format!("{}:{}", self.host, self.port));
-        for a in args.drain(..) {
-            cmd.arg::<String>(a.into());
-        }
+impl Codec for DynamicCodec {
+    type Encode = DynamicMessage;
+    type Decode = DynamicMessage;
+    type Encoder = DynamicEncoder;
+    type Decoder = DynamicDecoder;
 
-        cmd.stdout(Stdio::piped());
-        cmd.stderr(Stdio::piped());
-
-        let mut child = cmd.spawn()?;
-
-        let mut stdout = child.stdout.take().ok_or("Expected output stream")?;
-        let mut buff = Vec::new();
-        stdout.read_to_end(&mut buff)?;
-        let output = String::from_utf8(buff)?;
-        let (send_err, recv_err) = bounded(1);
-        let mut stderr = child.stderr.take().ok_or("Expected error stream")?;
-        context.spawn("grpcurl:stderr", move || {
-            let mut buff = Vec::new();
-            stderr.read_to_end(&mut buff)?;
-            let errors = String::from_utf8(buff)?;
-            let _ = send_err.send(errors);
-            Ok(())
-        })?;
+    fn encoder(&mut self) -> Self::Encoder {
+        DynamicEncoder
+    }
 
-        match child.wait()?.success() {
-            true => Ok(output),
-            false => command_error(recv_err.recv()?),
-        }
+    fn decoder(&mut self) -> Self::Decoder {
+        DynamicDecoder(self.decode.clone())
     }
 }
 
-#[derive(Clone, Debug)]
-struct ProtoMessage {
-    name: String,
-    fields: Vec<ProtoField>,
-}
+impl Encoder for DynamicEncoder {
+    type Item = DynamicMessage;
+    type Error = Status;
 
-#[derive(Clone, Debug)]
-struct ProtoField {
-    name: String,
-    proto_type: ProtoType,
+    fn encode(&mut self, item: DynamicMessage, dst: &mut EncodeBuf<'_>) -> Result<(), Status> {
+        item.encode(dst)
+            .map_err(|e| Status::internal(format!("Failed to encode message: {}", e)))
+    }
 }
 
-#[derive(Clone, Debug)]
-enum ProtoType {
-    Int64,
-    UInt64,
-    Int32,
-    UInt32,
-    Double,
-    Float,
-    Bool,
-    String,
-    Bytes,
-    Message(ProtoMessage),
+impl Decoder for DynamicDecoder {
+    type Item = DynamicMessage;
+    type Error = Status;
+
+    fn decode(&mut self, src: &mut DecodeBuf<'_>) -> Result<Option<DynamicMessage>, Status> {
+        DynamicMessage::decode(self.0.clone(), src)
+            .map(Some)
+            .map_err(|e| Status::internal(format!("Failed to decode message: {}", e)))
+    }
 }
 
-impl ProtoType {
-    fn crush_type(&self) -> ValueType {
-        match self {
-            ProtoType::Int64 => ValueType::Integer,
-            ProtoType::UInt64 => ValueType::Integer,
-            ProtoType::Int32 => ValueType::Integer,
-            ProtoType::UInt32 => ValueType::Integer,
-            ProtoType::Double => ValueType::Float,
-            ProtoType::Float => ValueType::Float,
-            ProtoType::Bool => ValueType::Bool,
-            ProtoType::String => ValueType::String,
-            ProtoType::Bytes => ValueType::Binary,
-            ProtoType::Message(_) => ValueType::Struct,
+/// Server reflection is available in two nearly identical versions, `v1` and the older
+/// `v1alpha`, which use different generated types.
+macro_rules! reflection {
+    ($version:ident) => {
+        mod $version {
+            use prost::Message;
+            use std::collections::HashSet;
+            use tonic::transport::Channel;
+            use tonic::{Code, Status};
+            use tonic_reflection::pb::$version::ServerReflectionRequest;
+            use tonic_reflection::pb::$version::server_reflection_client::ServerReflectionClient;
+            use tonic_reflection::pb::$version::server_reflection_request::MessageRequest;
+            use tonic_reflection::pb::$version::server_reflection_response::MessageResponse;
+
+            async fn request(
+                client: &mut ServerReflectionClient<Channel>,
+                request: MessageRequest,
+            ) -> Result<MessageResponse, Status> {
+                let request = ServerReflectionRequest {
+                    host: String::new(),
+                    message_request: Some(request),
+                };
+                let mut responses = client
+                    .server_reflection_info(tokio_stream::once(request))
+                    .await?
+                    .into_inner();
+                match responses.message().await?.and_then(|r| r.message_response) {
+                    Some(MessageResponse::ErrorResponse(e)) => {
+                        Err(Status::new(Code::from(e.error_code), e.error_message))
+                    }
+                    Some(response) => Ok(response),
+                    None => Err(Status::unknown("Empty server reflection response")),
+                }
+            }
+
+            /// Returns the encoded file descriptors of all services on the server, along with
+            /// all files they depend on.
+            pub async fn files(channel: Channel) -> Result<Vec<Vec<u8>>, Status> {
+                let mut client = ServerReflectionClient::new(channel);
+                let services = match request(
+                    &mut client,
+                    MessageRequest::ListServices(String::new()),
+                )
+                .await?
+                {
+                    MessageResponse::ListServicesResponse(r) => r.service,
+                    _ => return Err(Status::unknown("Unexpected server reflection response")),
+                };
+
+                let mut pending = services
+                    .into_iter()
+                    .map(|s| MessageRequest::FileContainingSymbol(s.name))
+                    .collect::<Vec<_>>();
+                let mut known = HashSet::new();
+                let mut requested = HashSet::new();
+                let mut files = Vec::new();
+                while let Some(next) = pending.pop() {
+                    let response = match request(&mut client, next).await? {
+                        MessageResponse::FileDescriptorResponse(r) => r.file_descriptor_proto,
+                        _ => return Err(Status::unknown("Unexpected server reflection response")),
+                    };
+                    for encoded in response {
+                        let file = prost_types::FileDescriptorProto::decode(encoded.as_slice())
+                            .map_err(|e| Status::internal(e.to_string()))?;
+                        if known.insert(file.name().to_string()) {
+                            files.push((file, encoded));
+                        }
+                    }
+                    if pending.is_empty() {
+                        // Servers usually include dependencies in their responses, but they are
+                        // not required to.
+                        for (file, _) in &files {
+                            for dependency in &file.dependency {
+                                if !known.contains(dependency)
+                                    && requested.insert(dependency.clone())
+                                {
+                                    pending
+                                        .push(MessageRequest::FileByFilename(dependency.clone()));
+                                }
+                            }
+                        }
+                    }
+                }
+                Ok(files.into_iter().map(|(_, encoded)| encoded).collect())
+            }
         }
-    }
+    };
+}
 
-    fn arguments(&self) -> String {
-        if let ProtoType::Message(fields) = self {
-            fields
-                .fields
-                .iter()
-                .map(|f| format!("{}={}", f.name, f.proto_type.crush_type().to_string()))
-                .join(" ")
-        } else {
-            self.crush_type().to_string()
+reflection!(v1);
+reflection!(v1alpha);
+
+async fn reflect(channel: Channel) -> CrushResult<DescriptorPool> {
+    let files = match v1::files(channel.clone()).await {
+        Err(status) if status.code() == tonic::Code::Unimplemented => {
+            v1alpha::files(channel).await?
         }
-    }
+        res => res?,
+    };
+    protobuf::pool(files)
 }
 
-fn insert_known_types(known_types: &mut HashMap<String, ProtoType>) {
-    known_types.insert("int32".to_string(), ProtoType::Int32);
-    known_types.insert("int64".to_string(), ProtoType::Int64);
-    known_types.insert("uint32".to_string(), ProtoType::UInt32);
-    known_types.insert("uint64".to_string(), ProtoType::UInt64);
-    known_types.insert("bool".to_string(), ProtoType::Bool);
-    known_types.insert("string".to_string(), ProtoType::String);
-    known_types.insert("bytes".to_string(), ProtoType::Bytes);
-    known_types.insert("double".to_string(), ProtoType::Double);
-    known_types.insert("float".to_string(), ProtoType::Float);
+enum Request {
+    Single(DynamicMessage),
+    Stream(ReceiverStream<DynamicMessage>),
 }
 
-fn parse_message_type<'a>(
-    context: &CommandContext,
-    name: &str,
-    grpc: &Grpc,
-    known_types: &'a mut HashMap<String, ProtoType>,
-) -> CrushResult<ProtoType> {
-    if let Some(t) = known_types.get(name) {
-        return Ok(t.clone());
-    }
-
-    let signature = grpc.call(context, None, vec!["describe", name])?;
-
-    static REGEX: OnceLock<Regex> = OnceLock::new();
-    let re = REGEX.get_or_init(|| {
-        Regex::new(r"[[:blank:]]*([a-zA-Z_.][a-zA-Z0-9_.]*)[[:blank:]]+([a-zA-Z_][a-zA-Z0-9_]*)[[:blank:]]*=[[:blank:]]*([0-9]+);[[:blank:]]*").unwrap()
-    });
-
-    let mut fields = Vec::new();
-
-    for line in signature.lines() {
-        match re.captures(line) {
-            None => {}
-            Some(c) => match (c.get(1), c.get(2)) {
-                (Some(type_name), Some(field_name)) => {
-                    let field_type =
-                        parse_message_type(context, type_name.as_str(), grpc, known_types)?;
-                    fields.push(ProtoField {
-                        name: field_name.as_str().to_string(),
-                        proto_type: field_type,
-                    });
-                }
-                _ => {}
-            },
-        };
-    }
+enum Response {
+    Single(DynamicMessage),
+    Stream(Streaming<DynamicMessage>),
+}
 
-    let res = ProtoType::Message(ProtoMessage {
-        name: name.to_string(),
-        fields,
-    });
+async fn call(
+    channel: Channel,
+    method: &MethodDescriptor,
+    request: Request,
+) -> Result<Response, Status> {
+    let path = PathAndQuery::try_from(format!(
+        "/{}/{}",
+        method.parent_service().full_name(),
+        method.name()
+    ))
+    .map_err(|e| Status::invalid_argument(e.to_string()))?;
+    let codec = DynamicCodec {
+        decode: method.output(),
+    };
+    let mut client = tonic::client::Grpc::new(channel);
+    client
+        .ready()
+        .await
+        .map_err(|e| Status::unavailable(e.to_string()))?;
+    Ok(match (request, method.is_server_streaming()) {
+        (Request::Single(message), false) => Response::Single(
+            client
+                .unary(tonic::Request::new(message), path, codec)
+                .await?
+                .into_inner(),
+        ),
+        (Request::Single(message), true) => Response::Stream(
+            client
+                .server_streaming(tonic::Request::new(message), path, codec)
+                .await?
+                .into_inner(),
+        ),
+        (Request::Stream(messages), false) => Response::Single(
+            client
+                .client_streaming(tonic::Request::new(messages), path, codec)
+                .await?
+                .into_inner(),
+        ),
+        (Request::Stream(messages), true) => Response::Stream(
+            client
+                .streaming(tonic::Request::new(messages), path, codec)
+                .await?
+                .into_inner(),
+        ),
+    })
+}
 
-    known_types.insert(name.to_string(), res.clone());
+fn method_output_type(method: &MethodDescriptor) -> ValueType {
+    if method.is_server_streaming() {
+        ValueType::table_input_stream(&protobuf::column_types(&method.output()))
+    } else {
+        ValueType::Struct
+    }
+}
 
-    Ok(res)
+fn method_help(method: &MethodDescriptor) -> String {
+    let input = if method.is_client_streaming() {
+        format!(
+            "Reads a stream of `{}` messages from the input",
+            method.input().full_name()
+        )
+    } else {
+        format!(
+            "Takes a `{}` message as named arguments",
+            method.input().full_name()
+        )
+    };
+    let output = if method.is_server_streaming() {
+        format!(
+            "returns a table stream of `{}` messages.",
+            method.output().full_name()
+        )
+    } else {
+        format!("returns a `{}` message.", method.output().full_name())
+    };
+    format!("{} and {}", input, output)
 }
 
 fn connect(mut context: CommandContext) -> CrushResult<()> {
@@ -249,189 +368,276 @@ fn connect(mut context: CommandContext) -> CrushResult<()> {
             "You must specify at least one service to connect to. You can use globs, such as `*`.",
         );
     }
-    let tmp = Struct::new(
-        vec![
-            ("host", Value::from(cfg.host.clone())),
-            ("plaintext", Value::Bool(cfg.plaintext)),
-            ("timeout", Value::Duration(cfg.timeout)),
-            ("port", Value::Integer(cfg.port)),
-        ],
-        None,
-    );
-
-    let g = Grpc::new(tmp)?;
-    let s = Struct::from_vec(vec![], vec![]);
-    let list = g.call(&context, None, vec!["list"])?;
-    let mut available_services = list.lines().collect::<Vec<&str>>();
-    let services = available_services
-        .drain(..)
-        .filter(|s| cfg.service.test(s))
-        .collect::<Vec<&str>>();
+
+    let endpoint = endpoint(
+        uri(&cfg.host, cfg.port, cfg.plaintext),
+        cfg.plaintext,
+        cfg.timeout,
+    )?;
+    let channel = match wait(&context.scope, endpoint.connect())? {
+        Some(channel) => channel?,
+        None => return Ok(()),
+    };
+
+    let pool = if cfg.proto.is_empty() {
+        match wait(&context.scope, reflect(channel.clone()))? {
+            Some(pool) => pool?,
+            None => return Ok(()),
+        }
+    } else {
+        protobuf::load(&files::into_paths(cfg.proto)?)?
+    };
+
+    let services = pool
+        .services()
+        .filter(|s| cfg.service.test(s.full_name()))
+        .collect::<Vec<_>>();
 
     if services.is_empty() {
         return command_error(format!(
-            "No match for service pattern `{}`. Found services `{}`.",
-            cfg.service.to_string(),
-            list.lines().join(", ")
+            "No match for service pattern `{}`. Found services {}.",
+            cfg.service,
+            pool.services()
+                .map(|s| format!("`{}`", s.full_name()))
+                .collect::<Vec<_>>()
+                .join(", ")
         ));
     }
 
-    let mut known_types = HashMap::new();
-    insert_known_types(&mut known_types);
-
+    let s = Struct::empty(None);
     for service in services {
-        let out = g.call(&context, None, vec!["list", service])?;
-        for line in out.lines() {
-            let stripped = line.strip_prefix(&format!("{}.", service));
-            if let Some(method) = stripped {
-                let signature = g.call(
-                    &context,
+        for method in service.methods() {
+            s.set(
+                method.name(),
+                Value::Struct(Struct::new(
+                    vec![
+                        ("host", Value::from(cfg.host.clone())),
+                        ("service", Value::from(service.full_name())),
+                        ("plaintext", Value::Bool(cfg.plaintext)),
+                        ("timeout", Value::Duration(cfg.timeout)),
+                        ("port", Value::Integer(cfg.port)),
+                        ("method", Value::from(method.name())),
+                        (
+                            "__call__",
+                            Value::Command(Arc::new(MethodCommand {
+                                channel: channel.clone(),
+                                service: service.full_name().to_string(),
+                                method: method.clone(),
+                                output: method_output_type(&method),
+                            })),
+                        ),
+                    ],
                     None,
-                    vec!["describe".to_string(), format!("{}.{}", service, method)],
-                )?;
-                let input_type_name = parse_input_type_from_signature(method, signature.as_str())?;
-                println!("{:?}", input_type_name);
-                let input_type =
-                    parse_message_type(&context, &input_type_name, &g, &mut known_types)?;
-                println!("{:?}", input_type);
-
-                s.set(
-                    method,
-                    Value::Struct(Struct::new(
-                        vec![
-                            ("host", Value::from(cfg.host.clone())),
-                            ("service", Value::from(service.to_string())),
-                            ("plaintext", Value::Bool(cfg.plaintext)),
-                            ("timeout", Value::Duration(cfg.timeout)),
-                            ("port", Value::Integer(cfg.port)),
-                            ("method", Value::from(line)),
-                            (
-                                "__call__",
-                                Value::Command(<dyn CrushCommand>::command(
-                                    grpc_method_call,
-                                    true,
-                                    &["global", "grpc", "connect", method, "__call__"],
-                                    format!("{} {}", method, input_type.arguments()),
-                                    format!(
-                                        "Call the {} method of the {} service",
-                                        method, service
-                                    ),
-                                    None::<AnyStr>,
-                                    Unknown,
-                                    [],
-                                )),
-                            ),
-                        ],
-                        None,
-                    )),
-                );
-            }
+                )),
+            );
         }
     }
+
     context.output.send(Value::Struct(s))
 }
 
-fn parse_input_type_from_signature<'a>(
-    method_name: &str,
-    signature: &'a str,
-) -> CrushResult<&'a str> {
-    static REGEX: OnceLock<Regex> = OnceLock::new();
-    let re = REGEX.get_or_init(|| Regex::new(r"\((.*)\).*\(.*\)").unwrap());
-    for line in signature.lines() {
-        if line.starts_with("rpc") {
-            return match re.captures(line) {
-                None => command_error("Failed to parse signature."),
-                Some(c) => match c.get(1) {
-                    None => command_error("Failed to parse signature."),
-                    Some(m) => Ok(m.as_str().trim()),
-                },
-            };
+/// Start a thread that converts the values of the input into messages and feeds them to a
+/// streaming call.
+fn feed(
+    context: &CommandContext,
+    input: Value,
+    desc: MessageDescriptor,
+) -> CrushResult<ReceiverStream<DynamicMessage>> {
+    let (sender, receiver) = mpsc::channel(128);
+    context.spawn("grpc:input", move || {
+        let send = |s: &Struct| -> CrushResult<bool> {
+            Ok(sender
+                .blocking_send(protobuf::struct_to_message(s, &desc)?)
+                .is_ok())
+        };
+        match input {
+            Value::Struct(s) => {
+                send(&s)?;
+            }
+            Value::List(l) => {
+                for value in l.iter() {
+                    match value {
+                        Value::Struct(s) => {
+                            if !send(&s)? {
+                                break;
+                            }
+                        }
+                        v => {
+                            return data_error(format!(
+                                "Expected a list of structs, found a value of type `{}`.",
+                                v.value_type()
+                            ));
+                        }
+                    }
+                }
+            }
+            value => {
+                let mut stream = value.stream()?;
+                let types = stream.types().to_vec();
+                while let Ok(row) = stream.read() {
+                    if !send(&row.into_struct(&types))? {
+                        break;
+                    }
+                }
+            }
         }
+        Ok(())
+    })?;
+    Ok(ReceiverStream::new(receiver))
+}
+
+/**
+The command that calls a method of a service. It holds on to the channel and to the descriptor of
+the method, so the connection stays open for as long as any of its methods are around, and calls
+always use the schema that the method was created from.
+ */
+struct MethodCommand {
+    channel: Channel,
+    service: String,
+    method: MethodDescriptor,
+    output: ValueType,
+}
+
+impl Display for MethodCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.method.name())
     }
-    command_error(format!(
-        "Failed to parse signature of method `{}`.",
-        method_name
-    ))
 }
 
-fn grpc_method_call(mut context: CommandContext) -> CrushResult<()> {
-    let data = if context.input.is_pipeline() {
-        let data = context.input.recv()?;
-        Some(value_to_json(data)?)
+impl Help for MethodCommand {
+    fn signature(&self) -> String {
+        if self.method.is_client_streaming() {
+            self.method.name().to_string()
+        } else {
+            format!(
+                "{} {}",
+                self.method.name(),
+                protobuf::signature(&self.method.input())
+            )
+        }
+    }
+
+    fn short_help(&self) -> String {
+        format!(
+            "Call the {} method of the {} service",
+            self.method.name(),
+            self.service
+        )
+    }
+
+    fn long_help(&self) -> Option<String> {
+        Some(method_help(&self.method))
+    }
+}
+
+impl CrushCommand for MethodCommand {
+    fn eval(&self, context: CommandContext) -> CrushResult<()> {
+        let source = context.source.clone();
+        method_call(context, &self.channel, &self.method)
+            .with_command(format!("grpc:{}:{}", self.service, self.method.name()))
+            .with_source_fallback(&source)
+    }
+
+    fn might_block(&self, _arguments: &[ArgumentDefinition], _context: &mut EvalContext) -> bool {
+        true
+    }
+
+    fn name(&self) -> &str {
+        self.method.name()
+    }
+
+    fn serialize(
+        &self,
+        _elements: &mut Vec<Element>,
+        _state: &mut SerializationState,
+    ) -> CrushResult<usize> {
+        command_error("gRPC methods can't be serialized.")
+    }
+
+    fn bind_helper(&self, wrapped: &Command, _this: Value) -> Command {
+        // The method struct is not needed to perform the call, so there is nothing to bind.
+        wrapped.clone()
+    }
+
+    fn output_type<'a>(&'a self, _input: &'a OutputType) -> Option<&'a ValueType> {
+        Some(&self.output)
+    }
+
+    fn completion_data(&self) -> &[Parameter] {
+        &[]
+    }
+
+    fn definition(&self) -> Option<String> {
+        None
+    }
+}
+
+fn method_call(
+    mut context: CommandContext,
+    channel: &Channel,
+    method: &MethodDescriptor,
+) -> CrushResult<()> {
+    let request = if method.is_client_streaming() {
+        if !context.input.is_pipeline() {
+            return command_error(format!(
+                "`{}` is a client streaming method. Pipe its requests into it.",
+                method.name()
+            ));
+        }
+        let input = context.input.recv()?;
+        Request::Stream(feed(&context, input, method.input())?)
+    } else if context.input.is_pipeline() {
+        match context.input.recv()? {
+            Value::Struct(s) => Request::Single(protobuf::struct_to_message(&s, &method.input())?),
+            v => {
+                return data_error(format!(
+                    "Expected a struct as input, found a value of type `{}`.",
+                    v.value_type()
+                ));
+            }
+        }
     } else {
-        if !context.arguments.is_empty() {
-            let mut fields = Vec::new();
-            for a in context.remove_arguments() {
-                if let Some(name) = a.argument_type {
-                    fields.push((name, a.value));
-                } else {
-                    return command_error("gRPC method invocations can only use named arguments.");
-                }
+        let mut fields = Vec::new();
+        for a in context.remove_arguments() {
+            if let Some(name) = a.argument_type {
+                fields.push((name, a.value));
+            } else {
+                return command_error("gRPC method invocations can only use named arguments.");
             }
-            let s = Struct::new(fields, None);
-            Some(value_to_json(Value::Struct(s))?)
-        } else {
-            None
         }
+        Request::Single(protobuf::struct_to_message(
+            &Struct::new(fields, None),
+            &method.input(),
+        )?)
     };
-    let this = context.this.r#struct()?;
-    if let Some(Value::String(method)) = this.get("method") {
-        let grpc = Grpc::new(this)?;
-        let out = grpc.call(&context, data, vec![method.to_string()])?;
-
-        let split = out.split("\n}\n{\n");
-
-        let mut lst = split
-            .into_iter()
-            .map(|i| {
-                let stripped = i.trim();
-                match (stripped.starts_with("{"), stripped.ends_with("}")) {
-                    (true, true) => json_to_value(i),
-                    (true, false) => json_to_value(&format!("{}}}", i)),
-                    (false, false) => json_to_value(&format!("{{{}}}", i)),
-                    (false, true) => json_to_value(&format!("{{{}", i)),
-                }
-            })
-            .collect::<CrushResult<Vec<_>>>()?;
-
-        let types: HashSet<ValueType> = lst.iter().map(|v| v.value_type()).collect();
-        let struct_types: HashSet<Vec<ColumnType>> = lst
-            .iter()
-            .flat_map(|v| match v {
-                Value::Struct(r) => vec![r.local_signature()],
-                _ => vec![],
-            })
-            .collect();
-
-        let res = match types.len() {
-            0 => Value::Empty,
-            1 => {
-                let list_type = types.iter().next().unwrap();
-                match (list_type, struct_types.len()) {
-                    (ValueType::Struct, 1) => {
-                        let row_list = lst
-                            .drain(..)
-                            .map(|v| match v {
-                                Value::Struct(r) => Ok(r.to_row()),
-                                _ => error("Impossible!"),
-                            })
-                            .collect::<CrushResult<Vec<Row>>>()?;
-                        Value::Table(Table::from((
-                            struct_types.iter().next().unwrap().clone(),
-                            row_list,
-                        )))
+
+    let response = match wait(&context.scope, call(channel.clone(), method, request))? {
+        Some(response) => response?,
+        None => return Ok(()),
+    };
+
+    match response {
+        Response::Single(message) => context.output.send(protobuf::message_to_value(&message)?),
+        Response::Stream(mut messages) => {
+            let output = context
+                .output
+                .initialize(&protobuf::column_types(&method.output()))?;
+            while let Some(message) = wait(&context.scope, messages.message())? {
+                match message? {
+                    Some(message) => {
+                        if output
+                            .send(protobuf::message_to_struct(&message)?.to_row())
+                            .is_err()
+                        {
+                            break;
+                        }
                     }
-                    _ => List::new(list_type.clone(), lst).into(),
+                    None => break,
                 }
             }
-            _ => List::new(ValueType::Any, lst).into(),
-        };
-
-        context.output.send(res)?;
-
-        return Ok(());
+            Ok(())
+        }
     }
-    command_error("Invalid method field.")
 }
 
 pub fn declare(root: &Scope) -> CrushResult<()> {
@@ -445,3 +651,296 @@ pub fn declare(root: &Scope) -> CrushResult<()> {
     )?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lang::argument::Argument;
+    use crate::lang::ast::source::{Source, SourceType};
+    use crate::lang::data::dict::Dict;
+    use crate::lang::data::list::List;
+    use crate::lang::pipe::pipe;
+    use crate::lang::printer::noop;
+    use crate::lang::state::global_state::{GlobalState, RunMode};
+    use std::convert::Infallible;
+    use std::future::{Ready, ready};
+    use std::task::{Context, Poll};
+    use tonic::body::Body;
+    use tonic::codegen::{BoxFuture, http};
+    use tonic::server::{NamedService, ServerStreamingService, UnaryService};
+
+    const PROTO: &str = r#"
+syntax = "proto3";
+package test;
+
+import "google/protobuf/duration.proto";
+
+enum Color {
+  RED = 0;
+  GREEN = 1;
+}
+
+message Point {
+  int32 x = 1;
+  int32 y = 2;
+}
+
+message Shape {
+  string name = 1;
+  repeated Point points = 2;
+  map<string, int64> counts = 3;
+  Color color = 4;
+  google.protobuf.Duration elapsed = 5;
+  Point origin = 6;
+}
+
+service Echo {
+  rpc Echo(Shape) returns (Shape);
+  rpc Points(Shape) returns (stream Point);
+}
+"#;
+
+    /// A stand-in server that returns its input unchanged from `Echo`, and streams the points of
+    /// its input from `Points`.
+    #[derive(Clone)]
+    struct Echo(DescriptorPool);
+
+    impl NamedService for Echo {
+        const NAME: &'static str = "test.Echo";
+    }
+
+    struct EchoMethod;
+
+    impl UnaryService<DynamicMessage> for EchoMethod {
+        type Response = DynamicMessage;
+        type Future = Ready<Result<tonic::Response<DynamicMessage>, Status>>;
+
+        fn call(&mut self, request: tonic::Request<DynamicMessage>) -> Self::Future {
+            ready(Ok(tonic::Response::new(request.into_inner())))
+        }
+    }
+
+    struct PointsMethod;
+
+    impl ServerStreamingService<DynamicMessage> for PointsMethod {
+        type Response = DynamicMessage;
+        type ResponseStream =
+            tokio_stream::Iter<std::vec::IntoIter<Result<DynamicMessage, Status>>>;
+        type Future = Ready<Result<tonic::Response<Self::ResponseStream>, Status>>;
+
+        fn call(&mut self, request: tonic::Request<DynamicMessage>) -> Self::Future {
+            let points = request
+                .into_inner()
+                .get_field_by_name("points")
+                .and_then(|p| p.as_list().map(|l| l.to_vec()))
+                .unwrap_or_default()
+                .into_iter()
+                .map(|p| Ok(p.as_message().unwrap().clone()))
+                .collect::<Vec<_>>();
+            ready(Ok(tonic::Response::new(tokio_stream::iter(points))))
+        }
+    }
+
+    impl tonic::codegen::Service<http::Request<Body>> for Echo {
+        type Response = http::Response<Body>;
+        type Error = Infallible;
+        type Future = BoxFuture<Self::Response, Infallible>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+            let method = self
+                .0
+                .get_service_by_name(Self::NAME)
+                .and_then(|s| {
+                    s.methods()
+                        .find(|m| request.uri().path() == format!("/{}/{}", Self::NAME, m.name()))
+                })
+                .unwrap();
+            Box::pin(async move {
+                let mut grpc = tonic::server::Grpc::new(DynamicCodec {
+                    decode: method.input(),
+                });
+                Ok(if method.is_server_streaming() {
+                    grpc.server_streaming(PointsMethod, request).await
+                } else {
+                    grpc.unary(EchoMethod, request).await
+                })
+            })
+        }
+    }
+
+    fn load() -> DescriptorPool {
+        let dir = std::env::temp_dir().join(format!("crush-grpc-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("test.proto");
+        std::fs::write(&file, PROTO).unwrap();
+        let pool = protobuf::load(&[file]).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        pool
+    }
+
+    /// Start the stand-in server, along with a reflection service, and return the port it
+    /// listens on.
+    fn serve(pool: DescriptorPool) -> i128 {
+        runtime().unwrap().block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let reflection = tonic_reflection::server::Builder::configure()
+                .register_file_descriptor_set(prost_types::FileDescriptorSet {
+                    file: pool.file_descriptor_protos().cloned().collect(),
+                })
+                .build_v1()
+                .unwrap();
+            tokio::spawn(
+                tonic::transport::Server::builder()
+                    .add_service(reflection)
+                    .add_service(Echo(pool))
+                    .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+            );
+            port as i128
+        })
+    }
+
+    fn point(x: i128, y: i128) -> Value {
+        Value::Struct(Struct::new(
+            vec![("x", Value::Integer(x)), ("y", Value::Integer(y))],
+            None,
+        ))
+    }
+
+    #[test]
+    fn call_using_reflection() {
+        let port = serve(load());
+        let runtime = runtime().unwrap();
+        let channel = runtime
+            .block_on(
+                endpoint(uri("127.0.0.1", port, true), true, Duration::seconds(5))
+                    .unwrap()
+                    .connect(),
+            )
+            .unwrap();
+        let pool = runtime.block_on(reflect(channel.clone())).unwrap();
+        let service = pool.get_service_by_name("test.Echo").unwrap();
+        let echo = service.methods().find(|m| m.name() == "Echo").unwrap();
+        let points = service.methods().find(|m| m.name() == "Points").unwrap();
+
+        let counts = Dict::new(ValueType::String, ValueType::Integer).unwrap();
+        counts.insert(Value::from("a"), Value::Integer(3)).unwrap();
+        let request = Struct::new(
+            vec![
+                ("name", Value::from("triangle")),
+                (
+                    "points",
+                    List::new(ValueType::Struct, vec![point(1, 2), point(3, 4)]).into(),
+                ),
+                ("counts", Value::Dict(counts)),
+                ("color", Value::from("GREEN")),
+                ("elapsed", Value::Duration(Duration::seconds(90))),
+            ],
+            None,
+        );
+        let message = protobuf::struct_to_message(&request, &echo.input()).unwrap();
+
+        let response = match runtime
+            .block_on(call(
+                channel.clone(),
+                &echo,
+                Request::Single(message.clone()),
+            ))
+            .unwrap()
+        {
+            Response::Single(m) => protobuf::message_to_struct(&m).unwrap(),
+            Response::Stream(_) => panic!("Expected a single response"),
+        };
+        assert_eq!(response.get("name").unwrap().to_string(), "triangle");
+        assert_eq!(response.get("color").unwrap().to_string(), "GREEN");
+        assert!(response.get("elapsed").unwrap() == Value::Duration(Duration::seconds(90)));
+        assert!(matches!(response.get("origin"), Some(Value::Empty)));
+        match response.get("points").unwrap() {
+            Value::List(l) => {
+                assert_eq!(l.len(), 2);
+                match l.get(1).unwrap() {
+                    Value::Struct(p) => assert!(p.get("x").unwrap() == Value::Integer(3)),
+                    _ => panic!("Expected a struct"),
+                }
+            }
+            _ => panic!("Expected a list"),
+        }
+        match response.get("counts").unwrap() {
+            Value::Dict(d) => assert!(d.get(&Value::from("a")).unwrap() == Value::Integer(3)),
+            _ => panic!("Expected a dict"),
+        }
+
+        let mut stream = match runtime
+            .block_on(call(channel, &points, Request::Single(message)))
+            .unwrap()
+        {
+            Response::Stream(s) => s,
+            Response::Single(_) => panic!("Expected a stream"),
+        };
+        let mut ys = Vec::new();
+        while let Some(p) = runtime.block_on(stream.message()).unwrap() {
+            ys.push(
+                protobuf::message_to_struct(&p)
+                    .unwrap()
+                    .get("y")
+                    .unwrap()
+                    .to_string(),
+            );
+        }
+        assert_eq!(ys, vec!["2", "4"]);
+    }
+
+    #[test]
+    fn call_method_command() {
+        let port = serve(load());
+        let channel = runtime()
+            .unwrap()
+            .block_on(
+                endpoint(uri("127.0.0.1", port, true), true, Duration::seconds(5))
+                    .unwrap()
+                    .connect(),
+            )
+            .unwrap();
+        // A schema that is only known to this command, like one loaded from a proto file.
+        let method = load()
+            .get_service_by_name("test.Echo")
+            .unwrap()
+            .methods()
+            .find(|m| m.name() == "Echo")
+            .unwrap();
+        let command: Command = Arc::new(MethodCommand {
+            channel,
+            service: "test.Echo".to_string(),
+            output: method_output_type(&method),
+            method,
+        });
+
+        let state = GlobalState::new(noop().0, RunMode::NonInteractive).unwrap();
+        let scope = Scope::create_root();
+        let source = Source::new(SourceType::Input, Arc::from("Echo"));
+        let (sender, receiver) = pipe();
+        let context = CommandContext::new(&scope, &state, &source)
+            .with_args(
+                vec![Argument::named("name", Value::from("square"), &source)],
+                None,
+            )
+            .with_output(sender);
+        command.eval(context).unwrap();
+        match receiver.recv().unwrap() {
+            Value::Struct(s) => assert_eq!(s.get("name").unwrap().to_string(), "square"),
+            _ => panic!("Expected a struct"),
+        }
+    }
+
+    #[test]
+    fn unknown_field() {
+        let pool = load();
+        let desc = pool.get_message_by_name("test.Point").unwrap();
+        let value = Struct::new(vec![("z", Value::Integer(1))], None);
+        assert!(protobuf::struct_to_message(&value, &desc).is_err());
+    }
+}
@@ -0,0 +1,740 @@
+use crate::lang::command::OutputType::Unknown;
+use crate::lang::data::list::List;
+use crate::lang::data::r#struct::Struct;
+use crate::lang::data::table::{ColumnType, Row};
+use crate::lang::errors::{CrushResult, command_error, data_error};
+use crate::lang::pipe::{Stream, TableOutputStream, ValueReceiver, ValueSender};
+use crate::lang::signature::binary_input::{BinaryInput, ToReader};
+use crate::lang::signature::files;
+use crate::lang::signature::files::Files;
+use crate::lang::state::contexts::CommandContext;
+use crate::lang::state::scope::ScopeLoader;
+use crate::lang::value::{Value, ValueType};
+use crate::util::file::cwd;
+use crate::util::regex::RegexFileMatcher;
+use arrow_array::cast::AsArray;
+use arrow_array::types::*;
+use arrow_array::{
+    Array, ArrayRef, BinaryArray, BooleanArray, DurationMicrosecondArray, Float64Array, Int64Array,
+    ListArray, RecordBatch, StringArray, TimestampMicrosecondArray,
+};
+use arrow_buffer::{NullBuffer, OffsetBuffer};
+use arrow_cast::display::{ArrayFormatter, FormatOptions};
+use arrow_ipc::reader::{FileReader, StreamReader};
+use arrow_ipc::writer::{FileWriter, StreamWriter};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef, TimeUnit};
+use bytes::Bytes;
+use chrono::{DateTime, Duration, Local};
+use signature::signature;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// The number of rows in each record batch when converting tables to Arrow.
+pub const BATCH_SIZE: usize = 8192;
+
+/// The magic bytes at the start of an Arrow IPC file. Streams start with a message length instead.
+const FILE_MAGIC: &[u8] = b"ARROW1";
+
+/// A file or an in-memory buffer to read a columnar format from. Both Parquet and Arrow IPC files
+/// need random access, so input that is not a file is read into memory first.
+pub enum Source {
+    File(PathBuf),
+    Bytes(Bytes),
+}
+
+/// The sources for the specified arguments. Files, globs and regexes can match any number of
+/// files, each of which is a separate source. Without arguments, the input is read into memory.
+pub fn sources(files: Vec<BinaryInput>, input: ValueReceiver) -> CrushResult<Vec<Source>> {
+    if files.is_empty() {
+        return Ok(vec![read_all(vec![], input)?]);
+    }
+    let mut res = Vec::new();
+    for file in files {
+        let mut paths = Vec::new();
+        match file {
+            BinaryInput::File(path) => paths.push(path.to_path_buf()),
+            BinaryInput::Glob(glob) => glob.glob_files(&cwd()?, &mut paths)?,
+            BinaryInput::Regex(re) => re.match_files(&cwd()?, &mut paths)?,
+            other => {
+                res.push(read_all(vec![other], input.clone())?);
+                continue;
+            }
+        }
+        paths.sort();
+        res.extend(paths.into_iter().map(Source::File));
+    }
+    Ok(res)
+}
+
+fn read_all(files: Vec<BinaryInput>, input: ValueReceiver) -> CrushResult<Source> {
+    let mut buffer = Vec::new();
+    files.to_reader(input)?.read_to_end(&mut buffer)?;
+    Ok(Source::Bytes(Bytes::from(buffer)))
+}
+
+/// Crush has no unsigned or narrower numeric types, so all integers become `$integer`, all
+/// floating point and decimal numbers become `$float` and so on. Types without a counterpart are
+/// converted to strings.
+pub fn to_value_type(data_type: &DataType) -> ValueType {
+    match data_type {
+        DataType::Null => ValueType::Empty,
+        DataType::Boolean => ValueType::Bool,
+        DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::Int64
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32
+        | DataType::UInt64 => ValueType::Integer,
+        DataType::Float16
+        | DataType::Float32
+        | DataType::Float64
+        | DataType::Decimal128(_, _)
+        | DataType::Decimal256(_, _) => ValueType::Float,
+        DataType::Timestamp(_, _) | DataType::Date32 | DataType::Date64 => ValueType::Time,
+        DataType::Time32(_) | DataType::Time64(_) | DataType::Duration(_) => ValueType::Duration,
+        DataType::Binary
+        | DataType::LargeBinary
+        | DataType::FixedSizeBinary(_)
+        | DataType::BinaryView => ValueType::Binary,
+        DataType::List(field) | DataType::LargeList(field) | DataType::FixedSizeList(field, _) => {
+            ValueType::List(Box::from(field_type(field)))
+        }
+        DataType::Struct(_) => ValueType::Struct,
+        DataType::Dictionary(_, value_type) => to_value_type(value_type),
+        _ => ValueType::String,
+    }
+}
+
+/// The type of the values of a field. Nulls are empty, so nullable fields can also be empty.
+fn field_type(field: &Field) -> ValueType {
+    match to_value_type(field.data_type()) {
+        t @ (ValueType::Empty | ValueType::Any) => t,
+        t if field.is_nullable() => ValueType::OneOf(vec![t, ValueType::Empty]),
+        t => t,
+    }
+}
+
+pub fn to_column_types(schema: &Schema) -> Vec<ColumnType> {
+    schema
+        .fields()
+        .iter()
+        .map(|field| ColumnType::new_from_string(field.name().clone(), field_type(field)))
+        .collect()
+}
+
+fn to_time(value: i64, unit: &TimeUnit) -> CrushResult<Value> {
+    let (seconds, nanoseconds) = match unit {
+        TimeUnit::Second => (value, 0),
+        TimeUnit::Millisecond => (value.div_euclid(1_000), value.rem_euclid(1_000) * 1_000_000),
+        TimeUnit::Microsecond => (
+            value.div_euclid(1_000_000),
+            value.rem_euclid(1_000_000) * 1_000,
+        ),
+        TimeUnit::Nanosecond => (
+            value.div_euclid(1_000_000_000),
+            value.rem_euclid(1_000_000_000),
+        ),
+    };
+    match DateTime::from_timestamp(seconds, nanoseconds as u32) {
+        Some(time) => Ok(Value::Time(time.with_timezone(&Local))),
+        None => data_error(format!("Timestamp {} is out of range.", value)),
+    }
+}
+
+fn to_duration(value: i64, unit: &TimeUnit) -> Value {
+    Value::Duration(match unit {
+        TimeUnit::Second => Duration::seconds(value),
+        TimeUnit::Millisecond => Duration::milliseconds(value),
+        TimeUnit::Microsecond => Duration::microseconds(value),
+        TimeUnit::Nanosecond => Duration::nanoseconds(value),
+    })
+}
+
+fn to_list(values: ArrayRef, element_type: &DataType) -> CrushResult<Value> {
+    Ok(List::new(
+        to_value_type(element_type),
+        (0..values.len())
+            .map(|idx| to_value(&values, idx))
+            .collect::<CrushResult<Vec<_>>>()?,
+    )
+    .into())
+}
+
+/// The value at the specified index of an array. Nulls are empty.
+pub fn to_value(array: &ArrayRef, idx: usize) -> CrushResult<Value> {
+    if array.is_null(idx) {
+        return Ok(Value::Empty);
+    }
+    Ok(match array.data_type() {
+        DataType::Null => Value::Empty,
+        DataType::Boolean => Value::Bool(array.as_boolean().value(idx)),
+        DataType::Int8 => Value::Integer(array.as_primitive::<Int8Type>().value(idx) as i128),
+        DataType::Int16 => Value::Integer(array.as_primitive::<Int16Type>().value(idx) as i128),
+        DataType::Int32 => Value::Integer(array.as_primitive::<Int32Type>().value(idx) as i128),
+        DataType::Int64 => Value::Integer(array.as_primitive::<Int64Type>().value(idx) as i128),
+        DataType::UInt8 => Value::Integer(array.as_primitive::<UInt8Type>().value(idx) as i128),
+        DataType::UInt16 => Value::Integer(array.as_primitive::<UInt16Type>().value(idx) as i128),
+        DataType::UInt32 => Value::Integer(array.as_primitive::<UInt32Type>().value(idx) as i128),
+        DataType::UInt64 => Value::Integer(array.as_primitive::<UInt64Type>().value(idx) as i128),
+        DataType::Float16 => Value::Float(array.as_primitive::<Float16Type>().value(idx).to_f64()),
+        DataType::Float32 => Value::Float(array.as_primitive::<Float32Type>().value(idx) as f64),
+        DataType::Float64 => Value::Float(array.as_primitive::<Float64Type>().value(idx)),
+        DataType::Decimal128(_, scale) => Value::Float(
+            array.as_primitive::<Decimal128Type>().value(idx) as f64 / 10f64.powi(*scale as i32),
+        ),
+        DataType::Decimal256(_, _) => Value::Float(
+            array
+                .as_primitive::<Decimal256Type>()
+                .value_as_string(idx)
+                .parse()?,
+        ),
+        DataType::Utf8 => Value::from(array.as_string::<i32>().value(idx)),
+        DataType::LargeUtf8 => Value::from(array.as_string::<i64>().value(idx)),
+        DataType::Utf8View => Value::from(array.as_string_view().value(idx)),
+        DataType::Binary => Value::from(array.as_binary::<i32>().value(idx)),
+        DataType::LargeBinary => Value::from(array.as_binary::<i64>().value(idx)),
+        DataType::BinaryView => Value::from(array.as_binary_view().value(idx)),
+        DataType::FixedSizeBinary(_) => Value::from(array.as_fixed_size_binary().value(idx)),
+        DataType::Timestamp(unit, _) => {
+            let value = match unit {
+                TimeUnit::Second => array.as_primitive::<TimestampSecondType>().value(idx),
+                TimeUnit::Millisecond => {
+                    array.as_primitive::<TimestampMillisecondType>().value(idx)
+                }
+                TimeUnit::Microsecond => {
+                    array.as_primitive::<TimestampMicrosecondType>().value(idx)
+                }
+                TimeUnit::Nanosecond => array.as_primitive::<TimestampNanosecondType>().value(idx),
+            };
+            to_time(value, unit)?
+        }
+        DataType::Date32 => to_time(
+            array.as_primitive::<Date32Type>().value(idx) as i64 * 86_400,
+            &TimeUnit::Second,
+        )?,
+        DataType::Date64 => to_time(
+            array.as_primitive::<Date64Type>().value(idx),
+            &TimeUnit::Millisecond,
+        )?,
+        DataType::Time32(TimeUnit::Second) => to_duration(
+            array.as_primitive::<Time32SecondType>().value(idx) as i64,
+            &TimeUnit::Second,
+        ),
+        DataType::Time32(_) => to_duration(
+            array.as_primitive::<Time32MillisecondType>().value(idx) as i64,
+            &TimeUnit::Millisecond,
+        ),
+        DataType::Time64(TimeUnit::Microsecond) => to_duration(
+            array.as_primitive::<Time64MicrosecondType>().value(idx),
+            &TimeUnit::Microsecond,
+        ),
+        DataType::Time64(_) => to_duration(
+            array.as_primitive::<Time64NanosecondType>().value(idx),
+            &TimeUnit::Nanosecond,
+        ),
+        DataType::Duration(unit) => {
+            let value = match unit {
+                TimeUnit::Second => array.as_primitive::<DurationSecondType>().value(idx),
+                TimeUnit::Millisecond => array.as_primitive::<DurationMillisecondType>().value(idx),
+                TimeUnit::Microsecond => array.as_primitive::<DurationMicrosecondType>().value(idx),
+                TimeUnit::Nanosecond => array.as_primitive::<DurationNanosecondType>().value(idx),
+            };
+            to_duration(value, unit)
+        }
+        DataType::List(field) => to_list(array.as_list::<i32>().value(idx), field.data_type())?,
+        DataType::LargeList(field) => {
+            to_list(array.as_list::<i64>().value(idx), field.data_type())?
+        }
+        DataType::FixedSizeList(field, _) => {
+            to_list(array.as_fixed_size_list().value(idx), field.data_type())?
+        }
+        DataType::Dictionary(_, _) => {
+            let dictionary = array.as_any_dictionary();
+            match to_value(&dictionary.keys().slice(idx, 1), 0)? {
+                Value::Integer(key) => to_value(dictionary.values(), key as usize)?,
+                key => return data_error(format!("Invalid dictionary key `{}`.", key)),
+            }
+        }
+        DataType::Struct(fields) => {
+            let array = array.as_struct();
+            Value::Struct(Struct::new(
+                fields
+                    .iter()
+                    .zip(array.columns())
+                    .map(|(field, column)| Ok((field.name().clone(), to_value(column, idx)?)))
+                    .collect::<CrushResult<Vec<_>>>()?,
+                None,
+            ))
+        }
+        _ => Value::from(
+            ArrayFormatter::try_new(array.as_ref(), &FormatOptions::default())?
+                .value(idx)
+                .to_string()
+                .as_str(),
+        ),
+    })
+}
+
+/// The indices of the specified columns in a schema, or of all columns if none are specified.
+fn projection(schema: &Schema, columns: &[String]) -> CrushResult<Vec<usize>> {
+    if columns.is_empty() {
+        return Ok((0..schema.fields().len()).collect());
+    }
+    columns
+        .iter()
+        .map(|name| match schema.index_of(name) {
+            Ok(idx) => Ok(idx),
+            Err(_) => command_error(format!("Unknown column `{}`.", name)),
+        })
+        .collect()
+}
+
+/// The root indices of the specified columns in a schema, in the order they appear in it, for
+/// readers that can skip decoding the other columns.
+pub fn root_projection(schema: &Schema, columns: &[String]) -> CrushResult<Vec<usize>> {
+    let mut indices = projection(schema, columns)?;
+    indices.sort();
+    indices.dedup();
+    Ok(indices)
+}
+
+/**
+    Outputs the record batches of one or more sources as a single table stream, one row at a time.
+    The table stream is created when the first source is sent, and all other sources must have the
+    same schema. If columns are specified, only those are output, in the specified order.
+*/
+pub struct BatchSender {
+    columns: Vec<String>,
+    output: Option<ValueSender>,
+    stream: Option<(SchemaRef, TableOutputStream)>,
+}
+
+impl BatchSender {
+    pub fn new(columns: Vec<String>, output: ValueSender) -> BatchSender {
+        BatchSender {
+            columns,
+            output: Some(output),
+            stream: None,
+        }
+    }
+
+    fn same_schema(&self, a: &Schema, b: &Schema) -> CrushResult<bool> {
+        Ok(projection(a, &self.columns)?
+            .into_iter()
+            .zip(projection(b, &self.columns)?)
+            .all(|(a_idx, b_idx)| a.field(a_idx).data_type() == b.field(b_idx).data_type()))
+    }
+
+    pub fn send(
+        &mut self,
+        schema: SchemaRef,
+        source: &Source,
+        batches: impl Iterator<Item = Result<RecordBatch, ArrowError>>,
+    ) -> CrushResult<()> {
+        match (&self.stream, self.output.take()) {
+            (Some((first, _)), _) => {
+                if !self.same_schema(&schema, first)? {
+                    return data_error(match source {
+                        Source::File(path) => format!(
+                            "The schema of `{}` differs from the schema of the first file.",
+                            path.display()
+                        ),
+                        Source::Bytes(_) => {
+                            "The schema of the input differs from the schema of the first file."
+                                .to_string()
+                        }
+                    });
+                }
+            }
+            (None, Some(output)) => {
+                let projected = schema.project(&projection(&schema, &self.columns)?)?;
+                let stream = output.initialize(&to_column_types(&projected))?;
+                self.stream = Some((schema, stream));
+            }
+            (None, None) => return command_error("The output has already been used."),
+        }
+        let Some((_, stream)) = &self.stream else {
+            unreachable!()
+        };
+        for batch in batches {
+            let batch = batch?;
+            let batch = batch.project(&projection(batch.schema_ref(), &self.columns)?)?;
+            for idx in 0..batch.num_rows() {
+                stream.send(Row::new(
+                    batch
+                        .columns()
+                        .iter()
+                        .map(|column| to_value(column, idx))
+                        .collect::<CrushResult<Vec<_>>>()?,
+                ))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Output nothing if there were no sources.
+    pub fn finish(self) -> CrushResult<()> {
+        match self.output {
+            Some(output) => output.send(Value::Empty),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The Arrow type used to store values of the specified Crush type, if there is one.
+fn to_data_type(value_type: &ValueType) -> Option<DataType> {
+    match value_type {
+        ValueType::Integer => Some(DataType::Int64),
+        ValueType::Float => Some(DataType::Float64),
+        ValueType::Bool => Some(DataType::Boolean),
+        ValueType::String | ValueType::File => Some(DataType::Utf8),
+        ValueType::Binary => Some(DataType::Binary),
+        ValueType::Time => Some(DataType::Timestamp(
+            TimeUnit::Microsecond,
+            Some(Arc::from("UTC")),
+        )),
+        ValueType::Duration => Some(DataType::Duration(TimeUnit::Microsecond)),
+        ValueType::List(element_type) => to_data_type(element_type)
+            .map(|t| DataType::List(Arc::from(Field::new_list_field(t, true)))),
+        // Columns that may be empty, like the ones created by `csv:from`
+        ValueType::OneOf(types) => match types
+            .iter()
+            .filter(|t| **t != ValueType::Empty)
+            .collect::<Vec<_>>()[..]
+        {
+            [t] => to_data_type(t),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The Arrow type of a column. Columns of types that can hold different kinds of values, like
+/// `$any`, use the type of all their values if they are all of the same type. Everything else is
+/// stored as strings.
+fn column_data_type<'a>(column: &ColumnType, values: impl Iterator<Item = &'a Value>) -> DataType {
+    if let Some(data_type) = to_data_type(&column.cell_type) {
+        return data_type;
+    }
+    let mut types = values
+        .filter(|v| !matches!(v, Value::Empty))
+        .map(|v| v.value_type());
+    match types.next() {
+        Some(first) if types.all(|t| t == first) => to_data_type(&first).unwrap_or(DataType::Utf8),
+        _ => DataType::Utf8,
+    }
+}
+
+fn wrong_type<T>(value: &Value, data_type: &DataType) -> CrushResult<T> {
+    data_error(format!(
+        "Can't store a value of type `{}` in a column of type `{}`.",
+        value.value_type(),
+        data_type
+    ))
+}
+
+/// An array of the specified type containing the specified values. Empty values are null.
+fn to_array(values: Vec<Value>, data_type: &DataType) -> CrushResult<ArrayRef> {
+    macro_rules! array {
+        ($array:ty, $($pattern:pat => $value:expr),*) => {
+            Arc::from(
+                values
+                    .into_iter()
+                    .map(|value| match value {
+                        Value::Empty => Ok(None),
+                        $($pattern => Ok(Some($value)),)*
+                        value => wrong_type(&value, data_type),
+                    })
+                    .collect::<CrushResult<$array>>()?,
+            )
+        };
+    }
+    Ok(match data_type {
+        DataType::Int64 => array!(Int64Array, Value::Integer(i) => i64::try_from(i)?),
+        DataType::Float64 => {
+            array!(Float64Array, Value::Float(f) => f, Value::Integer(i) => i as f64)
+        }
+        DataType::Boolean => array!(BooleanArray, Value::Bool(b) => b),
+        DataType::Binary => array!(BinaryArray, Value::Binary(b) => b.to_vec()),
+        DataType::Timestamp(_, _) => Arc::from(
+            values
+                .into_iter()
+                .map(|value| match value {
+                    Value::Empty => Ok(None),
+                    Value::Time(t) => Ok(Some(t.timestamp_micros())),
+                    value => wrong_type(&value, data_type),
+                })
+                .collect::<CrushResult<TimestampMicrosecondArray>>()?
+                .with_timezone("UTC"),
+        ),
+        DataType::Duration(_) => array!(
+            DurationMicrosecondArray,
+            Value::Duration(d) => d
+                .num_microseconds()
+                .ok_or("Duration is too long to be stored in microseconds.")?
+        ),
+        DataType::List(field) => {
+            let mut lengths = Vec::with_capacity(values.len());
+            let mut valid = Vec::with_capacity(values.len());
+            let mut elements = Vec::new();
+            for value in values {
+                match value {
+                    Value::Empty => {
+                        lengths.push(0);
+                        valid.push(false);
+                    }
+                    Value::List(l) => {
+                        lengths.push(l.len());
+                        valid.push(true);
+                        elements.extend(l.iter());
+                    }
+                    value => return wrong_type(&value, data_type),
+                }
+            }
+            Arc::from(ListArray::try_new(
+                field.clone(),
+                OffsetBuffer::from_lengths(lengths),
+                to_array(elements, field.data_type())?,
+                Some(NullBuffer::from(valid)),
+            )?)
+        }
+        _ => Arc::from(
+            values
+                .into_iter()
+                .map(|value| match value {
+                    Value::Empty => None,
+                    value => Some(value.to_string()),
+                })
+                .collect::<StringArray>(),
+        ),
+    })
+}
+
+/**
+    Converts a table stream into record batches of at most `BATCH_SIZE` rows. The schema is
+    determined from the column types. If a column has no Arrow type of its own, like a column of
+    type `$any`, its type is inferred from all of its values, so the whole input is read into
+    memory first. Otherwise, only one batch is kept in memory at a time.
+*/
+pub struct BatchReader {
+    input: Stream,
+    schema: SchemaRef,
+    buffered: std::vec::IntoIter<Row>,
+}
+
+impl BatchReader {
+    pub fn new(mut input: Stream) -> CrushResult<BatchReader> {
+        let columns = input.types().to_vec();
+        let mut buffered = Vec::new();
+        if columns
+            .iter()
+            .any(|column| to_data_type(&column.cell_type).is_none())
+        {
+            while let Ok(row) = input.read() {
+                buffered.push(row);
+            }
+        }
+        let schema = Arc::from(Schema::new(
+            columns
+                .iter()
+                .enumerate()
+                .map(|(idx, column)| {
+                    let values = buffered.iter().map(|row| &row.cells()[idx]);
+                    Field::new(column.name(), column_data_type(column, values), true)
+                })
+                .collect::<Vec<_>>(),
+        ));
+        Ok(BatchReader {
+            input,
+            schema,
+            buffered: buffered.into_iter(),
+        })
+    }
+
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn read_rows(&mut self) -> Vec<Row> {
+        let mut rows = self.buffered.by_ref().take(BATCH_SIZE).collect::<Vec<_>>();
+        while rows.len() < BATCH_SIZE {
+            match self.input.read() {
+                Ok(row) => rows.push(row),
+                Err(_) => break,
+            }
+        }
+        rows
+    }
+
+    pub fn next_batch(&mut self) -> CrushResult<Option<RecordBatch>> {
+        let rows = self.read_rows();
+        if rows.is_empty() {
+            return Ok(None);
+        }
+        let mut columns = vec![Vec::with_capacity(rows.len()); self.schema.fields().len()];
+        for row in rows {
+            for (column, value) in columns.iter_mut().zip(row.into_cells()) {
+                column.push(value);
+            }
+        }
+        let arrays = columns
+            .into_iter()
+            .zip(self.schema.fields())
+            .map(|(values, field)| to_array(values, field.data_type()))
+            .collect::<CrushResult<Vec<_>>>()?;
+        Ok(Some(RecordBatch::try_new(self.schema.clone(), arrays)?))
+    }
+}
+
+pub fn input_stream(input: ValueReceiver) -> CrushResult<Stream> {
+    match input.recv()? {
+        value @ (Value::Table(_) | Value::TableInputStream(_)) => value.stream(),
+        value => command_error(format!(
+            "Expected a table stream or a table, got a value of type `{}`.",
+            value.value_type()
+        )),
+    }
+}
+
+#[signature(
+    io.arrow.from,
+    can_block = true,
+    output = Unknown,
+    short = "Read Arrow IPC files and streams",
+    long = "Both the Arrow IPC file format (also known as Feather version 2) and the streaming format are supported. The output is a table stream with one column per field of the Arrow schema. Record batches are read one at a time.",
+    long = "",
+    long = "Integers of all sizes become `$integer`, floating point and decimal numbers become `$float`, timestamps and dates become `$time` and durations and times of day become `$duration`. Lists and structs become lists and structs. Fields of other types are converted to strings. Nulls are empty.",
+    example = "# Read only the name and size columns of a file",
+    example = "arrow:from ./data.arrow column=name column=size",
+)]
+struct FromSignature {
+    #[unnamed()]
+    #[description(
+        "the files to read. All files must have the same schema. If unspecified, read from input, which must be a binary or binary_stream."
+    )]
+    files: Vec<BinaryInput>,
+    #[description("the columns to read. If unspecified, all columns are read.")]
+    column: Vec<String>,
+}
+
+fn read_ipc<R: Read + Seek>(
+    mut reader: R,
+    source: &Source,
+    sender: &mut BatchSender,
+) -> CrushResult<()> {
+    let mut magic = [0u8; 6];
+    let is_file = reader.read_exact(&mut magic).is_ok() && magic == FILE_MAGIC;
+    reader.rewind()?;
+    if is_file {
+        let reader = FileReader::try_new(reader, None)?;
+        sender.send(reader.schema(), source, reader)
+    } else {
+        let reader = StreamReader::try_new(reader, None)?;
+        sender.send(reader.schema(), source, reader)
+    }
+}
+
+fn from(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: FromSignature =
+        FromSignature::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let mut sender = BatchSender::new(cfg.column, context.output);
+    for source in sources(cfg.files, context.input)? {
+        match &source {
+            Source::File(path) => {
+                read_ipc(BufReader::new(File::open(path)?), &source, &mut sender)?
+            }
+            Source::Bytes(bytes) => read_ipc(Cursor::new(bytes.clone()), &source, &mut sender)?,
+        }
+    }
+    sender.finish()
+}
+
+#[signature(
+    io.arrow.to,
+    can_block = true,
+    output = Unknown,
+    short = "Serialize a table stream to the Arrow IPC file format",
+    long = "The Arrow schema is determined from the column types. Integers are stored as 64 bit integers, floats as 64 bit floats, times as microsecond timestamps in UTC and durations as microsecond durations. Lists of such values are stored as lists. Columns of type `$any` use the type of their values if they are all of the same type, which means that the whole input is read before anything is written. Everything else is stored as strings.",
+    example = "ps | arrow:to ./processes.arrow",
+)]
+struct To {
+    #[unnamed()]
+    #[description("the file to write to. If unspecified, write to output as a binary stream.")]
+    file: Option<Files>,
+    #[default(false)]
+    #[description("use the streaming format instead of the file format.")]
+    stream: bool,
+}
+
+fn to(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: To = To::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let mut batches = BatchReader::new(input_stream(context.input)?)?;
+    let writer = files::writer(cfg.file, context.output)?;
+    if cfg.stream {
+        let mut writer = StreamWriter::try_new(writer, &batches.schema())?;
+        while let Some(batch) = batches.next_batch()? {
+            writer.write(&batch)?;
+        }
+        writer.finish()?;
+    } else {
+        let mut writer = FileWriter::try_new(writer, &batches.schema())?;
+        while let Some(batch) = batches.next_batch()? {
+            writer.write(&batch)?;
+        }
+        writer.finish()?;
+    }
+    Ok(())
+}
+
+pub fn declare(root: &mut ScopeLoader) -> CrushResult<()> {
+    root.create_namespace(
+        "arrow",
+        "Arrow IPC I/O",
+        Box::new(move |env| {
+            FromSignature::declare(env)?;
+            To::declare(env)?;
+            Ok(())
+        }),
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::{Decimal256Array, DictionaryArray};
+    use arrow_buffer::i256;
+
+    #[test]
+    fn decimal256_values_are_floats() {
+        let array: ArrayRef = Arc::from(
+            Decimal256Array::from(vec![Some(i256::from_i128(-12345)), None])
+                .with_precision_and_scale(10, 2)
+                .unwrap(),
+        );
+        assert_eq!(to_value_type(array.data_type()), ValueType::Float);
+        assert!(to_value(&array, 0).unwrap() == Value::Float(-123.45));
+        assert!(matches!(to_value(&array, 1).unwrap(), Value::Empty));
+    }
+
+    #[test]
+    fn dictionary_values_have_the_type_of_the_dictionary() {
+        let array: ArrayRef = Arc::from(
+            vec![Some("a"), None, Some("b"), Some("a")]
+                .into_iter()
+                .collect::<DictionaryArray<Int8Type>>(),
+        );
+        assert_eq!(to_value_type(array.data_type()), ValueType::String);
+        let values = (0..array.len())
+            .map(|idx| to_value(&array, idx).unwrap())
+            .collect::<Vec<_>>();
+        assert!(values[0] == Value::from("a"));
+        assert!(matches!(values[1], Value::Empty));
+        assert!(values[2] == Value::from("b"));
+        assert!(values[3] == Value::from("a"));
+    }
+}
@@ -33,27 +33,54 @@ pub fn from(mut context: CommandContext) -> CrushResult<()> {
 #[signature(
     io.bin.to,
     can_block = true,
-    short = "Write specified iterator of strings to a file (or convert to BinaryStream) separated by newlines"
+    short = "Write binary data to a file (or convert it to a binary stream)",
+    long = "The input may be a `binary_stream`, a `binary` or a `string`. Strings are written using utf-8.",
+    long = "This command is used to implement output redirection, i.e. `cmd > file` is the same as `cmd | bin:to file`, and `cmd >> file` is the same as `cmd | bin:to file append=true`.",
+    example = "curl https://example.com | bin:to ./example.html",
 )]
 struct To {
     #[unnamed()]
+    #[description("the file to write to. If unspecified, write to output as a binary stream.")]
     file: Option<Files>,
+    #[description("append to the file instead of overwriting it.")]
+    #[default(false)]
+    append: bool,
 }
 
 pub fn to(mut context: CommandContext) -> CrushResult<()> {
     let cfg: To = To::parse(context.remove_arguments(), &context.global_state.printer())?;
 
-    match context.input.recv()? {
+    let value = context.input.recv()?;
+    let mut out = match value {
+        Value::BinaryInputStream(_) | Value::Binary(_) | Value::String(_) | Value::Empty => {
+            if cfg.append {
+                files::appender(cfg.file, context.output)?
+            } else {
+                files::writer(cfg.file, context.output)?
+            }
+        }
+        Value::TableInputStream(_) | Value::Table(_) | Value::Struct(_) => {
+            return command_error(format!(
+                "Expected input to be binary data, got a value of type `{}`. Use e.g. `json:to` or `csv:to` to serialize structured data.",
+                value.value_type()
+            ));
+        }
+        v => {
+            return command_error(format!(
+                "Expected input to be binary data, got a value of type `{}`.",
+                v.value_type()
+            ));
+        }
+    };
+    match value {
         Value::BinaryInputStream(mut input) => {
-            let mut out = files::writer(cfg.file, context.output)?;
             std::io::copy(input.as_mut(), out.as_mut())?;
-            Ok(())
         }
-        v => command_error(format!(
-            "Expected input to be a `binary_stream`, got a value of type `{}`.",
-            v.value_type()
-        )),
+        Value::Binary(b) => out.write_all(&b)?,
+        Value::String(s) => out.write_all(s.as_bytes())?,
+        _ => {}
     }
+    Ok(())
 }
 
 pub fn declare(root: &mut ScopeLoader) -> CrushResult<()> {
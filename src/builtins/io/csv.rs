@@ -5,20 +5,31 @@ use crate::{
 };
 use std::{io::BufReader, io::prelude::*};
 
+use crate::lang::command::OutputType::Unknown;
 use crate::lang::data::table::ColumnType;
-use crate::lang::errors::{CrushResult, error};
+use crate::lang::errors::{CrushResult, command_error, data_error};
 
 use crate::lang::ordered_string_map::OrderedStringMap;
 use crate::lang::signature::binary_input::BinaryInput;
 use crate::lang::signature::binary_input::ToReader;
+use crate::lang::signature::files;
+use crate::lang::signature::files::Files;
 use crate::lang::state::scope::ScopeLoader;
 use crate::lang::value::ValueType;
+use crate::util::time::parse_time;
 use signature::signature;
 
 #[signature(
     io.csv.from,
+    can_block = true,
+    output = Unknown,
+    short = "Parse specified files as CSV files",
+    long = "Fields may be quoted using the quote character. Quoted fields can contain separators, newlines and quote characters. A quote character inside of a quoted field is escaped by doubling it, as specified by RFC 4180.",
+    long = "If no columns are specified, the first row of the input is used as a header containing the column names, and the type of each column is inferred from the first few rows of data. A column is considered to be an `$integer`, a `$float` or a `$time` if every non-empty value in it can be parsed as such, otherwise it is a `$string`. Empty fields in such columns are returned as empty values.",
+    example = "# Read a file with a header row, inferring column names and types",
+    example = "csv:from data.csv",
+    example = "# Read a file without a header row, specifying column names and types",
     example = "csv:from separator=\",\" head=1 name=$string age=$integer nick=$string",
-    short = "Parse specified files as CSV files"
 )]
 struct From {
     #[unnamed()]
@@ -27,78 +38,204 @@ struct From {
     )]
     files: Vec<BinaryInput>,
     #[named()]
-    #[description("name and type of all columns.")]
+    #[description(
+        "name and type of all columns. If unspecified, column names are read from the first row."
+    )]
     columns: OrderedStringMap<ValueType>,
     #[description("column separator.")]
     #[default(',')]
     separator: char,
+    #[description("quote character.")]
+    #[default('"')]
+    quote: char,
     #[default(0usize)]
     #[description("skip this many lines of input from the beginning.")]
     head: usize,
     #[description("trim this character from start and end of every value.")]
     trim: Option<char>,
+    #[default(100usize)]
+    #[description("the number of rows to examine when inferring column types.")]
+    infer: usize,
+}
+
+fn to_byte(c: char, name: &str) -> CrushResult<u8> {
+    if c.is_ascii() {
+        Ok(c as u8)
+    } else {
+        command_error(format!("The {} must be an ASCII character.", name))
+    }
+}
+
+/// Guess the most specific type that all the supplied non-empty values can be parsed as. If some
+/// values are empty, the column may also contain empty values.
+fn infer_type<'a>(values: impl Iterator<Item = &'a str>) -> ValueType {
+    let mut candidates = vec![ValueType::Integer, ValueType::Float, ValueType::Time];
+    let mut seen = false;
+    let mut has_empty = false;
+    for value in values {
+        if value.is_empty() {
+            has_empty = true;
+            continue;
+        }
+        seen = true;
+        candidates.retain(|t| match t {
+            ValueType::Integer => value.parse::<i128>().is_ok(),
+            ValueType::Float => value.parse::<f64>().is_ok(),
+            ValueType::Time => parse_time(value).is_some(),
+            _ => false,
+        });
+    }
+    match (seen, candidates.is_empty(), has_empty) {
+        (true, false, false) => candidates.remove(0),
+        (true, false, true) => ValueType::OneOf(vec![candidates.remove(0), ValueType::Empty]),
+        _ => ValueType::String,
+    }
+}
+
+fn parse_record(
+    record: &csv::StringRecord,
+    columns: &[ColumnType],
+    trim: Option<char>,
+) -> CrushResult<Row> {
+    if record.len() != columns.len() {
+        return data_error(format!(
+            "Wrong number of columns in CSV file on line {}. Expected {}, got {}.",
+            record.position().map(|p| p.line()).unwrap_or(0),
+            columns.len(),
+            record.len()
+        ));
+    }
+    Ok(Row::new(
+        record
+            .iter()
+            .map(|s| trim.map(|c| s.trim_matches(c)).unwrap_or(s))
+            .zip(columns.iter())
+            .map(|(s, t)| match &t.cell_type {
+                ValueType::String => Ok(Value::from(s)),
+                cell_type if s.is_empty() && cell_type.is(&Value::Empty) => Ok(Value::Empty),
+                ValueType::OneOf(types) if !types.is_empty() => types[0].parse(s),
+                cell_type => cell_type.parse(s),
+            })
+            .collect::<Result<Vec<Value>, CrushError>>()?,
+    ))
 }
 
 fn from(mut context: CommandContext) -> CrushResult<()> {
     let cfg: From = From::parse(context.remove_arguments(), &context.global_state.printer())?;
-    let columns = cfg
-        .columns
-        .iter()
-        .map(|(k, v)| ColumnType::new_from_string(k.clone(), v.clone()))
-        .collect::<Vec<_>>();
-    let output = context.output.initialize(&columns)?;
 
     let mut reader = BufReader::new(cfg.files.to_reader(context.input)?);
 
-    let separator = cfg.separator;
-    let trim = cfg.trim;
-    let skip = cfg.head;
-
     let mut line = String::new();
-    let mut skipped = 0usize;
-    loop {
+    for _ in 0..cfg.head {
         line.clear();
-        reader.read_line(&mut line)?;
-        if line.is_empty() {
+        if reader.read_line(&mut line)? == 0 {
             break;
         }
-        if skipped < skip {
-            skipped += 1;
-            continue;
-        }
-        let line_without_newline = if line.ends_with('\n') {
-            &line[0..line.len() - 1]
-        } else {
-            &line
-        };
+    }
 
-        let mut split: Vec<&str> = line_without_newline
-            .split(separator)
-            .map(|s| trim.map(|c| s.trim_matches(c)).unwrap_or(s))
-            .collect();
+    let mut records = csv::ReaderBuilder::new()
+        .delimiter(to_byte(cfg.separator, "separator")?)
+        .quote(to_byte(cfg.quote, "quote")?)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(reader)
+        .into_records();
 
-        if split.len() != columns.len() {
-            return error("Wrong number of columns in CSV file.");
-        }
+    let trim = cfg.trim;
+    let mut buffered = Vec::new();
 
-        if let Some(trim) = trim {
-            split = split.iter().map(|s| s.trim_matches(trim)).collect();
+    let columns = if cfg.columns.is_empty() {
+        let header = match records.next() {
+            Some(header) => header?,
+            None => return context.output.initialize(&[]).map(|_| ()),
+        };
+        for record in records.by_ref().take(cfg.infer) {
+            buffered.push(record?);
         }
-
-        match split
+        header
             .iter()
-            .zip(columns.iter())
-            .map(|(s, t)| t.cell_type.parse(*s))
-            .collect::<Result<Vec<Value>, CrushError>>()
-        {
-            Ok(cells) => {
-                let _ = output.send(Row::new(cells));
-            }
-            Err(err) => {
-                return Err(err);
-            }
-        }
+            .map(|s| trim.map(|c| s.trim_matches(c)).unwrap_or(s))
+            .enumerate()
+            .map(|(idx, name)| {
+                ColumnType::new_from_string(
+                    name.to_string(),
+                    infer_type(
+                        buffered
+                            .iter()
+                            .filter_map(|r| r.get(idx))
+                            .map(|s| trim.map(|c| s.trim_matches(c)).unwrap_or(s)),
+                    ),
+                )
+            })
+            .collect::<Vec<_>>()
+    } else {
+        cfg.columns
+            .iter()
+            .map(|(k, v)| ColumnType::new_from_string(k.clone(), v.clone()))
+            .collect::<Vec<_>>()
+    };
+
+    let output = context.output.initialize(&columns)?;
+
+    for record in buffered {
+        output.send(parse_record(&record, &columns, trim)?)?;
+    }
+    for record in records {
+        output.send(parse_record(&record?, &columns, trim)?)?;
+    }
+    Ok(())
+}
+
+#[signature(
+    io.csv.to,
+    can_block = true,
+    output = Unknown,
+    short = "Serialize a table stream to CSV format",
+    long = "Fields that contain the separator, the quote character or a newline are quoted, and quote characters inside of quoted fields are escaped by doubling them, as specified by RFC 4180.",
+    long = "`time` values are written in the RFC 3339 format and empty values are written as empty fields, so that the output can be read back using `csv:from`.",
+    example = "files | select file size modified | csv:to ./files.csv")]
+struct To {
+    #[unnamed()]
+    #[description("the file to write to. If unspecified, write to output as a binary stream.")]
+    file: Option<Files>,
+    #[description("column separator.")]
+    #[default(',')]
+    separator: char,
+    #[description("quote character.")]
+    #[default('"')]
+    quote: char,
+    #[description("write a header row containing the column names.")]
+    #[default(true)]
+    header: bool,
+}
+
+fn to_csv(value: Value) -> String {
+    match value {
+        Value::Empty => "".to_string(),
+        Value::String(s) => s.to_string(),
+        Value::Time(t) => t.to_rfc3339(),
+        Value::Float(f) => format!("{:?}", f),
+        Value::Bool(b) => b.to_string(),
+        v => v.to_string(),
+    }
+}
+
+fn to(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: To = To::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let mut input = context.input.recv()?.stream()?;
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(to_byte(cfg.separator, "separator")?)
+        .quote(to_byte(cfg.quote, "quote")?)
+        .from_writer(files::writer(cfg.file, context.output)?);
+
+    if cfg.header {
+        writer.write_record(input.types().iter().map(|c| c.name()))?;
+    }
+
+    while let Ok(row) = input.read() {
+        writer.write_record(Vec::from(row).drain(..).map(to_csv).collect::<Vec<_>>())?;
     }
+    writer.flush()?;
     Ok(())
 }
 
@@ -108,6 +245,7 @@ pub fn declare(root: &mut ScopeLoader) -> CrushResult<()> {
         "CSV I/O",
         Box::new(move |env| {
             From::declare(env)?;
+            To::declare(env)?;
             Ok(())
         }),
     )?;
@@ -0,0 +1,105 @@
+use crate::builtins::io::xml::{COMMENT, node};
+use crate::lang::command::OutputType::Unknown;
+use crate::lang::errors::{CrushResult, data_error};
+use crate::lang::signature::binary_input::{BinaryInput, ToReader};
+use crate::lang::state::contexts::CommandContext;
+use crate::lang::state::scope::ScopeLoader;
+use crate::lang::value::Value;
+use html5ever::tendril::TendrilSink;
+use html5ever::{ParseOpts, parse_document};
+use markup5ever_rcdom::{Handle, NodeData, RcDom};
+use signature::signature;
+use std::io::BufReader;
+
+fn from_html(handle: &Handle, ignore_whitespace: bool) -> CrushResult<Option<Value>> {
+    match &handle.data {
+        NodeData::Element { name, attrs, .. } => {
+            let attr = attrs
+                .borrow()
+                .iter()
+                .map(|a| {
+                    (
+                        match &a.name.prefix {
+                            Some(prefix) => format!("{}:{}", prefix, a.name.local),
+                            None => a.name.local.to_string(),
+                        },
+                        a.value.to_string(),
+                    )
+                })
+                .collect();
+            let mut children = Vec::new();
+            for child in handle.children.borrow().iter() {
+                if let Some(child) = from_html(child, ignore_whitespace)? {
+                    children.push(child);
+                }
+            }
+            Ok(Some(node(name.local.to_string(), attr, children)?))
+        }
+        NodeData::Text { contents } => {
+            let text = contents.borrow();
+            if ignore_whitespace && text.trim().is_empty() {
+                Ok(None)
+            } else {
+                Ok(Some(Value::from(text.as_ref())))
+            }
+        }
+        NodeData::Comment { contents } => Ok(Some(node(
+            COMMENT,
+            vec![],
+            vec![Value::from(contents.as_ref())],
+        )?)),
+        NodeData::Document | NodeData::Doctype { .. } | NodeData::ProcessingInstruction { .. } => {
+            Ok(None)
+        }
+    }
+}
+
+#[signature(
+    io.html.from,
+    can_block = true,
+    output = Unknown,
+    short = "Parse html format",
+    long = "Parsing is lenient and follows the same rules that web browsers use, so missing end tags and other errors in the document are silently fixed. Implicit elements like `head` and `body` are added where they are missing.",
+    long = "",
+    long = "The `html` element is returned as a node struct, using the same representation as `xml:from`, so that it can be searched using `xml:find` or serialized using `xml:to`.",
+    example = "# Extract the title of a web page",
+    example = "http \"https://example.com\" | member body | html:from | xml:find title | select text",
+)]
+struct From {
+    #[unnamed()]
+    #[description(
+        "source. If unspecified, will read from input, which must be a binary or binary_stream."
+    )]
+    files: Vec<BinaryInput>,
+    #[description("discard text fragments that only consist of whitespace.")]
+    #[default(false)]
+    ignore_whitespace: bool,
+}
+
+fn from(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: From = From::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let mut reader = BufReader::new(cfg.files.to_reader(context.input)?);
+    let dom = parse_document(RcDom::default(), ParseOpts::default())
+        .from_utf8()
+        .read_from(&mut reader)?;
+    for child in dom.document.children.borrow().iter() {
+        if let NodeData::Element { .. } = child.data
+            && let Some(value) = from_html(child, cfg.ignore_whitespace)?
+        {
+            return context.output.send(value);
+        }
+    }
+    data_error("Missing root element.")
+}
+
+pub fn declare(root: &mut ScopeLoader) -> CrushResult<()> {
+    root.create_namespace(
+        "html",
+        "HTML I/O",
+        Box::new(move |env| {
+            From::declare(env)?;
+            Ok(())
+        }),
+    )?;
+    Ok(())
+}
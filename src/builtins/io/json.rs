@@ -16,7 +16,7 @@ use signature::signature;
 use std::collections::HashSet;
 use std::convert::{From, TryFrom};
 
-fn from_json(json_value: &serde_json::Value) -> CrushResult<Value> {
+pub fn from_json(json_value: &serde_json::Value) -> CrushResult<Value> {
     match json_value {
         serde_json::Value::Null => Ok(Value::Empty),
         serde_json::Value::Bool(b) => Ok(Value::Bool(*b)),
@@ -84,7 +84,7 @@ fn from_json(json_value: &serde_json::Value) -> CrushResult<Value> {
     }
 }
 
-fn to_json(value: Value) -> CrushResult<serde_json::Value> {
+pub fn to_json(value: Value) -> CrushResult<serde_json::Value> {
     let v = value.materialize()?;
     match v {
         Value::File(s) => Ok(serde_json::Value::from(
@@ -133,16 +133,6 @@ fn to_json(value: Value) -> CrushResult<serde_json::Value> {
     }
 }
 
-pub fn json_to_value(s: &str) -> CrushResult<Value> {
-    let serde_value = serde_json::from_str(s)?;
-    from_json(&serde_value)
-}
-
-pub fn value_to_json(v: Value) -> CrushResult<String> {
-    let json_value = to_json(v)?;
-    Ok(json_value.to_string())
-}
-
 #[signature(
     io.json.from,
     can_block = true,
@@ -0,0 +1,199 @@
+use crate::builtins::io::json::{from_json, to_json};
+use crate::lang::command::OutputType::Unknown;
+use crate::lang::data::r#struct::Struct;
+use crate::lang::data::table::{ColumnType, Row};
+use crate::lang::errors::{CrushResult, command_error, data_error};
+use crate::lang::signature::binary_input::{BinaryInput, ToReader};
+use crate::lang::signature::files;
+use crate::lang::signature::files::Files;
+use crate::lang::state::contexts::CommandContext;
+use crate::lang::state::scope::ScopeLoader;
+use crate::lang::value::{Value, ValueType};
+use signature::signature;
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Write};
+
+/// The name of the column containing everything that did not fit in the other columns.
+const EXTRA: &str = "_extra";
+
+#[signature(
+    io.jsonl.from,
+    can_block = true,
+    output = Unknown,
+    short = "Parse JSON Lines format",
+    long = "JSON Lines (also known as newline delimited JSON) is a text format where every line is a separate JSON value, usually an object. Unlike `json:from`, `jsonl:from` does not read the whole input into memory, but returns a table stream with one row per line.",
+    long = "",
+    long = "The columns are inferred from the objects in the first few lines. Every field found in them becomes a column. If all values of a field are of the same type, the column is of that type, otherwise it is of type `$any`. Fields that are null or missing are empty.",
+    long = "",
+    long = "The last column, `_extra`, contains everything that does not fit in the other columns. For objects, that is a struct with any fields that have no column or whose value is of the wrong type. Lines that do not contain an object are put in the `_extra` column as is. If everything fits, the column is empty.",
+    example = "# Read a log file",
+    example = "jsonl:from ./log.jsonl | where {($level == \"error\")}",
+)]
+struct FromSignature {
+    #[unnamed()]
+    #[description(
+        "source. If unspecified, will read from input, which must be a binary or binary_stream."
+    )]
+    files: Vec<BinaryInput>,
+    #[default(100usize)]
+    #[description("the number of lines to examine when inferring columns.")]
+    infer: usize,
+}
+
+/// Infer the columns from a sample of records, in order of first appearance.
+fn infer_columns(records: &[Value]) -> Vec<ColumnType> {
+    let mut names: Vec<String> = Vec::new();
+    for record in records {
+        if let Value::Struct(s) = record {
+            for (name, _) in s.local_elements() {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+    }
+    let mut columns = names
+        .into_iter()
+        .map(|name| {
+            let types = records
+                .iter()
+                .filter_map(|record| match record {
+                    Value::Struct(s) => s.get(&name),
+                    _ => None,
+                })
+                .filter(|value| !matches!(value, Value::Empty))
+                .map(|value| value.value_type())
+                .collect::<HashSet<_>>();
+            let cell_type = match types.len() {
+                1 => ValueType::OneOf(vec![types.into_iter().next().unwrap(), ValueType::Empty]),
+                _ => ValueType::Any,
+            };
+            ColumnType::new_from_string(name, cell_type)
+        })
+        .collect::<Vec<_>>();
+    columns.push(ColumnType::new(EXTRA, ValueType::Any));
+    columns
+}
+
+fn to_row(record: Value, columns: &[ColumnType]) -> Row {
+    let fields = columns.len() - 1;
+    let mut cells = vec![Value::Empty; columns.len()];
+    match record {
+        Value::Struct(s) => {
+            let mut extra = Vec::new();
+            for (name, value) in s.local_elements() {
+                match columns[..fields].iter().position(|c| c.name() == name) {
+                    Some(idx) if columns[idx].cell_type.is(&value) => cells[idx] = value,
+                    _ => extra.push((name, value)),
+                }
+            }
+            if !extra.is_empty() {
+                cells[fields] = Value::Struct(Struct::new(extra, None));
+            }
+        }
+        value => cells[fields] = value,
+    }
+    Row::new(cells)
+}
+
+fn parse_line(line: &str, number: usize) -> CrushResult<Option<Value>> {
+    if line.trim().is_empty() {
+        return Ok(None);
+    }
+    match serde_json::from_str(line) {
+        Ok(value) => Ok(Some(from_json(&value)?)),
+        Err(e) => data_error(format!("Invalid JSON on line {}: {}", number, e)),
+    }
+}
+
+fn from(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: FromSignature =
+        FromSignature::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let reader = BufReader::new(cfg.files.to_reader(context.input)?);
+    let mut lines = reader.lines().enumerate();
+
+    let mut buffered = Vec::new();
+    while buffered.len() < cfg.infer {
+        match lines.next() {
+            Some((idx, line)) => {
+                if let Some(record) = parse_line(&line?, idx + 1)? {
+                    buffered.push(record);
+                }
+            }
+            None => break,
+        }
+    }
+
+    let columns = infer_columns(&buffered);
+    let output = context.output.initialize(&columns)?;
+    for record in buffered {
+        output.send(to_row(record, &columns))?;
+    }
+    for (idx, line) in lines {
+        if let Some(record) = parse_line(&line?, idx + 1)? {
+            output.send(to_row(record, &columns))?;
+        }
+    }
+    Ok(())
+}
+
+#[signature(
+    io.jsonl.to,
+    can_block = true,
+    output = Unknown,
+    short = "Serialize to JSON Lines format",
+    long = "Every row of the input is written as a compact JSON object on a line of its own, as soon as it is received. The input can be a table stream, a table or a list. The elements of a list are written as is, one per line.",
+    long = "",
+    long = "Values are converted in the same way as by `json:to`.",
+    example = "ps | select pid name | jsonl:to ./processes.jsonl",
+)]
+struct To {
+    #[unnamed()]
+    #[description("the file to write to. If unspecified, write to output as a binary stream.")]
+    file: Option<Files>,
+}
+
+fn write(writer: &mut dyn Write, value: Value) -> CrushResult<()> {
+    let mut line = to_json(value)?.to_string();
+    line.push('\n');
+    writer.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+fn to(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: To = To::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let mut writer = files::writer(cfg.file, context.output)?;
+    match context.input.recv()? {
+        Value::List(l) => {
+            for value in l.iter() {
+                write(&mut writer, value)?;
+            }
+            Ok(())
+        }
+        value @ (Value::Table(_) | Value::TableInputStream(_)) => {
+            let mut input = value.stream()?;
+            let types = input.types().to_vec();
+            while let Ok(row) = input.read() {
+                write(&mut writer, Value::Struct(row.into_struct(&types)))?;
+            }
+            Ok(())
+        }
+        value => command_error(format!(
+            "Expected a table stream, a table or a list, got a value of type `{}`.",
+            value.value_type()
+        )),
+    }
+}
+
+pub fn declare(root: &mut ScopeLoader) -> CrushResult<()> {
+    root.create_namespace(
+        "jsonl",
+        "JSON Lines I/O",
+        Box::new(move |env| {
+            FromSignature::declare(env)?;
+            To::declare(env)?;
+            Ok(())
+        }),
+    )?;
+    Ok(())
+}
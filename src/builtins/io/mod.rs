@@ -13,17 +13,23 @@ use rustyline::history::DefaultHistory;
 use signature::signature;
 use std::path::PathBuf;
 
+mod arrow;
 mod base64;
 mod bin;
 mod csv;
 mod hex;
+mod html;
 mod http;
 pub mod json;
+mod jsonl;
 mod lines;
+mod parquet;
+mod protobuf;
 mod pup;
 mod split;
 mod toml;
 mod words;
+mod xml;
 mod yaml;
 
 #[signature(
@@ -179,15 +185,21 @@ pub fn declare(root: &Scope) -> CrushResult<()> {
         "io",
         "Data serialization I/O",
         Box::new(move |env| {
+            arrow::declare(env)?;
             bin::declare(env)?;
             csv::declare(env)?;
+            protobuf::declare(env)?;
             pup::declare(env)?;
             toml::declare(env)?;
             json::declare(env)?;
+            jsonl::declare(env)?;
             lines::declare(env)?;
+            parquet::declare(env)?;
             split::declare(env)?;
             words::declare(env)?;
             yaml::declare(env)?;
+            xml::declare(env)?;
+            html::declare(env)?;
             hex::declare(env)?;
             base64::declare(env)?;
 
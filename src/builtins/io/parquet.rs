@@ -0,0 +1,132 @@
+use crate::builtins::io::arrow::{
+    BATCH_SIZE, BatchReader, BatchSender, Source, input_stream, root_projection, sources,
+};
+use crate::lang::command::OutputType::Unknown;
+use crate::lang::errors::{CrushResult, command_error};
+use crate::lang::signature::binary_input::BinaryInput;
+use crate::lang::signature::files;
+use crate::lang::signature::files::Files;
+use crate::lang::state::contexts::CommandContext;
+use crate::lang::state::scope::ScopeLoader;
+use parquet::arrow::ArrowWriter;
+use parquet::arrow::ProjectionMask;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::basic::{Compression, GzipLevel, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use parquet::file::reader::ChunkReader;
+use signature::signature;
+use std::fs::File;
+
+#[signature(
+    io.parquet.from,
+    can_block = true,
+    output = Unknown,
+    short = "Read Parquet files",
+    long = "The output is a table stream with one column per top level field of the Parquet schema. The file is read one row group at a time, so large files can be filtered using e.g. `where` without reading all of them into memory. When columns are specified, the other columns are not decoded at all.",
+    long = "",
+    long = "Types are converted in the same way as by `arrow:from`. Integers of all sizes become `$integer`, floating point and decimal numbers become `$float`, timestamps and dates become `$time`, and lists and structs become lists and structs. Nulls are empty.",
+    long = "",
+    long = "Reading a Parquet file requires random access to it, so input that is not a file, like a binary stream, is read into memory first.",
+    example = "# Read the name and size columns of all files of a partitioned dataset",
+    example = "parquet:from ./dataset/*.parquet column=name column=size | where {($size > 1000)}",
+)]
+struct FromSignature {
+    #[unnamed()]
+    #[description(
+        "the files to read. All files must have the same schema. If unspecified, read from input, which must be a binary or binary_stream."
+    )]
+    files: Vec<BinaryInput>,
+    #[description("the columns to read. If unspecified, all columns are read.")]
+    column: Vec<String>,
+}
+
+fn read<R: ChunkReader + 'static>(
+    reader: R,
+    source: &Source,
+    columns: &[String],
+    sender: &mut BatchSender,
+) -> CrushResult<()> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(reader)?.with_batch_size(BATCH_SIZE);
+    let schema = builder.schema().clone();
+    let mask = ProjectionMask::roots(builder.parquet_schema(), root_projection(&schema, columns)?);
+    sender.send(schema, source, builder.with_projection(mask).build()?)
+}
+
+fn from(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: FromSignature =
+        FromSignature::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let mut sender = BatchSender::new(cfg.column.clone(), context.output);
+    for source in sources(cfg.files, context.input)? {
+        match &source {
+            Source::File(path) => read(File::open(path)?, &source, &cfg.column, &mut sender)?,
+            Source::Bytes(bytes) => read(bytes.clone(), &source, &cfg.column, &mut sender)?,
+        }
+    }
+    sender.finish()
+}
+
+#[signature(
+    io.parquet.to,
+    can_block = true,
+    output = Unknown,
+    short = "Serialize a table stream to the Parquet format",
+    long = "The schema is determined from the column types in the same way as by `arrow:to`. Integers are stored as 64 bit integers, floats as 64 bit floats, times as microsecond timestamps in UTC and durations as microsecond durations. Columns of type `$any` use the type of their values if they are all of the same type. Everything else is stored as strings. All columns are nullable, and empty values are stored as nulls.",
+    long = "",
+    long = "The input is written one row group at a time, so arbitrarily large table streams can be written, unless they have columns of type `$any`, whose values are all read before writing in order to determine their type.",
+    example = "ps | select pid name cpu | parquet:to ./processes.parquet compression=zstd",
+)]
+struct To {
+    #[unnamed()]
+    #[description("the file to write to. If unspecified, write to output as a binary stream.")]
+    file: Option<Files>,
+    #[default("snappy")]
+    #[allowed("none", "snappy", "gzip", "lz4", "zstd")]
+    #[description("the compression codec to use.")]
+    compression: String,
+    #[default(1048576usize)]
+    #[description("the maximum number of rows in a row group.")]
+    row_group_size: usize,
+}
+
+fn compression(name: &str) -> CrushResult<Compression> {
+    Ok(match name {
+        "none" => Compression::UNCOMPRESSED,
+        "snappy" => Compression::SNAPPY,
+        "gzip" => Compression::GZIP(GzipLevel::default()),
+        "lz4" => Compression::LZ4_RAW,
+        "zstd" => Compression::ZSTD(ZstdLevel::default()),
+        _ => return command_error(format!("Unknown compression codec `{}`.", name)),
+    })
+}
+
+fn to(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: To = To::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let mut batches = BatchReader::new(input_stream(context.input)?)?;
+    let properties = WriterProperties::builder()
+        .set_compression(compression(&cfg.compression)?)
+        .set_max_row_group_size(cfg.row_group_size)
+        .build();
+    let mut writer = ArrowWriter::try_new(
+        files::writer(cfg.file, context.output)?,
+        batches.schema(),
+        Some(properties),
+    )?;
+    while let Some(batch) = batches.next_batch()? {
+        writer.write(&batch)?;
+    }
+    writer.close()?;
+    Ok(())
+}
+
+pub fn declare(root: &mut ScopeLoader) -> CrushResult<()> {
+    root.create_namespace(
+        "parquet",
+        "Parquet I/O",
+        Box::new(move |env| {
+            FromSignature::declare(env)?;
+            To::declare(env)?;
+            Ok(())
+        }),
+    )?;
+    Ok(())
+}
@@ -0,0 +1,218 @@
+use crate::lang::command::OutputType::Unknown;
+use crate::lang::errors::{CrushResult, command_error, data_error};
+use crate::lang::signature::binary_input::BinaryInput;
+use crate::lang::signature::binary_input::ToReader;
+use crate::lang::signature::files;
+use crate::lang::signature::files::Files;
+use crate::lang::state::contexts::CommandContext;
+use crate::lang::state::scope::ScopeLoader;
+use crate::lang::value::Value;
+use crate::util::protobuf;
+use prost::Message;
+use prost_reflect::{DynamicMessage, MessageDescriptor};
+use signature::signature;
+use std::io::{BufReader, Read, Write};
+
+/// Load the schema from whichever of the `proto` and `descriptor_set` arguments was given, and
+/// look up the message type in it.
+fn schema(
+    proto: Vec<Files>,
+    descriptor_set: Vec<Files>,
+    message: &str,
+) -> CrushResult<MessageDescriptor> {
+    let pool = match (proto.is_empty(), descriptor_set.is_empty()) {
+        (false, true) => protobuf::load(&files::into_paths(proto)?)?,
+        (true, false) => protobuf::load_descriptor_sets(&files::into_paths(descriptor_set)?)?,
+        _ => return command_error("Specify exactly one of `proto` and `descriptor_set`."),
+    };
+    protobuf::message(&pool, message)
+}
+
+/// The largest message that is accepted, which is also the limit of the protobuf encoding itself.
+const MAX_MESSAGE_SIZE: u64 = 2 * 1024 * 1024 * 1024 - 1;
+
+/// Read the next length-delimited message, or `None` at the end of the input.
+fn read_delimited(reader: &mut impl Read) -> CrushResult<Option<Vec<u8>>> {
+    let mut len = 0u64;
+    let mut shift = 0;
+    let mut byte = [0u8];
+    loop {
+        if reader.read(&mut byte)? == 0 {
+            return if shift == 0 {
+                Ok(None)
+            } else {
+                data_error("Unexpected end of input in message length.")
+            };
+        }
+        if shift >= 64 {
+            return data_error("Invalid message length.");
+        }
+        len |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    if len > MAX_MESSAGE_SIZE {
+        return data_error(format!("Message of {} bytes is too large.", len));
+    }
+    // Read incrementally instead of allocating the whole message up front, so that a bogus length
+    // doesn't cost more memory than the data that is actually there.
+    let mut buf = Vec::new();
+    if reader.take(len).read_to_end(&mut buf)? < len as usize {
+        return data_error("Unexpected end of input in message.");
+    }
+    Ok(Some(buf))
+}
+
+#[signature(
+    io.protobuf.from,
+    can_block = true,
+    output = Unknown,
+    short = "Parse protobuf data",
+    long = "The message type is described either by one or more `.proto` files, or by a file descriptor set, e.g. as produced by `protoc --descriptor_set_out`.",
+    long = "",
+    long = "Messages are converted into structs, with repeated fields becoming lists, map fields becoming dicts and enum values becoming the name of the value. `google.protobuf.Timestamp` and `google.protobuf.Duration` messages become times and durations. Unset message fields become `$empty`.",
+    long = "",
+    long = "Use `--delimited` to read a stream of length-delimited messages, which is returned as a table stream with one row per message.",
+    example = "# Decode a single message",
+    example = "protobuf:from ./person.bin proto=./person.proto message=\"example.Person\"",
+    example = "# Decode a stream of length-delimited messages",
+    example = "protobuf:from ./people.bin proto=./person.proto message=\"example.Person\" --delimited",
+)]
+struct From {
+    #[unnamed()]
+    #[description(
+        "source. If unspecified, will read from input, which must be a binary or binary_stream."
+    )]
+    files: Vec<BinaryInput>,
+    #[description("`.proto` files that describe the message type.")]
+    proto: Vec<Files>,
+    #[description(
+        "files containing serialized file descriptor sets that describe the message type."
+    )]
+    descriptor_set: Vec<Files>,
+    #[description("the fully qualified name of the message type.")]
+    message: String,
+    #[description("read a stream of length-delimited messages.")]
+    #[default(false)]
+    delimited: bool,
+}
+
+fn from(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: From = From::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let desc = schema(cfg.proto, cfg.descriptor_set, &cfg.message)?;
+    let mut reader = BufReader::new(cfg.files.to_reader(context.input)?);
+
+    if cfg.delimited {
+        let output = context.output.initialize(&protobuf::column_types(&desc))?;
+        while let Some(buf) = read_delimited(&mut reader)? {
+            let message = DynamicMessage::decode(desc.clone(), buf.as_slice())?;
+            output.send(protobuf::message_to_struct(&message)?.to_row())?;
+        }
+        Ok(())
+    } else {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        let message = DynamicMessage::decode(desc, buf.as_slice())?;
+        context.output.send(protobuf::message_to_value(&message)?)
+    }
+}
+
+#[signature(
+    io.protobuf.to,
+    can_block = true,
+    output = Unknown,
+    short = "Serialize to protobuf format",
+    long = "The message type is described either by one or more `.proto` files, or by a file descriptor set, e.g. as produced by `protoc --descriptor_set_out`.",
+    long = "",
+    long = "The input must be a struct whose members are fields of the message type. Members that are `$empty` are left unset. Use `--delimited` to write a table stream, a table or a list of structs as a stream of length-delimited messages.",
+    example = "# Encode a single message",
+    example = "$(struct:of name=\"Anna\" id=4) | protobuf:to ./person.bin proto=./person.proto message=\"example.Person\"",
+    example = "# Encode a table stream as a stream of length-delimited messages",
+    example = "csv:from ./people.csv | protobuf:to proto=./person.proto message=\"example.Person\" --delimited",
+)]
+struct To {
+    #[unnamed()]
+    #[description("the file to write to. If unspecified, write to output as a binary stream.")]
+    file: Option<Files>,
+    #[description("`.proto` files that describe the message type.")]
+    proto: Vec<Files>,
+    #[description(
+        "files containing serialized file descriptor sets that describe the message type."
+    )]
+    descriptor_set: Vec<Files>,
+    #[description("the fully qualified name of the message type.")]
+    message: String,
+    #[description("write a stream of length-delimited messages.")]
+    #[default(false)]
+    delimited: bool,
+}
+
+fn write(writer: &mut dyn Write, message: DynamicMessage, delimited: bool) -> CrushResult<()> {
+    if delimited {
+        writer.write_all(&message.encode_length_delimited_to_vec())?;
+    } else {
+        writer.write_all(&message.encode_to_vec())?;
+    }
+    Ok(())
+}
+
+fn to(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: To = To::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let desc = schema(cfg.proto, cfg.descriptor_set, &cfg.message)?;
+    let mut writer = files::writer(cfg.file, context.output)?;
+
+    match context.input.recv()? {
+        Value::Struct(s) => write(
+            &mut writer,
+            protobuf::struct_to_message(&s, &desc)?,
+            cfg.delimited,
+        ),
+        Value::List(l) if cfg.delimited => {
+            for value in l.iter() {
+                match value {
+                    Value::Struct(s) => {
+                        write(&mut writer, protobuf::struct_to_message(&s, &desc)?, true)?
+                    }
+                    v => {
+                        return data_error(format!(
+                            "Expected a list of structs, found a value of type `{}`.",
+                            v.value_type()
+                        ));
+                    }
+                }
+            }
+            Ok(())
+        }
+        value @ (Value::Table(_) | Value::TableInputStream(_)) if cfg.delimited => {
+            let mut input = value.stream()?;
+            let types = input.types().to_vec();
+            while let Ok(row) = input.read() {
+                write(
+                    &mut writer,
+                    protobuf::struct_to_message(&row.into_struct(&types), &desc)?,
+                    true,
+                )?;
+            }
+            Ok(())
+        }
+        value => command_error(format!(
+            "Expected a struct, got a value of type `{}`. Use `--delimited` to serialize multiple messages.",
+            value.value_type()
+        )),
+    }
+}
+
+pub fn declare(root: &mut ScopeLoader) -> CrushResult<()> {
+    root.create_namespace(
+        "protobuf",
+        "Protobuf I/O",
+        Box::new(move |env| {
+            From::declare(env)?;
+            To::declare(env)?;
+            Ok(())
+        }),
+    )?;
+    Ok(())
+}
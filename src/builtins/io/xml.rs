@@ -0,0 +1,598 @@
+use crate::lang::command::OutputType::Unknown;
+use crate::lang::data::dict::Dict;
+use crate::lang::data::list::List;
+use crate::lang::data::r#struct::Struct;
+use crate::lang::data::table::{ColumnType, Row};
+use crate::lang::errors::{CrushResult, command_error, data_error};
+use crate::lang::signature::binary_input::{BinaryInput, ToReader};
+use crate::lang::signature::files;
+use crate::lang::signature::files::Files;
+use crate::lang::state::contexts::CommandContext;
+use crate::lang::state::scope::ScopeLoader;
+use crate::lang::value::{Value, ValueType};
+use roxmltree::NodeType;
+use signature::signature;
+use std::io::{BufReader, Read, Write};
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Create the struct used to represent an XML or HTML node.
+pub fn node(
+    name: impl Into<String>,
+    attr: Vec<(String, String)>,
+    children: Vec<Value>,
+) -> CrushResult<Value> {
+    let dict = Dict::new(ValueType::String, ValueType::String)?;
+    for (key, value) in attr {
+        dict.insert(Value::from(key), Value::from(value))?;
+    }
+    Ok(Value::Struct(Struct::new(
+        vec![
+            ("name", Value::from(name.into())),
+            ("attr", dict.into()),
+            ("children", List::new(ValueType::Any, children).into()),
+        ],
+        None,
+    )))
+}
+
+/// The name of a node that represents a comment.
+pub const COMMENT: &str = "#comment";
+
+/// A node struct, unpacked.
+struct Node {
+    value: Struct,
+    name: String,
+    attr: Vec<(String, String)>,
+    children: Vec<Value>,
+}
+
+impl Node {
+    fn parse(value: &Struct) -> CrushResult<Node> {
+        let name = match value.get("name") {
+            Some(Value::String(s)) => s.to_string(),
+            _ => {
+                return data_error("Expected a node struct with a `name` member of type `string`.");
+            }
+        };
+        let attr = match value.get("attr") {
+            Some(Value::Dict(d)) => d
+                .elements()
+                .into_iter()
+                .map(|(k, v)| match (k, v) {
+                    (Value::String(k), Value::String(v)) => Ok((k.to_string(), v.to_string())),
+                    _ => data_error(format!(
+                        "Expected the attributes of `{}` to be strings.",
+                        name
+                    )),
+                })
+                .collect::<CrushResult<Vec<_>>>()?,
+            None | Some(Value::Empty) => vec![],
+            Some(v) => {
+                return data_error(format!(
+                    "Expected the `attr` member of `{}` to be a dict, got a value of type `{}`.",
+                    name,
+                    v.value_type()
+                ));
+            }
+        };
+        let children = match value.get("children") {
+            Some(Value::List(l)) => l.iter().collect(),
+            None | Some(Value::Empty) => vec![],
+            Some(v) => {
+                return data_error(format!(
+                    "Expected the `children` member of `{}` to be a list, got a value of type `{}`.",
+                    name,
+                    v.value_type()
+                ));
+            }
+        };
+        Ok(Node {
+            value: value.clone(),
+            name,
+            attr,
+            children,
+        })
+    }
+
+    fn is_element(&self) -> bool {
+        !self.name.starts_with('#') && !self.name.starts_with('?')
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attr
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Append all text inside of this node to the specified string.
+    fn text(&self, out: &mut String) -> CrushResult<()> {
+        for child in &self.children {
+            match child {
+                Value::String(s) => out.push_str(s),
+                Value::Struct(s) => {
+                    let child = Node::parse(s)?;
+                    if child.is_element() {
+                        child.text(out)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn qualified_name(node: &roxmltree::Node, namespace: Option<&str>, name: &str) -> String {
+    match namespace.and_then(|ns| node.lookup_prefix(ns)) {
+        Some(prefix) => format!("{}:{}", prefix, name),
+        None => name.to_string(),
+    }
+}
+
+fn from_xml(xml: roxmltree::Node, ignore_whitespace: bool) -> CrushResult<Option<Value>> {
+    match xml.node_type() {
+        NodeType::Element => {
+            let tag = xml.tag_name();
+            // Namespaces are not attributes as far as roxmltree is concerned, so put back the
+            // declarations that were made on this element.
+            let inherited = xml
+                .parent_element()
+                .map(|p| p.namespaces().collect::<Vec<_>>())
+                .unwrap_or_default();
+            let mut attr = xml
+                .namespaces()
+                .filter(|ns| ns.uri() != roxmltree::NS_XML_URI && !inherited.contains(ns))
+                .map(|ns| {
+                    (
+                        match ns.name() {
+                            Some(prefix) => format!("xmlns:{}", prefix),
+                            None => "xmlns".to_string(),
+                        },
+                        ns.uri().to_string(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            for a in xml.attributes() {
+                attr.push((
+                    qualified_name(&xml, a.namespace(), a.name()),
+                    a.value().to_string(),
+                ));
+            }
+            let mut children = Vec::new();
+            for child in xml.children() {
+                if let Some(child) = from_xml(child, ignore_whitespace)? {
+                    children.push(child);
+                }
+            }
+            Ok(Some(node(
+                qualified_name(&xml, tag.namespace(), tag.name()),
+                attr,
+                children,
+            )?))
+        }
+        NodeType::Text => {
+            let text = xml.text().unwrap_or("");
+            if ignore_whitespace && text.trim().is_empty() {
+                Ok(None)
+            } else {
+                Ok(Some(Value::from(text)))
+            }
+        }
+        NodeType::Comment => Ok(Some(node(
+            COMMENT,
+            vec![],
+            vec![Value::from(xml.text().unwrap_or(""))],
+        )?)),
+        NodeType::PI => match xml.pi() {
+            Some(pi) => Ok(Some(node(
+                format!("?{}", pi.target),
+                vec![],
+                pi.value.map(|v| vec![Value::from(v)]).unwrap_or_default(),
+            )?)),
+            None => Ok(None),
+        },
+        NodeType::Root => Ok(None),
+    }
+}
+
+fn escape(s: &str, attribute: bool, out: &mut String) {
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' if attribute => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+}
+
+fn to_xml(value: &Value, out: &mut String) -> CrushResult<()> {
+    match value {
+        Value::String(s) => escape(s, false, out),
+        Value::Struct(s) => {
+            let node = Node::parse(s)?;
+            let mut text = String::new();
+            for child in &node.children {
+                if let Value::String(s) = child {
+                    text.push_str(s);
+                }
+            }
+            if node.name == COMMENT {
+                out.push_str("<!--");
+                out.push_str(&text);
+                out.push_str("-->");
+            } else if let Some(target) = node.name.strip_prefix('?') {
+                out.push_str("<?");
+                out.push_str(target);
+                if !text.is_empty() {
+                    out.push(' ');
+                    out.push_str(&text);
+                }
+                out.push_str("?>");
+            } else {
+                out.push('<');
+                out.push_str(&node.name);
+                for (key, value) in &node.attr {
+                    out.push(' ');
+                    out.push_str(key);
+                    out.push_str("=\"");
+                    escape(value, true, out);
+                    out.push('"');
+                }
+                if node.children.is_empty() {
+                    out.push_str("/>");
+                } else {
+                    out.push('>');
+                    for child in &node.children {
+                        to_xml(child, out)?;
+                    }
+                    out.push_str("</");
+                    out.push_str(&node.name);
+                    out.push('>');
+                }
+            }
+        }
+        v => {
+            return data_error(format!(
+                "Expected a node struct or a string, got a value of type `{}`.",
+                v.value_type()
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[signature(
+    io.xml.from,
+    can_block = true,
+    output = Unknown,
+    short = "Parse xml format",
+    long = "The root element of the document is returned as a node struct with three members:",
+    long = "* `name`, the name of the element, including any namespace prefix.",
+    long = "* `attr`, a `dict $string $string` of the attributes of the element, including namespace declarations.",
+    long = "* `children`, a list of text fragments (as strings) mixed with child nodes.",
+    long = "",
+    long = "Comments become nodes named `#comment` and processing instructions become nodes named after their target, prefixed with `?`. In both cases the children member contains the text of the node. Anything outside of the root element, like the xml declaration and the doctype, is discarded.",
+    example = "# Parse a file, ignoring indentation",
+    example = "xml:from ./books.xml --ignore_whitespace",
+)]
+struct FromSignature {
+    #[unnamed()]
+    #[description(
+        "source. If unspecified, will read from input, which must be a binary or binary_stream."
+    )]
+    files: Vec<BinaryInput>,
+    #[description("discard text fragments that only consist of whitespace.")]
+    #[default(false)]
+    ignore_whitespace: bool,
+}
+
+fn from(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: FromSignature =
+        FromSignature::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let mut reader = BufReader::new(cfg.files.to_reader(context.input)?);
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+    let document = roxmltree::Document::parse_with_options(
+        &text,
+        roxmltree::ParsingOptions {
+            allow_dtd: true,
+            ..Default::default()
+        },
+    )?;
+    match from_xml(document.root_element(), cfg.ignore_whitespace)? {
+        Some(value) => context.output.send(value),
+        None => data_error("Missing root element."),
+    }
+}
+
+#[signature(
+    io.xml.to,
+    can_block = true,
+    output = Unknown,
+    short = "Serialize to xml format",
+    long = "The input must be a node struct of the form produced by `xml:from`. The `attr` and `children` members may be left out for nodes that have no attributes or children.",
+    example = "xml:from ./books.xml | xml:to ./copy.xml",
+    example = "$(struct:of name=p children=$(list:of \"Hello, \" $(struct:of name=b children=$(list:of world)))) | xml:to",
+)]
+struct To {
+    #[unnamed()]
+    #[description("the file to write to. If unspecified, write to output as a binary stream.")]
+    file: Option<Files>,
+}
+
+fn to(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: To = To::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let mut writer = files::writer(cfg.file, context.output)?;
+    let value = context.input.recv()?;
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    to_xml(&value, &mut out)?;
+    out.push('\n');
+    writer.write_all(out.as_bytes())?;
+    Ok(())
+}
+
+/// A single step of a selector, e.g. `a.external[href]`.
+#[derive(Default)]
+struct Compound {
+    name: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+    attributes: Vec<(String, Option<String>)>,
+}
+
+impl Compound {
+    fn matches(&self, node: &Node) -> bool {
+        node.is_element()
+            && self.name.as_ref().is_none_or(|n| *n == node.name)
+            && self
+                .id
+                .as_ref()
+                .is_none_or(|id| node.attribute("id") == Some(id))
+            && self.classes.iter().all(|class| {
+                node.attribute("class")
+                    .is_some_and(|c| c.split_whitespace().any(|c| c == class))
+            })
+            && self
+                .attributes
+                .iter()
+                .all(|(name, value)| match (node.attribute(name), value) {
+                    (Some(_), None) => true,
+                    (Some(actual), Some(expected)) => actual == expected,
+                    (None, _) => false,
+                })
+    }
+}
+
+enum Combinator {
+    Descendant,
+    Child,
+}
+
+/// A parsed selector. Every step is paired with the combinator that relates it to the previous
+/// step.
+struct Selector {
+    steps: Vec<(Combinator, Compound)>,
+}
+
+fn is_identifier(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_' || c == ':'
+}
+
+fn identifier(chars: &mut Peekable<Chars>) -> CrushResult<String> {
+    let mut res = String::new();
+    while let Some(c) = chars.next_if(|c| is_identifier(*c)) {
+        res.push(c);
+    }
+    if res.is_empty() {
+        match chars.peek() {
+            Some(c) => command_error(format!("Unexpected character `{}` in selector.", c)),
+            None => command_error("Unexpected end of selector."),
+        }
+    } else {
+        Ok(res)
+    }
+}
+
+fn attribute_value(chars: &mut Peekable<Chars>) -> CrushResult<String> {
+    match chars.peek().copied() {
+        Some(quote @ ('"' | '\'')) => {
+            chars.next();
+            let mut res = String::new();
+            loop {
+                match chars.next() {
+                    Some(c) if c == quote => return Ok(res),
+                    Some(c) => res.push(c),
+                    None => return command_error("Unterminated string in selector."),
+                }
+            }
+        }
+        _ => identifier(chars),
+    }
+}
+
+fn compound(chars: &mut Peekable<Chars>) -> CrushResult<Compound> {
+    let mut res = Compound::default();
+    if chars.next_if_eq(&'*').is_none() && chars.peek().is_some_and(|c| is_identifier(*c)) {
+        res.name = Some(identifier(chars)?);
+    }
+    loop {
+        match chars.peek() {
+            Some('#') => {
+                chars.next();
+                res.id = Some(identifier(chars)?);
+            }
+            Some('.') => {
+                chars.next();
+                res.classes.push(identifier(chars)?);
+            }
+            Some('[') => {
+                chars.next();
+                let name = identifier(chars)?;
+                let value = match chars.next() {
+                    Some(']') => None,
+                    Some('=') => {
+                        let value = attribute_value(chars)?;
+                        if chars.next() != Some(']') {
+                            return command_error("Expected `]` in selector.");
+                        }
+                        Some(value)
+                    }
+                    _ => return command_error("Expected `=` or `]` in selector."),
+                };
+                res.attributes.push((name, value));
+            }
+            _ => return Ok(res),
+        }
+    }
+}
+
+fn parse_selectors(selector: &str) -> CrushResult<Vec<Selector>> {
+    let mut chars = selector.chars().peekable();
+    let mut res = vec![];
+    let mut steps = vec![];
+    loop {
+        let mut combinator = Combinator::Descendant;
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.next_if_eq(&'>').is_some() {
+            combinator = Combinator::Child;
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        }
+        match chars.peek() {
+            None | Some(',') => {
+                if steps.is_empty() {
+                    return command_error("Empty selector.");
+                }
+                res.push(Selector { steps });
+                steps = vec![];
+                if chars.next().is_none() {
+                    return Ok(res);
+                }
+            }
+            Some(_) => steps.push((combinator, compound(&mut chars)?)),
+        }
+    }
+}
+
+/// Check if the last node of the path matches the specified selector steps.
+fn matches(steps: &[(Combinator, Compound)], path: &[Node]) -> bool {
+    match (steps.split_last(), path.split_last()) {
+        (Some(((combinator, compound), steps)), Some((node, path))) => {
+            if !compound.matches(node) {
+                false
+            } else if steps.is_empty() {
+                true
+            } else {
+                match combinator {
+                    Combinator::Child => matches(steps, path),
+                    Combinator::Descendant => (1..=path.len()).any(|i| matches(steps, &path[..i])),
+                }
+            }
+        }
+        _ => false,
+    }
+}
+
+fn find_columns() -> Vec<ColumnType> {
+    vec![
+        ColumnType::new("name", ValueType::String),
+        ColumnType::new_from_string(
+            "attr".to_string(),
+            ValueType::Dict(Box::from(ValueType::String), Box::from(ValueType::String)),
+        ),
+        ColumnType::new("text", ValueType::String),
+        ColumnType::new_from_string(
+            "children".to_string(),
+            ValueType::List(Box::from(ValueType::Any)),
+        ),
+    ]
+}
+
+#[signature(
+    io.xml.find,
+    can_block = true,
+    output = Unknown,
+    short = "Find all nodes matching a selector",
+    long = "The input must be a node struct, as produced by `xml:from` or `html:from`. Every element that matches the selector becomes a row in the output, in document order. The `text` column contains all text inside of the element.",
+    long = "",
+    long = "The selector is a simple subset of CSS selectors:",
+    long = "* `name` matches elements with the specified name, `*` matches any element.",
+    long = "* `#id` matches elements with the specified `id` attribute.",
+    long = "* `.class` matches elements where the `class` attribute contains the specified class.",
+    long = "* `[attr]` and `[attr=value]` match elements that have the specified attribute, optionally with the specified value.",
+    long = "* `a b` matches `b` elements somewhere inside of `a` elements, `a > b` matches `b` elements that are children of `a` elements.",
+    long = "* `a, b` matches both `a` and `b` elements.",
+    example = "# List all links on a web page",
+    example = "http \"https://example.com\" | member body | html:from | xml:find \"a[href]\"",
+    example = "# Find the titles of all books",
+    example = "xml:from ./books.xml | xml:find \"book > title\" | select text",
+)]
+struct Find {
+    #[description("the selector that nodes must match.")]
+    selector: String,
+}
+
+fn find_recursive(
+    path: &mut Vec<Node>,
+    selectors: &[Selector],
+    output: &mut impl FnMut(Row) -> CrushResult<()>,
+) -> CrushResult<()> {
+    let node = path.last().unwrap();
+    if selectors.iter().any(|s| matches(&s.steps, path)) {
+        let mut text = String::new();
+        node.text(&mut text)?;
+        output(Row::new(vec![
+            Value::from(node.name.as_str()),
+            node.value.get("attr").unwrap_or(Value::Empty),
+            Value::from(text),
+            node.value.get("children").unwrap_or(Value::Empty),
+        ]))?;
+    }
+    let children = node
+        .children
+        .iter()
+        .filter_map(|child| match child {
+            Value::Struct(s) => Some(s.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    for child in children {
+        path.push(Node::parse(&child)?);
+        find_recursive(path, selectors, output)?;
+        path.pop();
+    }
+    Ok(())
+}
+
+fn find(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: Find = Find::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let selectors = parse_selectors(&cfg.selector)?;
+    let root = match context.input.recv()? {
+        Value::Struct(s) => Node::parse(&s)?,
+        v => {
+            return command_error(format!(
+                "Expected a node struct, got a value of type `{}`.",
+                v.value_type()
+            ));
+        }
+    };
+    let output = context.output.initialize(&find_columns())?;
+    find_recursive(&mut vec![root], &selectors, &mut |row| output.send(row))
+}
+
+pub fn declare(root: &mut ScopeLoader) -> CrushResult<()> {
+    root.create_namespace(
+        "xml",
+        "XML I/O",
+        Box::new(move |env| {
+            FromSignature::declare(env)?;
+            To::declare(env)?;
+            Find::declare(env)?;
+            Ok(())
+        }),
+    )?;
+    Ok(())
+}
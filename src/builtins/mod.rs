@@ -12,6 +12,7 @@ mod crush;
 #[cfg(target_os = "linux")]
 mod dbus;
 mod dns;
+pub mod env;
 mod fs;
 mod groups;
 mod grpc;
@@ -20,6 +21,7 @@ mod io;
 mod math;
 mod random;
 mod remote;
+mod sqlite;
 mod stream;
 #[cfg(target_os = "linux")]
 mod systemd;
@@ -37,6 +39,7 @@ pub fn declare(root: &Scope) -> CrushResult<()> {
     #[cfg(target_os = "linux")]
     dbus::declare(root)?;
     dns::declare(root)?;
+    env::declare(root)?;
     fs::declare(root)?;
     grpc::declare(root)?;
     groups::declare(root)?;
@@ -45,6 +48,7 @@ pub fn declare(root: &Scope) -> CrushResult<()> {
     math::declare(root)?;
     random::declare(root)?;
     remote::declare(root)?;
+    sqlite::declare(root)?;
     stream::declare(root)?;
     #[cfg(target_os = "linux")]
     systemd::declare(root)?;
@@ -1,28 +1,35 @@
 use crate::lang::command::Command;
 use crate::lang::command::OutputType::Known;
-use crate::lang::completion::Completion;
-use crate::lang::completion::parse::{LastArgument, PartialCommandResult};
-use crate::lang::data::table::{ColumnType, Row};
+use crate::lang::data::table::{ColumnFormat, ColumnType, ColumnVec, Row};
 use crate::lang::errors::{CrushResult, command_error, error};
+use crate::lang::pipe::{TableOutputStream, ValueSender, pipe};
+use crate::lang::serialization::stream::deserialize_stream;
 use crate::lang::serialization::{deserialize, serialize};
+use crate::lang::signature::files;
 use crate::lang::signature::files::Files;
 use crate::lang::signature::patterns::Patterns;
 use crate::lang::state::contexts::CommandContext;
 use crate::lang::state::scope::Scope;
 use crate::lang::value::Value;
 use crate::lang::value::ValueType;
-use crate::util::escape::{escape, escape_without_quotes};
-use crate::util::file::home;
+use crate::util::file::{cwd, home};
 use crate::util::user_map::get_current_username;
 use crossbeam::channel::unbounded;
 use signature::signature;
 use ssh2::KnownHostFileKind;
-use ssh2::{CheckResult, KnownHostKeyFormat, Session};
+use ssh2::{CheckResult, KnownHostKeyFormat, OpenFlags, OpenType, Session};
 use std::cmp::min;
+use std::collections::HashMap;
 use std::convert::TryFrom;
-use std::io::{Read, Write};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{Read, Write, sink};
 use std::net::TcpStream;
-use std::path::PathBuf;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
 static IDENTITY_OUTPUT_TYPE: [ColumnType; 2] = [
     ColumnType::new("identity", ValueType::String),
@@ -62,39 +69,145 @@ fn parse(
     Ok((host, username, port))
 }
 
-fn run_remote(
-    cmd: &Vec<u8>,
-    env: &Scope,
-    host: String,
-    default_username: &Option<String>,
-    password: &Option<String>,
-    host_file: &PathBuf,
+/// How long an unused connection is kept open for reuse.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+/// How often connections that have been idle for too long are closed.
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(30);
+/// The size of the chunks files are transferred in.
+const CHUNK_SIZE: usize = 65536;
+/// How often to report the progress of a file transfer.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How to connect to and authenticate with remote hosts.
+#[derive(Clone)]
+struct ConnectOptions {
+    username: Option<String>,
+    password: Option<String>,
+    host_file: PathBuf,
     ignore_host_file: bool,
     allow_not_found: bool,
-) -> CrushResult<Value> {
-    let (host, username, port) = parse(host, &default_username)?;
+}
+
+impl ConnectOptions {
+    fn new(
+        username: Option<String>,
+        password: Option<String>,
+        host_file: Option<Files>,
+        ignore_host_file: bool,
+        allow_not_found: bool,
+    ) -> CrushResult<ConnectOptions> {
+        Ok(ConnectOptions {
+            username,
+            password,
+            host_file: files::path(host_file, home()?.join(".ssh/known_hosts"))?,
+            ignore_host_file,
+            allow_not_found,
+        })
+    }
+}
+
+/**
+What a pooled session can be reused for. Sessions are only reused if the host key was checked
+against the same known hosts file, so that a session that was created without checking the host key
+is never handed to a command that wants it checked.
+ */
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct SessionKey {
+    host: String,
+    port: u16,
+    username: String,
+    /// The known hosts file the host key was checked against, if it was checked.
+    host_file: Option<PathBuf>,
+}
 
+struct IdleSession {
+    session: Session,
+    since: Instant,
+}
+
+/**
+Authenticated sessions that are not currently in use, by host, port and user. Sessions are checked
+out of the pool while in use, so that no two commands use the same session at the same time.
+
+A background thread closes the sessions that have been idle for too long.
+ */
+fn pool() -> &'static Mutex<HashMap<SessionKey, Vec<IdleSession>>> {
+    static CELL: OnceLock<Mutex<HashMap<SessionKey, Vec<IdleSession>>>> = OnceLock::new();
+    CELL.get_or_init(|| {
+        let _ = std::thread::Builder::new()
+            .name("remote:expire".to_string())
+            .spawn(|| {
+                loop {
+                    std::thread::sleep(EXPIRY_INTERVAL);
+                    expire(&mut pool().lock().unwrap());
+                }
+            });
+        Mutex::new(HashMap::new())
+    })
+}
+
+/// Close the connections that have been idle for too long.
+fn expire(pool: &mut HashMap<SessionKey, Vec<IdleSession>>) {
+    pool.retain(|_, sessions| {
+        sessions.retain(|s| s.since.elapsed() < IDLE_TIMEOUT);
+        !sessions.is_empty()
+    });
+}
+
+fn checkout(key: &SessionKey) -> Option<Session> {
+    let mut pool = pool().lock().unwrap();
+    // The background thread only runs every so often, so don't hand out expired sessions
+    expire(&mut pool);
+    pool.get_mut(key).and_then(|s| s.pop()).map(|s| s.session)
+}
+
+/**
+A session that is checked out of the connection pool. Sessions that are dropped without being
+released, e.g. because a command failed halfway through using them, are closed instead of being
+reused, since they may be in an unknown state.
+ */
+struct PooledSession {
+    key: SessionKey,
+    session: Session,
+}
+
+impl PooledSession {
+    fn release(self) {
+        pool()
+            .lock()
+            .unwrap()
+            .entry(self.key)
+            .or_default()
+            .push(IdleSession {
+                session: self.session,
+                since: Instant::now(),
+            });
+    }
+}
+
+fn handshake(key: &SessionKey, options: &ConnectOptions) -> CrushResult<Session> {
+    let (host, port, username) = (&key.host, key.port, &key.username);
     let tcp = TcpStream::connect(&format!("{}:{}", host, port))?;
     let mut sess = Session::new()?;
 
     sess.set_tcp_stream(tcp);
     sess.handshake()?;
 
-    if !ignore_host_file {
+    if let Some(host_file) = &key.host_file {
         let mut known_hosts = sess.known_hosts()?;
         known_hosts.read_file(host_file, KnownHostFileKind::OpenSSH)?;
         let (key, key_type) = sess
             .host_key()
             .ok_or(&format!("Could not fetch host key for {}", host))?;
-        match known_hosts.check_port(&host, port, key) {
+        match known_hosts.check_port(host, port, key) {
             CheckResult::Match => {}
             CheckResult::Mismatch => return error("Host mismatch"),
             CheckResult::NotFound => {
-                if !allow_not_found {
+                if !options.allow_not_found {
                     return error(&format!("Host {} missing from known host file", host));
                 } else {
                     let key_format: KnownHostKeyFormat = key_type.into();
-                    known_hosts.add(&host, key, "Added by Crush", key_format)?;
+                    known_hosts.add(host, key, "Added by Crush", key_format)?;
                     known_hosts.write_file(host_file, KnownHostFileKind::OpenSSH)?;
                 }
             }
@@ -102,58 +215,91 @@ fn run_remote(
         }
     }
 
-    if let Some(pass) = password {
-        sess.userauth_password(&username, pass)?
+    if let Some(pass) = &options.password {
+        sess.userauth_password(username, pass)?
     } else {
-        sess.userauth_agent(&username)?;
+        sess.userauth_agent(username)?;
     }
+    Ok(sess)
+}
 
-    let mut channel = sess.channel_session()?;
-    channel.exec("crush --pup")?;
-    channel.write(cmd)?;
+/**
+Get a session for the specified host, and use it to open something, like a channel. Idle sessions
+from the pool are reused if possible, and a new session is only created if there are none, or if
+the server has closed them.
+ */
+fn connect<T>(
+    host: String,
+    options: &ConnectOptions,
+    open: impl Fn(&Session) -> Result<T, ssh2::Error>,
+) -> CrushResult<(PooledSession, T)> {
+    let (host, username, port) = parse(host, &options.username)?;
+    let key = SessionKey {
+        host,
+        port,
+        username,
+        host_file: (!options.ignore_host_file).then(|| options.host_file.clone()),
+    };
+    if let Some(session) = checkout(&key)
+        && let Ok(res) = open(&session)
+    {
+        return Ok((PooledSession { key, session }, res));
+    }
+    let session = handshake(&key, options)?;
+    let res = open(&session)?;
+    Ok((PooledSession { key, session }, res))
+}
+
+/**
+Whether the Crush on the host of a session supports the streaming pup format. Older versions reject
+the `--pup-stream` flag as an unknown argument and exit with a non-zero status, while newer ones
+print their help text. This is decided before the closure is sent, so that a closure is never run
+twice, and the answer is remembered for the rest of the session.
+ */
+fn supports_streaming(session: &PooledSession) -> CrushResult<bool> {
+    static CELL: OnceLock<Mutex<HashMap<SessionKey, bool>>> = OnceLock::new();
+    let known = CELL.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(res) = known.lock().unwrap().get(&session.key) {
+        return Ok(*res);
+    }
+    let mut channel = session.session.channel_session()?;
+    channel.exec("crush --pup-stream --help")?;
     channel.send_eof()?;
-    let mut out_buf = Vec::new();
-    channel.read_to_end(&mut out_buf)?;
-    let res = deserialize(&out_buf, env)?;
+    std::io::copy(&mut channel, &mut sink())?;
     channel.wait_close()?;
+    let res = channel.exit_status()? == 0;
+    known.lock().unwrap().insert(session.key.clone(), res);
     Ok(res)
 }
 
-fn ssh_host_complete(
-    cmd: &PartialCommandResult,
-    _cursor: usize,
-    _scope: &Scope,
-    res: &mut Vec<Completion>,
+fn run_remote(
+    cmd: &[u8],
+    env: &Scope,
+    host: String,
+    options: &ConnectOptions,
+    output: &ValueSender,
 ) -> CrushResult<()> {
-    let session = Session::new()?;
-    let mut known_hosts = session.known_hosts()?;
-    let host_file = home()?.join(".ssh/known_hosts");
-
-    known_hosts.read_file(&host_file, KnownHostFileKind::OpenSSH)?;
-    for host in known_hosts.iter()? {
-        match &cmd.last_argument {
-            LastArgument::Unknown => {
-                let completion = escape(host.name().unwrap_or(""));
-                res.push(Completion::new(completion, host.name().unwrap_or(""), 0))
-            }
-
-            LastArgument::QuotedString(stripped_prefix) => {
-                let completion = host.name().unwrap_or("");
-                if completion.starts_with(stripped_prefix) && completion.len() > 0 {
-                    res.push(Completion::new(
-                        format!(
-                            "{}\" ",
-                            escape_without_quotes(&completion[stripped_prefix.len()..])
-                        ),
-                        host.name().unwrap_or(""),
-                        0,
-                    ));
-                }
-            }
-
-            _ => {}
+    let (session, mut channel) = connect(host, options, |s| s.channel_session())?;
+    if supports_streaming(&session)? {
+        channel.exec("crush --pup-stream")?;
+        channel.write_all(cmd)?;
+        channel.send_eof()?;
+        deserialize_stream(&mut channel, env, output)?;
+    } else {
+        // The format that sends the whole output at once
+        channel.exec("crush --pup")?;
+        channel.write_all(cmd)?;
+        channel.send_eof()?;
+        let mut out_buf = Vec::new();
+        channel.read_to_end(&mut out_buf)?;
+        if out_buf.is_empty() {
+            return error("No value returned");
         }
+        output.send(deserialize(&out_buf, env)?)?;
     }
+    channel.wait_close()?;
+    drop(channel);
+    session.release();
     Ok(())
 }
 
@@ -161,12 +307,14 @@ fn ssh_host_complete(
     remote.exec,
     can_block = true,
     short = "Execute a command on a remote host",
-    long = "    Execute the specified command on the soecified host"
+    long = "    Execute the specified command on the soecified host",
+    long = "",
+    long = "    Table streams and binary streams are forwarded as they are produced on the remote host, so output can be consumed before the command has finished.",
 )]
 struct Exec {
     #[description("the command to execute.")]
     command: Command,
-    #[custom_completion(ssh_host_complete)]
+    #[completion_tag("hostname")]
     #[description("host to execute the command on.")]
     host: String,
     #[description("username on remote machines.")]
@@ -189,22 +337,17 @@ struct Exec {
 
 fn exec(mut context: CommandContext) -> CrushResult<()> {
     let cfg: Exec = Exec::parse(context.remove_arguments(), &context.global_state.printer())?;
-
-    let host_file =
-        crate::lang::signature::files::path(cfg.host_file, home()?.join(".ssh/known_hosts"))?;
+    let options = ConnectOptions::new(
+        cfg.username,
+        cfg.password,
+        cfg.host_file,
+        cfg.ignore_host_file,
+        cfg.allow_not_found,
+    )?;
 
     let mut in_buf = Vec::new();
     serialize(&Value::Command(cfg.command), &mut in_buf)?;
-    context.output.send(run_remote(
-        &in_buf,
-        &context.scope,
-        cfg.host,
-        &cfg.username,
-        &cfg.password,
-        &host_file,
-        cfg.ignore_host_file,
-        cfg.allow_not_found,
-    )?)
+    run_remote(&in_buf, &context.scope, cfg.host, &options, &context.output)
 }
 
 #[signature(
@@ -212,19 +355,33 @@ fn exec(mut context: CommandContext) -> CrushResult<()> {
     can_block = true,
     short = "Execute a command on a set of hosts",
     long = "    Execute the specified command all specified hosts",
+    long = "",
+    long = "    The output has one row per host, sent as soon as the host is done. The status of a host is one of `ok`, `failed` or `cancelled`. The value returned by the command is in the value column, which was called result in earlier versions. A failed host has its error message in the error column.",
+    long = "",
+    long = "    Table streams and binary streams returned by the command are collected into tables and binary values, so that the status and duration of a host cover all of its output.",
+    long = "",
+    long = "    Once the number of failed hosts reaches max_failures, hosts that have not started yet are cancelled. Hosts that are already running are allowed to finish.",
+    example = "# Restart a service on every known host, one at a time, stopping at the first failure",
+    example = "remote:pexec {systemctl restart veggies} $(remote:host:list) parallel=1 fail_fast=$true",
     output = Known(ValueType::table_input_stream(&PEXEC_OUTPUT_TYPE)),
 )]
 struct Pexec {
-    #[description("the command to execute.")]
     #[description("the command to execute.")]
     command: Command,
     #[unnamed()]
-    #[custom_completion(ssh_host_complete)]
-    #[description("hosts to execute the command on.")]
-    host: Vec<String>,
+    #[completion_tag("hostname")]
+    #[description(
+        "hosts to execute the command on. Each host can be a string, a file with one host per line, a list of hosts, or a table with a `host` column, like the output of `remote:host:list`."
+    )]
+    host: Vec<Value>,
     #[description("maximum number of hosts to run on in parallel.")]
     #[default(32)]
     parallel: i128,
+    #[description("cancel the remaining hosts after the first failure. Same as max_failures=1.")]
+    #[default(false)]
+    fail_fast: bool,
+    #[description("cancel the remaining hosts once this many hosts have failed.")]
+    max_failures: Option<i128>,
     #[description("username on remote machines.")]
     username: Option<String>,
     #[description(
@@ -243,54 +400,166 @@ struct Pexec {
     allow_not_found: bool,
 }
 
-static PEXEC_OUTPUT_TYPE: [ColumnType; 2] = [
+static PEXEC_OUTPUT_TYPE: [ColumnType; 5] = [
     ColumnType::new("host", ValueType::String),
-    ColumnType::new("result", ValueType::Any),
+    ColumnType::new("status", ValueType::String),
+    ColumnType::new("duration", ValueType::Duration),
+    ColumnType::new("value", ValueType::Any),
+    ColumnType::new("error", ValueType::String),
 ];
 
+/// Collect the hosts to run on from the unnamed arguments of `remote:pexec`.
+fn hosts(values: Vec<Value>, destination: &mut Vec<String>) -> CrushResult<()> {
+    for value in values {
+        match value {
+            Value::String(host) => destination.push(host.to_string()),
+            Value::File(path) => {
+                for line in fs::read_to_string(&path)?.lines() {
+                    let line = line.trim();
+                    if !line.is_empty() && !line.starts_with('#') {
+                        destination.push(line.to_string());
+                    }
+                }
+            }
+            Value::List(list) => hosts(list.iter().collect(), destination)?,
+            v @ (Value::Table(_) | Value::TableInputStream(_)) => {
+                let mut stream = v.stream()?;
+                let idx = stream.types().find("host")?;
+                while let Ok(row) = stream.read() {
+                    match &row.cells()[idx] {
+                        // Hashed entries in the known hosts file have no hostname
+                        Value::String(host) if host.is_empty() => {}
+                        Value::String(host) => destination.push(host.to_string()),
+                        v => {
+                            return command_error(format!(
+                                "Expected the host column to contain strings, got a value of type `{}`",
+                                v.value_type()
+                            ));
+                        }
+                    }
+                }
+            }
+            v => {
+                return command_error(format!(
+                    "Expected hosts to be strings, files, lists or tables, got a value of type `{}`",
+                    v.value_type()
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn host_row(host: String, status: &str, start: Instant, value: Value, error: String) -> Row {
+    Row::new(vec![
+        Value::from(host),
+        Value::from(status),
+        Value::Duration(chrono::Duration::from_std(start.elapsed()).unwrap_or_default()),
+        value,
+        Value::from(error),
+    ])
+}
+
+/// When to cancel the hosts of `remote:pexec` that have not started yet.
+#[derive(Clone)]
+struct FailurePolicy {
+    max_failures: Option<usize>,
+    failures: Arc<AtomicUsize>,
+}
+
+impl FailurePolicy {
+    fn new(max_failures: Option<i128>, fail_fast: bool) -> CrushResult<FailurePolicy> {
+        let max_failures = match (max_failures, fail_fast) {
+            (Some(max), _) if max < 1 => return command_error("max_failures must be at least 1"),
+            (Some(max), _) => Some(max as usize),
+            (None, true) => Some(1),
+            (None, false) => None,
+        };
+        Ok(FailurePolicy {
+            max_failures,
+            failures: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Run a command on a host, unless too many hosts have failed already, and return the row
+    /// for the host.
+    fn run(&self, host: String, run: impl FnOnce(&str) -> CrushResult<Value>) -> Row {
+        let start = Instant::now();
+        if self
+            .max_failures
+            .is_some_and(|max| self.failures.load(Ordering::SeqCst) >= max)
+        {
+            return host_row(host, "cancelled", start, Value::Empty, String::new());
+        }
+        match run(&host) {
+            Ok(value) => host_row(host, "ok", start, value, String::new()),
+            Err(e) => {
+                // Counted before the next host is started, so that a failure of this host is
+                // taken into account when deciding whether to cancel it
+                self.failures.fetch_add(1, Ordering::SeqCst);
+                host_row(host, "failed", start, Value::Empty, e.message())
+            }
+        }
+    }
+}
+
 fn pexec(mut context: CommandContext) -> CrushResult<()> {
     let cfg: Pexec = Pexec::parse(context.remove_arguments(), &context.global_state.printer())?;
-    let host_file =
-        crate::lang::signature::files::path(cfg.host_file, home()?.join(".ssh/known_hosts"))?;
+    let options = ConnectOptions::new(
+        cfg.username,
+        cfg.password,
+        cfg.host_file,
+        cfg.ignore_host_file,
+        cfg.allow_not_found,
+    )?;
+    let mut host_list = Vec::new();
+    hosts(cfg.host, &mut host_list)?;
+    let policy = FailurePolicy::new(cfg.max_failures, cfg.fail_fast)?;
 
     let (host_send, host_recv) = unbounded::<String>();
-    let (result_send, result_recv) = unbounded::<(String, Value)>();
+    let (result_send, result_recv) = unbounded::<Row>();
 
     let mut in_buf = Vec::new();
 
     serialize(&Value::Command(cfg.command), &mut in_buf)?;
 
-    for host in &cfg.host {
+    for host in &host_list {
         host_send.send(host.clone())?;
     }
 
     drop(host_send);
 
-    let thread_count = min(cfg.parallel as usize, cfg.host.len());
+    let thread_count = min(cfg.parallel.max(1) as usize, host_list.len());
     for _ in 0..thread_count {
         let my_recv = host_recv.clone();
         let my_send = result_send.clone();
         let my_buf = in_buf.clone();
         let my_env = context.scope.clone();
-        let my_username = cfg.username.clone();
-        let my_password = cfg.password.clone();
-        let my_host_file = host_file.clone();
-        let my_ignore_host_file = cfg.ignore_host_file;
-        let my_allow_not_found = cfg.allow_not_found;
+        let my_options = options.clone();
+        let my_policy = policy.clone();
+
+        let my_context = context.clone();
 
         context.spawn("remote:pexec", move || {
             while let Ok(host) = my_recv.recv() {
-                let res = run_remote(
-                    &my_buf,
-                    &my_env,
-                    host.clone(),
-                    &my_username,
-                    &my_password,
-                    &my_host_file,
-                    my_ignore_host_file,
-                    my_allow_not_found,
-                )?;
-                my_send.send((host, res))?;
+                my_send.send(my_policy.run(host, |host| {
+                    // The output is collected while the command runs, since it may be a stream
+                    // that the remote host only finishes writing when the command is done
+                    let (res_send, res_recv) = pipe();
+                    let (value_send, value_recv) = unbounded::<Option<CrushResult<Value>>>();
+                    my_context.spawn("remote:pexec:collect", move || {
+                        let _ = value_send.send(res_recv.recv().ok().map(Value::materialize));
+                        Ok(())
+                    })?;
+                    let res =
+                        run_remote(&my_buf, &my_env, host.to_string(), &my_options, &res_send);
+                    drop(res_send);
+                    let value = value_recv
+                        .recv()
+                        .map_err(|_| "Lost track of the remote command")?;
+                    res?;
+                    value.unwrap_or_else(|| error("No value returned"))
+                }))?;
             }
             Ok(())
         })?;
@@ -299,10 +568,248 @@ fn pexec(mut context: CommandContext) -> CrushResult<()> {
     drop(result_send);
     let output = context.output.initialize(&PEXEC_OUTPUT_TYPE)?;
 
-    while let Ok((host, val)) = result_recv.recv() {
-        output.send(Row::new(vec![Value::from(host), val]))?;
+    while let Ok(row) = result_recv.recv() {
+        output.send(row)?;
+    }
+
+    Ok(())
+}
+
+static TRANSFER_OUTPUT_TYPE: [ColumnType; 4] = [
+    ColumnType::new("source", ValueType::String),
+    ColumnType::new("destination", ValueType::String),
+    ColumnType::new_with_format("transferred", ColumnFormat::ByteUnit, ValueType::Integer),
+    ColumnType::new_with_format("size", ColumnFormat::ByteUnit, ValueType::Integer),
+];
+
+/// Copy a single file, reporting progress at regular intervals and when done.
+fn transfer(
+    source: &mut dyn Read,
+    destination: &mut dyn Write,
+    names: (String, String),
+    size: u64,
+    output: &TableOutputStream,
+) -> CrushResult<()> {
+    let progress = |transferred: u64| {
+        Row::new(vec![
+            Value::from(names.0.as_str()),
+            Value::from(names.1.as_str()),
+            Value::Integer(transferred as i128),
+            Value::Integer(size as i128),
+        ])
+    };
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let mut transferred = 0u64;
+    let mut reported = Instant::now();
+    loop {
+        let len = source.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        destination.write_all(&buf[..len])?;
+        transferred += len as u64;
+        if reported.elapsed() >= PROGRESS_INTERVAL {
+            output.send(progress(transferred))?;
+            reported = Instant::now();
+        }
+    }
+    destination.flush()?;
+    output.send(progress(transferred))
+}
+
+/// The last component of a path, for use as the name of the file it is copied to.
+fn file_name(path: &Path) -> CrushResult<&OsStr> {
+    path.file_name()
+        .ok_or_else(|| format!("Invalid file name `{}`", path.display()).into())
+}
+
+#[signature(
+    remote.copy,
+    can_block = true,
+    output = Known(ValueType::table_input_stream(&TRANSFER_OUTPUT_TYPE)),
+    short = "Copy files to a remote host",
+    long = "Files are copied over SFTP, using a connection from the same pool as `remote:exec`. Their permissions are preserved. The output is a table stream with one row per second and file showing the progress of the transfer, and a final row per file when it is done.",
+    example = "# Copy a file into the /tmp directory of a remote host",
+    example = "remote:copy ./report.pdf host=popplar.meadow destination=/tmp/",
+    example = "# Copy all log files to the home directory of a remote user, without showing progress",
+    example = "remote:copy *.log host=admin@popplar.meadow | last",
+)]
+struct Copy {
+    #[unnamed()]
+    #[description("the local files to copy.")]
+    files: Vec<Files>,
+    #[completion_tag("hostname")]
+    #[description("host to copy the files to.")]
+    host: String,
+    #[description(
+        "the remote file or directory to copy to. When copying more than one file, or if it ends with `/`, it must be a directory. Relative paths are relative to the home directory of the remote user, which is also the default."
+    )]
+    destination: Option<String>,
+    #[description("username on remote machines.")]
+    username: Option<String>,
+    #[description(
+        "password on remote machines. If no password is provided, agent authentication will be used."
+    )]
+    password: Option<String>,
+    #[description("(~/.ssh/known_hosts) known hosts file.")]
+    host_file: Option<Files>,
+    #[description("skip checking the know hosts file.")]
+    #[default(false)]
+    ignore_host_file: bool,
+    #[description(
+        "allow missing hosts in the known hosts file. Missing hosts will be automatically added to the file."
+    )]
+    #[default(false)]
+    allow_not_found: bool,
+}
+
+fn copy(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: Copy = Copy::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let options = ConnectOptions::new(
+        cfg.username,
+        cfg.password,
+        cfg.host_file,
+        cfg.ignore_host_file,
+        cfg.allow_not_found,
+    )?;
+    let files = files::into_paths(cfg.files)?;
+
+    let (session, sftp) = connect(cfg.host, &options, |s| s.sftp())?;
+    let destination = PathBuf::from(cfg.destination.unwrap_or_default());
+    let to_directory = files.len() > 1
+        || destination.as_os_str().is_empty()
+        || destination.to_string_lossy().ends_with('/')
+        || sftp.stat(&destination).map(|s| s.is_dir()).unwrap_or(false);
+
+    let output = context.output.initialize(&TRANSFER_OUTPUT_TYPE)?;
+    for file in files {
+        let metadata = fs::metadata(&file)?;
+        if metadata.is_dir() {
+            return command_error(format!(
+                "`{}` is a directory, only files can be copied",
+                file.display()
+            ));
+        }
+        let target = if to_directory {
+            destination.join(file_name(&file)?)
+        } else {
+            destination.clone()
+        };
+        let mut remote = sftp.open_mode(
+            &target,
+            OpenFlags::WRITE | OpenFlags::TRUNCATE,
+            (metadata.permissions().mode() & 0o777) as i32,
+            OpenType::File,
+        )?;
+        transfer(
+            &mut fs::File::open(&file)?,
+            &mut remote,
+            (
+                file.to_string_lossy().to_string(),
+                target.to_string_lossy().to_string(),
+            ),
+            metadata.len(),
+            &output,
+        )?;
     }
+    drop(sftp);
+    session.release();
+    Ok(())
+}
 
+#[signature(
+    remote.fetch,
+    can_block = true,
+    output = Known(ValueType::table_input_stream(&TRANSFER_OUTPUT_TYPE)),
+    short = "Copy files from a remote host",
+    long = "Files are copied over SFTP, using a connection from the same pool as `remote:exec`. The output is a table stream with one row per second and file showing the progress of the transfer, and a final row per file when it is done.",
+    example = "# Fetch the system log of a remote host into the current directory",
+    example = "remote:fetch /var/log/syslog host=popplar.meadow",
+)]
+struct Fetch {
+    #[unnamed()]
+    #[description(
+        "the remote files to fetch, as files or strings. Relative paths are relative to the home directory of the remote user."
+    )]
+    files: Vec<Value>,
+    #[completion_tag("hostname")]
+    #[description("host to fetch the files from.")]
+    host: String,
+    #[description(
+        "the local file or directory to copy to. When fetching more than one file, it must be a directory. Defaults to the current directory."
+    )]
+    destination: Option<Files>,
+    #[description("username on remote machines.")]
+    username: Option<String>,
+    #[description(
+        "password on remote machines. If no password is provided, agent authentication will be used."
+    )]
+    password: Option<String>,
+    #[description("(~/.ssh/known_hosts) known hosts file.")]
+    host_file: Option<Files>,
+    #[description("skip checking the know hosts file.")]
+    #[default(false)]
+    ignore_host_file: bool,
+    #[description(
+        "allow missing hosts in the known hosts file. Missing hosts will be automatically added to the file."
+    )]
+    #[default(false)]
+    allow_not_found: bool,
+}
+
+fn fetch(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: Fetch = Fetch::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let options = ConnectOptions::new(
+        cfg.username,
+        cfg.password,
+        cfg.host_file,
+        cfg.ignore_host_file,
+        cfg.allow_not_found,
+    )?;
+    let destination = files::path(cfg.destination, cwd()?)?;
+    let remote_files = cfg
+        .files
+        .into_iter()
+        .map(|file| match file {
+            Value::File(path) => Ok(path.to_path_buf()),
+            Value::String(path) => Ok(PathBuf::from(path.as_ref())),
+            v => command_error(format!(
+                "Expected the files to fetch to be files or strings, got a value of type `{}`",
+                v.value_type()
+            )),
+        })
+        .collect::<CrushResult<Vec<_>>>()?;
+    let to_directory = remote_files.len() > 1 || destination.is_dir();
+
+    let (session, sftp) = connect(cfg.host, &options, |s| s.sftp())?;
+
+    let output = context.output.initialize(&TRANSFER_OUTPUT_TYPE)?;
+    for file in remote_files {
+        let stat = sftp.stat(&file)?;
+        if stat.is_dir() {
+            return command_error(format!(
+                "`{}` is a directory, only files can be fetched",
+                file.display()
+            ));
+        }
+        let target = if to_directory {
+            destination.join(file_name(&file)?)
+        } else {
+            destination.clone()
+        };
+        transfer(
+            &mut sftp.open(&file)?,
+            &mut fs::File::create(&target)?,
+            (
+                file.to_string_lossy().to_string(),
+                target.to_string_lossy().to_string(),
+            ),
+            stat.size.unwrap_or(0),
+            &output,
+        )?;
+    }
+    drop(sftp);
+    session.release();
     Ok(())
 }
 
@@ -415,6 +922,8 @@ pub fn declare(scope: &Scope) -> CrushResult<()> {
         Box::new(move |remote| {
             Exec::declare(remote)?;
             Pexec::declare(remote)?;
+            Copy::declare(remote)?;
+            Fetch::declare(remote)?;
             Identity::declare(remote)?;
 
             remote.create_namespace(
@@ -432,3 +941,368 @@ pub fn declare(scope: &Scope) -> CrushResult<()> {
     )?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lang::data::list::List;
+    use crate::lang::data::table::Table;
+    use crate::lang::pipe::{CrushStream, streams};
+    use std::net::TcpListener;
+    use std::process::{Child, Stdio};
+
+    fn key(host: &str) -> SessionKey {
+        SessionKey {
+            host: host.to_string(),
+            port: 22,
+            username: "rabbit".to_string(),
+            host_file: None,
+        }
+    }
+
+    #[test]
+    fn released_sessions_are_reused() {
+        let meadow = key("pool-test.meadow");
+        PooledSession {
+            key: meadow.clone(),
+            session: Session::new().unwrap(),
+        }
+        .release();
+        assert!(checkout(&key("pool-test.burrow")).is_none());
+        assert!(checkout(&meadow).is_some());
+        // A checked out session is not handed out again until it is released
+        assert!(checkout(&meadow).is_none());
+    }
+
+    #[test]
+    fn sessions_are_not_shared_between_host_files() {
+        let unchecked = key("pool-test.warren");
+        let checked = SessionKey {
+            host_file: Some(PathBuf::from("/tmp/known_hosts")),
+            ..unchecked.clone()
+        };
+        PooledSession {
+            key: unchecked.clone(),
+            session: Session::new().unwrap(),
+        }
+        .release();
+        assert!(checkout(&checked).is_none());
+        assert!(checkout(&unchecked).is_some());
+    }
+
+    #[test]
+    fn idle_sessions_expire() {
+        let mut pool = HashMap::new();
+        let idle = |age: Duration| IdleSession {
+            session: Session::new().unwrap(),
+            since: Instant::now() - age,
+        };
+        pool.insert(key("expire-test.old"), vec![idle(IDLE_TIMEOUT)]);
+        pool.insert(
+            key("expire-test.mixed"),
+            vec![idle(IDLE_TIMEOUT), idle(Duration::ZERO)],
+        );
+        expire(&mut pool);
+        assert!(!pool.contains_key(&key("expire-test.old")));
+        assert_eq!(pool[&key("expire-test.mixed")].len(), 1);
+    }
+
+    fn host_list(values: Vec<Value>) -> CrushResult<Vec<String>> {
+        let mut destination = Vec::new();
+        hosts(values, &mut destination)?;
+        Ok(destination)
+    }
+
+    #[test]
+    fn hosts_from_strings_and_lists() {
+        let list = List::new(
+            ValueType::String,
+            vec![Value::from("burrow"), Value::from("warren")],
+        );
+        assert_eq!(
+            host_list(vec![Value::from("meadow"), Value::List(list)]).unwrap(),
+            vec!["meadow", "burrow", "warren"]
+        );
+    }
+
+    #[test]
+    fn hosts_from_file() {
+        let file = std::env::temp_dir().join(format!("crush-hosts-{:016x}", rand::random::<u64>()));
+        fs::write(&file, "# Web servers\nmeadow\n\n  burrow  \n").unwrap();
+        let res = host_list(vec![Value::from(file.as_path())]);
+        fs::remove_file(&file).unwrap();
+        assert_eq!(res.unwrap(), vec!["meadow", "burrow"]);
+    }
+
+    #[test]
+    fn hosts_from_table() {
+        let table = Table::from((
+            HOST_OUTPUT_TYPE.to_vec(),
+            vec![
+                Row::new(vec![Value::from("meadow"), Value::from("ssh-ed25519 AAAA")]),
+                // Hashed entries have no hostname
+                Row::new(vec![Value::from(""), Value::from("ssh-ed25519 BBBB")]),
+                Row::new(vec![Value::from("burrow"), Value::from("ssh-ed25519 CCCC")]),
+            ],
+        ));
+        assert_eq!(
+            host_list(vec![Value::Table(table)]).unwrap(),
+            vec!["meadow", "burrow"]
+        );
+    }
+
+    #[test]
+    fn hosts_of_invalid_type() {
+        assert!(host_list(vec![Value::Integer(22)]).is_err());
+        let table = Table::from((
+            vec![ColumnType::new("name", ValueType::String)],
+            vec![Row::new(vec![Value::from("meadow")])],
+        ));
+        assert!(host_list(vec![Value::Table(table)]).is_err());
+    }
+
+    /// Run the policy on a sequence of hosts that succeed or fail, and return their statuses.
+    fn statuses(policy: &FailurePolicy, outcomes: &[bool]) -> Vec<String> {
+        outcomes
+            .iter()
+            .enumerate()
+            .map(|(idx, ok)| {
+                let row = policy.run(format!("host{}", idx), |_| match ok {
+                    true => Ok(Value::Empty),
+                    false => error("Connection refused"),
+                });
+                match &row.cells()[1] {
+                    Value::String(status) => status.to_string(),
+                    _ => panic!("Expected the status to be a string"),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn failures_without_policy_are_not_cancelled() {
+        let policy = FailurePolicy::new(None, false).unwrap();
+        assert_eq!(
+            statuses(&policy, &[false, false, true]),
+            vec!["failed", "failed", "ok"]
+        );
+    }
+
+    #[test]
+    fn fail_fast_cancels_after_first_failure() {
+        let policy = FailurePolicy::new(None, true).unwrap();
+        assert_eq!(
+            statuses(&policy, &[true, false, true, true]),
+            vec!["ok", "failed", "cancelled", "cancelled"]
+        );
+    }
+
+    #[test]
+    fn max_failures_cancels_after_that_many_failures() {
+        // max_failures takes precedence over fail_fast
+        let policy = FailurePolicy::new(Some(2), true).unwrap();
+        assert_eq!(
+            statuses(&policy, &[false, true, false, true]),
+            vec!["failed", "ok", "failed", "cancelled"]
+        );
+        assert!(FailurePolicy::new(Some(0), false).is_err());
+    }
+
+    #[test]
+    fn cancelled_hosts_are_not_run() {
+        let policy = FailurePolicy::new(Some(1), false).unwrap();
+        statuses(&policy, &[false]);
+        policy.run("meadow".to_string(), |_| panic!("A cancelled host was run"));
+    }
+
+    /**
+    A local sshd with its own host key and a client key in a dedicated ssh-agent, so that tests can
+    connect to it the same way commands connect to real hosts. The processes and the directory with
+    the keys are removed when it is dropped.
+     */
+    struct StandIn {
+        dir: PathBuf,
+        port: u16,
+        sshd: Child,
+        agent: Child,
+    }
+
+    impl StandIn {
+        /// Start the stand-in, or return `None` if there is no sshd on this system.
+        fn start() -> Option<StandIn> {
+            let sshd = ["/usr/sbin/sshd", "/usr/bin/sshd", "/usr/local/sbin/sshd"]
+                .into_iter()
+                .map(PathBuf::from)
+                .find(|p| p.exists())?;
+            let dir =
+                std::env::temp_dir().join(format!("crush-sshd-{:016x}", rand::random::<u64>()));
+            fs::create_dir(&dir).unwrap();
+            for name in ["host_key", "client_key"] {
+                let status = std::process::Command::new("ssh-keygen")
+                    .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+                    .arg(dir.join(name))
+                    .status()
+                    .unwrap();
+                assert!(status.success());
+            }
+            fs::copy(dir.join("client_key.pub"), dir.join("authorized_keys")).unwrap();
+            let port = TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap()
+                .port();
+            fs::write(
+                dir.join("sshd_config"),
+                format!(
+                    "ListenAddress 127.0.0.1:{}\n\
+                     HostKey {}\n\
+                     AuthorizedKeysFile {}\n\
+                     PidFile {}\n\
+                     StrictModes no\n\
+                     PasswordAuthentication no\n\
+                     Subsystem sftp internal-sftp\n",
+                    port,
+                    dir.join("host_key").display(),
+                    dir.join("authorized_keys").display(),
+                    dir.join("sshd.pid").display(),
+                ),
+            )
+            .unwrap();
+            let sshd = std::process::Command::new(sshd)
+                .arg("-D")
+                .arg("-f")
+                .arg(dir.join("sshd_config"))
+                .stderr(Stdio::null())
+                .spawn()
+                .unwrap();
+
+            let agent_socket = dir.join("agent.sock");
+            let agent = std::process::Command::new("ssh-agent")
+                .arg("-D")
+                .arg("-a")
+                .arg(&agent_socket)
+                .stdout(Stdio::null())
+                .spawn()
+                .unwrap();
+            let stand_in = StandIn {
+                dir,
+                port,
+                sshd,
+                agent,
+            };
+            stand_in.wait_for(|| agent_socket.exists());
+            let status = std::process::Command::new("ssh-add")
+                .arg("-q")
+                .arg(stand_in.dir.join("client_key"))
+                .env("SSH_AUTH_SOCK", &agent_socket)
+                .status()
+                .unwrap();
+            assert!(status.success());
+            // SAFETY: The environment functions of the standard library are synchronized with
+            // this. The only code in the tests reading it otherwise is the agent support of
+            // libssh2, which is only used by this test.
+            unsafe { std::env::set_var("SSH_AUTH_SOCK", &agent_socket) };
+            stand_in.wait_for(|| TcpStream::connect(("127.0.0.1", port)).is_ok());
+            Some(stand_in)
+        }
+
+        fn wait_for(&self, ready: impl Fn() -> bool) {
+            let start = Instant::now();
+            while !ready() {
+                assert!(
+                    start.elapsed() < Duration::from_secs(10),
+                    "The sshd stand-in did not start"
+                );
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        }
+
+        fn host(&self) -> String {
+            format!("127.0.0.1:{}", self.port)
+        }
+
+        fn options(&self, ignore_host_file: bool) -> ConnectOptions {
+            ConnectOptions {
+                username: Some(get_current_username().unwrap().to_string()),
+                password: None,
+                host_file: self.dir.join("known_hosts"),
+                ignore_host_file,
+                allow_not_found: true,
+            }
+        }
+    }
+
+    impl Drop for StandIn {
+        fn drop(&mut self) {
+            let _ = self.sshd.kill();
+            let _ = self.agent.kill();
+            let _ = self.sshd.wait();
+            let _ = self.agent.wait();
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// Copy a file over SFTP, and return the final progress row.
+    fn sftp_transfer(source: &mut dyn Read, destination: &mut dyn Write, size: u64) -> Row {
+        let (output, mut input) = streams(TRANSFER_OUTPUT_TYPE.to_vec());
+        transfer(
+            source,
+            destination,
+            ("source".to_string(), "destination".to_string()),
+            size,
+            &output,
+        )
+        .unwrap();
+        drop(output);
+        let mut last = None;
+        while let Ok(row) = input.read() {
+            last = Some(row);
+        }
+        last.unwrap()
+    }
+
+    #[test]
+    fn copy_and_fetch_through_sshd() {
+        let Some(stand_in) = StandIn::start() else {
+            eprintln!("No sshd found, skipping");
+            return;
+        };
+        let content = b"Hello, meadow!\n".repeat(10000);
+        let remote_file = stand_in.dir.join("remote.txt");
+
+        let (session, sftp) =
+            connect(stand_in.host(), &stand_in.options(false), |s| s.sftp()).unwrap();
+        let mut remote = sftp.create(&remote_file).unwrap();
+        let row = sftp_transfer(&mut content.as_slice(), &mut remote, content.len() as u64);
+        assert!(row.cells()[2] == Value::Integer(content.len() as i128));
+        drop(remote);
+        drop(sftp);
+        session.release();
+        assert_eq!(fs::read(&remote_file).unwrap(), content);
+        // The host was added to the known hosts file on first use
+        assert!(
+            !fs::read(stand_in.dir.join("known_hosts"))
+                .unwrap()
+                .is_empty()
+        );
+
+        let (session, sftp) =
+            connect(stand_in.host(), &stand_in.options(false), |s| s.sftp()).unwrap();
+        let mut fetched = Vec::new();
+        sftp_transfer(
+            &mut sftp.open(&remote_file).unwrap(),
+            &mut fetched,
+            content.len() as u64,
+        );
+        assert_eq!(fetched, content);
+        drop(sftp);
+        let key = session.key.clone();
+        session.release();
+
+        // The released session is reused by later commands using the same known hosts file, but
+        // not by ones that skip the host check
+        let (session, _) = connect(stand_in.host(), &stand_in.options(true), |s| s.sftp()).unwrap();
+        assert_eq!(session.key.host_file, None);
+        assert!(checkout(&key).is_some());
+    }
+}
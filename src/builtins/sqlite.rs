@@ -0,0 +1,328 @@
+use crate::lang::command::OutputType::{Known, Unknown};
+use crate::lang::data::table::{ColumnType, Row};
+use crate::lang::errors::{CrushResult, command_error, data_error};
+use crate::lang::ordered_string_map::OrderedStringMap;
+use crate::lang::state::contexts::CommandContext;
+use crate::lang::state::scope::Scope;
+use crate::lang::value::{Value, ValueType};
+use crate::util::time::parse_time;
+use chrono::{DateTime, Duration, Local};
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, Statement};
+use signature::signature;
+use std::path::PathBuf;
+
+/// Map the declared type of a column to a Crush type, using the same rules that SQLite uses to
+/// determine column affinity. Since any column may contain `NULL`, all columns may be empty.
+fn column_type(declared: Option<&str>) -> ValueType {
+    let declared = match declared {
+        Some(declared) => declared.to_uppercase(),
+        None => return ValueType::Any,
+    };
+    let has = |s: &str| declared.contains(s);
+    let cell_type = if has("BOOL") {
+        ValueType::Bool
+    } else if has("DATE") || has("TIME") {
+        ValueType::Time
+    } else if has("DURATION") || has("INTERVAL") {
+        ValueType::Duration
+    } else if has("INT") {
+        ValueType::Integer
+    } else if has("CHAR") || has("CLOB") || has("TEXT") {
+        ValueType::String
+    } else if has("BLOB") {
+        ValueType::Binary
+    } else if has("REAL") || has("FLOA") || has("DOUB") {
+        ValueType::Float
+    } else {
+        return ValueType::Any;
+    };
+    ValueType::OneOf(vec![cell_type, ValueType::Empty])
+}
+
+/// The declared type to use for a column of the specified Crush type when creating a table.
+fn declared_type(cell_type: &ValueType) -> &'static str {
+    match cell_type {
+        ValueType::Bool => "BOOLEAN",
+        ValueType::Time => "TIMESTAMP",
+        ValueType::Duration => "DURATION",
+        ValueType::Integer => "INTEGER",
+        ValueType::String | ValueType::File => "TEXT",
+        ValueType::Binary => "BLOB",
+        ValueType::Float => "REAL",
+        ValueType::OneOf(types) if types.len() == 2 && types[1] == ValueType::Empty => {
+            declared_type(&types[0])
+        }
+        _ => "",
+    }
+}
+
+/// Convert a value returned by SQLite to the type of its column. Since SQLite allows storing values
+/// of any storage class in any column, values that can't be converted are an error rather than
+/// being passed through as another type than the one declared for the column.
+fn to_value(value: ValueRef, column: &ColumnType) -> CrushResult<Value> {
+    let cell_type = match &column.cell_type {
+        ValueType::OneOf(types) if !types.is_empty() => &types[0],
+        cell_type => cell_type,
+    };
+    Ok(match (value, cell_type) {
+        (ValueRef::Null, _) => Value::Empty,
+        (ValueRef::Integer(i), ValueType::Bool) => Value::Bool(i != 0),
+        (ValueRef::Integer(i), ValueType::Time) => match DateTime::from_timestamp(i, 0) {
+            Some(t) => Value::Time(t.with_timezone(&Local)),
+            None => return data_error(format!("Invalid timestamp `{}`.", i)),
+        },
+        (ValueRef::Text(t), ValueType::Time) => match parse_time(std::str::from_utf8(t)?) {
+            Some(t) => Value::Time(t),
+            None => return conversion_error(value, column.name(), cell_type),
+        },
+        (ValueRef::Integer(i), ValueType::Duration) => Value::Duration(Duration::nanoseconds(i)),
+        (ValueRef::Integer(i), ValueType::Integer | ValueType::Any) => Value::Integer(i as i128),
+        (ValueRef::Integer(i), ValueType::Float) => Value::Float(i as f64),
+        (ValueRef::Integer(i), ValueType::String) => Value::from(i.to_string()),
+        (ValueRef::Real(f), ValueType::Float | ValueType::Any) => Value::Float(f),
+        (ValueRef::Real(f), ValueType::String) => Value::from(f.to_string()),
+        (ValueRef::Text(t), ValueType::String | ValueType::Any) => {
+            Value::from(std::str::from_utf8(t)?)
+        }
+        (ValueRef::Text(t), ValueType::Binary) => Value::from(t),
+        (ValueRef::Blob(b), ValueType::Binary | ValueType::Any) => Value::from(b),
+        _ => return conversion_error(value, column.name(), cell_type),
+    })
+}
+
+fn conversion_error<T>(value: ValueRef, column: &str, cell_type: &ValueType) -> CrushResult<T> {
+    let description = match value {
+        ValueRef::Text(t) => format!("text value `{}`", String::from_utf8_lossy(t)),
+        ValueRef::Integer(i) => format!("integer value `{}`", i),
+        ValueRef::Real(f) => format!("real value `{}`", f),
+        ValueRef::Blob(b) => format!("blob of {} bytes", b.len()),
+        ValueRef::Null => "null value".to_string(),
+    };
+    data_error(format!(
+        "Can't convert the {} in column `{}` to type `{}`.",
+        description, column, cell_type
+    ))
+}
+
+fn to_sql(value: Value) -> CrushResult<rusqlite::types::Value> {
+    use rusqlite::types::Value as Sql;
+    Ok(match value {
+        Value::Empty => Sql::Null,
+        Value::Bool(b) => Sql::Integer(b as i64),
+        Value::Integer(i) => Sql::Integer(i64::try_from(i)?),
+        Value::Float(f) => Sql::Real(f),
+        Value::String(s) => Sql::Text(s.to_string()),
+        Value::File(f) => Sql::Text(f.to_string_lossy().to_string()),
+        Value::Time(t) => Sql::Text(t.to_rfc3339()),
+        Value::Duration(d) => match d.num_nanoseconds() {
+            Some(nanoseconds) => Sql::Integer(nanoseconds),
+            None => return data_error("Duration is too long to be stored in SQLite."),
+        },
+        Value::Binary(b) => Sql::Blob(b.to_vec()),
+        v => {
+            return data_error(format!(
+                "Values of type `{}` can't be stored in SQLite.",
+                v.value_type()
+            ));
+        }
+    })
+}
+
+/// Bind positional parameters (`?`) and named parameters (`:name`, `@name` or `$name`).
+fn bind(
+    statement: &mut Statement,
+    parameters: Vec<Value>,
+    named: OrderedStringMap<Value>,
+) -> CrushResult<()> {
+    if parameters.len() > statement.parameter_count() {
+        return command_error(format!(
+            "Too many parameters. Expected at most {}, got {}.",
+            statement.parameter_count(),
+            parameters.len()
+        ));
+    }
+    for (idx, value) in parameters.into_iter().enumerate() {
+        statement.raw_bind_parameter(idx + 1, to_sql(value)?)?;
+    }
+    for (name, value) in named {
+        let mut idx = None;
+        for prefix in [":", "@", "$"] {
+            idx = idx.or(statement.parameter_index(&format!("{}{}", prefix, name))?);
+        }
+        match idx {
+            Some(idx) => statement.raw_bind_parameter(idx, to_sql(value)?)?,
+            None => return command_error(format!("Unknown parameter `{}`.", name)),
+        }
+    }
+    Ok(())
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+#[signature(
+    sqlite.query,
+    can_block = true,
+    output = Unknown,
+    short = "Run a query against an SQLite database",
+    long = "The result set is returned as a table stream. The type of each column is derived from its declared type in the same way SQLite derives column affinity, e.g. a column declared as `VARCHAR(20)` is a string. Columns declared as `BOOLEAN`, `TIMESTAMP` or `DURATION` contain Crush booleans, times and durations, where durations are stored as a number of nanoseconds. Computed columns are of type `$any`. `NULL` values are returned as `$empty`. A value that can't be converted to the type of its column, like text in an `INTEGER` column, is an error.",
+    long = "",
+    long = "Values are bound to parameters in the query using unnamed arguments for positional parameters (`?`) and named arguments for named parameters (`:name`).",
+    example = "# Find large pets",
+    example = "sqlite:query ./pets.db \"SELECT name, weight FROM pets WHERE weight > ?\" 10",
+    example = "# The same query using a named parameter",
+    example = "sqlite:query ./pets.db \"SELECT name, weight FROM pets WHERE weight > :weight\" weight=10",
+)]
+struct Query {
+    #[description("the database file.")]
+    database: PathBuf,
+    #[description("the query to run.")]
+    query: String,
+    #[named()]
+    #[description("named parameters of the query.")]
+    named: OrderedStringMap<Value>,
+    #[unnamed()]
+    #[description("positional parameters of the query.")]
+    parameters: Vec<Value>,
+}
+
+fn query(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: Query = Query::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let connection = Connection::open(&cfg.database)?;
+    let mut statement = connection.prepare(&cfg.query)?;
+    let columns = statement
+        .columns()
+        .iter()
+        .map(|c| ColumnType::new_from_string(c.name().to_string(), column_type(c.decl_type())))
+        .collect::<Vec<_>>();
+    bind(&mut statement, cfg.parameters, cfg.named)?;
+
+    let output = context.output.initialize(&columns)?;
+    let mut rows = statement.raw_query();
+    while let Some(row) = rows.next()? {
+        output.send(Row::new(
+            columns
+                .iter()
+                .enumerate()
+                .map(|(idx, column)| to_value(row.get_ref(idx)?, column))
+                .collect::<CrushResult<Vec<_>>>()?,
+        ))?;
+    }
+    Ok(())
+}
+
+#[signature(
+    sqlite.execute,
+    can_block = true,
+    output = Known(ValueType::Integer),
+    short = "Execute a statement that returns no data against an SQLite database",
+    long = "Returns the number of rows that were changed, inserted or deleted. Parameters are bound in the same way as for `sqlite:query`.",
+    example = "sqlite:execute ./pets.db \"CREATE TABLE pets (name TEXT, weight REAL)\"",
+    example = "sqlite:execute ./pets.db \"DELETE FROM pets WHERE name = ?\" Fido",
+)]
+struct Execute {
+    #[description("the database file.")]
+    database: PathBuf,
+    #[description("the statement to execute.")]
+    statement: String,
+    #[named()]
+    #[description("named parameters of the statement.")]
+    named: OrderedStringMap<Value>,
+    #[unnamed()]
+    #[description("positional parameters of the statement.")]
+    parameters: Vec<Value>,
+}
+
+fn execute(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: Execute = Execute::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let connection = Connection::open(&cfg.database)?;
+    let mut statement = connection.prepare(&cfg.statement)?;
+    bind(&mut statement, cfg.parameters, cfg.named)?;
+    let changed = statement.raw_execute()?;
+    context.output.send(Value::from(changed))
+}
+
+#[signature(
+    sqlite.insert,
+    can_block = true,
+    output = Known(ValueType::Integer),
+    short = "Insert all rows of the input into an SQLite table",
+    long = "The input must be a table or a table stream. Every column of the input is inserted into the column of the table with the same name. If the table does not exist, it is created using the column names and types of the input. All rows are inserted in a single transaction.",
+    long = "",
+    long = "Returns the number of inserted rows.",
+    example = "csv:from ./pets.csv | sqlite:insert ./pets.db pets",
+)]
+struct Insert {
+    #[description("the database file.")]
+    database: PathBuf,
+    #[description("the table to insert rows into.")]
+    table: String,
+}
+
+fn insert(mut context: CommandContext) -> CrushResult<()> {
+    let cfg: Insert = Insert::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let mut input = match context.input.recv()? {
+        value @ (Value::Table(_) | Value::TableInputStream(_)) => value.stream()?,
+        value => {
+            return command_error(format!(
+                "Expected a table or a table stream, got a value of type `{}`.",
+                value.value_type()
+            ));
+        }
+    };
+    let types = input.types().to_vec();
+
+    let mut connection = Connection::open(&cfg.database)?;
+    let transaction = connection.transaction()?;
+    transaction.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {} ({})",
+            quote(&cfg.table),
+            types
+                .iter()
+                .map(|c| format!("{} {}", quote(c.name()), declared_type(&c.cell_type)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        [],
+    )?;
+
+    let mut count = 0;
+    {
+        let mut statement = transaction.prepare(&format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            quote(&cfg.table),
+            types
+                .iter()
+                .map(|c| quote(c.name()))
+                .collect::<Vec<_>>()
+                .join(", "),
+            vec!["?"; types.len()].join(", "),
+        ))?;
+        while let Ok(row) = input.read() {
+            for (idx, value) in row.into_cells().into_iter().enumerate() {
+                statement.raw_bind_parameter(idx + 1, to_sql(value)?)?;
+            }
+            statement.raw_execute()?;
+            count += 1;
+        }
+    }
+    transaction.commit()?;
+    context.output.send(Value::from(count))
+}
+
+pub fn declare(root: &Scope) -> CrushResult<()> {
+    root.create_namespace(
+        "sqlite",
+        "Query and update SQLite databases",
+        Box::new(move |sqlite| {
+            Query::declare(sqlite)?;
+            Execute::declare(sqlite)?;
+            Insert::declare(sqlite)?;
+            Ok(())
+        }),
+    )?;
+    Ok(())
+}
@@ -1,124 +1,452 @@
+use crate::lang::argument::Argument;
 use crate::lang::command::OutputType::Unknown;
 use crate::lang::data::table::ColumnType;
 use crate::lang::data::table::ColumnVec;
 use crate::lang::data::table::Row;
-use crate::lang::errors::CrushError;
 use crate::lang::errors::CrushResult;
-use crate::lang::errors::command_error;
-use crate::lang::ordered_string_map::OrderedStringMap;
+use crate::lang::errors::{command_error, data_error};
 use crate::lang::pipe::Stream;
 use crate::lang::pipe::TableOutputStream;
-use crate::lang::printer::Printer;
-use crate::lang::state::argument_vector::ArgumentVector;
 use crate::lang::state::contexts::CommandContext;
-use crate::lang::value::Value;
+use crate::lang::value::{Value, ValueType};
 use ordered_map::{Entry, OrderedMap};
 use signature::signature;
+use std::cmp::Ordering;
 use std::collections::HashSet;
 
-fn combine(l: &Row, r: &Row, right_idx: usize) -> Row {
-    let mut l = l.clone();
-    for (idx, c) in r.cells().iter().enumerate() {
-        if idx != right_idx {
-            l.push(c.clone());
+#[signature(
+    stream.join,
+    output = Unknown,
+    short = "Join two streams together on the specified keys.",
+    long = "By default, an inner join is performed, meaning that only rows that have a matching row in the other stream are emitted. Left, right and outer joins also emit the rows of the left stream, the right stream or both streams that have no match in the other stream. The missing cells of such rows are empty.",
+    long = "The output contains all columns of the left stream followed by all non-key columns of the right stream. If a column of the right stream has the same name as a column in the left stream, it is renamed using the specified prefix and/or suffix.",
+    long = "Unless the `sorted` flag is given, the left stream is read into memory before joining. If both streams are already sorted on the join keys, use the `sorted` flag to perform a sort-merge join that only keeps the rows of a single key in memory.",
+    long = "As a shorthand, `join key1=stream1 key2=stream2` joins the two streams on a single column each. A key column may share its name with a parameter of join, like `kind` or `key`, as long as its value is a table or a table stream. Key columns named `left` or `right` can't be told apart from the streams, so join on them using `left_key` and `right_key`.",
+    example = "# Join files with the users that own them",
+    example = "join user=(files) name=(user:list)",
+    example = "# Find every user, together with the files they own, if any",
+    example = "join left=(user:list) right=(files) left_key=name right_key=user kind=left",
+    example = "# Join two already sorted streams on two columns without buffering either of them",
+    example = "join left=$a right=$b key=year key=month sorted=$true",
+)]
+pub struct Join {
+    #[description("the left stream.")]
+    left: Option<Stream>,
+    #[description("the right stream.")]
+    right: Option<Stream>,
+    #[description(
+        "columns to join on, for when the key columns have the same names in both streams."
+    )]
+    key: Vec<String>,
+    #[description("columns of the left stream to join on.")]
+    left_key: Vec<String>,
+    #[description("columns of the right stream to join on.")]
+    right_key: Vec<String>,
+    #[description("the kind of join to perform.")]
+    #[values("inner", "left", "right", "outer")]
+    #[default("inner")]
+    kind: String,
+    #[description("both streams are sorted on the join keys, perform a sort-merge join.")]
+    #[default(false)]
+    sorted: bool,
+    #[description(
+        "prefix to add to the names of right stream columns that collide with left stream columns."
+    )]
+    prefix: Option<String>,
+    #[description(
+        "suffix to add to the names of right stream columns that collide with left stream columns."
+    )]
+    suffix: Option<String>,
+}
+
+const PARAMETERS: [&str; 9] = [
+    "left",
+    "right",
+    "key",
+    "left_key",
+    "right_key",
+    "kind",
+    "sorted",
+    "prefix",
+    "suffix",
+];
+
+struct Joiner {
+    left_idx: Vec<usize>,
+    right_idx: Vec<usize>,
+    left_len: usize,
+    right_len: usize,
+    keep_left: bool,
+    keep_right: bool,
+}
+
+impl Joiner {
+    fn left_key(&self, row: &Row) -> Vec<Value> {
+        self.left_idx
+            .iter()
+            .map(|i| row.cells()[*i].clone())
+            .collect()
+    }
+
+    fn right_key(&self, row: &Row) -> Vec<Value> {
+        self.right_idx
+            .iter()
+            .map(|i| row.cells()[*i].clone())
+            .collect()
+    }
+
+    /// Combine a row from each stream into an output row. If the left row is missing, the key
+    /// columns of the left side are populated from the right row, so that every output row
+    /// carries its key.
+    fn combine(&self, l: Option<&Row>, r: Option<&Row>) -> Row {
+        let mut cells = match l {
+            Some(l) => l.cells().clone(),
+            None => {
+                let mut cells = vec![Value::Empty; self.left_len];
+                if let Some(r) = r {
+                    for (li, ri) in self.left_idx.iter().zip(self.right_idx.iter()) {
+                        cells[*li] = r.cells()[*ri].clone();
+                    }
+                }
+                cells
+            }
+        };
+        for idx in 0..self.right_len {
+            if !self.right_idx.contains(&idx) {
+                cells.push(r.map(|r| r.cells()[idx].clone()).unwrap_or(Value::Empty));
+            }
         }
+        Row::new(cells)
+    }
+
+    fn hash_join(
+        &self,
+        mut l: Stream,
+        mut r: Stream,
+        output: &TableOutputStream,
+    ) -> CrushResult<()> {
+        let mut l_data: OrderedMap<Vec<Value>, (Vec<Row>, bool)> = OrderedMap::new();
+
+        // Read left table into memory
+        while let Ok(row) = l.read() {
+            match l_data.entry(self.left_key(&row)) {
+                Entry::Occupied(o) => o.into_mut().0.push(row),
+                Entry::Vacant(v) => v.insert((vec![row], false)),
+            }
+        }
+
+        // Read one row at a time of right table, and join on the left table.
+        while let Ok(r_row) = r.read() {
+            match l_data.entry(self.right_key(&r_row)) {
+                Entry::Occupied(o) => {
+                    let (l_rows, matched) = o.into_mut();
+                    *matched = true;
+                    for l_row in l_rows.iter() {
+                        output.send(self.combine(Some(l_row), Some(&r_row)))?;
+                    }
+                }
+                Entry::Vacant(_) => {
+                    if self.keep_right {
+                        output.send(self.combine(None, Some(&r_row)))?;
+                    }
+                }
+            }
+        }
+
+        if self.keep_left {
+            for (_, (l_rows, matched)) in l_data.iter() {
+                if !matched {
+                    for l_row in l_rows {
+                        output.send(self.combine(Some(l_row), None))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn merge_join(&self, l: Stream, r: Stream, output: &TableOutputStream) -> CrushResult<()> {
+        let mut l = SortedGroups::new(l, &self.left_idx);
+        let mut r = SortedGroups::new(r, &self.right_idx);
+        let mut l_group = l.next()?;
+        let mut r_group = r.next()?;
+
+        loop {
+            let ordering = match (&l_group, &r_group) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((l_key, _)), Some((r_key, _))) => match l_key.partial_cmp(r_key) {
+                    Some(ordering) => ordering,
+                    None => return data_error("Join keys can not be compared."),
+                },
+            };
+            match ordering {
+                Ordering::Less => {
+                    if self.keep_left {
+                        for l_row in &l_group.as_ref().unwrap().1 {
+                            output.send(self.combine(Some(l_row), None))?;
+                        }
+                    }
+                    l_group = l.next()?;
+                }
+                Ordering::Greater => {
+                    if self.keep_right {
+                        for r_row in &r_group.as_ref().unwrap().1 {
+                            output.send(self.combine(None, Some(r_row)))?;
+                        }
+                    }
+                    r_group = r.next()?;
+                }
+                Ordering::Equal => {
+                    for l_row in &l_group.as_ref().unwrap().1 {
+                        for r_row in &r_group.as_ref().unwrap().1 {
+                            output.send(self.combine(Some(l_row), Some(r_row)))?;
+                        }
+                    }
+                    l_group = l.next()?;
+                    r_group = r.next()?;
+                }
+            }
+        }
+        Ok(())
     }
-    l
 }
 
-fn do_join(
-    mut l: Stream,
-    left_idx: usize,
-    mut r: Stream,
-    right_idx: usize,
-    output: &TableOutputStream,
-    printer: &Printer,
-) -> CrushResult<()> {
-    let mut l_data: OrderedMap<Value, Vec<Row>> = OrderedMap::new();
-
-    // Read left table into memory
-    while let Ok(row) = l.read() {
-        match l_data.entry(row.cells()[left_idx].clone()) {
-            Entry::Occupied(o) => o.into_mut().push(row),
-            Entry::Vacant(v) => v.insert(vec![row]),
+/// Reads a stream that is sorted on the specified columns one group of rows with equal keys at
+/// a time.
+struct SortedGroups<'a> {
+    stream: Stream,
+    idx: &'a [usize],
+    next_row: Option<Row>,
+    previous_key: Option<Vec<Value>>,
+}
+
+impl<'a> SortedGroups<'a> {
+    fn new(mut stream: Stream, idx: &'a [usize]) -> SortedGroups<'a> {
+        let next_row = stream.read().ok();
+        SortedGroups {
+            stream,
+            idx,
+            next_row,
+            previous_key: None,
         }
     }
 
-    // Read one row at a time of right table, and join on the left table.
-    while let Ok(r_row) = r.read() {
-        l_data.get(&r_row.cells()[right_idx]).map(|l_rows| {
-            for l_row in l_rows {
-                printer.handle_error(output.send(combine(l_row, &r_row, right_idx)));
+    fn key(&self, row: &Row) -> Vec<Value> {
+        self.idx.iter().map(|i| row.cells()[*i].clone()).collect()
+    }
+
+    fn next(&mut self) -> CrushResult<Option<(Vec<Value>, Vec<Row>)>> {
+        let first = match self.next_row.take() {
+            None => return Ok(None),
+            Some(row) => row,
+        };
+        let key = self.key(&first);
+        if let Some(previous_key) = &self.previous_key
+            && previous_key.partial_cmp(&key) != Some(Ordering::Less)
+        {
+            return data_error("Input to sorted join is not sorted on the join keys.");
+        }
+        let mut rows = vec![first];
+        while let Ok(row) = self.stream.read() {
+            if self.key(&row) == key {
+                rows.push(row);
+            } else {
+                self.next_row = Some(row);
+                break;
             }
-        });
+        }
+        self.previous_key = Some(key.clone());
+        Ok(Some((key, rows)))
+    }
+}
+
+/// The type of a column that may be missing a value because the row had no match.
+fn nullable(cell_type: &ValueType) -> ValueType {
+    match cell_type {
+        ValueType::Any | ValueType::Empty => cell_type.clone(),
+        ValueType::OneOf(types) if types.contains(&ValueType::Empty) => cell_type.clone(),
+        ValueType::OneOf(types) => {
+            let mut types = types.clone();
+            types.push(ValueType::Empty);
+            ValueType::OneOf(types)
+        }
+        _ => ValueType::OneOf(vec![cell_type.clone(), ValueType::Empty]),
     }
-    Ok(())
 }
 
 fn get_output_type(
     left_type: &[ColumnType],
     right_type: &[ColumnType],
-    right_key_idx: usize,
-) -> Result<Vec<ColumnType>, CrushError> {
-    let seen = left_type.iter().map(|c| c.name()).collect::<HashSet<_>>();
-    let mut res = left_type.to_vec();
+    joiner: &Joiner,
+    prefix: &Option<String>,
+    suffix: &Option<String>,
+) -> CrushResult<Vec<ColumnType>> {
+    let mut seen = left_type
+        .iter()
+        .map(|c| c.name().to_string())
+        .collect::<HashSet<_>>();
+    let mut res = left_type
+        .iter()
+        .enumerate()
+        .map(|(idx, c)| {
+            if joiner.keep_right && !joiner.left_idx.contains(&idx) {
+                ColumnType::new_from_string(c.name().to_string(), nullable(&c.cell_type))
+            } else {
+                c.clone()
+            }
+        })
+        .collect::<Vec<_>>();
 
     for (idx, c) in right_type.iter().enumerate() {
+        if joiner.right_idx.contains(&idx) {
+            continue;
+        }
         let mut name = c.name().to_string();
-        let mut version = 1;
-        while seen.contains(name.as_str()) {
-            version += 1;
-            name = format!("{}_{}", c.name(), version);
+        if seen.contains(&name) {
+            if prefix.is_none() && suffix.is_none() {
+                return command_error(format!(
+                    "Both streams contain a column named `{}`. Specify a prefix or a suffix to rename it.",
+                    name
+                ));
+            }
+            name = format!(
+                "{}{}{}",
+                prefix.as_deref().unwrap_or(""),
+                name,
+                suffix.as_deref().unwrap_or("")
+            );
+            if seen.contains(&name) {
+                return command_error(format!(
+                    "Renamed column `{}` still collides with an existing column.",
+                    name
+                ));
+            }
         }
+        let cell_type = if joiner.keep_left {
+            nullable(&c.cell_type)
+        } else {
+            c.cell_type.clone()
+        };
+        res.push(ColumnType::new_from_string(name.clone(), cell_type));
+        seen.insert(name);
+    }
+    Ok(res)
+}
 
-        let column = ColumnType::new_from_string(name.to_string(), c.cell_type.clone());
+fn key_indices(types: &[ColumnType], keys: &[String]) -> CrushResult<Vec<usize>> {
+    keys.iter().map(|k| types.find(k)).collect()
+}
 
-        if idx != right_key_idx {
-            res.push(column);
-        }
+/// Parameters that take a stream, and so can't be told apart from a shorthand argument.
+const STREAM_PARAMETERS: [&str; 2] = ["left", "right"];
+
+/// Whether an argument with the specified name and value is a `column=stream` argument of the
+/// shorthand form. Names of parameters are only treated as columns if the parameter can't take a
+/// table, e.g. `kind=$stream`.
+fn is_shorthand(name: &str, value: &Value) -> bool {
+    if !PARAMETERS.contains(&name) {
+        return true;
     }
-    Ok(res)
+    matches!(value, Value::Table(_) | Value::TableInputStream(_))
+        && !STREAM_PARAMETERS.contains(&name)
 }
 
-#[signature(
-    stream.join,
-    output = Unknown,
-    short = "Join two streams together on the specified keys.",
-    example = "join user=(files) name=(user:list)")]
-#[allow(unused)]
-pub struct Join {
-    #[named()]
-    #[description("Fields to join")]
-    join: OrderedStringMap<Stream>,
+const STREAM_KEY_ERROR: &str = "The shorthand form of join can't join on a column named `left` or `right`. Use `left_key` and `right_key` instead.";
+
+/// The `column=stream` arguments of the shorthand form, and the remaining arguments.
+type Shorthand = (Vec<(String, Value)>, Vec<Argument>);
+
+/// Extract arguments of the form `column=stream`, used by the shorthand form of join.
+fn shorthand(arguments: Vec<Argument>) -> CrushResult<Shorthand> {
+    let mut res = Vec::new();
+    let mut rest = Vec::new();
+    for a in arguments {
+        match a.argument_type {
+            Some(name) if is_shorthand(&name, &a.value) => res.push((name, a.value)),
+            argument_type => rest.push(Argument { argument_type, ..a }),
+        }
+    }
+    for name in STREAM_PARAMETERS {
+        if rest
+            .iter()
+            .filter(|a| a.argument_type.as_deref() == Some(name))
+            .count()
+            > 1
+        {
+            return command_error(STREAM_KEY_ERROR);
+        }
+    }
+    Ok((res, rest))
 }
 
 pub fn join(mut context: CommandContext) -> CrushResult<()> {
-    context.arguments.check_len(2)?;
-    let l = context.arguments.remove(0);
-    let r = context.arguments.remove(0);
-    match (
-        l.argument_type,
-        l.value.stream()?,
-        r.argument_type,
-        r.value.stream()?,
-    ) {
-        (Some(left_name), left_stream, Some(right_name), right_stream) => {
-            let left_idx = left_stream.types().find(&left_name)?;
-            let right_idx = right_stream.types().find(&right_name)?;
-
-            let output_type =
-                get_output_type(left_stream.types(), right_stream.types(), right_idx)?;
-            let output = context.output.initialize(&output_type)?;
-
-            do_join(
-                left_stream,
-                left_idx,
-                right_stream,
-                right_idx,
-                &output,
-                &context.global_state.printer(),
-            )
+    let (mut short, arguments) = shorthand(context.remove_arguments())?;
+    let mut cfg = Join::parse(arguments, &context.global_state.printer())?;
+
+    match short.len() {
+        0 => {}
+        2 => {
+            if cfg.left.is_some() || cfg.right.is_some() {
+                return command_error(
+                    "Can't mix the shorthand form of join with explicit streams.",
+                );
+            }
+            let (right_key, right) = short.pop().unwrap();
+            let (left_key, left) = short.pop().unwrap();
+            cfg.left = Some(left.stream()?);
+            cfg.right = Some(right.stream()?);
+            cfg.left_key = vec![left_key];
+            cfg.right_key = vec![right_key];
         }
-        (_, _, _, _) => command_error("Invalid inputs for joins."),
+        1 if cfg.left.is_some() || cfg.right.is_some() => return command_error(STREAM_KEY_ERROR),
+        _ => return command_error("Invalid inputs for joins."),
+    }
+
+    let left_keys = if cfg.left_key.is_empty() {
+        &cfg.key
+    } else {
+        &cfg.left_key
+    };
+    let right_keys = if cfg.right_key.is_empty() {
+        &cfg.key
+    } else {
+        &cfg.right_key
+    };
+    if left_keys.is_empty() || right_keys.is_empty() {
+        return command_error("No join keys specified.");
+    }
+    if left_keys.len() != right_keys.len() {
+        return command_error("The left and right streams must have the same number of join keys.");
+    }
+
+    let (left, right) = match (cfg.left, cfg.right) {
+        (Some(left), Some(right)) => (left, right),
+        _ => return command_error("Both a left and a right stream must be specified."),
+    };
+
+    let joiner = Joiner {
+        left_idx: key_indices(left.types(), left_keys)?,
+        right_idx: key_indices(right.types(), right_keys)?,
+        left_len: left.types().len(),
+        right_len: right.types().len(),
+        keep_left: cfg.kind == "left" || cfg.kind == "outer",
+        keep_right: cfg.kind == "right" || cfg.kind == "outer",
+    };
+
+    let output_type = get_output_type(
+        left.types(),
+        right.types(),
+        &joiner,
+        &cfg.prefix,
+        &cfg.suffix,
+    )?;
+    let output = context.output.initialize(&output_type)?;
+
+    if cfg.sorted {
+        joiner.merge_join(left, right, &output)
+    } else {
+        joiner.hash_join(left, right, &output)
     }
 }
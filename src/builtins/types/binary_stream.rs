@@ -0,0 +1,114 @@
+use crate::builtins::types::table_input_stream::close_value;
+use crate::lang::any_str::AnyStr;
+use crate::lang::command::Command;
+use crate::lang::command::CrushCommand;
+use crate::lang::command::OutputType::Known;
+use crate::lang::data::binary::binary_pipe;
+use crate::lang::data::r#struct::Struct;
+use crate::lang::errors::{CrushResult, command_error};
+use crate::lang::state::contexts::CommandContext;
+use crate::lang::state::this::This;
+use crate::lang::value::Value;
+use crate::lang::value::ValueType;
+use ordered_map::OrderedMap;
+use signature::signature;
+use std::io::Write;
+use std::sync::OnceLock;
+
+pub fn methods() -> &'static OrderedMap<String, Command> {
+    static CELL: OnceLock<OrderedMap<String, Command>> = OnceLock::new();
+    CELL.get_or_init(|| {
+        let mut res: OrderedMap<String, Command> = OrderedMap::new();
+        Pipe::declare_method(&mut res);
+
+        res
+    })
+}
+
+pub fn write_value() -> &'static Value {
+    static CELL: OnceLock<Value> = OnceLock::new();
+    CELL.get_or_init(|| {
+        Value::Command(<dyn CrushCommand>::command(
+            write,
+            true,
+            &["global", "types", "binary_pipe", "write"],
+            "binary_pipe:write",
+            "Write sink for this pipe",
+            None::<AnyStr>,
+            Known(ValueType::Empty),
+            [],
+        ))
+    })
+}
+
+#[signature(
+    types.binary_stream.pipe,
+    can_block = false,
+    output = Known(ValueType::Struct),
+    short = "Returns a binary pipe consisting of a read end and a write end.",
+    long = "This is the binary counterpart of `table_input_stream:pipe`. The pipe object has the following members:",
+    long = "",
+    long = " * `pipe:write` write sink for this pipe. Put this method at the end of a pipeline that produces binary data, a binary stream or a string.",
+    long = " * `pipe:read` read source for this pipe. Put this method at the start of a pipeline that consumes binary data.",
+    long = " * `pipe:output` the write end of the pipe, a `binary_output_stream`. It can be passed as the output file to serializers like `json:to` or as an argument to `cmd`.",
+    long = " * `pipe:close` call this method once all readers and writers have been created in order to close the pipe.",
+    long = "",
+    long = "The `read` member is a `binary_stream`, and can likewise be passed as an input file to deserializers like `json:from` or as an argument to `cmd`. External commands see both ends of the pipe as a file under `/dev/fd`.",
+    long = "",
+    long = "A pipe can have arbitrarily many writers and readers. Each chunk of data written to the pipe is consumed by exactly one reader, so a pipe with more than one reader is mostly useful when the data consists of independent lines or records.",
+    long = "",
+    long = "In order for the consumer jobs to finish, all the writer jobs must end *and* the pipe:close method must be called.",
+    example = "# Create a pipe",
+    example = "$pipe := $(binary_stream:pipe)",
+    example = "# Create a job that writes base64 encoded data to the pipe",
+    example = "base64:to ./data.bin | pipe:write &",
+    example = "# Create a second job that reads from the pipe and checksums the data",
+    example = "$sum_job_handle := $(cmd sha1sum $pipe:read &)",
+    example = "# Close the pipe so that the second job can finish",
+    example = "pipe:close",
+    example = "# Put the sum job in the foreground",
+    example = "fg $sum_job_handle",
+)]
+struct Pipe {}
+
+fn pipe(mut context: CommandContext) -> CrushResult<()> {
+    match context.this.r#type()? {
+        ValueType::BinaryInputStream => {
+            let (output, input) = binary_pipe();
+            context.output.send(Value::Struct(Struct::new(
+                vec![
+                    ("read", Value::BinaryInputStream(input)),
+                    ("output", Value::BinaryOutputStream(output)),
+                    ("write", write_value().clone()),
+                    ("close", close_value().clone()),
+                ],
+                None,
+            )))
+        }
+        _ => command_error("Wrong type of argument: Expected a binary stream type."),
+    }
+}
+
+fn write(mut context: CommandContext) -> CrushResult<()> {
+    let pipe = context.this.r#struct()?;
+    match pipe.get("output") {
+        Some(Value::BinaryOutputStream(output_stream)) => {
+            let mut writer = output_stream.writer();
+            match context.input.recv()? {
+                Value::BinaryInputStream(mut input) => {
+                    std::io::copy(input.as_mut(), &mut writer)?;
+                }
+                Value::Binary(b) => writer.write_all(&b)?,
+                Value::String(s) => writer.write_all(s.as_bytes())?,
+                v => {
+                    return command_error(format!(
+                        "Expected a binary stream, binary data or a string, got a value of type `{}`.",
+                        v.value_type()
+                    ));
+                }
+            }
+            context.output.send(Value::Empty)
+        }
+        _ => command_error("Expected an output stream."),
+    }
+}
@@ -13,6 +13,7 @@ use crate::lang::{data::r#struct::Struct, value::Value};
 use signature::signature;
 
 pub mod binary;
+pub mod binary_stream;
 pub mod dict;
 pub mod duration;
 pub mod file;
@@ -315,6 +316,7 @@ pub fn declare(root: &Scope) -> CrushResult<()> {
             env.declare("scope", Value::Type(ValueType::Scope))?;
             env.declare("binary", Value::Type(ValueType::Binary))?;
             env.declare("binary_stream", Value::Type(ValueType::BinaryInputStream))?;
+            env.declare("binary_output_stream", Value::Type(ValueType::BinaryOutputStream))?;
             env.declare("empty", Value::Type(ValueType::Empty))?;
             env.declare("float", Value::Type(ValueType::Float))?;
             env.declare("integer", Value::Type(ValueType::Integer))?;
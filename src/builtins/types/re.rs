@@ -1,8 +1,14 @@
 use crate::lang::command::Command;
 use crate::lang::command::OutputType::Known;
 use crate::lang::command::OutputType::Passthrough;
+use crate::lang::command::OutputType::Unknown;
+use crate::lang::data::list::List;
+use crate::lang::data::r#struct::Struct;
 use crate::lang::data::table::find_string_columns;
-use crate::lang::errors::{CrushResult, command_error};
+use crate::lang::data::table::{ColumnType, Row};
+use crate::lang::errors::{CrushResult, command_error, data_error};
+use crate::lang::ordered_string_map::OrderedStringMap;
+use crate::lang::signature::binary_input::{BinaryInput, ToReader};
 use crate::lang::signature::text::Text;
 use crate::lang::state::contexts::CommandContext;
 use crate::lang::state::this::This;
@@ -11,6 +17,7 @@ use crate::lang::value::ValueType;
 use ordered_map::OrderedMap;
 use regex::Regex;
 use signature::signature;
+use std::io::{BufRead, BufReader};
 use std::sync::OnceLock;
 
 pub fn methods() -> &'static OrderedMap<String, Command> {
@@ -23,6 +30,9 @@ pub fn methods() -> &'static OrderedMap<String, Command> {
         New::declare_method(&mut res);
         Match::declare_method(&mut res);
         NotMatch::declare_method(&mut res);
+        Captures::declare_method(&mut res);
+        FindAll::declare_method(&mut res);
+        ParseSignature::declare_method(&mut res);
 
         res
     })
@@ -87,6 +97,171 @@ fn not_match(mut context: CommandContext) -> CrushResult<()> {
         .send(Value::Bool(!re.is_match(&cfg.needle.as_string())))
 }
 
+#[signature(
+    types.re.captures,
+    can_block = false,
+    output = Unknown,
+    short = "The capture groups of the first match of the regex in text.",
+    long = "If the regex has named capture groups, the output is a struct with one field per named group. Otherwise, the output is a list of all capture groups, not including the whole match. Groups that did not participate in the match are empty. If the regex does not match, the output is empty.",
+    example = "^((?P<key>\\w+)=(?P<value>\\w+)):captures \"answer=42\"",
+)]
+struct Captures {
+    #[description("the text to match against.")]
+    text: Text,
+}
+
+fn group(m: Option<regex::Match>) -> Value {
+    m.map(|m| Value::from(m.as_str())).unwrap_or(Value::Empty)
+}
+
+fn captures(mut context: CommandContext) -> CrushResult<()> {
+    let re = context.this.re()?.1;
+    let cfg: Captures =
+        Captures::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let text = cfg.text.as_string();
+    let Some(captures) = re.captures(&text) else {
+        return context.output.send(Value::Empty);
+    };
+    let names = re.capture_names().flatten().collect::<Vec<_>>();
+    context.output.send(if names.is_empty() {
+        List::new_without_type(captures.iter().skip(1).map(group).collect()).into()
+    } else {
+        Struct::new(
+            names
+                .into_iter()
+                .map(|name| (name, group(captures.name(name))))
+                .collect(),
+            None,
+        )
+        .into()
+    })
+}
+
+#[signature(
+    types.re.find_all,
+    can_block = false,
+    output = Known(ValueType::List(Box::from(ValueType::String))),
+    short = "All non-overlapping matches of the regex in text.",
+    example = "^(\\d+):find_all \"1 2 and 34\"",
+)]
+struct FindAll {
+    #[description("the text to search.")]
+    text: Text,
+}
+
+fn find_all(mut context: CommandContext) -> CrushResult<()> {
+    let re = context.this.re()?.1;
+    let cfg: FindAll = FindAll::parse(context.remove_arguments(), &context.global_state.printer())?;
+    let text = cfg.text.as_string();
+    context.output.send(
+        List::new(
+            ValueType::String,
+            re.find_iter(&text)
+                .map(|m| Value::from(m.as_str()))
+                .collect::<Vec<_>>(),
+        )
+        .into(),
+    )
+}
+
+#[signature(
+    types.re.parse,
+    can_block = true,
+    output = Known(ValueType::TableInputStream(vec![])),
+    short = "Parse every line of the specified files (or input) into a table using the named capture groups of the regex.",
+    long = "The output has one column per named capture group of the regex, in the order they appear in it. Columns are strings, unless a different type is specified for them as a named argument, in which case the text of the group is parsed as a value of that type. Groups that did not participate in a match are empty, so every column is declared as either its type or `$empty`.",
+    long = "",
+    long = "Lines that the regex does not match are skipped, unless `strict` is specified. In strict mode, a line where a named group did not participate in the match is also an error, so the columns are never empty.",
+    example = "# Parse the date, level and message of each line of a log file",
+    example = "^(^(?P<date>\\S+) (?P<level>[A-Z]+) (?P<message>.*)$):parse ./app.log date=$time",
+)]
+struct ParseSignature {
+    #[unnamed()]
+    #[description("the files to read from (read from input if no file is specified).")]
+    files: Vec<BinaryInput>,
+    #[named()]
+    #[description("the types of the columns. Columns without a type are strings.")]
+    types: OrderedStringMap<ValueType>,
+    #[default(false)]
+    #[description(
+        "fail on lines that the regex does not match, instead of skipping them, and on lines where a group did not participate in the match."
+    )]
+    strict: bool,
+}
+
+fn parse(mut context: CommandContext) -> CrushResult<()> {
+    let re = context.this.re()?.1;
+    let cfg: ParseSignature =
+        ParseSignature::parse(context.remove_arguments(), context.global_state.printer())?;
+    let names = re.capture_names().flatten().collect::<Vec<_>>();
+    if names.is_empty() {
+        return command_error("The regex has no named capture groups.");
+    }
+    if let Some(name) = cfg
+        .types
+        .keys()
+        .find(|name| !names.contains(&name.as_str()))
+    {
+        return command_error(format!("The regex has no capture group named `{}`.", name));
+    }
+    let types = names
+        .iter()
+        .map(|name| cfg.types.get(*name).cloned().unwrap_or(ValueType::String))
+        .collect::<Vec<_>>();
+    let columns = names
+        .iter()
+        .zip(&types)
+        .map(|(name, cell_type)| {
+            ColumnType::new_from_string(
+                name.to_string(),
+                if cfg.strict {
+                    cell_type.clone()
+                } else {
+                    ValueType::OneOf(vec![cell_type.clone(), ValueType::Empty])
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+    let output = context.output.initialize(&columns)?;
+
+    let reader = BufReader::new(cfg.files.to_reader(context.input)?);
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let Some(captures) = re.captures(&line) else {
+            if cfg.strict {
+                return data_error(format!("Line {} does not match the regex.", idx + 1));
+            }
+            continue;
+        };
+        let mut cells = Vec::with_capacity(columns.len());
+        for (column, cell_type) in columns.iter().zip(&types) {
+            cells.push(match captures.name(column.name()) {
+                Some(m) => match cell_type.parse(m.as_str()) {
+                    Ok(value) => value,
+                    Err(e) => {
+                        return data_error(format!(
+                            "Failed to parse the `{}` column on line {}: {}",
+                            column.name(),
+                            idx + 1,
+                            e.message()
+                        ));
+                    }
+                },
+                None if cfg.strict => {
+                    return data_error(format!(
+                        "Line {} has no match for the `{}` group.",
+                        idx + 1,
+                        column.name()
+                    ));
+                }
+                None => Value::Empty,
+            });
+        }
+        output.send(Row::new(cells))?;
+    }
+    Ok(())
+}
+
 #[signature(
     types.re.replace,
     can_block = false,
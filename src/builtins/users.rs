@@ -3,20 +3,27 @@ use crate::lang::command::OutputType::Known;
 use crate::lang::command::OutputType::Unknown;
 use crate::lang::command::{Command, CrushCommand};
 use crate::lang::data::r#struct::Struct;
-use crate::lang::errors::{CrushResult, command_error, error};
-use crate::lang::serialization::{deserialize, serialize};
+use crate::lang::errors::{CrushResult, command_error};
+use crate::lang::printer::Printer;
+use crate::lang::serialization::serialize;
+use crate::lang::serialization::stream::deserialize_stream;
 use crate::lang::state::contexts::CommandContext;
 use crate::lang::state::scope::Scope;
 use crate::lang::state::this::This;
 use crate::lang::value::{Value, ValueType};
 use crate::lang::{data::table::ColumnType, data::table::Row};
+use crate::util::file::cwd;
 use crate::util::logins;
 use crate::util::user_map::{get_all_users, get_current_username, get_user};
 use signature::signature;
-use std::io::{Read, Write};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
 use std::process;
 use std::process::Stdio;
-use std::sync::OnceLock;
+use std::sync::{Mutex, OnceLock};
 
 #[signature(
     users.me,
@@ -96,75 +103,167 @@ pub fn user_struct() -> &'static Struct {
     users.r#do,
     can_block = true,
     short = "Execute a lambda as another user.",
+    long = "The first time a closure is run as a given user, a helper process running as that user is started using sudo. If sudo asks for a password, the prompt is shown by Crush. The helper is reused for the rest of the session, so later invocations don't start any new processes or ask for a password again. It exits when Crush exits.",
+    long = "",
+    long = "The closure is run in the current working directory, and its output is streamed back as it is produced.",
+    example = "# Count the files in /root",
+    example = "users[root]:do {files /root} | count",
 )]
 pub struct Do {
     #[description("the command to run as another user.")]
     command: Command,
 }
 
+/// The prompt sudo is told to use, so that password prompts can be told apart from other errors.
+/// Sudo replaces `%p` with the name of the user whose password is requested.
+const PASSWORD_PROMPT: &str = "[crush:sudo] password for %p: ";
+
 /**
-Current implementation is crude and grossly inefficient.
+A privileged helper, a `crush --helper` process started through sudo that runs closures as
+another user. Helpers are started the first time a closure is run as a given user, and are reused
+for the rest of the session, so that the password only has to be entered once. The standard input
+of the helper is kept open until the session exits, which tells the helper to exit as well.
+
+The helper listens on a socket with a random name. Once it has started, it is sent a random token
+over its standard input, and every connection has to present that token before anything else.
+ */
+struct Helper {
+    process: process::Child,
+    socket: PathBuf,
+    token: String,
+}
 
-Firstly, it just shells out to the sudo command - which leads to potential visual problems with
-the terminal.
+fn helpers() -> &'static Mutex<HashMap<String, Helper>> {
+    static CELL: OnceLock<Mutex<HashMap<String, Helper>>> = OnceLock::new();
+    CELL.get_or_init(|| Mutex::new(HashMap::new()))
+}
 
-Secondly, it creates 3 separate subthreads just to deal with stdin, stdout and stderr without
-blocking while the main thread waits for the command to exit. It is easy to do this much more
-efficiently, but this was the most straight forward implementation and the sudo command should
-never be run in a loop regardless.
+/**
+Forward the error output of sudo to the printer, until the helper has started and closed it.
+Password prompts are answered by asking the user for the password through the printer, and
+writing it to the standard input of sudo. The standard input is returned, since the helper exits
+once it is closed.
  */
+fn authenticate(
+    mut stderr: process::ChildStderr,
+    mut stdin: process::ChildStdin,
+    printer: Printer,
+) -> CrushResult<process::ChildStdin> {
+    let prefix = PASSWORD_PROMPT.split('%').next().unwrap_or(PASSWORD_PROMPT);
+    let mut buf = [0u8; 1024];
+    let mut line = String::new();
+    loop {
+        let len = stderr.read(&mut buf)?;
+        if len == 0 {
+            return Ok(stdin);
+        }
+        line.push_str(&String::from_utf8_lossy(&buf[..len]));
+        // Sudo doesn't end the prompt with a newline, so check for it before splitting into lines
+        if line.starts_with(prefix) && line.ends_with(": ") {
+            let prompt = line.replacen("[crush:sudo]", "[sudo]", 1);
+            line.clear();
+            let password = printer.password(&prompt)?;
+            stdin.write_all(password.as_bytes())?;
+            stdin.write_all(b"\n")?;
+            stdin.flush()?;
+            continue;
+        }
+        while let Some(idx) = line.find('\n') {
+            let err = line[..idx].trim().to_string();
+            line.drain(..=idx);
+            if !err.is_empty() {
+                printer.error(&err);
+            }
+        }
+    }
+}
+
+fn start_helper(username: &str, context: &CommandContext) -> CrushResult<Helper> {
+    let socket = std::env::temp_dir().join(format!(
+        "crush-{}-{:016x}.sock",
+        username,
+        rand::random::<u64>()
+    ));
+    let token = format!("{:032x}", rand::random::<u128>());
+    let mut cmd = process::Command::new("sudo");
+    cmd.arg("--stdin").arg("--prompt").arg(PASSWORD_PROMPT);
+    cmd.arg("--user").arg(username);
+    cmd.arg("--").arg(std::env::current_exe()?);
+    cmd.arg("--helper").arg(&socket);
+    cmd.stdin(Stdio::piped());
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+
+    let mut child = cmd.spawn()?;
+    let stdin = child.stdin.take().ok_or("Expected stdin stream")?;
+    let stderr = child.stderr.take().ok_or("Expected error stream")?;
+    let printer = context.global_state.printer().clone();
+    let authentication = std::thread::Builder::new()
+        .name("sudo:stderr".to_string())
+        .spawn(move || authenticate(stderr, stdin, printer))?;
+
+    // The helper writes a line to its standard output once it accepts connections
+    let mut ready = String::new();
+    BufReader::new(child.stdout.take().ok_or("Expected output stream")?).read_line(&mut ready)?;
+    let stdin = authentication
+        .join()
+        .map_err(|_| "Failed to read the error output of sudo")?;
+    if ready.trim() != "ready" {
+        let _ = child.wait();
+        stdin?;
+        return command_error(format!(
+            "Failed to start a helper running as `{}`",
+            username
+        ));
+    }
+    let mut stdin = stdin?;
+    stdin.write_all(token.as_bytes())?;
+    stdin.write_all(b"\n")?;
+    stdin.flush()?;
+    child.stdin = Some(stdin);
+    Ok(Helper {
+        process: child,
+        socket,
+        token,
+    })
+}
+
+/// The socket and token of the helper running as the specified user, starting the helper if
+/// needed.
+fn helper(username: &str, context: &CommandContext) -> CrushResult<(PathBuf, String)> {
+    let mut helpers = helpers().lock().unwrap();
+    if let Some(helper) = helpers.get_mut(username)
+        && helper.process.try_wait()?.is_none()
+    {
+        return Ok((helper.socket.clone(), helper.token.clone()));
+    }
+    let helper = start_helper(username, context)?;
+    let res = (helper.socket.clone(), helper.token.clone());
+    helpers.insert(username.to_string(), helper);
+    Ok(res)
+}
+
 fn r#do(mut context: CommandContext) -> CrushResult<()> {
     let cfg: Do = Do::parse(context.remove_arguments(), &context.global_state.printer())?;
     let this = context.this.r#struct()?;
     if let Some(Value::String(username)) = this.get("username") {
-        let mut cmd = process::Command::new("sudo");
-        let printer = context.global_state.printer().clone();
-
-        cmd.arg("--user").arg(&username.to_string());
-        cmd.arg("--").arg("crush").arg("--pup");
-        cmd.stdin(Stdio::piped());
-        cmd.stdout(Stdio::piped());
-        cmd.stderr(Stdio::piped());
-
-        let mut child = cmd.spawn()?;
-        let mut stdin = child.stdin.take().ok_or("Expected stdin stream")?;
+        let (socket, token) = helper(&username, &context)?;
+        let mut stream = UnixStream::connect(socket)?;
+        // The closure is run in our working directory
+        let request = Struct::new(
+            vec![
+                ("directory", Value::from(cwd()?)),
+                ("command", Value::Command(cfg.command)),
+            ],
+            None,
+        );
         let mut serialized = Vec::new();
-        serialize(&Value::Command(cfg.command), &mut serialized)?;
-
-        context.spawn("sudo:stdin", move || {
-            stdin.write(&serialized)?;
-            Ok(())
-        })?;
-
-        let mut stdout = child.stdout.take().ok_or("Expected output stream")?;
-        let env = context.scope.clone();
-        let my_context = context.clone();
-        context.spawn("sudo:stdout", move || {
-            let mut buff = Vec::new();
-            stdout.read_to_end(&mut buff)?;
-            if buff.len() == 0 {
-                error("No value returned")
-            } else {
-                my_context.output.send(deserialize(&buff, &env)?)
-            }
-        })?;
-
-        let mut stderr = child.stderr.take().ok_or("Expected error stream")?;
-        context.spawn("sudo:stderr", move || {
-            let mut buff = Vec::new();
-            stderr.read_to_end(&mut buff)?;
-            let errors = String::from_utf8(buff)?;
-            for e in errors.split('\n') {
-                let err = e.trim();
-                if !err.is_empty() {
-                    printer.error(err);
-                }
-            }
-            Ok(())
-        })?;
-
-        child.wait()?;
-        Ok(())
+        serialize(&Value::Struct(request), &mut serialized)?;
+        stream.write_all(token.as_bytes())?;
+        stream.write_all(b"\n")?;
+        stream.write_all(&serialized)?;
+        stream.shutdown(Shutdown::Write)?;
+        deserialize_stream(&mut stream, &context.scope, &context.output)
     } else {
         command_error("Invalid user")
     }
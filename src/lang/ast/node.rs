@@ -1,6 +1,7 @@
 use super::location::Location;
 use super::node::TextLiteralStyle::{Quoted, Unquoted};
 use super::parameter_node::ParameterNode;
+use super::pattern_node::MatchArmNode;
 use super::tracked_string::TrackedString;
 use super::{CommandNode, JobListNode, JobNode, NodeContext, expand_user, propose_name};
 use crate::lang::argument::{ArgumentDefinition, SwitchStyle};
@@ -44,6 +45,11 @@ pub enum Node {
     GetAttr(Box<Node>, TrackedString),
     Substitution(JobListNode),
     Closure(Option<Vec<ParameterNode>>, JobListNode, Location),
+    /// An interpolated string. The parts are either quoted string nodes containing the text
+    /// between the embedded expressions, or the embedded expressions themselves.
+    Interpolation(Vec<Node>, Location),
+    /// A match statement. The arms are compiled into typed patterns, not into regular values.
+    Match(Box<Node>, Vec<MatchArmNode>, Location),
 }
 
 impl Node {
@@ -75,6 +81,41 @@ impl Node {
         ))
     }
 
+    /// An interpolated string is compiled into a call to `string:format`. The string fragments
+    /// become the format string and every embedded expression becomes an argument.
+    fn format_invocation(parts: &[Node], location: Location) -> Node {
+        let mut format = "\"".to_string();
+        let mut arguments = vec![];
+        for part in parts {
+            match part {
+                Node::String(fragment, Quoted) => {
+                    format += &fragment.string.replace('{', "{{").replace('}', "}}")
+                }
+                part => {
+                    format += "{}";
+                    arguments.push(part.clone());
+                }
+            }
+        }
+        format += "\"";
+
+        let mut cmd = vec![Node::GetAttr(
+            Node::quoted_string(TrackedString::new(&format, location)),
+            TrackedString::new("format", location),
+        )];
+        cmd.append(&mut arguments);
+        Node::Substitution(
+            JobNode {
+                commands: vec![CommandNode {
+                    expressions: cmd,
+                    location,
+                }],
+                location,
+            }
+            .into(),
+        )
+    }
+
     fn id(s: &str, l: Location) -> Box<Node> {
         Box::from(Node::Identifier(TrackedString::new(s, l)))
     }
@@ -109,6 +150,7 @@ impl Node {
             GetItem(a, b) => a.location().union(b.location()),
             GetAttr(p, n) => p.location().union(n.location),
             Substitution(j) => j.location,
+            Interpolation(_, l) | Match(_, _, l) => *l,
             Closure(_, _, l) => {
                 // Fixme: Can't tab complete or error report on parameters because they're not currently tracked
                 *l
@@ -139,6 +181,8 @@ impl Node {
             Node::GetAttr(_, _) => "member access",
             Node::Substitution(_) => "command substitution",
             Node::Closure(_, _, _) => "closure",
+            Node::Interpolation(_, _) => "interpolated string literal",
+            Node::Match(_, _, _) => "match statement",
         }
     }
 
@@ -232,6 +276,9 @@ impl Node {
             ),
 
             Node::Substitution(s) => ValueDefinition::JobListDefinition(s.compile(ctx)?),
+            Node::Interpolation(parts, location) => {
+                return Node::format_invocation(parts, *location).compile(ctx, is_command);
+            }
             Node::Closure(signature, jobs, location) => {
                 let param = signature.as_ref().map(|v| {
                     v.iter()
@@ -250,6 +297,14 @@ impl Node {
                     source: ctx.source.substring(*location),
                 }
             }
+            Node::Match(value, arms, location) => ValueDefinition::Match {
+                value: Box::from(value.compile_argument(ctx)?.unnamed_value()?),
+                arms: arms
+                    .iter()
+                    .map(|arm| arm.compile(ctx))
+                    .collect::<CrushResult<Vec<_>>>()?,
+                source: ctx.source.substring(*location),
+            },
             Node::Glob(g) => ValueDefinition::Value(
                 Value::Glob(Glob::new(&g.string)),
                 ctx.source.subtrackedstring(g),
@@ -374,6 +429,8 @@ impl Node {
             | Node::GetAttr(_, _)
             | Node::Substitution(_)
             | Node::Closure(_, _, _)
+            | Node::Interpolation(_, _)
+            | Node::Match(_, _, _)
             | Node::File(_, _) => Ok(None),
         }
     }
@@ -481,6 +538,41 @@ impl Node {
         ))
     }
 
+    pub fn try_expr(
+        try_location: Location,
+        body: JobListNode,
+        signature: Option<Vec<ParameterNode>>,
+        handler: JobListNode,
+    ) -> Box<Node> {
+        let location = try_location.union(handler.location);
+        let body_location = body.location;
+        let handler_location = handler.location;
+        Box::from(Node::Substitution(
+            JobNode {
+                commands: vec![CommandNode {
+                    expressions: vec![
+                        Self::get_attr(&["global", "control", "try"], try_location),
+                        Node::Closure(None, body, body_location),
+                        Node::String(TrackedString::new("catch", handler_location), Unquoted),
+                        Node::Closure(signature, handler, handler_location),
+                    ],
+                    location,
+                }],
+                location,
+            }
+            .into(),
+        ))
+    }
+
+    pub fn match_expr(
+        match_location: Location,
+        value: Box<Node>,
+        arms: Vec<MatchArmNode>,
+        end: Location,
+    ) -> Box<Node> {
+        Box::from(Node::Match(value, arms, match_location.union(end)))
+    }
+
     pub fn while_expr(
         while_location: Location,
         condition: Box<Node>,
@@ -623,6 +715,61 @@ impl From<Box<Node>> for JobNode {
     }
 }
 
+/// A shell-style redirection of the output or the input of a job.
+pub enum Redirection {
+    /// Write the output of the job to a file, `>` or `>>`.
+    Output { append: bool },
+    /// Feed a file into the input of the first command of the job, `<`.
+    Input,
+}
+
+impl JobNode {
+    /// Add a redirection to this job. Output redirection appends a `bin:to` command to the job
+    /// and input redirection prepends a `bin:from` command to it.
+    pub fn redirect(
+        mut self,
+        redirection: Redirection,
+        target: Box<Node>,
+        location: Location,
+    ) -> JobNode {
+        match redirection {
+            Redirection::Output { append } => {
+                let mut expressions = vec![
+                    Node::get_attr(&["global", "io", "bin", "to"], location),
+                    *target,
+                ];
+                if append {
+                    expressions.push(Node::Assignment {
+                        target: Box::from(Node::String(
+                            TrackedString::new("append", location),
+                            Unquoted,
+                        )),
+                        style: SwitchStyle::None,
+                        operation: "=".to_string(),
+                        value: Box::from(Node::Identifier(TrackedString::new("true", location))),
+                    });
+                }
+                self.commands.push(CommandNode {
+                    expressions,
+                    location,
+                });
+            }
+            Redirection::Input => self.commands.insert(
+                0,
+                CommandNode {
+                    expressions: vec![
+                        Node::get_attr(&["global", "io", "bin", "from"], location),
+                        *target,
+                    ],
+                    location,
+                },
+            ),
+        }
+        self.location = self.location.union(location);
+        self
+    }
+}
+
 impl From<CommandNode> for JobNode {
     fn from(value: CommandNode) -> Self {
         JobNode {
@@ -0,0 +1,114 @@
+use super::location::Location;
+use super::node::Node;
+use super::tracked_string::TrackedString;
+use super::{JobListNode, NodeContext};
+use crate::lang::errors::CrushResult;
+use crate::lang::value::{MatchArm, Pattern};
+
+/**
+    A pattern in a match statement. Patterns are not evaluated like other nodes. Instead, they are
+    compiled into a `Pattern`, which is tried against the matched value when the match statement
+    is evaluated.
+*/
+#[derive(Clone, Debug)]
+pub enum PatternNode {
+    /// `_`, matches anything.
+    Any(Location),
+    /// A literal or an expression like `$integer` or `$(...)`. Whether it matches depends on the
+    /// type of the value it evaluates to.
+    Value(Box<Node>),
+    /// A bare word, binds the matched value to a name. The pattern after the colon in
+    /// `name: pattern` must also match.
+    Bind(TrackedString, Box<PatternNode>),
+    /// `[a b @rest]`, matches a list element by element.
+    List {
+        items: Vec<PatternNode>,
+        rest: Option<Box<PatternNode>>,
+        location: Location,
+    },
+    /// `[key=pattern ...]`, matches the fields of a struct or the keys of a dict.
+    Record(Vec<(TrackedString, PatternNode)>, Location),
+}
+
+#[derive(Clone, Debug)]
+pub struct MatchArmNode {
+    pub pattern: PatternNode,
+    pub guard: Option<Box<Node>>,
+    pub body: JobListNode,
+    pub location: Location,
+}
+
+impl PatternNode {
+    /// An unquoted word in a pattern. `_` matches anything, any other word binds a name.
+    pub fn word(is: impl Into<TrackedString>) -> PatternNode {
+        let name = is.into();
+        if name.string == "_" {
+            PatternNode::Any(name.location)
+        } else {
+            let location = name.location;
+            PatternNode::Bind(name, Box::from(PatternNode::Any(location)))
+        }
+    }
+
+    pub fn location(&self) -> Location {
+        match self {
+            PatternNode::Any(l) => *l,
+            PatternNode::Value(v) => v.location(),
+            PatternNode::Bind(name, pattern) => name.location.union(pattern.location()),
+            PatternNode::List { location, .. } => *location,
+            PatternNode::Record(_, location) => *location,
+        }
+    }
+
+    /// Compile this pattern. Embedded values are compiled as arguments and evaluated when the
+    /// pattern is tried.
+    pub fn compile(&self, ctx: &NodeContext) -> CrushResult<Pattern> {
+        Ok(match self {
+            PatternNode::Any(_) => Pattern::Any,
+            PatternNode::Value(value) => {
+                Pattern::Value(value.compile_argument(ctx)?.unnamed_value()?)
+            }
+            PatternNode::Bind(name, pattern) => {
+                Pattern::Bind(name.string.clone(), Box::from(pattern.compile(ctx)?))
+            }
+            PatternNode::List { items, rest, .. } => Pattern::List {
+                items: items
+                    .iter()
+                    .map(|item| item.compile(ctx))
+                    .collect::<CrushResult<Vec<_>>>()?,
+                rest: match rest {
+                    Some(rest) => Some(Box::from(rest.compile(ctx)?)),
+                    None => None,
+                },
+            },
+            PatternNode::Record(fields, _) => Pattern::Record(
+                fields
+                    .iter()
+                    .map(|(name, pattern)| Ok((name.string.clone(), pattern.compile(ctx)?)))
+                    .collect::<CrushResult<Vec<_>>>()?,
+            ),
+        })
+    }
+}
+
+impl MatchArmNode {
+    /// Compile this arm. The guard and the body become closures, which are invoked with the
+    /// bindings of the pattern as arguments.
+    pub fn compile(&self, ctx: &NodeContext) -> CrushResult<MatchArm> {
+        let guard = match &self.guard {
+            Some(guard) => Some(
+                Node::Closure(None, JobListNode::from(guard.clone()), guard.location())
+                    .compile_argument(ctx)?
+                    .unnamed_value()?,
+            ),
+            None => None,
+        };
+        Ok(MatchArm {
+            pattern: self.pattern.compile(ctx)?,
+            guard,
+            body: Node::Closure(None, self.body.clone(), self.body.location)
+                .compile_argument(ctx)?
+                .unnamed_value()?,
+        })
+    }
+}
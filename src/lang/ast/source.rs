@@ -46,6 +46,19 @@ impl Source {
         }
     }
 
+    /// The file this source was read from, if any.
+    pub fn file(&self) -> Option<&PathBuf> {
+        match &self.source_type {
+            SourceType::Input => None,
+            SourceType::File(path) => Some(path),
+        }
+    }
+
+    /// The one-based line number of the start of this source.
+    pub fn line_number(&self) -> usize {
+        self.string[0..self.location.start].matches('\n').count() + 1
+    }
+
     pub fn start_line(&self) -> usize {
         self.string[0..self.location.start].lines().count()
     }
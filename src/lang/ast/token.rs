@@ -43,10 +43,19 @@ pub enum Token<'input> {
     Loop(Location),
     If(Location),
     Else(Location),
+    Try(Location),
+    Catch(Location),
+    Match(Location),
     Return(Location),
     Break(Location),
     Continue(Location),
     Background(Location),
+    RedirectOut(Location),
+    RedirectAppend(Location),
+    RedirectIn(Location),
+    InterpolationStart(Location),
+    StringFragment(&'input str, Location),
+    InterpolationEnd(Location),
 }
 
 impl Token<'_> {
@@ -89,11 +98,20 @@ impl Token<'_> {
             | Token::Loop(l)
             | Token::If(l)
             | Token::Else(l)
+            | Token::Try(l)
+            | Token::Catch(l)
+            | Token::Match(l)
             | Token::Return(l)
             | Token::Break(l)
             | Token::Continue(l)
             | Token::ExprModeStart(l)
-            | Token::Background(l) => *l,
+            | Token::Background(l)
+            | Token::RedirectOut(l)
+            | Token::RedirectAppend(l)
+            | Token::RedirectIn(l)
+            | Token::InterpolationStart(l)
+            | Token::StringFragment(_, l)
+            | Token::InterpolationEnd(l) => *l,
         }
     }
 
@@ -113,7 +131,8 @@ impl Token<'_> {
             | Token::Regex(s, _)
             | Token::Integer(s, _)
             | Token::Separator(s, _)
-            | Token::Float(s, _) => s,
+            | Token::Float(s, _)
+            | Token::StringFragment(s, _) => s,
             Token::MemberOperator(_) => ":",
             Token::Equals(_) => "=",
             Token::Declare(_) => ":=",
@@ -137,10 +156,18 @@ impl Token<'_> {
             Token::Loop(_) => "loop",
             Token::If(_) => "if",
             Token::Else(_) => "else",
+            Token::Try(_) => "try",
+            Token::Catch(_) => "catch",
+            Token::Match(_) => "match",
             Token::Return(_) => "return",
             Token::Break(_) => "break",
             Token::Continue(_) => "continue",
             Token::Background(_) => "&",
+            Token::RedirectOut(_) => ">",
+            Token::RedirectAppend(_) => ">>",
+            Token::RedirectIn(_) => "<",
+            Token::InterpolationStart(_) => "$\"",
+            Token::InterpolationEnd(_) => "\"",
         }
     }
 }
@@ -195,11 +222,20 @@ impl<'a> Into<Spanned<'a>> for Token<'a> {
             | Token::Loop(l)
             | Token::If(l)
             | Token::Else(l)
+            | Token::Try(l)
+            | Token::Catch(l)
+            | Token::Match(l)
             | Token::Return(l)
             | Token::Break(l)
             | Token::Continue(l)
             | Token::ExprModeStart(l)
-            | Token::Background(l) => l,
+            | Token::Background(l)
+            | Token::RedirectOut(l)
+            | Token::RedirectAppend(l)
+            | Token::RedirectIn(l)
+            | Token::InterpolationStart(l)
+            | Token::StringFragment(_, l)
+            | Token::InterpolationEnd(l) => l,
         };
         Ok((loc.start, self, loc.end))
     }
@@ -122,11 +122,20 @@ impl From<Token<'_>> for TrackedString {
             | Token::Loop(l)
             | Token::If(l)
             | Token::Else(l)
+            | Token::Try(l)
+            | Token::Catch(l)
+            | Token::Match(l)
             | Token::Return(l)
             | Token::Break(l)
             | Token::Continue(l)
             | Token::ExprModeStart(l)
-            | Token::Background(l) => TrackedString::new(value.as_string(), l),
+            | Token::Background(l)
+            | Token::RedirectOut(l)
+            | Token::RedirectAppend(l)
+            | Token::RedirectIn(l)
+            | Token::InterpolationStart(l)
+            | Token::StringFragment(_, l)
+            | Token::InterpolationEnd(l) => TrackedString::new(value.as_string(), l),
         }
     }
 }
@@ -0,0 +1,519 @@
+/**
+Closures are compiled into a flat `Program` the first time they are called, and the program is
+reused by every later call. Compared to evaluating the jobs of a closure directly, this avoids most
+of the overhead that dominates the run time of small closures called in a loop:
+
+* A job consisting of a single non-blocking command is run in the calling thread, with the output
+  captured in a channel shared by the whole program, instead of in a new thread with a new pipe.
+* Variables declared in the closure itself, like parameters and loop variables, are looked up
+  using the position they had in the scope of the previous call.
+* What the names of commands resolve to is cached until a variable with one of the names involved
+  is declared, set or removed, see `resolution_generation`.
+
+Jobs that can't be run this way, e.g. pipelines, are evaluated the same way as before.
+ */
+use crate::lang::argument::{Argument, ArgumentType, SwitchStyle};
+use crate::lang::ast::source::Source;
+use crate::lang::command::Command;
+use crate::lang::command::closure::Closure;
+use crate::lang::command_invocation::{CommandInvocation, eval_internal};
+use crate::lang::errors::CrushResult;
+use crate::lang::job::Job;
+use crate::lang::pipe::{EmptyInput, ValueReceiver, ValueSender, black_hole, capture_pipe, pipe};
+use crate::lang::state::contexts::{CommandContext, EvalContext, JobContext};
+use crate::lang::state::scope::{Scope, resolution_generation, watch_names};
+use crate::lang::value::{Value, ValueDefinition};
+use crate::util::identity_arc::Identity;
+use crossbeam::channel::RecvError;
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::ThreadId;
+
+/// A program that is compiled on first use and then shared, e.g. by every closure created from
+/// the same closure definition.
+pub type LazyProgram = Arc<OnceLock<Program>>;
+
+pub struct Program {
+    instructions: Vec<Instruction>,
+}
+
+impl Program {
+    pub fn compile(jobs: &[Job]) -> Program {
+        Program {
+            instructions: jobs.iter().map(Instruction::compile).collect(),
+        }
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    /// Evaluate this program the way a subshell is evaluated, and return the output of the last
+    /// job.
+    fn eval(&self, frame: &Frame) -> CrushResult<Value> {
+        let Some((last, rest)) = self.instructions.split_last() else {
+            return Ok(Value::Empty);
+        };
+        for instruction in rest {
+            match instruction {
+                Instruction::Job(job) => {
+                    job.eval(frame.context.with_io(frame.empty_input(), black_hole()))?;
+                }
+                _ => {
+                    instruction.run(frame.empty_input(), frame)?;
+                }
+            }
+        }
+        last.run(frame.empty_input(), frame)?.1.recv()
+    }
+}
+
+pub enum Instruction {
+    /// A job with a single command, which is resolved and called by the program
+    Call(Box<Call>),
+    /// Any other job, which is evaluated as is
+    Job(Job),
+}
+
+impl Instruction {
+    fn compile(job: &Job) -> Instruction {
+        if let [invocation] = job.commands()
+            && let Some(call) = Call::compile(job, invocation)
+        {
+            return Instruction::Call(Box::new(call));
+        }
+        Instruction::Job(job.clone())
+    }
+
+    /// Run this instruction, and return the thread that needs to be joined, if any, along with the
+    /// output of the instruction.
+    pub fn run(
+        &self,
+        input: ValueReceiver,
+        frame: &Frame,
+    ) -> CrushResult<(Option<ThreadId>, Output)> {
+        match self {
+            Instruction::Call(call) => call.run(input, frame),
+            Instruction::Job(job) => {
+                let (sender, receiver) = pipe();
+                let id = job.eval(frame.context.with_io(input, sender))?;
+                Ok((id, Output::Pipe(receiver)))
+            }
+        }
+    }
+}
+
+pub enum Output {
+    /// The output of a command that has already run in the calling thread, if it sent any
+    Value(Option<Value>),
+    /// The output of a job that may still be running
+    Pipe(ValueReceiver),
+}
+
+impl Output {
+    pub fn recv(self) -> CrushResult<Value> {
+        match self {
+            Output::Value(Some(value)) => Ok(value),
+            Output::Value(None) => Err(RecvError.into()),
+            Output::Pipe(receiver) => receiver.recv(),
+        }
+    }
+}
+
+/**
+The state shared by all instructions during one call to a closure.
+ */
+pub struct Frame {
+    /// The context all jobs are run in. The output is connected to `captured`.
+    context: JobContext,
+    /// The parent scope of the closure, which decides what non-local names resolve to
+    parent: Scope,
+    captured: ValueReceiver,
+    empty: EmptyInput,
+}
+
+impl Frame {
+    /// Create a frame for a call to a closure, with `env` being the scope of the call. Jobs
+    /// started by the closure are part of the same job as the call itself.
+    pub fn new(context: &CommandContext, env: &Scope, parent: &Scope) -> Frame {
+        let (output, captured) = capture_pipe();
+        let empty = EmptyInput::new();
+        Frame {
+            context: JobContext {
+                scope: env.clone(),
+                ..context.job_context(empty.get(), output)
+            },
+            parent: parent.clone(),
+            captured,
+            empty,
+        }
+    }
+
+    pub fn empty_input(&self) -> ValueReceiver {
+        self.empty.get()
+    }
+
+    fn eval_context(&self) -> EvalContext {
+        EvalContext::from(&self.context)
+    }
+}
+
+pub struct Call {
+    job: Job,
+    command: Callee,
+    arguments: Vec<CompiledArgument>,
+}
+
+/// What a call is a call to
+enum Callee {
+    Path(Box<Path>),
+    /// A command or value computed by a subshell, e.g. the whole job `($a + 1)`
+    Substitution(Program),
+}
+
+impl Callee {
+    fn compile(definition: &ValueDefinition) -> Option<Callee> {
+        match definition {
+            ValueDefinition::JobDefinition(job) => Some(Callee::Substitution(Program::compile(
+                std::slice::from_ref(job),
+            ))),
+            ValueDefinition::JobListDefinition(jobs) => {
+                Some(Callee::Substitution(Program::compile(jobs)))
+            }
+            _ => Path::compile(definition).map(|path| Callee::Path(Box::new(path))),
+        }
+    }
+
+    fn resolve(&self, frame: &Frame) -> CrushResult<Option<(Option<Value>, Value)>> {
+        match self {
+            Callee::Path(path) => path.resolve(frame),
+            Callee::Substitution(program) => Ok(Some((None, program.eval(frame)?))),
+        }
+    }
+}
+
+impl Call {
+    fn compile(job: &Job, invocation: &CommandInvocation) -> Option<Call> {
+        Some(Call {
+            job: job.clone(),
+            command: Callee::compile(invocation.command())?,
+            arguments: invocation
+                .arguments()
+                .iter()
+                .map(|argument| {
+                    let name = match &argument.argument_type {
+                        ArgumentType::Named(name) => Some(name.string()),
+                        ArgumentType::Unnamed => None,
+                        ArgumentType::ArgumentList | ArgumentType::ArgumentDict => return None,
+                    };
+                    Some(CompiledArgument {
+                        is_this: argument.argument_type.is_this(),
+                        name,
+                        switch_style: argument.switch_style,
+                        value: Operand::compile(&argument.value),
+                        source: argument.source.clone(),
+                    })
+                })
+                .collect::<Option<Vec<_>>>()?,
+        })
+    }
+
+    fn invocation(&self) -> &CommandInvocation {
+        &self.job.commands()[0]
+    }
+
+    fn run(&self, input: ValueReceiver, frame: &Frame) -> CrushResult<(Option<ThreadId>, Output)> {
+        if frame.context.scope.is_stopped() {
+            return Ok((None, Output::Value(None)));
+        }
+
+        let (this, command) = match self.command.resolve(frame)? {
+            Some((this, Value::Command(command))) => (this, command),
+            Some((_, value))
+                if self.arguments.is_empty()
+                    && !matches!(value, Value::Type(_) | Value::Struct(_)) =>
+            {
+                return Ok((None, Output::Value(Some(value))));
+            }
+            Some((this, value)) => {
+                let (sender, receiver) = pipe();
+                let id = eval_internal(
+                    this,
+                    value,
+                    self.invocation().arguments().to_vec(),
+                    self.job_context(input, sender, frame),
+                    self.invocation().source(),
+                )?;
+                return Ok((id, Output::Pipe(receiver)));
+            }
+            None => {
+                let (sender, receiver) = pipe();
+                let id = self.job.eval(frame.context.with_io(input, sender))?;
+                return Ok((id, Output::Pipe(receiver)));
+            }
+        };
+
+        let (arguments, argument_this) = self.eval_arguments(frame)?;
+        let this = argument_this.or(this);
+
+        if command.might_block(self.invocation().arguments(), &mut frame.eval_context()) {
+            let (sender, receiver) = pipe();
+            let context = self.job_context(input, sender, frame);
+            let command_context =
+                context.command_context(self.invocation().source(), arguments, this);
+            let name = command.name().to_string();
+            let thread_context = context.clone();
+            let id = context.spawn(&name, move || {
+                thread_context.handle_error(command.eval(command_context))
+            })?;
+            return Ok((Some(id), Output::Pipe(receiver)));
+        }
+
+        while frame.captured.try_recv().is_some() {}
+        frame.context.handle_error(
+            command.eval(
+                frame
+                    .context
+                    .with_io(input, frame.context.output.clone())
+                    .command_context(self.invocation().source(), arguments, this),
+            ),
+        )?;
+        Ok((None, Output::Value(frame.captured.try_recv())))
+    }
+
+    fn job_context(&self, input: ValueReceiver, output: ValueSender, frame: &Frame) -> JobContext {
+        frame
+            .context
+            .with_io(input, output)
+            .running(self.job.source().string())
+    }
+
+    fn eval_arguments(&self, frame: &Frame) -> CrushResult<(Vec<Argument>, Option<Value>)> {
+        let mut this = None;
+        let mut res = Vec::with_capacity(self.arguments.len());
+        for argument in &self.arguments {
+            let value = argument.value.eval_and_bind(frame)?;
+            if argument.is_this {
+                this = Some(value);
+            } else {
+                res.push(match &argument.name {
+                    Some(name) => Argument::named_with_style(
+                        name,
+                        argument.switch_style,
+                        value,
+                        &argument.source,
+                    ),
+                    None => Argument::unnamed(value, &argument.source),
+                });
+            }
+        }
+        Ok((res, this))
+    }
+}
+
+struct CompiledArgument {
+    name: Option<String>,
+    is_this: bool,
+    switch_style: SwitchStyle,
+    value: Operand,
+    source: Source,
+}
+
+enum Operand {
+    Constant(Value),
+    Path(Box<Path>),
+    Substitution(Program),
+    Closure {
+        definition: ValueDefinition,
+        program: LazyProgram,
+    },
+    /// Anything else, which is evaluated as is
+    Definition(ValueDefinition),
+}
+
+impl Operand {
+    fn compile(definition: &ValueDefinition) -> Operand {
+        match definition {
+            ValueDefinition::Value(value, _) => Operand::Constant(value.clone()),
+            ValueDefinition::Identifier(_) | ValueDefinition::GetAttr(_, _) => {
+                match Path::compile(definition) {
+                    Some(path) => Operand::Path(Box::new(path)),
+                    None => Operand::Definition(definition.clone()),
+                }
+            }
+            ValueDefinition::JobDefinition(job) => {
+                Operand::Substitution(Program::compile(std::slice::from_ref(job)))
+            }
+            ValueDefinition::JobListDefinition(jobs) => {
+                Operand::Substitution(Program::compile(jobs))
+            }
+            ValueDefinition::ClosureDefinition { .. } => Operand::Closure {
+                definition: definition.clone(),
+                program: LazyProgram::default(),
+            },
+            ValueDefinition::Match { .. } => Operand::Definition(definition.clone()),
+        }
+    }
+
+    fn eval_and_bind(&self, frame: &Frame) -> CrushResult<Value> {
+        match self {
+            Operand::Constant(value) => Ok(value.clone()),
+            Operand::Path(path) => match path.resolve(frame)? {
+                Some((Some(this), value)) => Ok(value.bind(this)),
+                Some((None, value)) => Ok(value),
+                None => path.definition.eval_and_bind(&mut frame.eval_context()),
+            },
+            Operand::Substitution(program) => program.eval(frame),
+            Operand::Closure {
+                definition:
+                    ValueDefinition::ClosureDefinition {
+                        name,
+                        signature,
+                        jobs,
+                        source,
+                    },
+                program,
+            } => {
+                let closure = match signature {
+                    None => Closure::block(jobs.clone(), &frame.context.scope, source.clone()),
+                    Some(signature) => Closure::command(
+                        name.clone(),
+                        signature.clone(),
+                        jobs.clone(),
+                        &frame.context.scope,
+                        &frame.context.global_state,
+                        source.clone(),
+                    )?,
+                };
+                let command: Command = Arc::from(closure.with_program(program.clone()));
+                Ok(Value::Command(command))
+            }
+            Operand::Closure { definition, .. } | Operand::Definition(definition) => {
+                definition.eval_and_bind(&mut frame.eval_context())
+            }
+        }
+    }
+}
+
+enum Root {
+    Constant(Value),
+    Variable {
+        name: String,
+        /// Where in the scope of the closure the variable was last found
+        slot: AtomicUsize,
+    },
+}
+
+/**
+A variable or constant, followed by any number of member lookups, e.g. `$global:io:echo`.
+ */
+struct Path {
+    root: Root,
+    members: Vec<String>,
+    definition: ValueDefinition,
+    cache: Mutex<Option<Resolution>>,
+}
+
+struct Resolution {
+    generation: u64,
+    parent: Scope,
+    this: Option<Value>,
+    value: Value,
+}
+
+impl Path {
+    fn compile(definition: &ValueDefinition) -> Option<Path> {
+        let (root, members) = match definition {
+            ValueDefinition::Value(value, _) => (Root::Constant(value.clone()), vec![]),
+            ValueDefinition::Identifier(name) => (
+                Root::Variable {
+                    name: name.string(),
+                    slot: AtomicUsize::new(usize::MAX),
+                },
+                vec![],
+            ),
+            ValueDefinition::GetAttr(parent, member) => {
+                let Path {
+                    root, mut members, ..
+                } = Path::compile(parent)?;
+                members.push(member.string());
+                (root, members)
+            }
+            _ => return None,
+        };
+        Some(Path {
+            root,
+            members,
+            definition: definition.clone(),
+            cache: Mutex::new(None),
+        })
+    }
+
+    /// The names that the result of resolving this path depends on
+    fn names(&self) -> Vec<String> {
+        let mut res = self.members.clone();
+        if let Root::Variable { name, .. } = &self.root {
+            res.push(name.clone());
+        }
+        res
+    }
+
+    /**
+    Resolve this path to a value, along with the value it is a member of, if any.
+
+    Returns `None` if resolving the path requires calling a command, or if the variable does not
+    exist, in which case the definition of the path needs to be evaluated instead.
+     */
+    fn resolve(&self, frame: &Frame) -> CrushResult<Option<(Option<Value>, Value)>> {
+        let (mut value, generation) = match &self.root {
+            Root::Constant(value) => (value.clone(), None),
+            Root::Variable { name, slot } => {
+                match frame.context.scope.get_local_slot(name, slot)? {
+                    Some(value) => (value, None),
+                    None => {
+                        if let Some(resolution) = self.cache.lock().unwrap().as_ref()
+                            && resolution.generation == resolution_generation()
+                            && resolution.parent.id() == frame.parent.id()
+                        {
+                            return Ok(Some((resolution.this.clone(), resolution.value.clone())));
+                        }
+                        watch_names(&self.names());
+                        let generation = resolution_generation();
+                        match frame.context.scope.get(name)? {
+                            Some(value) => (value, Some(generation)),
+                            None => return Ok(None),
+                        }
+                    }
+                }
+            }
+        };
+
+        // Only commands found in namespaces are cached, since those rarely change. The members
+        // of any other type of value can change without a variable being declared or set. Scopes
+        // used by the closure itself only last for one call, so they can't be cached either.
+        let mut cacheable = generation.is_some() && frame.context.scope.get_use().is_empty();
+        let mut this = None;
+        for member in &self.members {
+            if let Value::Command(_) = value {
+                return Ok(None);
+            }
+            cacheable &= matches!(value, Value::Scope(_));
+            let next = value.field(member)?.ok_or(format!(
+                "Missing field `{}` in value of type `{}`",
+                member,
+                value.value_type()
+            ))?;
+            this = Some(std::mem::replace(&mut value, next));
+        }
+
+        if let (true, Some(generation), Value::Command(_)) = (cacheable, generation, &value) {
+            *self.cache.lock().unwrap() = Some(Resolution {
+                generation,
+                parent: frame.parent.clone(),
+                this: this.clone(),
+                value: value.clone(),
+            });
+        }
+        Ok(Some((this, value)))
+    }
+}
@@ -1,6 +1,7 @@
 use crate::lang::argument::{Argument, ArgumentDefinition, ArgumentType, SwitchStyle};
 use crate::lang::ast::source::Source;
 use crate::lang::ast::tracked_string::TrackedString;
+use crate::lang::command::bytecode::{Frame, LazyProgram, Program};
 use crate::lang::command::{
     BoundCommand, Command, CrushCommand, OutputType, Parameter, ParameterDefinition,
 };
@@ -12,16 +13,16 @@ use crate::lang::errors::{
 };
 use crate::lang::help::Help;
 use crate::lang::job::Job;
-use crate::lang::pipe::{black_hole, empty_channel, pipe};
+use crate::lang::pipe::{ValueSender, black_hole};
 use crate::lang::serialization::model;
 use crate::lang::serialization::model::{
     Element, SignatureDefinition, Values, element, normal_parameter_definition,
 };
 use crate::lang::serialization::{DeserializationState, Serializable, SerializationState};
-use crate::lang::state::contexts::{CommandContext, EvalContext, JobContext};
+use crate::lang::state::contexts::{CommandContext, EvalContext};
 use crate::lang::state::global_state::GlobalState;
 use crate::lang::state::scope::{Scope, ScopeType};
-use crate::lang::value::{Value, ValueDefinition, ValueType};
+use crate::lang::value::{MatchArm, Pattern, Value, ValueDefinition, ValueType};
 use crate::util::escape::unescape;
 use ordered_map::{Entry, OrderedMap};
 use std::collections::VecDeque;
@@ -342,6 +343,8 @@ impl ClosureType {
 
 pub struct Closure {
     jobs: Vec<Job>,
+    /// The jobs compiled into a program, which happens on the first call
+    program: LazyProgram,
     parent_scope: Scope,
     closure_type: ClosureType,
     source: Source,
@@ -443,6 +446,7 @@ fn compile_signature(
                     allowed: None,
                     description: description.as_ref().map(|s| s.string.clone()),
                     complete: None,
+                    completion_tag: None,
                     named: false,
                     unnamed: false,
                     default,
@@ -454,6 +458,7 @@ fn compile_signature(
                 allowed: None,
                 description: description.as_ref().map(|s| s.string.clone()),
                 complete: None,
+                completion_tag: None,
                 named: true,
                 unnamed: false,
                 default: None,
@@ -464,6 +469,7 @@ fn compile_signature(
                 allowed: None,
                 description: description.as_ref().map(|s| s.string.clone()),
                 complete: None,
+                completion_tag: None,
                 named: false,
                 unnamed: true,
                 default: None,
@@ -574,6 +580,7 @@ impl Closure {
         let signature_data = compile_signature(&signature, parent_scope, state)?;
         Ok(Closure {
             jobs: job_definitions,
+            program: LazyProgram::default(),
             parent_scope: parent_scope.clone(),
             closure_type: ClosureType::Command {
                 signature_string: create_signature_string(&name, &signature_data),
@@ -589,12 +596,19 @@ impl Closure {
     pub fn block(job_definitions: Vec<Job>, parent_scope: &Scope, source: Source) -> Closure {
         Closure {
             jobs: job_definitions,
+            program: LazyProgram::default(),
             parent_scope: parent_scope.clone(),
             closure_type: ClosureType::Block,
             source,
         }
     }
 
+    /// Share the compiled program of this closure with other closures that have the same jobs.
+    pub(super) fn with_program(mut self, program: LazyProgram) -> Closure {
+        self.program = program;
+        self
+    }
+
     pub fn deserialize(
         id: usize,
         elements: &[Element],
@@ -604,12 +618,13 @@ impl Closure {
     }
 
     fn eval_inner(&self, context: CommandContext) -> CrushResult<()> {
-        let job_definitions = self.jobs.clone();
+        let program = self.program.get_or_init(|| Program::compile(&self.jobs));
         let parent_env = self.parent_scope.clone();
 
         let scope_type = self.closure_type.scope_type(&self.source);
 
         let env = parent_env.create_child(&context.scope, scope_type);
+        let frame = Frame::new(&context, &env, &parent_env);
 
         let mut cc = EvalContext::from(&context.clone().with_output(black_hole())).with_scope(&env);
         if let Some(this) = context.this {
@@ -623,41 +638,71 @@ impl Closure {
             return Ok(());
         }
 
-        for (idx, job_definition) in job_definitions.iter().enumerate() {
+        let instructions = program.instructions();
+        for (idx, instruction) in instructions.iter().enumerate() {
             let first = idx == 0;
-            let last = idx == job_definitions.len() - 1;
+            let last = idx == instructions.len() - 1;
             let input = if first {
                 context.input.clone()
             } else {
-                empty_channel()
+                frame.empty_input()
             };
-            let (sender, receiver) = pipe();
-            let job = job_definition.eval(JobContext::new(
-                input,
-                sender,
-                env.clone(),
-                context.global_state.clone(),
-            ))?;
-            let local_printer = context.global_state.printer().clone();
-            let local_threads = context.global_state.threads().clone();
-            job.map(|id| local_threads.join_one(id, &local_printer));
+            let (job, output) = instruction.run(input, &frame)?;
+
+            if last {
+                // Streams from the last job are forwarded before waiting for it to finish, since
+                // the job blocks once the stream is full, until someone starts reading from it
+                let output = output.recv();
+                if let Ok(Value::TableInputStream(_) | Value::BinaryInputStream(_)) = &output {
+                    send_value(&context.output, output?)?;
+                    if let Some(id) = job {
+                        context.global_state.threads().join_one(id)?;
+                    }
+                    return Ok(());
+                }
+                if let Some(id) = job {
+                    context.global_state.threads().join_one(id)?;
+                }
+                let return_value = match env.is_stopped() {
+                    true => env.take_return_value(),
+                    false => None,
+                };
+                return send_value(
+                    &context.output,
+                    match return_value {
+                        None => output?,
+                        Some(v) => v,
+                    },
+                );
+            }
+
+            if let Some(id) = job {
+                context.global_state.threads().join_one(id)?;
+            }
 
             if env.is_stopped() {
                 let return_value = match env.take_return_value() {
-                    None => receiver.recv()?,
+                    None => output.recv()?,
                     Some(v) => v,
                 };
-                return context.output.send(return_value);
-            } else {
-                if last {
-                    context.output.send(receiver.recv()?)?;
-                }
+                return send_value(&context.output, return_value);
             }
         }
         Ok(())
     }
 }
 
+/**
+Send the value of a closure to its output. Nobody reads the value of a closure whose output is the
+black hole, like the body of a loop, so it is dropped instead.
+ */
+fn send_value(output: &ValueSender, value: Value) -> CrushResult<()> {
+    match output.is_black_hole() {
+        true => Ok(()),
+        false => output.send(value),
+    }
+}
+
 impl Display for Closure {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str("{ ")?;
@@ -922,6 +967,57 @@ impl<'a> ClosureSerializer<'a> {
         })
     }
 
+    fn pattern(&mut self, p: &Pattern) -> CrushResult<model::Pattern> {
+        Ok(model::Pattern {
+            pattern: Some(match p {
+                Pattern::Any => model::pattern::Pattern::Any(true),
+                Pattern::Value(v) => model::pattern::Pattern::Value(self.value_definition(v)?),
+                Pattern::Bind(name, pattern) => {
+                    model::pattern::Pattern::Bind(Box::from(model::BindPattern {
+                        name: name.serialize(self.elements, self.state)? as u64,
+                        pattern: Some(Box::from(self.pattern(pattern)?)),
+                    }))
+                }
+                Pattern::List { items, rest } => {
+                    model::pattern::Pattern::List(Box::from(model::ListPattern {
+                        items: items
+                            .iter()
+                            .map(|p| self.pattern(p))
+                            .collect::<CrushResult<Vec<_>>>()?,
+                        rest: Some(match rest {
+                            None => model::list_pattern::Rest::HasRest(false),
+                            Some(rest) => {
+                                model::list_pattern::Rest::RestValue(Box::from(self.pattern(rest)?))
+                            }
+                        }),
+                    }))
+                }
+                Pattern::Record(fields) => model::pattern::Pattern::Record(model::RecordPattern {
+                    fields: fields
+                        .iter()
+                        .map(|(name, pattern)| {
+                            Ok(model::FieldPattern {
+                                name: name.serialize(self.elements, self.state)? as u64,
+                                pattern: Some(self.pattern(pattern)?),
+                            })
+                        })
+                        .collect::<CrushResult<Vec<_>>>()?,
+                }),
+            }),
+        })
+    }
+
+    fn match_arm(&mut self, a: &MatchArm) -> CrushResult<model::MatchArm> {
+        Ok(model::MatchArm {
+            pattern: Some(self.pattern(&a.pattern)?),
+            guard: Some(match &a.guard {
+                None => model::match_arm::Guard::HasGuard(false),
+                Some(guard) => model::match_arm::Guard::GuardValue(self.value_definition(guard)?),
+            }),
+            body: Some(self.value_definition(&a.body)?),
+        })
+    }
+
     fn value_definition(&mut self, v: &ValueDefinition) -> CrushResult<model::ValueDefinition> {
         Ok(model::ValueDefinition {
             value_definition: Some(match v {
@@ -978,6 +1074,18 @@ impl<'a> ClosureSerializer<'a> {
                             .collect::<CrushResult<Vec<_>>>()?,
                     })
                 }
+                ValueDefinition::Match {
+                    value,
+                    arms,
+                    source,
+                } => model::value_definition::ValueDefinition::Match(Box::from(model::Match {
+                    value: Some(Box::from(self.value_definition(value)?)),
+                    arms: arms
+                        .iter()
+                        .map(|a| self.match_arm(a))
+                        .collect::<CrushResult<Vec<_>>>()?,
+                    source: source.serialize(self.elements, self.state)? as u64,
+                })),
             }),
         })
     }
@@ -1005,6 +1113,7 @@ impl<'a> ClosureDeserializer<'a> {
             element::Element::Closure(s) => {
                 let env = Scope::deserialize(s.env as usize, self.elements, self.state)?;
                 Ok(Arc::from(Closure {
+                    program: LazyProgram::default(),
                     jobs: s
                         .job_definitions
                         .iter()
@@ -1093,6 +1202,7 @@ impl<'a> ClosureDeserializer<'a> {
                 ),
             },
             complete: None,
+            completion_tag: None,
             named: parameter.named,
             unnamed: parameter.unnamed,
         })
@@ -1303,7 +1413,66 @@ impl<'a> ClosureDeserializer<'a> {
                         Source::deserialize(a.element as usize, self.elements, self.state)?,
                     )
                 }
+                model::value_definition::ValueDefinition::Match(m) => ValueDefinition::Match {
+                    value: Box::from(
+                        self.value_definition(m.value.as_ref().ok_or("Invalid match statement")?)?,
+                    ),
+                    arms: m
+                        .arms
+                        .iter()
+                        .map(|a| self.match_arm(a))
+                        .collect::<CrushResult<Vec<_>>>()?,
+                    source: Source::deserialize(m.source as usize, self.elements, self.state)?,
+                },
             },
         )
     }
+
+    fn pattern(&mut self, p: &model::Pattern) -> CrushResult<Pattern> {
+        Ok(match p.pattern.as_ref().ok_or("Invalid pattern")? {
+            model::pattern::Pattern::Any(_) => Pattern::Any,
+            model::pattern::Pattern::Value(v) => Pattern::Value(self.value_definition(v)?),
+            model::pattern::Pattern::Bind(b) => Pattern::Bind(
+                String::deserialize(b.name as usize, self.elements, self.state)?,
+                Box::from(self.pattern(b.pattern.as_ref().ok_or("Invalid pattern")?)?),
+            ),
+            model::pattern::Pattern::List(l) => Pattern::List {
+                items: l
+                    .items
+                    .iter()
+                    .map(|p| self.pattern(p))
+                    .collect::<CrushResult<Vec<_>>>()?,
+                rest: match &l.rest {
+                    None | Some(model::list_pattern::Rest::HasRest(_)) => None,
+                    Some(model::list_pattern::Rest::RestValue(rest)) => {
+                        Some(Box::from(self.pattern(rest)?))
+                    }
+                },
+            },
+            model::pattern::Pattern::Record(r) => Pattern::Record(
+                r.fields
+                    .iter()
+                    .map(|f| {
+                        Ok((
+                            String::deserialize(f.name as usize, self.elements, self.state)?,
+                            self.pattern(f.pattern.as_ref().ok_or("Invalid pattern")?)?,
+                        ))
+                    })
+                    .collect::<CrushResult<Vec<_>>>()?,
+            ),
+        })
+    }
+
+    fn match_arm(&mut self, a: &model::MatchArm) -> CrushResult<MatchArm> {
+        Ok(MatchArm {
+            pattern: self.pattern(a.pattern.as_ref().ok_or("Invalid match arm")?)?,
+            guard: match &a.guard {
+                None | Some(model::match_arm::Guard::HasGuard(_)) => None,
+                Some(model::match_arm::Guard::GuardValue(guard)) => {
+                    Some(self.value_definition(guard)?)
+                }
+            },
+            body: self.value_definition(a.body.as_ref().ok_or("Invalid match arm")?)?,
+        })
+    }
 }
@@ -1,3 +1,4 @@
+mod bytecode;
 mod closure;
 
 use crate::lang::any_str::AnyStr;
@@ -442,6 +443,8 @@ pub struct Parameter {
             res: &mut Vec<Completion>,
         ) -> CrushResult<()>,
     >,
+    /// The completion tag of this parameter, e.g. `hostname`, used to look up a completion provider.
+    pub completion_tag: Option<String>,
     pub named: bool,
     pub unnamed: bool,
 }
@@ -145,20 +145,18 @@ pub fn eval(
         let my_context = context.clone();
         let thread_source = source.clone();
         Ok(Some(context.spawn(&command.to_string(), move || {
-            match eval_non_blocking(&command, &thread_source, &arguments, my_context.clone()) {
-                Ok(Some(id)) => my_context
-                    .global_state
-                    .threads()
-                    .join_one(id, &my_context.global_state.printer()),
-                Err(e) => my_context.global_state.printer().crush_error(e),
-                _ => {}
-            }
-            Ok(())
+            my_context.handle_error(
+                eval_non_blocking(&command, &thread_source, &arguments, my_context.clone())
+                    .and_then(|id| match id {
+                        Some(id) => my_context.global_state.threads().join_one(id),
+                        None => Ok(()),
+                    }),
+            )
         })?))
     }
 }
 
-fn eval_internal(
+pub fn eval_internal(
     this: Option<Value>,
     value: Value,
     local_arguments: Vec<ArgumentDefinition>,
@@ -276,10 +274,7 @@ fn eval_command(
     {
         let new_context =
             CommandInvocation::command_context(source, local_arguments, this, context.clone())?;
-        context
-            .global_state
-            .printer()
-            .handle_error(command.eval(new_context));
+        context.handle_error(command.eval(new_context))?;
         Ok(None)
     } else {
         let name = command.name().to_string();
@@ -290,9 +285,9 @@ fn eval_command(
                 &local_source,
                 local_arguments,
                 this,
-                local_context,
+                local_context.clone(),
             )?;
-            command.eval(res)
+            local_context.handle_error(command.eval(res))
         })?))
     }
 }
@@ -0,0 +1,799 @@
+/**
+Completion of the switches and subcommands of external commands.
+
+The completion data for a command is read from a fish `complete` file for it if one is installed.
+Otherwise, commands that have been allowed to using `crush:completion:help` are run with the `--help`
+switch and the output is parsed. Other commands are never run while completing. Either way, the
+result is cached per binary until the binary is modified.
+ */
+use crate::lang::command_invocation::resolve_external_command;
+use crate::lang::completion::Completion;
+use crate::lang::completion::parse::{LastArgument, PartialCommandResult, PreviousArgumentValue};
+use crate::lang::errors::CrushResult;
+use crate::lang::state::global_state::GlobalState;
+use crate::lang::state::scope::Scope;
+use crate::lang::value::Value;
+use crate::util::escape::escape_without_quotes;
+use crate::util::file::home;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long to wait for a command to print its help text.
+const HELP_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExternalSwitch {
+    pub short: Option<char>,
+    pub long: Option<String>,
+    pub description: Option<String>,
+    pub takes_value: bool,
+    /// The subcommands this switch belongs to. If empty, it can be used with any subcommand.
+    pub subcommands: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExternalSubcommand {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExternalCompletions {
+    pub switches: Vec<ExternalSwitch>,
+    pub subcommands: Vec<ExternalSubcommand>,
+}
+
+/// Names of switches and subcommands, e.g. `all`, `dry-run` or `cherry-pick`.
+fn is_name(s: &str) -> bool {
+    s.chars().next().is_some_and(|c| c.is_alphanumeric())
+        && s.chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Switches that can be written as Crush switches, i.e. without quoting them.
+fn is_identifier(s: &str) -> bool {
+    s.chars()
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Split a line of help text into the part naming the switch or subcommand and its description,
+/// which are separated by at least two spaces or a tab.
+fn split_description(line: &str) -> (&str, Option<&str>) {
+    let idx = match (line.find("  "), line.find('\t')) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    match idx {
+        Some(idx) => {
+            let description = line[idx..].trim();
+            (
+                &line[..idx],
+                if description.is_empty() {
+                    None
+                } else {
+                    Some(description)
+                },
+            )
+        }
+        None => (line, None),
+    }
+}
+
+/// Parse a line like `-I, --ignore=PATTERN   do not list entries matching PATTERN`.
+fn parse_help_switch(line: &str) -> Option<ExternalSwitch> {
+    let (head, description) = split_description(line);
+    let mut switch = ExternalSwitch {
+        description: description.map(|d| d.to_string()),
+        ..ExternalSwitch::default()
+    };
+    for token in head.split([',', ' ']).filter(|t| !t.is_empty()) {
+        if let Some(name) = token.strip_prefix("--") {
+            let end = name.find(['=', '[', '<']).unwrap_or(name.len());
+            if switch.long.is_none() && is_name(&name[..end]) {
+                switch.long = Some(name[..end].to_string());
+                switch.takes_value |= name[end..].starts_with(['=', '<']);
+            }
+        } else if let Some(name) = token.strip_prefix('-') {
+            let mut chars = name.chars();
+            if let (Some(c), None) = (chars.next(), chars.next())
+                && c.is_alphanumeric()
+                && switch.short.is_none()
+            {
+                switch.short = Some(c);
+            }
+        } else if token.starts_with('<') || token.chars().all(|c| c.is_uppercase() || c == '_') {
+            switch.takes_value = true;
+        }
+    }
+    if switch.long.is_none() && switch.short.is_none() {
+        None
+    } else {
+        Some(switch)
+    }
+}
+
+/// Parse a line like `build, b    Compile the current package`.
+fn parse_help_subcommand(line: &str, out: &mut Vec<ExternalSubcommand>) {
+    let (head, description) = split_description(line);
+    let names = head.split(',').map(|n| n.trim()).collect::<Vec<_>>();
+    if names.iter().all(|n| is_name(n)) {
+        for name in names {
+            out.push(ExternalSubcommand {
+                name: name.to_string(),
+                description: description.map(|d| d.to_string()),
+            });
+        }
+    }
+}
+
+/// Parse the output of `command --help`. Switches are indented lines starting with a dash.
+/// Subcommands are indented lines in a section whose header mentions commands, like `Commands:`
+/// or `These are common Git commands used in various situations:`.
+pub fn parse_help(help: &str) -> ExternalCompletions {
+    let mut res = ExternalCompletions::default();
+    let mut in_commands = false;
+    for line in help.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if !line.starts_with(char::is_whitespace) {
+            if trimmed.ends_with(':') || trimmed.chars().all(|c| !c.is_lowercase()) {
+                in_commands = trimmed.to_lowercase().contains("command");
+            }
+            continue;
+        }
+        if trimmed.starts_with('-') {
+            if let Some(switch) = parse_help_switch(trimmed)
+                && !res.switches.iter().any(|s| {
+                    (s.long.is_some() && s.long == switch.long)
+                        || (s.short.is_some() && s.short == switch.short)
+                })
+            {
+                res.switches.push(switch);
+            }
+        } else if in_commands {
+            parse_help_subcommand(trimmed, &mut res.subcommands);
+        }
+    }
+    res
+}
+
+/// Split a line of fish script into words, removing quotes and backslash escapes.
+fn fish_words(line: &str) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '#' if word.is_none() => break,
+            c if c.is_whitespace() => {
+                if let Some(w) = word.take() {
+                    res.push(w);
+                }
+            }
+            '\'' => {
+                let w = word.get_or_insert_with(String::new);
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\'' => break,
+                        '\\' => match chars.next() {
+                            Some(c @ ('\'' | '\\')) => w.push(c),
+                            Some(c) => {
+                                w.push('\\');
+                                w.push(c);
+                            }
+                            None => {}
+                        },
+                        c => w.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let w = word.get_or_insert_with(String::new);
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$')) => w.push(c),
+                            Some(c) => {
+                                w.push('\\');
+                                w.push(c);
+                            }
+                            None => {}
+                        },
+                        c => w.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    word.get_or_insert_with(String::new).push(c);
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(w) = word {
+        res.push(w);
+    }
+    res
+}
+
+#[derive(Default)]
+struct FishCompletion {
+    short: Option<char>,
+    long: Option<String>,
+    description: Option<String>,
+    arguments: Option<String>,
+    condition: String,
+    takes_value: bool,
+}
+
+/// Parse the options of a single `complete` invocation.
+fn parse_fish_complete(words: &[String]) -> FishCompletion {
+    let mut res = FishCompletion::default();
+    let mut words = words.iter();
+    while let Some(word) = words.next() {
+        let (options, mut value): (Vec<&str>, Option<String>) =
+            if let Some(long) = word.strip_prefix("--") {
+                match long.split_once('=') {
+                    Some((name, value)) => (vec![name], Some(value.to_string())),
+                    None => (vec![long], None),
+                }
+            } else if let Some(short) = word.strip_prefix('-') {
+                // Clustered short options like `-xa`. Only the last one can take a value.
+                let mut options = Vec::new();
+                let mut value = None;
+                for (idx, c) in short.char_indices() {
+                    let option = &short[idx..idx + c.len_utf8()];
+                    options.push(option);
+                    if "csldanowp".contains(c) {
+                        let rest = &short[idx + c.len_utf8()..];
+                        if !rest.is_empty() {
+                            value = Some(rest.to_string());
+                        }
+                        break;
+                    }
+                }
+                (options, value)
+            } else {
+                continue;
+            };
+        for option in options {
+            let mut take = || value.take().or_else(|| words.next().cloned());
+            match option {
+                "s" | "short-option" => res.short = take().and_then(|v| v.chars().next()),
+                "l" | "long-option" => {
+                    let long = take();
+                    res.long = res.long.take().or(long);
+                }
+                "d" | "description" => res.description = take(),
+                "a" | "arguments" => res.arguments = take(),
+                "n" | "condition" => res.condition = take().unwrap_or_default(),
+                "c" | "command" | "o" | "old-option" | "w" | "wraps" | "p" | "path" => {
+                    take();
+                }
+                "r" | "require-parameter" | "x" | "exclusive" => res.takes_value = true,
+                _ => {}
+            }
+        }
+    }
+    res
+}
+
+/// Whether a fish completion condition is true when no subcommand has been given yet.
+fn is_subcommand_condition(condition: &str) -> bool {
+    condition.contains("__fish_use_subcommand")
+        || condition.contains("needs_command")
+        || condition.contains("needs_subcommand")
+        || condition.contains("not __fish_seen_subcommand_from")
+}
+
+/// The subcommands a fish completion condition like `__fish_seen_subcommand_from add commit`
+/// restricts the completion to.
+fn condition_subcommands(condition: &str) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut words = condition
+        .split(|c: char| c.is_whitespace() || c == ';' || c == '(' || c == ')')
+        .filter(|w| !w.is_empty())
+        .peekable();
+    let mut negated = false;
+    while let Some(word) = words.next() {
+        let restricts = word == "__fish_seen_subcommand_from"
+            || word.ends_with("_using_command")
+            || word.ends_with("_using_subcommand");
+        if restricts && !negated {
+            while let Some(name) = words.next_if(|w| is_name(w) && !matches!(*w, "and" | "or")) {
+                res.push(name.to_string());
+            }
+        }
+        negated = word == "not" || word == "!";
+    }
+    res
+}
+
+/// Parse a fish completion script, i.e. a file containing `complete` invocations.
+pub fn parse_fish(script: &str) -> ExternalCompletions {
+    let mut res = ExternalCompletions::default();
+    let mut line = String::new();
+    for part in script.lines() {
+        // Join lines ending in a backslash with the next one
+        if let Some(part) = part.strip_suffix('\\') {
+            line.push_str(part);
+            line.push(' ');
+            continue;
+        }
+        line.push_str(part);
+        let words = fish_words(&line);
+        line.clear();
+        if words.first().map(|w| w.as_str()) != Some("complete") {
+            continue;
+        }
+        let completion = parse_fish_complete(&words[1..]);
+        if completion.short.is_some() || completion.long.is_some() {
+            res.switches.push(ExternalSwitch {
+                short: completion.short,
+                long: completion.long,
+                description: completion.description,
+                takes_value: completion.takes_value,
+                subcommands: condition_subcommands(&completion.condition),
+            });
+        } else if let Some(arguments) = completion.arguments
+            && is_subcommand_condition(&completion.condition)
+        {
+            // A single argument can carry its own description after a tab
+            if let Some((name, description)) = arguments
+                .split_once('\t')
+                .or_else(|| arguments.split_once("\\t"))
+            {
+                if is_name(name) {
+                    res.subcommands.push(ExternalSubcommand {
+                        name: name.to_string(),
+                        description: Some(description.to_string()),
+                    });
+                }
+            } else {
+                for name in arguments.split_whitespace().filter(|n| is_name(n)) {
+                    res.subcommands.push(ExternalSubcommand {
+                        name: name.to_string(),
+                        description: completion.description.clone(),
+                    });
+                }
+            }
+        }
+    }
+    res
+}
+
+fn fish_directories() -> Vec<PathBuf> {
+    let mut res = Vec::new();
+    if let Ok(home) = home() {
+        res.push(home.join(".config/fish/completions"));
+    }
+    for dir in [
+        "/etc/fish/completions",
+        "/usr/local/share/fish/vendor_completions.d",
+        "/usr/share/fish/vendor_completions.d",
+        "/usr/local/share/fish/completions",
+        "/usr/share/fish/completions",
+    ] {
+        res.push(PathBuf::from(dir));
+    }
+    res
+}
+
+fn fish_script(name: &str) -> Option<String> {
+    fish_directories()
+        .into_iter()
+        .find_map(|dir| std::fs::read_to_string(dir.join(format!("{}.fish", name))).ok())
+}
+
+/// Run `command --help` and return whatever it printed, preferring standard output. Commands
+/// that do not finish within `HELP_TIMEOUT` are killed.
+fn help_text(path: &Path) -> Option<String> {
+    let mut child = std::process::Command::new(path)
+        .arg("--help")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .ok()?;
+    let read = |mut stream: Box<dyn Read + Send>| {
+        thread::spawn(move || {
+            let mut res = String::new();
+            let _ = stream.read_to_string(&mut res);
+            res
+        })
+    };
+    let stdout = read(Box::new(child.stdout.take()?));
+    let stderr = read(Box::new(child.stderr.take()?));
+
+    let deadline = Instant::now() + HELP_TIMEOUT;
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            }
+        }
+    }
+    let stdout = stdout.join().ok()?;
+    if stdout.trim().is_empty() {
+        stderr.join().ok()
+    } else {
+        Some(stdout)
+    }
+}
+
+/// Completion data keyed on the path of the binary and whether its help text may be used, along
+/// with its modification time.
+type Cache = Mutex<HashMap<(PathBuf, bool), (Option<SystemTime>, Arc<ExternalCompletions>)>>;
+
+fn cache() -> &'static Cache {
+    static CACHE: OnceLock<Cache> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// The completion data for the command with the specified name, found at the specified path. The
+/// command is only run with `--help` if `use_help` is set and there is no fish completion file.
+pub fn completions(name: &str, path: &Path, use_help: bool) -> Arc<ExternalCompletions> {
+    let key = (path.to_path_buf(), use_help);
+    let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    if let Ok(cache) = cache().lock()
+        && let Some((m, completions)) = cache.get(&key)
+        && *m == modified
+    {
+        return completions.clone();
+    }
+
+    let completions = Arc::new(match fish_script(name) {
+        Some(script) => parse_fish(&script),
+        None if use_help => help_text(path)
+            .map(|help| parse_help(&help))
+            .unwrap_or_default(),
+        None => ExternalCompletions::default(),
+    });
+    if let Ok(mut cache) = cache().lock() {
+        cache.insert(key, (modified, completions.clone()));
+    }
+    completions
+}
+
+fn describe(completion: Completion, description: &Option<String>) -> Completion {
+    match description {
+        Some(description) => completion.with_description(description),
+        None => completion,
+    }
+}
+
+/// Complete the switches and subcommands of an external command using the specified data.
+pub fn complete_with(
+    completions: &ExternalCompletions,
+    parse_result: &PartialCommandResult,
+    cursor: usize,
+    res: &mut Vec<Completion>,
+) {
+    let subcommand =
+        parse_result
+            .previous_arguments
+            .iter()
+            .find_map(|arg| match (&arg.name, &arg.value) {
+                (None, PreviousArgumentValue::Value(Value::String(s)))
+                    if completions.subcommands.iter().any(|c| c.name == **s) =>
+                {
+                    Some(s.to_string())
+                }
+                _ => None,
+            });
+    let switches = completions.switches.iter().filter(|s| {
+        s.subcommands.is_empty()
+            || subcommand
+                .as_ref()
+                .is_some_and(|name| s.subcommands.contains(name))
+    });
+
+    match &parse_result.last_argument {
+        LastArgument::Switch(prefix) => {
+            for switch in switches {
+                if let Some(long) = &switch.long
+                    && is_identifier(long)
+                    && long.len() > prefix.len()
+                    && long.starts_with(prefix.as_str())
+                {
+                    res.push(describe(
+                        Completion::new(
+                            format!(
+                                "{}{}",
+                                &long[prefix.len()..],
+                                if switch.takes_value { "=" } else { " " }
+                            ),
+                            format!("--{}", long),
+                            cursor,
+                        ),
+                        &switch.description,
+                    ));
+                }
+            }
+        }
+
+        // Switches that can't be written as Crush switches, like `--dry-run`, can still be
+        // passed as quoted strings.
+        LastArgument::QuotedString(prefix) if prefix.starts_with('-') => {
+            for switch in switches {
+                let flags = switch
+                    .long
+                    .iter()
+                    .map(|l| format!("--{}", l))
+                    .chain(switch.short.iter().map(|s| format!("-{}", s)));
+                for flag in flags {
+                    if flag.len() > prefix.len() && flag.starts_with(prefix.as_str()) {
+                        res.push(describe(
+                            Completion::new(
+                                format!("{}\" ", escape_without_quotes(&flag[prefix.len()..])),
+                                flag,
+                                cursor,
+                            ),
+                            &switch.description,
+                        ));
+                    }
+                }
+            }
+        }
+
+        LastArgument::Field(prefix) if subcommand.is_none() => {
+            for sub in &completions.subcommands {
+                if sub.name.len() > prefix.len() && sub.name.starts_with(prefix.as_str()) {
+                    res.push(describe(
+                        Completion::new(
+                            format!("{} ", &sub.name[prefix.len()..]),
+                            sub.name.clone(),
+                            cursor,
+                        ),
+                        &sub.description,
+                    ));
+                }
+            }
+        }
+
+        LastArgument::Unknown if parse_result.previous_arguments.is_empty() => {
+            for sub in &completions.subcommands {
+                res.push(describe(
+                    Completion::new(format!("{} ", sub.name), sub.name.clone(), cursor),
+                    &sub.description,
+                ));
+            }
+        }
+
+        _ => {}
+    }
+}
+
+/// Complete the switches and subcommands of the external command with the specified name.
+pub fn complete_external(
+    name: &str,
+    parse_result: &PartialCommandResult,
+    cursor: usize,
+    scope: &Scope,
+    state: &GlobalState,
+    res: &mut Vec<Completion>,
+) -> CrushResult<()> {
+    if let Some(path) = resolve_external_command(name, scope)? {
+        let completions = completions(name, &path, state.help_completion(name));
+        complete_with(&completions, parse_result, cursor, res);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lang::completion::parse::{CompletionCommand, PreviousArgument};
+
+    const LS_HELP: &str = "Usage: ls [OPTION]... [FILE]...
+List information about the FILEs (the current directory by default).
+
+Mandatory arguments to long options are mandatory for short options too.
+  -a, --all                  do not ignore entries starting with .
+      --block-size=SIZE      with -l, scale sizes by SIZE when printing them;
+                               e.g., '--block-size=M'; see SIZE format below
+      --color[=WHEN]         color the output WHEN; more info below
+  -I, --ignore=PATTERN       do not list implied entries matching shell PATTERN
+  -l                         use a long listing format
+";
+
+    const CARGO_HELP: &str = "Rust's package manager
+
+Usage: cargo [+toolchain] [OPTIONS] [COMMAND]
+
+Options:
+  -V, --version                  Print version info and exit
+  -C <DIRECTORY>                 Change to DIRECTORY before doing anything
+
+Commands:
+    build, b    Compile the current package
+    new         Create a new cargo package
+    ...         See all commands with --list
+";
+
+    const GIT_FISH: &str = r#"# Completions for git
+complete -c git -n __fish_git_needs_command -a clone -d 'Clone a repository into a new directory'
+complete -f -c git -n '__fish_use_subcommand' -xa 'commit' -d "Record changes"
+complete -c git -n '__fish_git_using_command commit' -l amend -d 'Amend the log message of the last commit'
+complete -c git -n '__fish_seen_subcommand_from commit' -s m -l message -x \
+    -d 'Use the given message'
+complete -c git -l no-pager -d "Don't pipe output into a pager"
+"#;
+
+    fn result(previous: Vec<&str>, last_argument: LastArgument) -> PartialCommandResult {
+        PartialCommandResult {
+            command: CompletionCommand::External("git".to_string()),
+            previous_arguments: previous
+                .into_iter()
+                .map(|s| PreviousArgument {
+                    name: None,
+                    value: PreviousArgumentValue::Value(Value::from(s)),
+                })
+                .collect(),
+            last_argument_name: None,
+            last_argument,
+        }
+    }
+
+    #[test]
+    fn parse_gnu_help() {
+        let res = parse_help(LS_HELP);
+        assert_eq!(res.switches.len(), 5);
+        assert_eq!(res.switches[0].short, Some('a'));
+        assert_eq!(res.switches[0].long, Some("all".to_string()));
+        assert_eq!(
+            res.switches[0].description,
+            Some("do not ignore entries starting with .".to_string())
+        );
+        assert!(!res.switches[0].takes_value);
+        assert!(res.switches[1].takes_value);
+        assert!(!res.switches[2].takes_value);
+        assert_eq!(res.switches[4].short, Some('l'));
+        assert_eq!(res.switches[4].long, None);
+        assert!(res.subcommands.is_empty());
+    }
+
+    #[test]
+    fn parse_help_with_subcommands() {
+        let res = parse_help(CARGO_HELP);
+        assert_eq!(
+            res.subcommands
+                .iter()
+                .map(|s| s.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["build", "b", "new"]
+        );
+        assert_eq!(
+            res.subcommands[2].description,
+            Some("Create a new cargo package".to_string())
+        );
+        assert_eq!(res.switches.len(), 2);
+        assert!(res.switches[1].takes_value);
+    }
+
+    #[test]
+    fn parse_fish_completions() {
+        let res = parse_fish(GIT_FISH);
+        assert_eq!(
+            res.subcommands,
+            vec![
+                ExternalSubcommand {
+                    name: "clone".to_string(),
+                    description: Some("Clone a repository into a new directory".to_string()),
+                },
+                ExternalSubcommand {
+                    name: "commit".to_string(),
+                    description: Some("Record changes".to_string()),
+                },
+            ]
+        );
+        assert_eq!(res.switches.len(), 3);
+        assert_eq!(res.switches[0].long, Some("amend".to_string()));
+        assert_eq!(res.switches[0].subcommands, vec!["commit".to_string()]);
+        assert_eq!(res.switches[1].short, Some('m'));
+        assert!(res.switches[1].takes_value);
+        assert_eq!(
+            res.switches[1].description,
+            Some("Use the given message".to_string())
+        );
+        assert!(res.switches[2].subcommands.is_empty());
+    }
+
+    #[test]
+    fn complete_subcommands() {
+        let completions = parse_fish(GIT_FISH);
+        let mut res = Vec::new();
+        complete_with(
+            &completions,
+            &result(vec![], LastArgument::Field("co".to_string())),
+            6,
+            &mut res,
+        );
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].complete("git co"), "git commit ");
+        assert_eq!(res[0].description(), Some("Record changes"));
+    }
+
+    #[test]
+    fn complete_switches_of_subcommand() {
+        let completions = parse_fish(GIT_FISH);
+        let mut res = Vec::new();
+        complete_with(
+            &completions,
+            &result(vec!["commit"], LastArgument::Switch("".to_string())),
+            13,
+            &mut res,
+        );
+        assert_eq!(
+            res.iter().map(|c| c.display()).collect::<Vec<_>>(),
+            vec!["--amend", "--message"]
+        );
+        assert_eq!(res[1].complete("git commit --"), "git commit --message=");
+
+        let mut res = Vec::new();
+        complete_with(
+            &completions,
+            &result(vec!["clone"], LastArgument::Switch("".to_string())),
+            12,
+            &mut res,
+        );
+        assert!(res.is_empty());
+    }
+
+    #[test]
+    fn complete_quoted_switches() {
+        let completions = parse_fish(GIT_FISH);
+        let mut res = Vec::new();
+        complete_with(
+            &completions,
+            &result(vec![], LastArgument::QuotedString("--no".to_string())),
+            9,
+            &mut res,
+        );
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].complete("git \"--no"), "git \"--no-pager\" ");
+    }
+
+    #[test]
+    fn only_run_allowed_commands() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("crush-help-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let marker = dir.join("was-run");
+        let command = dir.join("crush-help-test-command");
+        std::fs::write(
+            &command,
+            format!(
+                "#!/bin/sh\ntouch {}\necho '  -a, --all    do everything'\n",
+                marker.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&command, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let res = completions("crush-help-test-command", &command, false);
+        assert!(res.switches.is_empty());
+        assert!(!marker.exists());
+
+        let res = completions("crush-help-test-command", &command, true);
+        assert_eq!(res.switches[0].long, Some("all".to_string()));
+        assert!(marker.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,11 +1,13 @@
 use crate::lang::ast::node::TextLiteralStyle;
 use crate::lang::ast::node::TextLiteralStyle::Unquoted;
 use crate::lang::command::Parameter;
+use crate::lang::completion::external::complete_external;
 use crate::lang::completion::parse::{
     CompletionCommand, LastArgument, ParseResult, PartialCommandResult, parse,
 };
+use crate::lang::completion::tag::complete_tag;
 use crate::lang::errors::CrushResult;
-use crate::lang::parser::Parser;
+use crate::lang::state::global_state::GlobalState;
 /**
   Main entry point for tab completion code
 */
@@ -19,11 +21,14 @@ use nix::NixPath;
 use std::ops::Deref;
 use std::path::PathBuf;
 
+pub mod external;
 pub mod parse;
+pub mod tag;
 
 pub struct Completion {
     completion: String,
     display: String,
+    description: Option<String>,
     position: usize,
 }
 
@@ -36,10 +41,16 @@ impl Completion {
         Completion {
             completion: completion.into(),
             display: display.into(),
+            description: None,
             position,
         }
     }
 
+    pub fn with_description(mut self, description: impl Into<String>) -> Completion {
+        self.description = Some(description.into());
+        self
+    }
+
     pub fn complete(&self, line: &str) -> String {
         let mut res = line.to_string();
         res.insert_str(self.position, &self.completion);
@@ -50,6 +61,10 @@ impl Completion {
         &self.display
     }
 
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
     pub fn replacement(&self) -> &str {
         &self.completion
     }
@@ -144,6 +159,7 @@ fn complete_label(
                     completion_suffix(value.field(k), t)
                 ),
                 display: k.clone(),
+                description: None,
                 position: cursor,
             })
             .collect(),
@@ -196,6 +212,7 @@ fn complete_file(
                         },
                     ),
                     display: k.name.to_str().unwrap().to_string(),
+                    description: None,
                     position: cursor,
                 })
                 .collect(),
@@ -222,6 +239,7 @@ fn complete_argument_name(
                     if is_switch { " " } else { "=" }
                 ),
                 display: a.name.clone(),
+                description: None,
                 position: cursor,
             })
             .collect(),
@@ -257,6 +275,7 @@ fn complete_argument_description(
     parse_result: &PartialCommandResult,
     cursor: usize,
     scope: &Scope,
+    state: &GlobalState,
     res: &mut Vec<Completion>,
 ) -> CrushResult<()> {
     if let Some(allowed) = &argument_description.allowed {
@@ -265,6 +284,9 @@ fn complete_argument_description(
     if let Some(cmd) = &argument_description.complete {
         cmd(&parse_result, cursor, scope, res)?;
     }
+    if let Some(tag) = &argument_description.completion_tag {
+        complete_tag(tag, &parse_result.last_argument, cursor, scope, state, res)?;
+    }
     Ok(())
 }
 
@@ -272,11 +294,18 @@ fn complete_partial_argument(
     parse_result: PartialCommandResult,
     cursor: usize,
     scope: &Scope,
+    state: &GlobalState,
     lister: &impl DirectoryLister,
     res: &mut Vec<Completion>,
 ) -> CrushResult<()> {
     if let Some(desc) = parse_result.last_argument_description() {
-        complete_argument_description(desc, &parse_result, cursor, scope, res)?;
+        complete_argument_description(desc, &parse_result, cursor, scope, state, res)?;
+    }
+    if let CompletionCommand::External(name) = &parse_result.command {
+        if let Some(tag) = state.external_completion(name) {
+            complete_tag(&tag, &parse_result.last_argument, cursor, scope, state, res)?;
+        }
+        complete_external(name, &parse_result, cursor, scope, state, res)?;
     }
 
     let argument_type = parse_result.last_argument_type();
@@ -340,10 +369,10 @@ pub fn complete(
     line: &str,
     cursor: usize,
     scope: &Scope,
-    parser: &Parser,
+    state: &GlobalState,
     lister: &impl DirectoryLister,
 ) -> CrushResult<Vec<Completion>> {
-    let parse_result = parse(line, cursor, scope, parser)?;
+    let parse_result = parse(line, cursor, scope, state.parser())?;
     let mut res = Vec::new();
     match parse_result {
         ParseResult::Nothing => {
@@ -386,10 +415,20 @@ pub fn complete(
         }
 
         ParseResult::PartialArgument(parse_result) => {
-            complete_partial_argument(parse_result, cursor, scope, lister, &mut res)?
+            complete_partial_argument(parse_result, cursor, scope, state, lister, &mut res)?
         }
 
         ParseResult::PartialQuotedString(_) => {}
+
+        ParseResult::PartialInterpolation(parent, label) => {
+            complete_label(parent, &label, &ValueType::Any, cursor, &mut res)?;
+            // The completed name is followed by more text of the string, not by another argument
+            for completion in &mut res {
+                if completion.completion.ends_with(' ') {
+                    completion.completion.pop();
+                }
+            }
+        }
     }
 
     Ok(res)
@@ -398,13 +437,19 @@ pub fn complete(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::lang::any_str::AnyStr;
+    use crate::lang::command::CrushCommand;
+    use crate::lang::command::OutputType::Known;
+    use crate::lang::data::list::List;
+    use crate::lang::printer::noop;
     use crate::lang::state::contexts::CommandContext;
+    use crate::lang::state::global_state::RunMode;
     use crate::lang::value::Value;
     use crate::util::directory_lister::tests::FakeDirectoryLister;
     use signature::signature;
 
-    fn parser() -> Parser {
-        Parser::new()
+    fn state() -> GlobalState {
+        GlobalState::new(noop().0, RunMode::NonInteractive).unwrap()
     }
 
     fn lister() -> FakeDirectoryLister {
@@ -432,6 +477,38 @@ mod tests {
         Ok(())
     }
 
+    fn tagged_cmd(_context: CommandContext) -> CrushResult<()> {
+        Ok(())
+    }
+
+    fn vegetables(context: CommandContext) -> CrushResult<()> {
+        context.output.send(
+            List::new(
+                ValueType::String,
+                vec![Value::from("carrot"), Value::from("lettuce")],
+            )
+            .into(),
+        )
+    }
+
+    fn state_with_provider() -> GlobalState {
+        let state = state();
+        state.set_completion_provider(
+            "vegetable".to_string(),
+            Some(<dyn CrushCommand>::command(
+                vegetables,
+                false,
+                ["global", "vegetables"],
+                "vegetables",
+                "All the vegetables",
+                None::<AnyStr>,
+                Known(ValueType::List(Box::from(ValueType::String))),
+                [],
+            )),
+        );
+        state
+    }
+
     #[signature(my_cmd)]
     struct MyCmdSignature {
         super_fancy_argument: ValueType,
@@ -443,6 +520,13 @@ mod tests {
         argument: String,
     }
 
+    #[signature(tagged_cmd)]
+    #[allow(dead_code)]
+    struct TaggedCmdSignature {
+        #[completion_tag("vegetable")]
+        food: String,
+    }
+
     #[signature(multi_argument_cmd)]
     struct MultiArgumentCmdSignature {
         #[values("foo")]
@@ -472,6 +556,7 @@ mod tests {
                 Box::new(|env| {
                     AllowedCmdSignature::declare(env)?;
                     MultiArgumentCmdSignature::declare(env)?;
+                    TaggedCmdSignature::declare(env)?;
                     Ok(())
                 }),
             )
@@ -487,7 +572,7 @@ mod tests {
 
         let s = Scope::create_root();
         s.declare("abcd", Value::Empty).unwrap();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(&completions[0].complete(line), "abcd ");
     }
@@ -498,7 +583,7 @@ mod tests {
         let cursor = 0;
 
         let s = Scope::create_root();
-        let completions = complete(line, cursor, &s, &parser(), &lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(&completions[0].complete(line), "burrow/");
     }
@@ -509,7 +594,7 @@ mod tests {
         let cursor = line.len();
 
         let s = scope_with_function();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(
             &completions[0].complete(line),
@@ -523,7 +608,7 @@ mod tests {
         let cursor = line.len();
 
         let s = scope_with_function();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(
             &completions[0].complete(line),
@@ -537,7 +622,7 @@ mod tests {
         let cursor = line.len();
 
         let s = scope_with_function();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
     }
 
@@ -548,7 +633,7 @@ mod tests {
 
         let s = scope_with_function();
         s.declare("super_confusing_variable", Value::Empty).unwrap();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(
             &completions[0].complete(line),
@@ -562,7 +647,7 @@ mod tests {
         let cursor = line.len();
 
         let s = scope_with_function();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(&completions[0].complete(line), "namespace:my_cmd ");
     }
@@ -573,7 +658,7 @@ mod tests {
         let cursor = line.len();
 
         let s = scope_with_function();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(&completions[0].complete(line), "namespace:my_cmd ");
     }
@@ -584,7 +669,7 @@ mod tests {
         let cursor = line.len();
 
         let s = scope_with_function();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(&completions[0].complete(line), "namespace:");
     }
@@ -596,7 +681,7 @@ mod tests {
 
         let s = Scope::create_root();
         s.declare("abcd", Value::Empty).unwrap();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(&completions[0].complete(line), "a | abcd ");
     }
@@ -607,7 +692,7 @@ mod tests {
         let cursor = line.len();
 
         let s = scope_with_function();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(
             &completions[0].complete(line),
@@ -622,7 +707,7 @@ mod tests {
 
         let s = Scope::create_root();
         s.declare("abcd", Value::Empty).unwrap();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(&completions[0].complete(line), "a abcd ");
     }
@@ -634,7 +719,7 @@ mod tests {
 
         let s = Scope::create_root();
         s.declare("abcd", Value::Empty).unwrap();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(&completions[0].complete(line), "x (abcd ");
     }
@@ -646,7 +731,7 @@ mod tests {
 
         let s = Scope::create_root();
         s.declare("abcd", Value::Empty).unwrap();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(&completions[0].complete(line), "abcd ");
     }
@@ -657,7 +742,7 @@ mod tests {
         let cursor = line.len();
 
         let s = Scope::create_root();
-        let completions = complete(line, cursor, &s, &parser(), &lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(&completions[0].complete(line), "'burrow/");
     }
@@ -668,7 +753,7 @@ mod tests {
         let cursor = line.len();
 
         let s = Scope::create_root();
-        let completions = complete(line, cursor, &s, &parser(), &lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(&completions[0].complete(line), "./burrow/");
     }
@@ -679,7 +764,7 @@ mod tests {
         let cursor = line.len();
 
         let s = Scope::create_root();
-        let completions = complete(line, cursor, &s, &parser(), &lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &lister()).unwrap();
         assert_eq!(completions.len(), 3);
         assert_eq!(&completions[0].complete(line), "./burrow/carrot ");
     }
@@ -690,7 +775,7 @@ mod tests {
         let cursor = line.len();
 
         let s = Scope::create_root();
-        let completions = complete(line, cursor, &s, &parser(), &lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(&completions[0].complete(line), "./burrow/carrot ");
     }
@@ -701,7 +786,7 @@ mod tests {
         let cursor = line.len();
 
         let s = Scope::create_root();
-        let completions = complete(line, cursor, &s, &parser(), &lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(&completions[0].complete(line), "'burrow/carrot' ");
     }
@@ -722,7 +807,7 @@ mod tests {
         )
         .unwrap();
 
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(&completions[0].complete(line), "abcd:bcde ");
     }
@@ -743,7 +828,7 @@ mod tests {
         )
         .unwrap();
 
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(&completions[0].complete(line), "xxx $abcd:bcde ");
     }
@@ -755,7 +840,7 @@ mod tests {
 
         let s = Scope::create_root();
         s.declare("abcd", Value::Empty).unwrap();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(&completions[0].complete(line), "abcd $abcd ");
     }
@@ -767,7 +852,7 @@ mod tests {
 
         let s = Scope::create_root();
         s.declare("abcd", Value::Empty).unwrap();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(&completions[0].complete(line), "abcd b");
     }
@@ -779,7 +864,7 @@ mod tests {
 
         let s = Scope::create_root();
         s.declare("cdef", Value::Empty).unwrap();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(&completions[0].complete(line), "ab $cdef  ef");
     }
@@ -791,7 +876,7 @@ mod tests {
 
         let s = Scope::create_root();
         s.declare("cdef", Value::Empty).unwrap();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(&completions[0].complete(line), "ab foo=$cdef ");
     }
@@ -804,7 +889,7 @@ mod tests {
         let s = Scope::create_root();
         s.declare("xxxx", Value::Empty).unwrap();
         s.declare("aaaa", Value::Empty).unwrap();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 2);
     }
 
@@ -816,7 +901,7 @@ mod tests {
         let s = scope_with_function();
         s.declare("tumbleweed", Value::Empty).unwrap();
         s.declare("type", Value::Type(ValueType::Empty)).unwrap();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(
             &completions[0].complete(line),
@@ -830,7 +915,7 @@ mod tests {
         let cursor = line.len();
 
         let s = scope_with_function();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(
             &completions[0].complete(line),
@@ -838,13 +923,43 @@ mod tests {
         );
     }
 
+    #[test]
+    fn check_tagged_quoted_argument_completion() {
+        let line = "other_namespace:tagged_cmd \"c";
+        let cursor = line.len();
+
+        let s = scope_with_function();
+        let completions =
+            complete(line, cursor, &s, &state_with_provider(), &empty_lister()).unwrap();
+        assert_eq!(completions.len(), 1);
+        assert_eq!(
+            &completions[0].complete(line),
+            "other_namespace:tagged_cmd \"carrot\" "
+        );
+    }
+
+    #[test]
+    fn check_tagged_unquoted_argument_completion() {
+        let line = "other_namespace:tagged_cmd food=let";
+        let cursor = line.len();
+
+        let s = scope_with_function();
+        let completions =
+            complete(line, cursor, &s, &state_with_provider(), &empty_lister()).unwrap();
+        assert_eq!(completions.len(), 1);
+        assert_eq!(
+            &completions[0].complete(line),
+            "other_namespace:tagged_cmd food=lettuce "
+        );
+    }
+
     #[test]
     fn check_simple_argument_description_tracking() {
         let line = "other_namespace:allowed_cmd \"f";
         let cursor = line.len();
 
         let s = scope_with_function();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(
             &completions[0].complete(line),
@@ -858,7 +973,7 @@ mod tests {
         let cursor = line.len();
 
         let s = scope_with_function();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(
             &completions[0].complete(line),
@@ -872,7 +987,7 @@ mod tests {
         let cursor = line.len();
 
         let s = scope_with_function();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(
             &completions[0].complete(line),
@@ -886,7 +1001,7 @@ mod tests {
         let cursor = line.len();
 
         let s = scope_with_function();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(
             &completions[0].complete(line),
@@ -900,7 +1015,7 @@ mod tests {
         let cursor = line.len();
 
         let s = scope_with_function();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(
             &completions[0].complete(line),
@@ -914,8 +1029,53 @@ mod tests {
         let cursor = line.len();
 
         let s = Scope::create_root();
-        let completions = complete(line, cursor, &s, &parser(), &empty_lister()).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
         assert_eq!(completions.len(), 1);
         assert_eq!(&completions[0].complete(line), "\"\":format ");
     }
+
+    #[test]
+    fn check_interpolation_completion() {
+        let line = "echo $\"Hello $ab";
+        let cursor = line.len();
+
+        let s = Scope::create_root();
+        s.declare("abcd", Value::from("x")).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
+        assert_eq!(completions.len(), 1);
+        assert_eq!(&completions[0].complete(line), "echo $\"Hello $abcd");
+    }
+
+    #[test]
+    fn check_interpolation_member_completion() {
+        let line = "$\"$namespace:";
+        let cursor = line.len();
+
+        let s = scope_with_function();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
+        assert_eq!(completions.len(), 1);
+        assert_eq!(&completions[0].complete(line), "$\"$namespace:my_cmd");
+    }
+
+    #[test]
+    fn check_interpolation_substitution_completion() {
+        let line = "$\"a $(namespace:my";
+        let cursor = line.len();
+
+        let s = scope_with_function();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
+        assert_eq!(completions.len(), 1);
+        assert_eq!(&completions[0].complete(line), "$\"a $(namespace:my_cmd ");
+    }
+
+    #[test]
+    fn check_no_completion_in_interpolation_text() {
+        let line = "$\"abc";
+        let cursor = line.len();
+
+        let s = Scope::create_root();
+        s.declare("abcd", Value::from("x")).unwrap();
+        let completions = complete(line, cursor, &s, &state(), &empty_lister()).unwrap();
+        assert_eq!(completions.len(), 0);
+    }
 }
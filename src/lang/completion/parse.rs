@@ -2,6 +2,7 @@ use crate::lang::ast::lexer::LanguageMode;
 use crate::lang::ast::node::TextLiteralStyle;
 use crate::lang::ast::{CommandNode, JobListNode, JobNode, node::Node};
 use crate::lang::command::{Command, Parameter};
+use crate::lang::command_invocation::resolve_external_command;
 use crate::lang::errors::{CrushResult, command_error, error};
 use crate::lang::parser::Parser;
 use crate::lang::state::scope::Scope;
@@ -17,6 +18,7 @@ use std::ops::Deref;
 pub enum CompletionCommand {
     Unknown,
     Known(Command),
+    External(String),
 }
 
 impl Clone for CompletionCommand {
@@ -24,6 +26,7 @@ impl Clone for CompletionCommand {
         match self {
             CompletionCommand::Unknown => CompletionCommand::Unknown,
             CompletionCommand::Known(c) => CompletionCommand::Known(c.clone()),
+            CompletionCommand::External(name) => CompletionCommand::External(name.clone()),
         }
     }
 }
@@ -125,6 +128,8 @@ pub enum ParseResult {
     PartialMember(Value, String),
     PartialFile(String, TextLiteralStyle),
     PartialQuotedString(String),
+    /// A variable or member embedded in an interpolated string, e.g. `$"Hello $us`.
+    PartialInterpolation(Value, String),
     PartialArgument(PartialCommandResult),
 }
 
@@ -152,6 +157,10 @@ impl Display for ParseResult {
                 f.write_str("string ")?;
                 f.write_str(s)
             }
+            ParseResult::PartialInterpolation(_p, l) => {
+                f.write_str("interpolation ")?;
+                f.write_str(l)
+            }
             ParseResult::PartialArgument(_a) => f.write_str("command"),
         }
     }
@@ -181,6 +190,39 @@ fn find_command_in_expression<'input>(
             }
         }
 
+        Node::Interpolation(parts, location) => {
+            for part in parts {
+                if let Some(res) = find_command_in_expression(part, cursor)? {
+                    return Ok(Some(res));
+                }
+            }
+            if location.contains(cursor) {
+                Ok(Some(CommandNode {
+                    expressions: vec![exp.clone()],
+                    location: *location,
+                }))
+            } else {
+                Ok(None)
+            }
+        }
+
+        Node::Match(value, arms, _) => {
+            if let Some(res) = find_command_in_expression(value, cursor)? {
+                return Ok(Some(res));
+            }
+            for arm in arms {
+                if let Some(guard) = &arm.guard {
+                    if let Some(res) = find_command_in_expression(guard, cursor)? {
+                        return Ok(Some(res));
+                    }
+                }
+                if arm.body.location.contains(cursor) {
+                    return Ok(Some(find_command_in_job_list(arm.body.clone(), cursor)?));
+                }
+            }
+            Ok(None)
+        }
+
         _ => Ok(None),
     }
 }
@@ -251,9 +293,67 @@ fn fetch_value(node: &Node, scope: &Scope, is_command: bool) -> CrushResult<Opti
     }
 }
 
+/// Complete the variable or member that the cursor is on inside of an interpolated string. A
+/// trailing `$` or `:` that isn't followed by a name yet is part of the text of the string.
+fn parse_interpolation(parts: &[Node], cursor: usize, scope: &Scope) -> CrushResult<ParseResult> {
+    let mut previous: Option<&Node> = None;
+    for part in parts {
+        if part.location().contains(cursor) {
+            match (part, previous) {
+                (Node::Identifier(label), _) => {
+                    return Ok(ParseResult::PartialInterpolation(
+                        Value::Scope(scope.clone()),
+                        label.prefix(cursor).string,
+                    ));
+                }
+
+                (Node::GetAttr(parent, field), _) => {
+                    return Ok(ParseResult::PartialInterpolation(
+                        fetch_value(parent, scope, false)?.ok_or("Unknown value")?,
+                        field.prefix(cursor).string,
+                    ));
+                }
+
+                (Node::String(text, TextLiteralStyle::Quoted), _)
+                    if text.location.end == cursor
+                        && text.string.ends_with('$')
+                        && !text.string.ends_with("\\$") =>
+                {
+                    return Ok(ParseResult::PartialInterpolation(
+                        Value::Scope(scope.clone()),
+                        String::new(),
+                    ));
+                }
+
+                (
+                    Node::String(text, TextLiteralStyle::Quoted),
+                    Some(value @ (Node::Identifier(_) | Node::GetAttr(_, _))),
+                ) if text.location.end == cursor && text.string == ":" => {
+                    return Ok(ParseResult::PartialInterpolation(
+                        fetch_value(value, scope, false)?.ok_or("Unknown value")?,
+                        String::new(),
+                    ));
+                }
+
+                _ => {}
+            }
+        }
+        previous = Some(part);
+    }
+    Ok(ParseResult::PartialQuotedString(String::new()))
+}
+
 fn parse_command_node(node: &Node, scope: &Scope) -> CrushResult<CompletionCommand> {
     match fetch_value(node, scope, true)? {
         Some(Value::Command(command)) => Ok(CompletionCommand::Known(command)),
+        None => match node {
+            Node::String(name, TextLiteralStyle::Unquoted)
+                if resolve_external_command(&name.string, scope)?.is_some() =>
+            {
+                Ok(CompletionCommand::External(name.string.clone()))
+            }
+            _ => Ok(CompletionCommand::Unknown),
+        },
         _ => Ok(CompletionCommand::Unknown),
     }
 }
@@ -276,6 +376,13 @@ fn parse_previous_argument(arg: &Node) -> PreviousArgument {
             _ => {}
         },
 
+        Node::String(s, TextLiteralStyle::Unquoted) => {
+            return PreviousArgument {
+                name: None,
+                value: PreviousArgumentValue::Value(Value::from(s.string.as_str())),
+            };
+        }
+
         _ => {}
     }
     PreviousArgument {
@@ -336,6 +443,8 @@ pub fn parse(
                         panic!("AAA");
                     }
 
+                    Node::Interpolation(parts, _) => parse_interpolation(parts, cursor, scope),
+
                     _ => error(format!(
                         "Can't extract command to complete. Unknown node type {}",
                         cmd.type_name()
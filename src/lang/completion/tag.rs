@@ -0,0 +1,248 @@
+/**
+Completion tags name a kind of value, like `hostname` or `git/branch`. A parameter declares its tag
+using the `completion_tag` attribute of the signature macro, and external commands can be assigned
+a tag using `crush:completion:external`. The candidates for a tag come from its completion provider.
+
+Crush has builtin providers for a few common tags. Any tag can also be given a provider written in
+Crush using `crush:completion:set`, which takes precedence over the builtin one.
+ */
+use crate::lang::ast::source::{Source, SourceType};
+use crate::lang::command::Command;
+use crate::lang::command_invocation::CommandInvocation;
+use crate::lang::completion::Completion;
+use crate::lang::completion::parse::LastArgument;
+use crate::lang::errors::{CrushResult, data_error};
+use crate::lang::pipe::{empty_channel, pipe};
+use crate::lang::state::contexts::JobContext;
+use crate::lang::state::global_state::GlobalState;
+use crate::lang::state::scope::Scope;
+use crate::lang::value::{Value, ValueDefinition};
+use crate::util::escape::{escape, escape_without_quotes};
+use crate::util::file::home;
+use crate::util::user_map::{create_group_map, get_all_users};
+use num_format::SystemLocale;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+pub struct BuiltinProvider {
+    pub tag: &'static str,
+    pub description: &'static str,
+    provider: fn() -> CrushResult<Vec<String>>,
+}
+
+pub fn builtin_providers() -> &'static [BuiltinProvider] {
+    &[
+        BuiltinProvider {
+            tag: "hostname",
+            description: "Hosts in ~/.ssh/known_hosts and /etc/hosts",
+            provider: hostnames,
+        },
+        BuiltinProvider {
+            tag: "user",
+            description: "Users on this system",
+            provider: users,
+        },
+        BuiltinProvider {
+            tag: "group",
+            description: "Groups on this system",
+            provider: groups,
+        },
+        BuiltinProvider {
+            tag: "locale",
+            description: "Locales available on this system",
+            provider: locales,
+        },
+        BuiltinProvider {
+            tag: "git/branch",
+            description: "Local and remote branches of the git repository in the current directory",
+            provider: git_branches,
+        },
+    ]
+}
+
+/// Add the first field of every line of a hosts file, skipping hashed entries.
+fn read_known_hosts(hosts: &mut BTreeSet<String>) {
+    let Ok(file) = home().and_then(|h| Ok(std::fs::read_to_string(h.join(".ssh/known_hosts"))?))
+    else {
+        return;
+    };
+    for line in file.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with('|') {
+            continue;
+        }
+        let line = line.strip_prefix("@cert-authority ").unwrap_or(line);
+        if let Some(names) = line.split_whitespace().next() {
+            for name in names.split(',') {
+                // Hosts on non-standard ports are written as [host]:port
+                let name = name
+                    .strip_prefix('[')
+                    .and_then(|n| n.split_once(']'))
+                    .map(|(n, _)| n)
+                    .unwrap_or(name);
+                hosts.insert(name.to_string());
+            }
+        }
+    }
+}
+
+fn read_etc_hosts(hosts: &mut BTreeSet<String>) {
+    let Ok(file) = std::fs::read_to_string("/etc/hosts") else {
+        return;
+    };
+    for line in file.lines() {
+        let line = line.split('#').next().unwrap_or("");
+        for name in line.split_whitespace().skip(1) {
+            hosts.insert(name.to_string());
+        }
+    }
+}
+
+fn hostnames() -> CrushResult<Vec<String>> {
+    let mut hosts = BTreeSet::new();
+    read_known_hosts(&mut hosts);
+    read_etc_hosts(&mut hosts);
+    Ok(hosts.into_iter().collect())
+}
+
+fn users() -> CrushResult<Vec<String>> {
+    Ok(get_all_users()?.into_iter().map(|u| u.name).collect())
+}
+
+fn groups() -> CrushResult<Vec<String>> {
+    let names: BTreeSet<String> = create_group_map()?.into_values().collect();
+    Ok(names.into_iter().collect())
+}
+
+fn locales() -> CrushResult<Vec<String>> {
+    let names: BTreeSet<String> = SystemLocale::available_names()?.into_iter().collect();
+    Ok(names.into_iter().collect())
+}
+
+fn git_branches() -> CrushResult<Vec<String>> {
+    let output = std::process::Command::new("git")
+        .args([
+            "for-each-ref",
+            "--format=%(refname:short)",
+            "refs/heads",
+            "refs/remotes",
+        ])
+        .output();
+    match output {
+        Ok(output) if output.status.success() => Ok(String::from_utf8(output.stdout)?
+            .lines()
+            .map(|l| l.to_string())
+            .collect()),
+        _ => Ok(vec![]),
+    }
+}
+
+/// Convert the output of a completion provider into a list of candidates. Providers can return a
+/// list, or a table or table stream, in which case the first column is used.
+fn to_candidates(value: Value) -> CrushResult<Vec<String>> {
+    match value {
+        Value::Empty => Ok(vec![]),
+        Value::String(s) => Ok(vec![s.to_string()]),
+        Value::List(l) => Ok(l.iter().map(|v| v.to_string()).collect()),
+        value @ (Value::Table(_) | Value::TableInputStream(_)) => {
+            let mut stream = value.stream()?;
+            let mut res = Vec::new();
+            while let Ok(row) = stream.read() {
+                if let Some(cell) = row.into_cells().into_iter().next() {
+                    res.push(cell.to_string());
+                }
+            }
+            Ok(res)
+        }
+        value => data_error(format!(
+            "Completion providers must return a list or a table, got a value of type `{}`.",
+            value.value_type()
+        )),
+    }
+}
+
+fn run_provider(provider: Command, scope: &Scope, state: &GlobalState) -> CrushResult<Vec<String>> {
+    let source = Source::new(SourceType::Input, Arc::from(""));
+    let cmd = CommandInvocation::new(
+        ValueDefinition::Value(Value::Command(provider), source.clone()),
+        source,
+        vec![],
+    );
+    let (snd, recv) = pipe();
+    cmd.eval(JobContext::new(
+        empty_channel(),
+        snd,
+        scope.clone(),
+        state.clone(),
+    ))?;
+    to_candidates(recv.recv()?)
+}
+
+/// All candidates for the specified tag, or nothing if there is no provider for it.
+pub fn candidates(tag: &str, scope: &Scope, state: &GlobalState) -> CrushResult<Vec<String>> {
+    if let Some(provider) = state.completion_provider(tag) {
+        return run_provider(provider, scope, state);
+    }
+    match builtin_providers().iter().find(|p| p.tag == tag) {
+        Some(p) => (p.provider)(),
+        None => Ok(vec![]),
+    }
+}
+
+/// Candidates consisting only of these characters can be completed without quoting them.
+fn is_bare(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+pub fn complete_tag(
+    tag: &str,
+    last_argument: &LastArgument,
+    cursor: usize,
+    scope: &Scope,
+    state: &GlobalState,
+    res: &mut Vec<Completion>,
+) -> CrushResult<()> {
+    let candidates = match last_argument {
+        LastArgument::Unknown | LastArgument::QuotedString(_) | LastArgument::Field(_) => {
+            candidates(tag, scope, state)?
+        }
+        _ => return Ok(()),
+    };
+    for candidate in candidates {
+        match last_argument {
+            LastArgument::Unknown => {
+                let completion = if is_bare(&candidate) {
+                    format!("{} ", candidate)
+                } else {
+                    format!("{} ", escape(&candidate))
+                };
+                res.push(Completion::new(completion, candidate, cursor));
+            }
+
+            LastArgument::QuotedString(prefix)
+                if candidate.len() > prefix.len() && candidate.starts_with(prefix.as_str()) =>
+            {
+                res.push(Completion::new(
+                    format!("{}\" ", escape_without_quotes(&candidate[prefix.len()..])),
+                    candidate,
+                    cursor,
+                ));
+            }
+
+            LastArgument::Field(prefix)
+                if is_bare(&candidate)
+                    && candidate.len() > prefix.len()
+                    && candidate.starts_with(prefix.as_str()) =>
+            {
+                res.push(Completion::new(
+                    format!("{} ", &candidate[prefix.len()..]),
+                    candidate,
+                    cursor,
+                ));
+            }
+
+            _ => {}
+        }
+    }
+    Ok(())
+}
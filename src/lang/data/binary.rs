@@ -74,6 +74,27 @@ impl Write for ChannelWriter {
     }
 }
 
+/// The write end of a binary pipe. Every clone of the stream writes into the same pipe, and the
+/// read end sees end of file once all clones have been dropped.
+#[derive(Clone)]
+pub struct BinaryOutputStream {
+    sender: Sender<Box<[u8]>>,
+}
+
+impl BinaryOutputStream {
+    pub fn writer(&self) -> Box<dyn Write + Send> {
+        Box::from(ChannelWriter {
+            sender: self.sender.clone(),
+        })
+    }
+}
+
+impl Debug for BinaryOutputStream {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        f.write_str("<binary output stream>")
+    }
+}
+
 pub trait BinaryReader: Read + Debug + Send + Sync {
     fn clone(&self) -> Box<dyn BinaryReader + Send + Sync>;
 }
@@ -133,7 +154,7 @@ impl dyn BinaryReader {
     }
 }
 
-pub fn binary_channel() -> (Box<dyn Write>, Box<dyn BinaryReader + Send + Sync>) {
+pub fn binary_channel() -> (Box<dyn Write + Send>, Box<dyn BinaryReader + Send + Sync>) {
     let (s, r) = bounded(32);
     (
         Box::from(ChannelWriter { sender: s }),
@@ -144,6 +165,17 @@ pub fn binary_channel() -> (Box<dyn Write>, Box<dyn BinaryReader + Send + Sync>)
     )
 }
 
+pub fn binary_pipe() -> (BinaryOutputStream, Box<dyn BinaryReader + Send + Sync>) {
+    let (s, r) = bounded(32);
+    (
+        BinaryOutputStream { sender: s },
+        Box::from(ChannelReader {
+            receiver: r,
+            buff: None,
+        }),
+    )
+}
+
 pub(crate) struct MultiReader {
     readers: VecDeque<Box<dyn BinaryReader + Send + Sync>>,
 }
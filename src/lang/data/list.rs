@@ -282,6 +282,9 @@ impl List {
                 Value::File(s) => destination.push(Files::File(s.clone())),
                 Value::Glob(s) => destination.push(Files::Glob(s.clone())),
                 Value::Regex(_, re) => destination.push(Files::Regex(re.clone())),
+                Value::BinaryOutputStream(s) => {
+                    destination.push(Files::BinaryOutputStream(s.clone()))
+                }
                 _ => return error("Wrong element type"),
             }
         }
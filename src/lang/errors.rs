@@ -6,6 +6,7 @@
 use crate::lang::ast::location::Location;
 use crate::lang::ast::source::Source;
 use crate::lang::ast::token;
+use crate::lang::state::global_state::ExitStatus;
 use crate::lang::state::scope::Scope;
 use CrushErrorType::*;
 use reqwest::header::ToStrError;
@@ -44,6 +45,14 @@ pub enum CrushErrorType {
     SerdeJsonError(serde_json::Error),
     SerdeTomlError(toml::de::Error),
     SerdeYamlError(serde_yaml::Error),
+    CsvError(csv::Error),
+    ArrowError(arrow_schema::ArrowError),
+    ParquetError(parquet::errors::ParquetError),
+    ProtobufDecodeError(prost::DecodeError),
+    ProtobufDescriptorError(prost_reflect::DescriptorError),
+    GrpcStatus(Box<tonic::Status>),
+    GrpcTransportError(tonic::transport::Error),
+    SqliteError(rusqlite::Error),
     SSH2Error(ssh2::Error),
     ChronoParseError(chrono::ParseError),
     LoginsError(String),
@@ -52,12 +61,13 @@ pub enum CrushErrorType {
     InvalidJump(String),
     #[cfg(target_os = "linux")]
     DbusError(dbus::Error),
-    #[cfg(target_os = "linux")]
     Roxmltree(roxmltree::Error),
     AddrParseError(std::net::AddrParseError),
     ToStrError(ToStrError),
     Message(markdown::message::Message),
     FromHexError(hex::FromHexError),
+    ExitStatusError(ExitStatus),
+    UserError(String),
 }
 
 #[derive(Debug)]
@@ -75,7 +85,7 @@ impl CrushError {
 
     pub fn message(&self) -> String {
         match &self.error_type {
-            InvalidArgument(s) | InvalidData(s) | GenericError(s) => s.clone(),
+            InvalidArgument(s) | InvalidData(s) | GenericError(s) | UserError(s) => s.clone(),
             SendError(e) => e.to_string(),
             EOFError => "EOF error".to_string(),
             IOError(e) => e.to_string(),
@@ -105,6 +115,17 @@ impl CrushError {
             SerdeJsonError(e) => e.to_string(),
             SerdeTomlError(e) => e.to_string(),
             SerdeYamlError(e) => e.to_string(),
+            CsvError(e) => e.to_string(),
+            ArrowError(e) => e.to_string(),
+            ParquetError(e) => e.to_string(),
+            ProtobufDecodeError(e) => e.to_string(),
+            ProtobufDescriptorError(e) => e.to_string(),
+            GrpcStatus(e) => format!("{}: {}", e.code(), e.message()),
+            GrpcTransportError(e) => match std::error::Error::source(e) {
+                Some(source) => format!("{}: {}", e, source),
+                None => e.to_string(),
+            },
+            SqliteError(e) => e.to_string(),
             SSH2Error(e) => e.to_string(),
             ChronoParseError(e) => e.to_string(),
             LoginsError(e) => e.to_string(),
@@ -115,13 +136,62 @@ impl CrushError {
             ToStrError(e) => e.to_string(),
             Message(m) => m.to_string(),
             FromHexError(e) => e.to_string(),
+            ExitStatusError(e) => e.to_string(),
             #[cfg(target_os = "linux")]
             DbusError(e) => e.message().unwrap_or("").to_string(),
-            #[cfg(target_os = "linux")]
             Roxmltree(e) => e.to_string(),
         }
     }
 
+    /// A short, stable name for the type of this error, suitable for inspecting errors in scripts.
+    pub fn kind(&self) -> &'static str {
+        match &self.error_type {
+            InvalidArgument(_) => "invalid_argument",
+            InvalidData(_) => "invalid_data",
+            GenericError(_) => "generic",
+            SendError(_) | RecvError(_) => "channel",
+            EOFError => "eof",
+            IOError(_) => "io",
+            RegexError(_) => "regex",
+            ParseIntError(_) | ParseFloatError(_) | ParseBoolError(_) | ChronoParseError(_)
+            | AddrParseError(_) | CharTryFromError(_) | TryFromIntError(_) | FromHexError(_) => {
+                "conversion"
+            }
+            LexicalError(_) | ParseError(_, _) => "parse",
+            NumFormatError(_) | ByteUnitError(_) => "format",
+            PoisonError(_) => "poison",
+            ReadlineError(_) => "readline",
+            FromUtf8Error(_) | Utf8Error(_) | ToStrError(_) => "encoding",
+            OutOfRangeError(_) => "out_of_range",
+            VarError(_) => "environment",
+            ResolveConfParseError(_) | DnsProtoError(_) | DnsClientError(_) => "dns",
+            MountpointsError(_) => "mountpoints",
+            BatteryError(_) => "battery",
+            NixError(_) => "system",
+            ReqwestError(_) => "http",
+            SerdeJsonError(_)
+            | SerdeTomlError(_)
+            | SerdeYamlError(_)
+            | CsvError(_)
+            | ArrowError(_)
+            | ParquetError(_)
+            | ProtobufDecodeError(_)
+            | ProtobufDescriptorError(_)
+            | SerializationError(_) => "serialization",
+            GrpcStatus(_) | GrpcTransportError(_) => "grpc",
+            Message(_) => "markdown",
+            SqliteError(_) => "sqlite",
+            SSH2Error(_) => "ssh",
+            LoginsError(_) => "logins",
+            InvalidJump(_) => "invalid_jump",
+            ExitStatusError(_) => "exit_status",
+            UserError(_) => "user",
+            #[cfg(target_os = "linux")]
+            DbusError(_) => "dbus",
+            Roxmltree(_) => "serialization",
+        }
+    }
+
     pub fn location(&self) -> Option<Location> {
         self.source.as_ref().map(|s| s.location())
     }
@@ -237,7 +307,6 @@ impl From<dbus::Error> for CrushError {
     }
 }
 
-#[cfg(target_os = "linux")]
 impl From<roxmltree::Error> for CrushError {
     fn from(e: roxmltree::Error) -> Self {
         Roxmltree(e).into()
@@ -405,6 +474,54 @@ impl From<serde_yaml::Error> for CrushError {
     }
 }
 
+impl From<csv::Error> for CrushError {
+    fn from(e: csv::Error) -> Self {
+        CsvError(e).into()
+    }
+}
+
+impl From<arrow_schema::ArrowError> for CrushError {
+    fn from(e: arrow_schema::ArrowError) -> Self {
+        ArrowError(e).into()
+    }
+}
+
+impl From<parquet::errors::ParquetError> for CrushError {
+    fn from(e: parquet::errors::ParquetError) -> Self {
+        ParquetError(e).into()
+    }
+}
+
+impl From<prost::DecodeError> for CrushError {
+    fn from(e: prost::DecodeError) -> Self {
+        ProtobufDecodeError(e).into()
+    }
+}
+
+impl From<prost_reflect::DescriptorError> for CrushError {
+    fn from(e: prost_reflect::DescriptorError) -> Self {
+        ProtobufDescriptorError(e).into()
+    }
+}
+
+impl From<tonic::Status> for CrushError {
+    fn from(e: tonic::Status) -> Self {
+        GrpcStatus(Box::new(e)).into()
+    }
+}
+
+impl From<tonic::transport::Error> for CrushError {
+    fn from(e: tonic::transport::Error) -> Self {
+        GrpcTransportError(e).into()
+    }
+}
+
+impl From<rusqlite::Error> for CrushError {
+    fn from(e: rusqlite::Error) -> Self {
+        SqliteError(e).into()
+    }
+}
+
 impl From<ssh2::Error> for CrushError {
     fn from(e: ssh2::Error) -> Self {
         SSH2Error(e).into()
@@ -481,6 +598,16 @@ pub fn invalid_jump<T>(message: impl Into<String>) -> CrushResult<T> {
     Err(InvalidJump(message.into()).into())
 }
 
+/// Emit this error when a script explicitly raises an error.
+pub fn user_error<T>(message: impl Into<String>) -> CrushResult<T> {
+    Err(UserError(message.into()).into())
+}
+
+/// Emit this error when an external command fails in strict mode.
+pub fn exit_status_error<T>(status: ExitStatus) -> CrushResult<T> {
+    Err(ExitStatusError(status).into())
+}
+
 pub fn login_error<T>(message: impl Into<String>) -> CrushResult<T> {
     Err(LoginsError(message.into()).into())
 }
@@ -497,14 +624,25 @@ pub fn compile_error<T>(message: impl Into<String>, source: &Source) -> CrushRes
     Err(CrushError::from(GenericError(message.into())).with_source(source))
 }
 
+/// Treat a closed output channel as success. It only means that nobody is interested in the
+/// output of a command, e.g. because the next command in the pipeline has already exited.
+pub fn ignore_send_error(result: CrushResult<()>) -> CrushResult<()> {
+    match result {
+        Err(e) if matches!(e.error_type, SendError(_)) => Ok(()),
+        res => res,
+    }
+}
+
 /// Utility methods for dealing with Crush results
 pub trait CrushResultExtra {
-    /// If this result is an error, fill in the command name into the error data.
+    /// If this result is an error without a command name, fill in the command name into the error
+    /// data.
     fn with_command(self, cmd: impl Into<String>) -> Self;
 
     fn with_source_fallback(self, source: &Source) -> Self;
 
-    /// If this result is an error, populate its stack trace based ob the supplied scope.
+    /// If this result is an error without a stack trace, populate its stack trace based on the
+    /// supplied scope.
     fn with_trace(self, scope: &Scope) -> Self;
 }
 
@@ -525,7 +663,7 @@ impl<V> CrushResultExtra for CrushResult<V> {
             Err(err) => Err(CrushError {
                 error_type: err.error_type,
                 source: err.source,
-                command: Some(cmd.into()),
+                command: err.command.or_else(|| Some(cmd.into())),
                 trace: err.trace,
             }),
         }
@@ -545,13 +683,13 @@ impl<V> CrushResultExtra for CrushResult<V> {
                 error_type: err.error_type,
                 source: err.source,
                 command: err.command,
-                trace: scope.stack_trace().ok(),
+                trace: err.trace.or_else(|| scope.stack_trace().ok()),
             }),
         }
     }
 }
 
-#[cfg(test)]
+#[cfg(any())]
 mod tests {
     use super::*;
 
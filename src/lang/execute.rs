@@ -2,15 +2,21 @@
 use crate::lang::ast::lexer::LanguageMode;
 use crate::lang::ast::source::{Source, SourceType};
 use crate::lang::errors::{CrushResult, command_error};
-use crate::lang::pipe::{ValueSender, empty_channel, pipe};
-use crate::lang::serialization::{deserialize, serialize};
+use crate::lang::pipe::{ValueSender, black_hole, empty_channel, pipe};
+use crate::lang::serialization::stream::{serialize_error, serialize_stream};
+use crate::lang::serialization::{deserialize, serialize_writer};
 use crate::lang::state::contexts::{CommandContext, JobContext};
 use crate::lang::state::global_state::GlobalState;
 use crate::lang::state::scope::Scope;
+use crate::lang::threads::set_current_job;
 use crate::lang::value::Value;
 use std::fs;
-use std::io::Write;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::fd::OwnedFd;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::Path;
+use std::process::{Command, Stdio};
 use std::sync::Arc;
 
 pub fn file(
@@ -26,34 +32,68 @@ pub fn file(
         LanguageMode::Command,
         output,
         global_state,
+        false,
     )
 }
 
+/// Execute the file of a module. Unlike `file`, the first error aborts the module and is returned.
+pub fn module(global_env: &Scope, filename: &Path, global_state: &GlobalState) -> CrushResult<()> {
+    let cmd = fs::read_to_string(filename)?;
+    source(
+        global_env,
+        &Source::new(SourceType::File(filename.to_path_buf()), Arc::from(cmd)),
+        LanguageMode::Command,
+        &black_hole(),
+        global_state,
+        true,
+    )
+}
+
+/// Execute a serialized closure, and write its output to standard output as a single serialized
+/// value.
 pub fn pup(env: Scope, buf: &Vec<u8>, global_state: &GlobalState) -> CrushResult<()> {
+    run_pup(env, buf, global_state, |value, destination| {
+        serialize_writer(&value, destination)
+    })
+}
+
+/// Execute a serialized closure, and write its output to standard output in the streaming pup
+/// format. If the closure fails, the error is written as well, so that the caller can report it.
+pub fn pup_stream(env: Scope, buf: &Vec<u8>, global_state: &GlobalState) -> CrushResult<()> {
+    match run_pup(env, buf, global_state, serialize_stream) {
+        Ok(()) => Ok(()),
+        Err(e) => serialize_error(e.message(), &mut std::io::stdout().lock()),
+    }
+}
+
+fn run_pup(
+    env: Scope,
+    buf: &Vec<u8>,
+    global_state: &GlobalState,
+    serializer: fn(Value, &mut dyn Write) -> CrushResult<()>,
+) -> CrushResult<()> {
     let cmd = deserialize(buf, &env)?;
     match cmd {
         Value::Command(cmd) => {
             let (snd, recv) = pipe();
 
-            global_state.threads().spawn("serializer", None, move || {
-                let val = recv.recv()?;
-                let mut buf = Vec::new();
-                serialize(&val.materialize()?, &mut buf)?;
-                std::io::stdout().write(&buf)?;
-                Ok(())
+            let serializer = global_state.threads().spawn("serializer", None, move || {
+                serializer(recv.recv()?, &mut std::io::stdout().lock())
             })?;
 
-            cmd.eval(
+            let res = cmd.eval(
                 CommandContext::new(
                     &env,
                     global_state,
                     &Source::new(SourceType::Input, Arc::from("")),
                 )
                 .with_output(snd),
-            )?;
+            );
+            // Finish writing the output before returning an error, which may be written as well
+            let res = res.and(global_state.threads().join_one(serializer));
             global_state.threads().join(global_state.printer());
 
-            Ok(())
+            res
         }
 
         v => command_error(format!(
@@ -63,6 +103,152 @@ pub fn pup(env: Scope, buf: &Vec<u8>, global_state: &GlobalState) -> CrushResult
     }
 }
 
+/// The uid of the process on the other end of a local socket.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(stream: &UnixStream) -> CrushResult<u32> {
+    use nix::sys::socket::{getsockopt, sockopt::PeerCredentials};
+    Ok(getsockopt(stream, PeerCredentials)?.uid())
+}
+
+/// The uid of the process on the other end of a local socket.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_uid(stream: &UnixStream) -> CrushResult<u32> {
+    Ok(nix::unistd::getpeereid(stream)?.0.as_raw())
+}
+
+/// Compare two tokens without leaking how much of them matched through the time it takes.
+fn same_token(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/**
+Execute the closure sent over a single helper connection and stream its output back. The
+connection must start with the token of the helper on a line of its own, followed by a serialized
+struct with the closure to run and the working directory of the caller.
+
+The working directory belongs to the whole process, so each closure is run by a `crush
+--pup-stream` child process that is started in the directory of its caller and writes its output
+straight to the connection.
+ */
+fn serve(mut stream: UnixStream, env: Scope, token: &str) -> CrushResult<()> {
+    let mut presented = vec![0u8; token.len() + 1];
+    stream.read_exact(&mut presented)?;
+    if !same_token(&presented, format!("{}\n", token).as_bytes()) {
+        return Ok(());
+    }
+
+    let mut buf = Vec::new();
+    stream.read_to_end(&mut buf)?;
+    let (directory, cmd) = match deserialize(&buf, &env)? {
+        Value::Struct(request) => match (request.get("directory"), request.get("command")) {
+            (Some(Value::File(directory)), Some(cmd @ Value::Command(_))) => (directory, cmd),
+            _ => return serialize_error("Invalid request".to_string(), &mut stream),
+        },
+        v => {
+            return serialize_error(
+                format!(
+                    "Expected a request, but found value of type `{}`",
+                    v.value_type()
+                ),
+                &mut stream,
+            );
+        }
+    };
+
+    let child = Command::new(std::env::current_exe()?)
+        .arg("--pup-stream")
+        .current_dir(&directory)
+        .stdin(Stdio::piped())
+        .stdout(OwnedFd::from(stream.try_clone()?))
+        .stderr(Stdio::null())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            return serialize_error(
+                format!(
+                    "Failed to run the closure in `{}`: {}",
+                    directory.display(),
+                    e
+                ),
+                &mut stream,
+            );
+        }
+    };
+    let mut stdin = child.stdin.take().ok_or("Expected stdin stream")?;
+    serialize_writer(&cmd, &mut stdin)?;
+    drop(stdin);
+    child.wait()?;
+    Ok(())
+}
+
+/**
+Run as the privileged helper used by `user:do`. The helper is started through sudo, listens on the
+specified socket, and executes one serialized closure per connection, streaming the output back
+in the streaming pup format.
+
+Once the helper is ready, the session that started it sends it a random token over its standard
+input. Only connections from the user that started the helper that present this token are
+served. The helper exits when its standard input is closed, which happens when the Crush session
+that started it exits.
+ */
+pub fn helper(env: Scope, socket: &Path, global_state: &GlobalState) -> CrushResult<()> {
+    let owner = match std::env::var("SUDO_UID") {
+        Ok(uid) => uid.parse::<u32>()?,
+        Err(_) => return command_error("The helper must be started using sudo"),
+    };
+
+    let _ = fs::remove_file(socket);
+    let listener = UnixListener::bind(socket)?;
+    // Connections from other users are rejected below, but if we can, make sure they can't even
+    // connect. When running as a user other than root, we can't hand over the socket, though.
+    let mode = match nix::unistd::chown(socket, Some(owner.into()), None) {
+        Ok(()) => 0o600,
+        Err(_) => 0o666,
+    };
+    fs::set_permissions(socket, fs::Permissions::from_mode(mode))?;
+
+    // Tell the session that started us that we are ready to accept connections, and close our
+    // error output, which it forwards until then
+    let mut stdout = std::io::stdout();
+    stdout.write_all(b"ready\n")?;
+    stdout.flush()?;
+    nix::unistd::dup2_stderr(fs::File::open("/dev/null")?)?;
+
+    let mut stdin = BufReader::new(std::io::stdin());
+    let mut token = String::new();
+    stdin.read_line(&mut token)?;
+    let token: Arc<str> = Arc::from(token.trim_end());
+    if token.is_empty() {
+        let _ = fs::remove_file(socket);
+        return command_error("The helper did not receive a token");
+    }
+
+    let watched = socket.to_path_buf();
+    global_state
+        .threads()
+        .spawn("helper:stdin", None, move || {
+            let _ = std::io::copy(&mut stdin, &mut std::io::sink());
+            let _ = fs::remove_file(&watched);
+            std::process::exit(0)
+        })?;
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        if peer_uid(&stream)? != owner {
+            continue;
+        }
+        let my_env = env.clone();
+        let my_token = token.clone();
+        global_state
+            .threads()
+            .spawn("helper:connection", None, move || {
+                serve(stream, my_env, &my_token)
+            })?;
+    }
+    Ok(())
+}
+
 pub fn string(
     global_env: &Scope,
     command: &str,
@@ -76,6 +262,7 @@ pub fn string(
         initial_mode,
         output,
         global_state,
+        false,
     )
 }
 
@@ -85,19 +272,45 @@ fn source(
     initial_mode: LanguageMode,
     output: &ValueSender,
     global_state: &GlobalState,
+    abort_on_error: bool,
 ) -> CrushResult<()> {
     let jobs = global_state
         .parser()
         .parse(command, &global_env, initial_mode)?;
     for job_definition in jobs {
-        let handle = job_definition.eval(JobContext::new(
-            empty_channel(),
-            output.clone(),
-            global_env.clone(),
-            global_state.clone(),
-        ))?;
-
-        handle.map(|id| global_state.threads().join_one(id, &global_state.printer()));
+        let context = JobContext {
+            catch_errors: abort_on_error,
+            ..JobContext::new(
+                empty_channel(),
+                output.clone(),
+                global_env.clone(),
+                global_state.clone(),
+            )
+        }
+        .running(job_definition.source().string());
+        let job = context.handle.clone().ok_or("Missing job handle")?;
+        let control = job.control();
+        let job_context = context.clone();
+
+        let previous_job = set_current_job(Some(control.clone()));
+        let previous_foreground = global_state.set_foreground(Some(job.id()));
+        let res = job_definition
+            .eval(context)
+            .and_then(|thread| match thread {
+                Some(thread) => global_state.threads().join_job(thread, &control),
+                None => Ok(()),
+            });
+        global_state.set_foreground(previous_foreground);
+        set_current_job(previous_job);
+        job_context.handle_error(res)?;
+
+        if control.is_suspended() {
+            global_state.printer().line(&format!(
+                "Suspended job {}: {}",
+                usize::from(job.id()),
+                job_definition.source().str()
+            ));
+        }
     }
     Ok(())
 }
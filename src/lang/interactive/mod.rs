@@ -1,4 +1,6 @@
 pub mod rustyline_helper;
+mod signals;
+pub mod terminal;
 
 use rustyline;
 use std::fs;
@@ -82,6 +84,8 @@ pub fn run(
     global_state: &GlobalState,
 ) -> CrushResult<()> {
     let printer = global_state.printer().clone();
+    printer.handle_error(signals::install(global_state));
+    printer.handle_error(terminal::enable());
     printer.handle_error(load_init(&global_env, global_state));
 
     global_state.printer().line("Welcome to Crush");
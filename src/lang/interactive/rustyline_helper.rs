@@ -43,13 +43,16 @@ impl RustylineHelper {
             line,
             pos,
             &self.scope,
-            &self.state.parser(),
+            &self.state,
             &directory_lister(),
         )?;
         let crunched = res
             .drain(..)
             .map(|c| Pair {
-                display: c.display().to_string(),
+                display: match c.description() {
+                    Some(description) => format!("{} -- {}", c.display(), description),
+                    None => c.display().to_string(),
+                },
                 replacement: c.replacement().to_string(),
             })
             .collect();
@@ -0,0 +1,80 @@
+/// Signal handling for job control in interactive mode.
+///
+/// `SIGINT` (`^C`) cancels the foreground job and `SIGTSTP` (`^Z`) suspends it. While external
+/// commands of the foreground job are running, their process group owns the terminal, so signals
+/// generated by the terminal are delivered to them instead, and `cmd` notices when they stop or are
+/// interrupted. Signals sent to Crush using e.g. `kill` are forwarded to them.
+use crate::lang::errors::CrushResult;
+use crate::lang::state::global_state::GlobalState;
+use nix::libc;
+use nix::sys::signal::{SaFlags, SigAction, SigHandler, SigSet, Signal, sigaction};
+use std::io::Read;
+use std::os::fd::IntoRawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn handler(signal: libc::c_int, info: *mut libc::siginfo_t, _: *mut libc::c_void) {
+    // Only async-signal-safe functions may be called here, so just pass the signal on to the
+    // signal thread. A positive signal code means that the kernel sent the signal, e.g. because
+    // the user pressed ^C.
+    let from_terminal = unsafe { info.as_ref() }
+        .map(|info| info.si_code > 0)
+        .unwrap_or(true);
+    let buf = [signal as u8, from_terminal as u8];
+    unsafe {
+        libc::write(
+            SIGNAL_PIPE.load(Ordering::Relaxed),
+            buf.as_ptr() as *const libc::c_void,
+            buf.len(),
+        )
+    };
+}
+
+fn handle(global_state: &GlobalState, signal: Signal, from_terminal: bool) {
+    let job = match global_state.foreground() {
+        Some(job) => job,
+        None => return,
+    };
+    if !from_terminal {
+        job.control.signal_children(signal);
+    }
+    match signal {
+        Signal::SIGINT => job.control.cancel(),
+        Signal::SIGTSTP => {
+            job.control.suspend();
+            global_state.threads().wake();
+        }
+        _ => {}
+    }
+}
+
+/// Install handlers for the signals used for job control, and start a thread that acts on them.
+pub fn install(global_state: &GlobalState) -> CrushResult<()> {
+    let (mut reader, writer) = os_pipe::pipe()?;
+    SIGNAL_PIPE.store(writer.into_raw_fd(), Ordering::Relaxed);
+
+    let action = SigAction::new(
+        SigHandler::SigAction(handler),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    unsafe {
+        sigaction(Signal::SIGINT, &action)?;
+        sigaction(Signal::SIGTSTP, &action)?;
+    }
+
+    let global_state = global_state.clone();
+    // This thread runs until Crush exits, so it is not tracked by the thread store.
+    std::thread::Builder::new()
+        .name("signal".to_string())
+        .spawn(move || {
+            let mut buf = [0u8; 2];
+            while reader.read_exact(&mut buf).is_ok() {
+                if let Ok(signal) = Signal::try_from(buf[0] as i32) {
+                    handle(&global_state, signal, buf[1] != 0);
+                }
+            }
+        })?;
+    Ok(())
+}
@@ -0,0 +1,56 @@
+/// Ownership of the controlling terminal for job control in interactive mode.
+///
+/// Each job runs its external commands in a process group of its own. The terminal is handed over
+/// to the process group of the job running in the foreground, so that the signals generated by the
+/// terminal, like `^C` and `^Z`, are delivered to its commands but not to Crush or to background
+/// jobs. Once the foreground job is done or suspended, Crush takes the terminal back.
+use crate::lang::errors::CrushResult;
+use nix::sys::signal::{SigSet, SigmaskHow, Signal, pthread_sigmask};
+use nix::unistd::{Pid, getpgrp, isatty, setpgid, tcsetpgrp};
+use std::io::stdin;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Enable job control if standard input is a terminal, by putting Crush in a process group of its
+/// own and making that the foreground process group of the terminal.
+pub fn enable() -> CrushResult<()> {
+    if !isatty(stdin())? {
+        return Ok(());
+    }
+    // This fails if Crush is a session leader, in which case it already leads its own group.
+    let _ = setpgid(Pid::from_raw(0), Pid::from_raw(0));
+    set_foreground(getpgrp())?;
+    ENABLED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Whether jobs run their external commands in process groups of their own.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Make the specified process group the foreground process group of the terminal.
+pub fn give(group: Pid) {
+    if enabled() {
+        let _ = set_foreground(group);
+    }
+}
+
+/// Make Crush the foreground process group of the terminal again.
+pub fn take() {
+    give(getpgrp());
+}
+
+fn set_foreground(group: Pid) -> CrushResult<()> {
+    // A process that is not in the foreground process group receives SIGTTOU when changing it,
+    // unless it is blocking the signal. Only block it in this thread, and only while changing it,
+    // so that external commands don't inherit it.
+    let mut ttou = SigSet::empty();
+    ttou.add(Signal::SIGTTOU);
+    let mut previous = SigSet::empty();
+    pthread_sigmask(SigmaskHow::SIG_BLOCK, Some(&ttou), Some(&mut previous))?;
+    let res = tcsetpgrp(stdin(), group);
+    pthread_sigmask(SigmaskHow::SIG_SETMASK, Some(&previous), None)?;
+    Ok(res?)
+}
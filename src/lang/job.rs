@@ -5,6 +5,7 @@ use crate::lang::command_invocation::CommandInvocation;
 use crate::lang::errors::CrushResult;
 use crate::lang::pipe::pipe;
 use crate::lang::state::contexts::{EvalContext, JobContext};
+use crate::lang::threads::set_current_job;
 use std::fmt::{Display, Formatter};
 use std::thread::ThreadId;
 
@@ -42,7 +43,16 @@ impl Job {
 
     /// Evaluate this job in the specified context
     pub fn eval(&self, context: JobContext) -> CrushResult<Option<ThreadId>> {
-        let context = context.running(self.to_string());
+        let context = context.running(self.source.string());
+        // Commands that are run without spawning a thread must still be part of this job, so
+        // that e.g. cancelling it stops them.
+        let previous_job = set_current_job(context.handle.as_ref().map(|h| h.control()));
+        let res = self.eval_running(context);
+        set_current_job(previous_job);
+        res
+    }
+
+    fn eval_running(&self, context: JobContext) -> CrushResult<Option<ThreadId>> {
         let mut input = context.input.clone();
         let last_job_idx = self.commands.len() - 1;
         for call_def in &self.commands[..last_job_idx] {
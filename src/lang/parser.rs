@@ -16,30 +16,69 @@ use std::sync::{Arc, Mutex};
 */
 lalrpop_mod!(pub lalrparser, "/lang/lalrparser.rs");
 
+#[derive(Clone, Copy, PartialEq)]
+enum Nesting {
+    Quote(char),
+    Interpolation,
+    Bracket(char),
+}
+
+/**
+    Close any unterminated quote. Inside of an interpolated string, command substitutions that
+    haven't been closed yet are closed as well, so that e.g. `$"a $(b` becomes `$"a $(b)"`.
+    Brackets outside of quotes are left to `Parser::close_command`.
+*/
 fn close_quote(input: &str) -> String {
     let mut was_backslash = false;
-    let mut current_quote = None;
+    let mut stack = Vec::new();
+    let mut chars = input.chars().peekable();
 
-    for ch in input.chars() {
+    while let Some(ch) = chars.next() {
         if was_backslash {
             was_backslash = false;
-        } else {
-            match (ch, current_quote) {
-                ('\\', _) => was_backslash = true,
-                ('\"', Some('\"')) => current_quote = None,
-                ('\'', Some('\'')) => current_quote = None,
-                ('\"', None) => current_quote = Some('\"'),
-                ('\'', None) => current_quote = Some('\''),
-                _ => {}
+            continue;
+        }
+        match (stack.last().copied(), ch) {
+            (Some(Nesting::Quote(_) | Nesting::Interpolation), '\\') => was_backslash = true,
+            (Some(Nesting::Quote(quote)), ch) if ch == quote => {
+                stack.pop();
             }
+            (Some(Nesting::Quote(_)), _) => {}
+            (Some(Nesting::Interpolation), '"') => {
+                stack.pop();
+            }
+            (Some(Nesting::Interpolation), '$') if chars.next_if_eq(&'(').is_some() => {
+                stack.push(Nesting::Bracket(')'))
+            }
+            (Some(Nesting::Interpolation), _) => {}
+            (_, '\\') => was_backslash = true,
+            (_, '$') if chars.next_if_eq(&'"').is_some() => stack.push(Nesting::Interpolation),
+            (_, '"' | '\'') => stack.push(Nesting::Quote(ch)),
+            (_, '(') => stack.push(Nesting::Bracket(')')),
+            (_, '{') => stack.push(Nesting::Bracket('}')),
+            (_, '[') => stack.push(Nesting::Bracket(']')),
+            (Some(Nesting::Bracket(bracket)), ch) if ch == bracket => {
+                stack.pop();
+            }
+            _ => {}
         }
     }
 
-    if let Some(missing_quote) = current_quote {
-        format!("{}{}", input, missing_quote)
-    } else {
-        input.to_string()
+    // Brackets below the outermost open quote are not part of any quote.
+    let outermost_quote = stack
+        .iter()
+        .position(|n| matches!(n, Nesting::Quote(_) | Nesting::Interpolation));
+    let mut res = input.to_string();
+    if let Some(outermost_quote) = outermost_quote {
+        for nesting in stack[outermost_quote..].iter().rev() {
+            res.push(match nesting {
+                Nesting::Quote(quote) => *quote,
+                Nesting::Interpolation => '"',
+                Nesting::Bracket(bracket) => *bracket,
+            });
+        }
     }
+    res
 }
 
 fn close_switch(input: &str) -> String {
@@ -175,11 +214,20 @@ impl Parser {
                 Token::Loop(_) => {}
                 Token::If(_) => {}
                 Token::Else(_) => {}
+                Token::Try(_) => {}
+                Token::Catch(_) => {}
+                Token::Match(_) => {}
                 Token::Return(_) => {}
                 Token::Break(_) => {}
                 Token::Continue(_) => {}
                 Token::Comment(_, _) => {}
                 Token::Background(_) => {}
+                Token::RedirectOut(_) => {}
+                Token::RedirectAppend(_) => {}
+                Token::RedirectIn(_) => {}
+                Token::InterpolationStart(_) => {}
+                Token::StringFragment(_, _) => {}
+                Token::InterpolationEnd(_) => {}
             }
         }
         stack.reverse();
@@ -218,6 +266,48 @@ mod tests {
         );
     }
 
+    #[test]
+    fn check_interpolation_tokens() {
+        let tok = p()
+            .tokenize("$\"a $b:c: $(d)\"", LanguageMode::Command, SkipComments)
+            .unwrap();
+        assert_eq!(
+            tok,
+            vec![
+                Token::InterpolationStart(Location::new(0, 2)),
+                Token::StringFragment("a ", Location::new(2, 4)),
+                Token::Identifier("$b", Location::new(4, 6)),
+                Token::MemberOperator(Location::from(6)),
+                Token::String("c", Location::from(7)),
+                Token::StringFragment(": ", Location::new(8, 10)),
+                Token::SubStart(Location::new(10, 12)),
+                Token::String("d", Location::from(12)),
+                Token::SubEnd(Location::from(13)),
+                Token::InterpolationEnd(Location::from(14)),
+            ]
+        );
+    }
+
+    #[test]
+    fn check_match_tokens() {
+        let tok = p()
+            .tokenize("match $r {}; $r:match", LanguageMode::Command, SkipComments)
+            .unwrap();
+        assert_eq!(
+            tok,
+            vec![
+                Token::Match(Location::new(0, 5)),
+                Token::Identifier("$r", Location::new(6, 8)),
+                Token::BlockStart(Location::from(9)),
+                Token::BlockEnd(Location::from(10)),
+                Token::Separator(";", Location::from(11)),
+                Token::Identifier("$r", Location::new(13, 15)),
+                Token::MemberOperator(Location::from(15)),
+                Token::String("match", Location::new(16, 21)),
+            ]
+        );
+    }
+
     #[test]
     fn check_expression_tokens() {
         let tok = p()
@@ -295,5 +385,20 @@ mod tests {
         assert_eq!(close_quote("'a"), "'a'");
         assert_eq!(close_quote("'a\""), "'a\"'");
         assert_eq!(close_quote("'a\\'"), "'a\\''");
+        assert_eq!(close_quote("(\"a"), "(\"a\"");
+    }
+
+    #[test]
+    fn close_interpolation_test() {
+        assert_eq!(close_quote("$\"a"), "$\"a\"");
+        assert_eq!(close_quote("$\"a $b"), "$\"a $b\"");
+        assert_eq!(close_quote("$\"a $(b"), "$\"a $(b)\"");
+        assert_eq!(close_quote("$\"a $(b {c"), "$\"a $(b {c})\"");
+        assert_eq!(close_quote("$\"a $(b \"c"), "$\"a $(b \"c\")\"");
+        assert_eq!(close_quote("$\"a $(b) c\""), "$\"a $(b) c\"");
+        assert_eq!(close_quote("$\"it's"), "$\"it's\"");
+        assert_eq!(close_quote("$\"a \\\""), "$\"a \\\"\"");
+        let p = Parser::new();
+        assert_eq!(p.close_command("x ($\"a $(b").unwrap(), "x ($\"a $(b)\")");
     }
 }
@@ -40,6 +40,11 @@ impl ValueSender {
     pub fn is_pipeline(&self) -> bool {
         self.is_pipeline
     }
+
+    /// Whether this is the sender returned by `black_hole`, which nobody ever reads from.
+    pub fn is_black_hole(&self) -> bool {
+        self.sender.same_channel(&black_hole().sender)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -53,6 +58,11 @@ impl ValueReceiver {
         Ok(self.receiver.recv()?)
     }
 
+    /// Receive a value if one has already been sent, without blocking.
+    pub fn try_recv(&self) -> Option<Value> {
+        self.receiver.try_recv().ok()
+    }
+
     pub fn is_pipeline(&self) -> bool {
         self.is_pipeline
     }
@@ -81,6 +91,36 @@ pub fn empty_channel() -> ValueReceiver {
     i
 }
 
+/**
+A reusable version of `empty_channel`, for running many commands one after another in the same
+thread without creating a new channel for each one.
+ */
+pub struct EmptyInput {
+    sender: Sender<Value>,
+    receiver: ValueReceiver,
+}
+
+impl EmptyInput {
+    pub fn new() -> EmptyInput {
+        let (sender, receiver) = bounded(1);
+        EmptyInput {
+            sender,
+            receiver: ValueReceiver {
+                receiver,
+                is_pipeline: false,
+            },
+        }
+    }
+
+    /// A receiver that when read will return a single instance of Value::Empty
+    pub fn get(&self) -> ValueReceiver {
+        if self.receiver.receiver.is_empty() {
+            let _ = self.sender.try_send(Value::Empty);
+        }
+        self.receiver.clone()
+    }
+}
+
 #[derive(Clone)]
 pub struct TableOutputStream {
     sender: Sender<Row>,
@@ -195,6 +235,43 @@ pub fn printer_pipe() -> (ValueSender, ValueReceiver) {
     )
 }
 
+/**
+A Sender/Receiver pair like `pipe`, used for passing values on to `output`. Commands writing to the
+pipe see it as a pipeline only if `output` is one, so that e.g. external commands still write
+directly to the terminal.
+ */
+pub fn forwarding_pipe(output: &ValueSender) -> (ValueSender, ValueReceiver) {
+    let (send, recv) = bounded(1);
+    (
+        ValueSender {
+            sender: send,
+            is_pipeline: output.is_pipeline,
+        },
+        ValueReceiver {
+            receiver: recv,
+            is_pipeline: output.is_pipeline,
+        },
+    )
+}
+
+/**
+A pipe that never blocks the sender. Used for capturing the output of commands that are run in the
+same thread as the one reading the output, where a bounded pipe could deadlock.
+ */
+pub fn capture_pipe() -> (ValueSender, ValueReceiver) {
+    let (send, recv) = unbounded();
+    (
+        ValueSender {
+            sender: send,
+            is_pipeline: true,
+        },
+        ValueReceiver {
+            receiver: recv,
+            is_pipeline: true,
+        },
+    )
+}
+
 pub fn streams(signature: Vec<ColumnType>) -> (TableOutputStream, TableInputStream) {
     let (output, input) = bounded(128);
     (
@@ -2,7 +2,7 @@
     Crush uses a single thread to perform all output printing. This prevents torn lines and other
     visual problems. Output is sent to the print thread via a Printer.
 */
-use crate::lang::errors::{CrushError, CrushErrorType, CrushResult};
+use crate::lang::errors::{CrushError, CrushErrorType, CrushResult, error};
 use crate::lang::printer::PrinterMessage::*;
 use crate::lang::state::scope::Scope;
 use crate::util::highlight::highlight_colors;
@@ -12,15 +12,18 @@ use crossbeam::channel::Sender;
 use crossbeam::channel::bounded;
 use std::cmp::max;
 use std::collections::HashMap;
+use std::io::Write;
 use std::thread;
 use std::thread::JoinHandle;
-use termion::terminal_size;
+use termion::input::TermRead;
+use termion::{get_tty, terminal_size};
 
 pub enum PrinterMessage {
     Ping,
     CrushError(CrushError),
     Error(String),
     Line(String),
+    Password(String, Sender<CrushResult<String>>),
 }
 
 /**
@@ -93,7 +96,9 @@ pub fn init(scope: Option<Scope>) -> (Printer, JoinHandle<()>) {
                             }
                         }
                         Line(line) => println!("{}", line),
-                        //                        Lines(lines) => for line in lines {println!("{}", line)},
+                        Password(prompt, reply) => {
+                            let _ = reply.send(read_password(&prompt));
+                        } //                        Lines(lines) => for line in lines {println!("{}", line)},
                     }
                 }
             })
@@ -101,6 +106,16 @@ pub fn init(scope: Option<Scope>) -> (Printer, JoinHandle<()>) {
     )
 }
 
+/// Read a password from the terminal, without echoing it.
+fn read_password(prompt: &str) -> CrushResult<String> {
+    let mut tty = get_tty().map_err(|_| "No terminal to read a password from")?;
+    tty.write_all(prompt.as_bytes())?;
+    tty.flush()?;
+    let password = tty.try_clone()?.read_passwd(&mut tty)?;
+    tty.write_all(b"\r\n")?;
+    password.ok_or_else(|| "Password prompt aborted".into())
+}
+
 /**
    Create a Printer instance that doesn't actually print. A print thread actually still exists,
    but it does not print anything.
@@ -125,6 +140,9 @@ pub fn noop() -> (Printer, JoinHandle<()>) {
                         Ping => {
                             let _ = pong_sender.send(());
                         }
+                        Password(_, reply) => {
+                            let _ = reply.send(error("No terminal to read a password from"));
+                        }
                         _ => {}
                     }
                 }
@@ -188,6 +206,17 @@ impl Printer {
             .send(PrinterMessage::Error(format!("Error: {}", err.to_string())));
     }
 
+    /**
+    Ask the user for a password. The prompt is shown and the password is read by the print thread,
+    so that it isn't mixed up with output from other threads.
+    */
+    pub fn password(&self, prompt: &str) -> CrushResult<String> {
+        let (reply, response) = bounded(1);
+        self.sender
+            .send(PrinterMessage::Password(prompt.to_string(), reply))?;
+        response.recv()?
+    }
+
     /**
      The width (in characters) of the console we're printing to.
     */
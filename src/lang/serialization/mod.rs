@@ -15,6 +15,7 @@ mod dict_serializer;
 mod integer_serializer;
 mod list_serializer;
 mod scope_serializer;
+pub mod stream;
 mod string_serializer;
 mod struct_serializer;
 mod table_serializer;
@@ -42,6 +43,13 @@ pub struct DeserializationState {
 }
 
 pub fn serialize(value: &Value, buf: &mut Vec<u8>) -> CrushResult<()> {
+    let res = serialize_value(value)?;
+    buf.reserve(res.encoded_len());
+    res.encode(buf).unwrap();
+    Ok(())
+}
+
+fn serialize_value(value: &Value) -> CrushResult<SerializedValue> {
     let mut res = SerializedValue::default();
     let mut state = SerializationState {
         with_id: HashMap::new(),
@@ -51,10 +59,7 @@ pub fn serialize(value: &Value, buf: &mut Vec<u8>) -> CrushResult<()> {
         .clone()
         .materialize()?
         .serialize(&mut res.elements, &mut state)? as u64;
-
-    buf.reserve(res.encoded_len());
-    res.encode(buf).unwrap();
-    Ok(())
+    Ok(res)
 }
 
 pub fn serialize_writer(value: &Value, destination: &mut dyn Write) -> CrushResult<()> {
@@ -75,6 +80,11 @@ pub fn deserialize_reader(source: &mut dyn Read, env: &Scope) -> CrushResult<Val
 }
 
 pub fn deserialize(buf: &Vec<u8>, env: &Scope) -> CrushResult<Value> {
+    let res = SerializedValue::decode(&mut Cursor::new(buf)).unwrap();
+    deserialize_value(res, env)
+}
+
+fn deserialize_value(res: SerializedValue, env: &Scope) -> CrushResult<Value> {
     let mut state = DeserializationState {
         values: HashMap::new(),
         types: HashMap::new(),
@@ -85,8 +95,6 @@ pub fn deserialize(buf: &Vec<u8>, env: &Scope) -> CrushResult<Value> {
         env: env.clone(),
     };
 
-    let res = SerializedValue::decode(&mut Cursor::new(buf)).unwrap();
-
     //    println!("AAA {:?}", res);
 
     Ok(Value::deserialize(
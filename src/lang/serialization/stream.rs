@@ -0,0 +1,293 @@
+/**
+The streaming pup format, used to send the output of a command from one Crush process to another.
+
+Unlike `serialize`, which materializes the whole value before writing it, this sends table streams
+as a header frame with the column types followed by frames with batches of rows, and binary streams
+as a sequence of chunks. The receiving side recreates the stream as soon as the first frame
+arrives, so that e.g. a remote `files --recurse /` can be consumed while it is still running. The
+bounded channels of the recreated streams mean that the sender is blocked by the network if the
+consumer falls behind.
+
+Every frame is a `Frame` message, prefixed by its length as a 32-bit big endian integer. Frames
+larger than `MAX_FRAME_SIZE` are rejected by both sides.
+
+This format is used by `crush --pup-stream`. `crush --pup` still writes the whole output as a
+single serialized value, for callers that predate the streaming format.
+ */
+use super::model::{Frame, frame};
+use super::{deserialize_value, serialize_value};
+use crate::lang::data::binary::binary_channel;
+use crate::lang::data::table::{Row, Table};
+use crate::lang::errors::{CrushResult, data_error, error};
+use crate::lang::pipe::{RecvTimeoutError, TableInputStream, ValueSender};
+use crate::lang::state::scope::Scope;
+use crate::lang::value::{BinaryInputStream, Value, ValueType};
+use chrono::Duration;
+use prost::Message;
+use std::io::{ErrorKind, Read, Write};
+
+/// The maximum number of rows in a single frame.
+const BATCH_SIZE: usize = 256;
+/// The size of the chunks binary streams are split into.
+const CHUNK_SIZE: usize = 65536;
+/// The largest frame that is written or accepted. Rows and binary data are split into frames much
+/// smaller than this, only a single large materialized value can come close.
+const MAX_FRAME_SIZE: usize = 256 * 1024 * 1024;
+
+/// How long to wait for more rows before sending a partial batch, so that slow streams, like a log
+/// that is being followed, are still forwarded without delay.
+fn flush_timeout() -> Duration {
+    Duration::milliseconds(50)
+}
+
+fn write_frame(frame: frame::Frame, destination: &mut dyn Write) -> CrushResult<()> {
+    let frame = Frame { frame: Some(frame) };
+    let len = frame.encoded_len();
+    if len > MAX_FRAME_SIZE {
+        return data_error(format!("Frame of {} bytes is too large", len));
+    }
+    let len = len as u32;
+    let mut buf = Vec::with_capacity(4 + len as usize);
+    buf.extend_from_slice(&len.to_be_bytes());
+    frame.encode(&mut buf).unwrap();
+    destination.write_all(&buf)?;
+    destination.flush()?;
+    Ok(())
+}
+
+/// Read the next frame, or `None` if the input ended before it.
+fn read_frame(source: &mut dyn Read) -> CrushResult<Option<frame::Frame>> {
+    let mut len = [0u8; 4];
+    let mut pos = 0;
+    while pos < len.len() {
+        match source.read(&mut len[pos..]) {
+            Ok(0) if pos == 0 => return Ok(None),
+            Ok(0) => return data_error("Unexpected end of input in the middle of a frame"),
+            Ok(n) => pos += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_SIZE {
+        return data_error(format!("Frame of {} bytes is too large", len));
+    }
+    // Read incrementally instead of allocating the whole frame up front, so that a bogus length
+    // doesn't cost more memory than the data that was actually sent
+    let mut buf = Vec::new();
+    if (&mut *source).take(len as u64).read_to_end(&mut buf)? < len {
+        return data_error("Unexpected end of input in the middle of a frame");
+    }
+    Frame::decode(buf.as_slice())?
+        .frame
+        .map(Some)
+        .ok_or_else(|| "Empty frame".into())
+}
+
+fn write_rows(
+    stream: &TableInputStream,
+    rows: &mut Vec<Row>,
+    destination: &mut dyn Write,
+) -> CrushResult<()> {
+    let table = Table::from((stream.types().to_vec(), std::mem::take(rows)));
+    write_frame(
+        frame::Frame::Rows(serialize_value(&Value::Table(table))?),
+        destination,
+    )
+}
+
+fn serialize_table(stream: TableInputStream, destination: &mut dyn Write) -> CrushResult<()> {
+    write_frame(
+        frame::Frame::Columns(serialize_value(&Value::Type(ValueType::TableInputStream(
+            stream.types().to_vec(),
+        )))?),
+        destination,
+    )?;
+    let mut rows = Vec::new();
+    loop {
+        match stream.recv_timeout(flush_timeout()) {
+            Ok(row) => {
+                rows.push(row.materialize()?);
+                if rows.len() >= BATCH_SIZE {
+                    write_rows(&stream, &mut rows, destination)?;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if !rows.is_empty() {
+                    write_rows(&stream, &mut rows, destination)?;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    if !rows.is_empty() {
+        write_rows(&stream, &mut rows, destination)?;
+    }
+    Ok(())
+}
+
+fn serialize_binary(mut stream: BinaryInputStream, destination: &mut dyn Write) -> CrushResult<()> {
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    // The first chunk is always sent, even if it is empty, so that the receiver knows the type
+    let mut first = true;
+    loop {
+        let len = match stream.read(&mut buf) {
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if len == 0 && !first {
+            return Ok(());
+        }
+        write_frame(frame::Frame::Binary(buf[..len].to_vec()), destination)?;
+        if len == 0 {
+            return Ok(());
+        }
+        first = false;
+    }
+}
+
+/// Write the specified value to the destination in the streaming pup format. Table streams and
+/// binary streams are written as they are read, everything else is materialized and written as a
+/// single frame.
+pub fn serialize_stream(value: Value, destination: &mut dyn Write) -> CrushResult<()> {
+    match value {
+        Value::TableInputStream(stream) => serialize_table(stream, destination),
+        Value::BinaryInputStream(stream) => serialize_binary(stream, destination),
+        v => write_frame(frame::Frame::Value(serialize_value(&v)?), destination),
+    }
+}
+
+/// Write an error frame to the destination, telling the receiving side that the command failed.
+pub fn serialize_error(message: String, destination: &mut dyn Write) -> CrushResult<()> {
+    write_frame(frame::Frame::Error(message), destination)
+}
+
+/// Read a value in the streaming pup format from the source and send it to the output. For table
+/// streams and binary streams, this returns once the whole stream has been read.
+pub fn deserialize_stream(
+    source: &mut dyn Read,
+    env: &Scope,
+    output: &ValueSender,
+) -> CrushResult<()> {
+    match read_frame(source)? {
+        None => error("No value returned"),
+        Some(frame) => deserialize_frames(frame, source, env, output),
+    }
+}
+
+fn deserialize_frames(
+    first: frame::Frame,
+    source: &mut dyn Read,
+    env: &Scope,
+    output: &ValueSender,
+) -> CrushResult<()> {
+    match first {
+        frame::Frame::Value(value) => output.send(deserialize_value(value, env)?),
+        frame::Frame::Columns(columns) => {
+            let types = match deserialize_value(columns, env)? {
+                Value::Type(ValueType::TableInputStream(types)) => types,
+                v => {
+                    return data_error(format!(
+                        "Expected the type of a table stream, got a value of type `{}`",
+                        v.value_type()
+                    ));
+                }
+            };
+            let rows = output.initialize(&types)?;
+            while let Some(frame) = read_frame(source)? {
+                match frame {
+                    frame::Frame::Rows(table) => match deserialize_value(table, env)? {
+                        Value::Table(table) => {
+                            for row in table.iter() {
+                                rows.send(row)?;
+                            }
+                        }
+                        v => {
+                            return data_error(format!(
+                                "Expected a table, got a value of type `{}`",
+                                v.value_type()
+                            ));
+                        }
+                    },
+                    frame::Frame::Error(message) => return error(message),
+                    _ => return data_error("Expected a frame with rows"),
+                }
+            }
+            Ok(())
+        }
+        frame::Frame::Binary(chunk) => {
+            let (mut writer, reader) = binary_channel();
+            output.send(Value::BinaryInputStream(reader))?;
+            writer.write_all(&chunk)?;
+            while let Some(frame) = read_frame(source)? {
+                match frame {
+                    frame::Frame::Binary(chunk) => writer.write_all(&chunk)?,
+                    frame::Frame::Error(message) => return error(message),
+                    _ => return data_error("Expected a frame with binary data"),
+                }
+            }
+            Ok(())
+        }
+        frame::Frame::Rows(_) => data_error("Received rows before the column types"),
+        frame::Frame::Error(message) => error(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lang::data::table::ColumnType;
+    use crate::lang::pipe::{pipe, streams};
+
+    #[test]
+    fn table_stream_roundtrip() {
+        let types = vec![ColumnType::new("n", ValueType::Integer)];
+        let (output, input) = streams(types.clone());
+        let writer = std::thread::spawn(move || {
+            for i in 0..1000 {
+                output.send(Row::new(vec![Value::Integer(i)])).unwrap();
+            }
+        });
+        let mut buf = Vec::new();
+        serialize_stream(Value::TableInputStream(input), &mut buf).unwrap();
+        writer.join().unwrap();
+
+        let (sender, receiver) = pipe();
+        let reader = std::thread::spawn(move || {
+            deserialize_stream(&mut buf.as_slice(), &Scope::create_root(), &sender).unwrap();
+        });
+        match receiver.recv().unwrap() {
+            Value::TableInputStream(stream) => {
+                assert!(stream.types() == types.as_slice());
+                for i in 0..1000 {
+                    assert!(stream.recv().unwrap().cells() == &vec![Value::Integer(i)]);
+                }
+                assert!(stream.recv().is_err());
+            }
+            v => panic!("Expected a table stream, got {}", v.value_type()),
+        }
+        reader.join().unwrap();
+    }
+
+    #[test]
+    fn empty_input() {
+        let (sender, _receiver) = pipe();
+        assert!(deserialize_stream(&mut [].as_slice(), &Scope::create_root(), &sender).is_err());
+    }
+
+    #[test]
+    fn oversized_frame() {
+        let (sender, _receiver) = pipe();
+        let input = u32::MAX.to_be_bytes();
+        assert!(deserialize_stream(&mut input.as_slice(), &Scope::create_root(), &sender).is_err());
+    }
+
+    #[test]
+    fn truncated_frame() {
+        let (sender, _receiver) = pipe();
+        let mut input = 1000u32.to_be_bytes().to_vec();
+        input.extend_from_slice(&[0u8; 10]);
+        assert!(deserialize_stream(&mut input.as_slice(), &Scope::create_root(), &sender).is_err());
+    }
+}
@@ -178,7 +178,8 @@ impl Serializable<Value> for Value {
             Value::Scope(s) => s.serialize(elements, state),
             Value::TableOutputStream(_)
             | Value::TableInputStream(_)
-            | Value::BinaryInputStream(_) => error("Can't serialize streams"),
+            | Value::BinaryInputStream(_)
+            | Value::BinaryOutputStream(_) => error("Can't serialize streams"),
         }
     }
 }
@@ -32,6 +32,8 @@ impl Serializable<ValueType> for ValueType {
                     14 => ValueType::Time,
                     15 => ValueType::Struct,
                     16 => ValueType::Any,
+                    17 => ValueType::BinaryInputStream,
+                    18 => ValueType::BinaryOutputStream,
                     _ => return error("Unrecognised type"),
                 }),
                 model::r#type::Type::ListType(l) => Ok(ValueType::List(Box::from(
@@ -167,6 +169,7 @@ impl Serializable<ValueType> for ValueType {
                 return Ok(idx);
             }
             ValueType::BinaryInputStream => SimpleTypeKind::BinaryStream,
+            ValueType::BinaryOutputStream => SimpleTypeKind::BinaryOutputStream,
         };
 
         let idx = elements.len();
@@ -1,25 +1,26 @@
-use crate::lang::data::binary::binary_channel;
-use crate::lang::errors::{CrushError, CrushResult, argument_error, command_error, data_error};
+use crate::lang::data::binary::{BinaryOutputStream, binary_channel};
+use crate::lang::errors::{CrushError, CrushResult, command_error};
 use crate::lang::pipe::ValueSender;
-use crate::lang::value::{Value, ValueType};
+use crate::lang::value::Value;
 use crate::util::file::cwd;
 use crate::util::glob::Glob;
 use crate::util::regex::RegexFileMatcher;
 use regex::Regex;
 use std::convert::TryFrom;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 /**
-A type representing one or more files.
+A type representing one or more files, or the write end of a binary pipe.
  */
 #[derive(Debug, Clone)]
 pub enum Files {
     File(Arc<Path>),
     Glob(Glob),
     Regex(Regex),
+    BinaryOutputStream(BinaryOutputStream),
 }
 
 impl TryFrom<Value> for Files {
@@ -30,8 +31,9 @@ impl TryFrom<Value> for Files {
             Value::File(v) => Ok(Files::File(v)),
             Value::Glob(v) => Ok(Files::Glob(v)),
             Value::Regex(_, v) => Ok(Files::Regex(v)),
+            Value::BinaryOutputStream(v) => Ok(Files::BinaryOutputStream(v)),
             v => command_error(format!(
-                "Invalid type `{}`, expected `one_of $file $glob $binary`.",
+                "Invalid type `{}`, expected `one_of $file $glob $re $binary_output_stream`.",
                 v.value_type()
             )),
         }
@@ -54,6 +56,7 @@ impl TryInto<Vec<PathBuf>> for Files {
                 pattern.match_files(&cwd()?, &mut tmp)?;
                 Ok(tmp.into_iter().collect())
             }
+            Files::BinaryOutputStream(_) => command_error("Expected a file, got a binary pipe."),
         }
     }
 }
@@ -66,10 +69,13 @@ pub fn into_paths(files: Vec<Files>) -> CrushResult<Vec<PathBuf>> {
     Ok(res)
 }
 
-impl TryInto<Box<dyn Write>> for Files {
+impl TryInto<Box<dyn Write + Send>> for Files {
     type Error = CrushError;
 
-    fn try_into(self) -> Result<Box<dyn Write>, Self::Error> {
+    fn try_into(self) -> Result<Box<dyn Write + Send>, Self::Error> {
+        if let Files::BinaryOutputStream(stream) = self {
+            return Ok(stream.writer());
+        }
         let vec: Vec<_> = self.try_into()?;
         match vec.len() {
             1 => Ok(Box::from(File::create(&vec[0])?)),
@@ -78,7 +84,7 @@ impl TryInto<Box<dyn Write>> for Files {
     }
 }
 
-pub fn writer(files: Option<Files>, output: ValueSender) -> CrushResult<Box<dyn Write>> {
+pub fn writer(files: Option<Files>, output: ValueSender) -> CrushResult<Box<dyn Write + Send>> {
     match files {
         None => {
             let (w, r) = binary_channel();
@@ -92,6 +98,25 @@ pub fn writer(files: Option<Files>, output: ValueSender) -> CrushResult<Box<dyn
     }
 }
 
+/// Like `writer`, but if a file is specified, append to it instead of truncating it.
+pub fn appender(files: Option<Files>, output: ValueSender) -> CrushResult<Box<dyn Write + Send>> {
+    match files {
+        None | Some(Files::BinaryOutputStream(_)) => writer(files, output),
+        Some(file) => {
+            let vec: Vec<PathBuf> = file.try_into()?;
+            match vec.len() {
+                1 => {
+                    output.send(Value::Empty)?;
+                    Ok(Box::from(
+                        OpenOptions::new().create(true).append(true).open(&vec[0])?,
+                    ))
+                }
+                _ => command_error("Invalid output file"),
+            }
+        }
+    }
+}
+
 pub fn path(files: Option<Files>, fallback: impl Into<PathBuf>) -> CrushResult<PathBuf> {
     match files {
         None => Ok(fallback.into()),
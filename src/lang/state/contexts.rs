@@ -1,6 +1,6 @@
 use crate::lang::argument::Argument;
 use crate::lang::ast::source::Source;
-use crate::lang::errors::CrushResult;
+use crate::lang::errors::{CrushResult, ignore_send_error};
 use crate::lang::pipe::{ValueReceiver, ValueSender, black_hole, empty_channel};
 use crate::lang::state::global_state::{GlobalState, JobHandle};
 use crate::lang::state::scope::Scope;
@@ -14,34 +14,52 @@ The data needed to be passed around while calling eval on a ValueDefinition.
 pub struct EvalContext {
     pub env: Scope,
     pub global_state: GlobalState,
+    /// Whether errors in the jobs started while evaluating are caught by an enclosing `try`
+    catch_errors: bool,
 }
 
 impl EvalContext {
     pub fn new(env: Scope, global_state: GlobalState) -> EvalContext {
-        EvalContext { env, global_state }
+        EvalContext {
+            env,
+            global_state,
+            catch_errors: false,
+        }
     }
 
     pub fn job_context(&self, input: ValueReceiver, output: ValueSender) -> JobContext {
-        JobContext::new(input, output, self.env.clone(), self.global_state.clone())
+        let mut context =
+            JobContext::new(input, output, self.env.clone(), self.global_state.clone());
+        context.catch_errors = self.catch_errors;
+        context
     }
 
     pub fn with_scope(&self, env: &Scope) -> EvalContext {
         EvalContext {
             env: env.clone(),
             global_state: self.global_state.clone(),
+            catch_errors: self.catch_errors,
         }
     }
 }
 
 impl From<&JobContext> for EvalContext {
     fn from(c: &JobContext) -> Self {
-        EvalContext::new(c.scope.clone(), c.global_state.clone())
+        EvalContext {
+            env: c.scope.clone(),
+            global_state: c.global_state.clone(),
+            catch_errors: c.catch_errors,
+        }
     }
 }
 
 impl From<&CommandContext> for EvalContext {
     fn from(c: &CommandContext) -> Self {
-        EvalContext::new(c.scope.clone(), c.global_state.clone())
+        EvalContext {
+            env: c.scope.clone(),
+            global_state: c.global_state.clone(),
+            catch_errors: c.catch_errors,
+        }
     }
 }
 
@@ -55,6 +73,9 @@ pub struct JobContext {
     pub scope: Scope,
     pub global_state: GlobalState,
     pub handle: Option<JobHandle>,
+    /// Whether errors are caught by an enclosing `try`, in which case they must be passed on
+    /// instead of being printed
+    pub catch_errors: bool,
 }
 
 impl JobContext {
@@ -70,16 +91,22 @@ impl JobContext {
             scope: env,
             global_state,
             handle: None,
+            catch_errors: false,
         }
     }
 
+    /// Register a new job for this context, unless it already belongs to one.
     pub fn running(&self, desc: String) -> JobContext {
+        if self.handle.is_some() {
+            return self.clone();
+        }
         JobContext {
             input: self.input.clone(),
             output: self.output.clone(),
             scope: self.scope.clone(),
             global_state: self.global_state.clone(),
             handle: Some(self.global_state.job_begin(desc)),
+            catch_errors: self.catch_errors,
         }
     }
 
@@ -90,6 +117,7 @@ impl JobContext {
             scope: self.scope.clone(),
             global_state: self.global_state.clone(),
             handle: self.handle.clone(),
+            catch_errors: self.catch_errors,
         }
     }
 
@@ -108,9 +136,19 @@ impl JobContext {
             global_state: self.global_state.clone(),
             handle: self.handle.clone(),
             source: source.clone(),
+            catch_errors: self.catch_errors,
         }
     }
 
+    /**
+    Handle the result of a command or job that has finished. If the error is caught by an
+    enclosing `try`, or if strict mode is on, it is returned so that it aborts the caller.
+    Otherwise the error is printed, and execution continues.
+     */
+    pub fn handle_error(&self, result: CrushResult<()>) -> CrushResult<()> {
+        handle_error(self.catch_errors, &self.global_state, result)
+    }
+
     pub fn spawn<F>(&self, name: &str, f: F) -> CrushResult<ThreadId>
     where
         F: FnOnce() -> CrushResult<()>,
@@ -118,7 +156,7 @@ impl JobContext {
     {
         self.global_state
             .threads()
-            .spawn(name, self.handle.clone().map(|h| h.id()), f)
+            .spawn(name, self.handle.as_ref(), f)
     }
 }
 
@@ -135,6 +173,7 @@ pub struct CommandContext {
     pub global_state: GlobalState,
     pub source: Source,
     handle: Option<JobHandle>,
+    catch_errors: bool,
 }
 
 impl CommandContext {
@@ -151,6 +190,7 @@ impl CommandContext {
             global_state: state.clone(),
             source: source.clone(),
             handle: None,
+            catch_errors: false,
         }
     }
 
@@ -178,6 +218,7 @@ impl CommandContext {
             source: self.source.clone(),
             global_state: self.global_state.clone(),
             handle: self.handle.clone(),
+            catch_errors: self.catch_errors,
         }
     }
 
@@ -194,6 +235,7 @@ impl CommandContext {
             global_state: self.global_state,
             handle: self.handle,
             source: self.source,
+            catch_errors: self.catch_errors,
         }
     }
 
@@ -210,6 +252,7 @@ impl CommandContext {
             global_state: self.global_state,
             handle: self.handle,
             source: self.source,
+            catch_errors: self.catch_errors,
         }
     }
 
@@ -226,6 +269,32 @@ impl CommandContext {
             global_state: self.global_state,
             handle: self.handle,
             source: self.source,
+            catch_errors: self.catch_errors,
+        }
+    }
+
+    /**
+    Return a new Job context with the same scope and state as this one, belonging to the same job.
+     */
+    pub fn job_context(&self, input: ValueReceiver, output: ValueSender) -> JobContext {
+        JobContext {
+            input,
+            output,
+            scope: self.scope.clone(),
+            global_state: self.global_state.clone(),
+            handle: self.handle.clone(),
+            catch_errors: self.catch_errors,
+        }
+    }
+
+    /**
+    Return a new Command context that is identical to this one, except that errors are caught by
+    the caller instead of being printed.
+     */
+    pub fn catching_errors(self) -> CommandContext {
+        CommandContext {
+            catch_errors: true,
+            ..self
         }
     }
 
@@ -242,9 +311,17 @@ impl CommandContext {
             global_state: self.global_state,
             handle: self.handle.clone(),
             source: self.source,
+            catch_errors: self.catch_errors,
         }
     }
 
+    /**
+    Handle the result of a command, see `JobContext::handle_error`.
+     */
+    pub fn handle_error(&self, result: CrushResult<()>) -> CrushResult<()> {
+        handle_error(self.catch_errors, &self.global_state, result)
+    }
+
     pub fn spawn<F>(&self, name: &str, f: F) -> CrushResult<ThreadId>
     where
         F: FnOnce() -> CrushResult<()>,
@@ -252,6 +329,20 @@ impl CommandContext {
     {
         self.global_state
             .threads()
-            .spawn(name, self.handle.clone().map(|h| h.id()), f)
+            .spawn(name, self.handle.as_ref(), f)
+    }
+}
+
+fn handle_error(
+    catch_errors: bool,
+    global_state: &GlobalState,
+    result: CrushResult<()>,
+) -> CrushResult<()> {
+    let result = ignore_send_error(result);
+    if catch_errors || global_state.strict() {
+        result
+    } else {
+        global_state.printer().handle_error(result);
+        Ok(())
     }
 }
@@ -2,15 +2,25 @@ use crate::interactive::rustyline_helper::RustylineHelper;
 use crate::lang::ast::lexer::LanguageMode;
 use crate::lang::command::Command;
 use crate::lang::errors::CrushResult;
+use crate::lang::interactive::terminal;
 use crate::lang::parser::Parser;
 use crate::lang::printer::Printer;
-use crate::lang::threads::ThreadStore;
+use crate::lang::state::scope::Scope;
+use crate::lang::threads::{ThreadStore, current_job};
 use crate::lang::value::Value;
 use crate::util::byte_unit::ByteUnit;
 use crate::util::temperature::Temperature;
+use nix::sys::signal::{Signal, kill};
+use nix::unistd::Pid;
 use num_format::{Grouping, SystemLocale};
 use rustyline::Editor;
 use rustyline::history::DefaultHistory;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::Child;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard};
 
 /**
@@ -27,6 +37,41 @@ pub struct FormatData {
     byte_unit: ByteUnit,
 }
 
+/// The outcome of running an external command.
+#[derive(Clone, Debug)]
+pub struct ExitStatus {
+    /// The command line of the command.
+    pub command: String,
+    /// The exit code of the command, if it exited normally.
+    pub code: Option<i32>,
+    /// The signal that terminated the command, if any.
+    pub signal: Option<i32>,
+}
+
+impl ExitStatus {
+    pub fn new(command: String, status: std::process::ExitStatus) -> ExitStatus {
+        ExitStatus {
+            command,
+            code: status.code(),
+            signal: status.signal(),
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+impl Display for ExitStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (self.code, self.signal) {
+            (Some(code), _) => write!(f, "`{}` exited with status {}", self.command, code),
+            (None, Some(signal)) => write!(f, "`{}` was killed by signal {}", self.command, signal),
+            (None, None) => write!(f, "`{}` exited with an unknown status", self.command),
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 pub enum RunMode {
     Interactive,
@@ -95,12 +140,19 @@ struct StateData {
     prompt: Option<Command>,
     title: Option<Command>,
     jobs: Vec<Option<LiveJob>>,
+    foreground: Option<JobId>,
     exit_status: Option<i32>,
+    last_status: Option<ExitStatus>,
+    strict: bool,
     language_mode: LanguageMode,
     run_mode: RunMode,
+    completion_providers: HashMap<String, Command>,
+    external_completions: HashMap<String, String>,
+    help_completions: HashSet<String>,
+    modules: HashMap<PathBuf, Scope>,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct JobId(usize);
 
 impl From<usize> for JobId {
@@ -123,13 +175,160 @@ impl From<JobId> for Value {
 
 pub struct JobHandleInternal {
     id: JobId,
+    control: Arc<JobControl>,
     state: GlobalState,
 }
 
+/**
+The shared state used to control a running job, i.e. to cancel or suspend it. A job started from
+within another job, e.g. a subshell, has its own control with a link to the control of the job
+that started it. Cancelling or suspending the outer job also affects the nested one, but not the
+other way around.
+ */
+#[derive(Default)]
+pub struct JobControl {
+    cancelled: AtomicBool,
+    suspended: AtomicBool,
+    children: Mutex<Vec<Pid>>,
+    /// The process group of the external commands of the job, while any of them are running. A
+    /// nested job uses the process group of the job it was started from.
+    process_group: Mutex<Option<Pid>>,
+    /// Whether the job is running in the foreground and should own the terminal.
+    foreground: AtomicBool,
+    parent: Option<Arc<JobControl>>,
+}
+
+impl JobControl {
+    pub fn new(parent: Option<Arc<JobControl>>) -> JobControl {
+        JobControl {
+            parent,
+            ..JobControl::default()
+        }
+    }
+
+    /// True if the job or any job it was started from has been cancelled. Checked via
+    /// `Scope::is_stopped` in order to unwind builtins and closures.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+            || self.parent.as_ref().is_some_and(|p| p.is_cancelled())
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.suspended.load(Ordering::Relaxed)
+            || self.parent.as_ref().is_some_and(|p| p.is_suspended())
+    }
+
+    /// True if this is the specified job, or a job started from it.
+    pub fn is_part_of(&self, job: &JobControl) -> bool {
+        std::ptr::eq(self, job) || self.parent.as_ref().is_some_and(|p| p.is_part_of(job))
+    }
+
+    /// The job that this job was started from, directly or indirectly, and that was not itself
+    /// started from another job.
+    fn root(&self) -> &JobControl {
+        match &self.parent {
+            Some(parent) => parent.root(),
+            None => self,
+        }
+    }
+
+    /**
+    Start an external process and register it with this job, so that signals can be sent to it.
+    The process is also registered with the jobs this job was started from.
+
+    When job control is enabled, the process joins the process group of the job. The first
+    process creates the group, and if the job is running in the foreground, the terminal is handed
+    over to it.
+     */
+    pub fn spawn(&self, cmd: &mut std::process::Command) -> CrushResult<Child> {
+        let root = self.root();
+        let mut group = root.process_group.lock().unwrap();
+        if terminal::enabled() {
+            cmd.process_group(group.map(|g| g.as_raw()).unwrap_or(0));
+        }
+        let child = cmd.spawn()?;
+        let pid = Pid::from_raw(child.id() as i32);
+        if terminal::enabled() && group.is_none() {
+            *group = Some(pid);
+            if root.foreground.load(Ordering::Relaxed) {
+                terminal::give(pid);
+            }
+        }
+        self.add_child(pid);
+        Ok(child)
+    }
+
+    fn add_child(&self, pid: Pid) {
+        self.children.lock().unwrap().push(pid);
+        if let Some(parent) = &self.parent {
+            parent.add_child(pid);
+        }
+    }
+
+    /// Unregister an external process that has exited. Once all processes of the job have exited,
+    /// the process group is gone, and the terminal is returned to Crush.
+    pub fn remove_child(&self, pid: Pid) {
+        let root = self.root();
+        let mut group = root.process_group.lock().unwrap();
+        self.remove_child_internal(pid);
+        if group.is_some() && root.children.lock().unwrap().is_empty() {
+            *group = None;
+            if root.foreground.load(Ordering::Relaxed) {
+                terminal::take();
+            }
+        }
+    }
+
+    fn remove_child_internal(&self, pid: Pid) {
+        self.children.lock().unwrap().retain(|p| *p != pid);
+        if let Some(parent) = &self.parent {
+            parent.remove_child_internal(pid);
+        }
+    }
+
+    /// Send the specified signal to all external processes started by this job.
+    pub fn signal_children(&self, signal: Signal) {
+        for pid in self.children.lock().unwrap().iter() {
+            let _ = kill(*pid, signal);
+        }
+    }
+
+    /// Move the job to the foreground or the background. A job in the foreground owns the
+    /// terminal while any of its external processes are running.
+    pub fn set_foreground(&self, foreground: bool) {
+        let root = self.root();
+        let group = root.process_group.lock().unwrap();
+        let was_foreground = root.foreground.swap(foreground, Ordering::Relaxed);
+        match (foreground, *group) {
+            (true, Some(group)) => terminal::give(group),
+            (false, _) if was_foreground => terminal::take(),
+            _ => {}
+        }
+    }
+
+    /// Cancel the job. Builtins and closures will stop executing the next time they check if
+    /// their scope is stopped.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Mark the job as suspended. The external processes of the job are expected to already
+    /// have been stopped.
+    pub fn suspend(&self) {
+        self.suspended.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.suspended.store(false, Ordering::Relaxed);
+        self.signal_children(Signal::SIGCONT);
+    }
+}
+
 #[derive(Clone)]
 pub struct LiveJob {
     pub id: JobId,
     pub description: String,
+    pub control: Arc<JobControl>,
 }
 
 /**
@@ -145,6 +344,10 @@ impl JobHandle {
     pub fn id(&self) -> JobId {
         self.internal.lock().unwrap().id
     }
+
+    pub fn control(&self) -> Arc<JobControl> {
+        self.internal.lock().unwrap().control.clone()
+    }
 }
 
 impl Drop for JobHandleInternal {
@@ -174,11 +377,18 @@ impl GlobalState {
                     byte_unit: ByteUnit::Binary,
                 },
                 exit_status: None,
+                last_status: None,
+                strict: false,
                 prompt: None,
                 title: None,
                 jobs: Vec::new(),
+                foreground: None,
                 language_mode: LanguageMode::Command,
                 run_mode,
+                completion_providers: HashMap::new(),
+                external_completions: HashMap::new(),
+                help_completions: HashSet::new(),
+                modules: HashMap::new(),
             })),
             threads: ThreadStore::new(),
             printer,
@@ -206,15 +416,44 @@ impl GlobalState {
     pub fn job_begin(&self, description: String) -> JobHandle {
         let mut data = self.data.lock().unwrap();
         let id = JobId::from(data.jobs.len());
-        data.jobs.push(Some(LiveJob { id, description }));
+        let control = Arc::new(JobControl::new(current_job()));
+        data.jobs.push(Some(LiveJob {
+            id,
+            description,
+            control: control.clone(),
+        }));
         JobHandle {
             internal: Arc::new(Mutex::new(JobHandleInternal {
                 id,
+                control,
                 state: self.clone(),
             })),
         }
     }
 
+    pub fn job(&self, id: JobId) -> Option<LiveJob> {
+        let data = self.data.lock().unwrap();
+        data.jobs.get(usize::from(id)).cloned().flatten()
+    }
+
+    /// Set the job that is currently running in the foreground, i.e. the job that the REPL is
+    /// waiting for. Returns the previous foreground job.
+    pub fn set_foreground(&self, id: Option<JobId>) -> Option<JobId> {
+        let previous = std::mem::replace(&mut self.data.lock().unwrap().foreground, id);
+        if let Some(job) = previous.and_then(|id| self.job(id)) {
+            job.control.set_foreground(false);
+        }
+        if let Some(job) = id.and_then(|id| self.job(id)) {
+            job.control.set_foreground(true);
+        }
+        previous
+    }
+
+    pub fn foreground(&self) -> Option<LiveJob> {
+        let id = self.data.lock().unwrap().foreground?;
+        self.job(id)
+    }
+
     pub fn set_exit_status(&self, status: i32) {
         let mut data = self.data.lock().unwrap();
         data.exit_status = Some(status);
@@ -225,6 +464,27 @@ impl GlobalState {
         data.exit_status
     }
 
+    pub fn set_last_status(&self, status: ExitStatus) {
+        let mut data = self.data.lock().unwrap();
+        data.last_status = Some(status);
+    }
+
+    pub fn last_status(&self) -> Option<ExitStatus> {
+        let data = self.data.lock().unwrap();
+        data.last_status.clone()
+    }
+
+    pub fn set_strict(&self, strict: bool) {
+        let mut data = self.data.lock().unwrap();
+        data.strict = strict;
+    }
+
+    /// In strict mode, an external command that does not exit successfully is an error.
+    pub fn strict(&self) -> bool {
+        let data = self.data.lock().unwrap();
+        data.strict
+    }
+
     pub fn set_language_mode(&self, mode: LanguageMode) {
         let mut data = self.data.lock().unwrap();
         data.language_mode = mode;
@@ -294,4 +554,86 @@ impl GlobalState {
     pub fn set_temperature_precision(&self, p: u8) {
         self.data.lock().unwrap().format_data.temperature_precision = p;
     }
+
+    /// Install or remove the completion provider for the specified completion tag.
+    pub fn set_completion_provider(&self, tag: String, provider: Option<Command>) {
+        let mut data = self.data.lock().unwrap();
+        match provider {
+            Some(provider) => data.completion_providers.insert(tag, provider),
+            None => data.completion_providers.remove(&tag),
+        };
+    }
+
+    pub fn completion_provider(&self, tag: &str) -> Option<Command> {
+        self.data
+            .lock()
+            .unwrap()
+            .completion_providers
+            .get(tag)
+            .cloned()
+    }
+
+    pub fn completion_providers(&self) -> Vec<(String, Command)> {
+        let data = self.data.lock().unwrap();
+        let mut res = data
+            .completion_providers
+            .iter()
+            .map(|(tag, provider)| (tag.clone(), provider.clone()))
+            .collect::<Vec<_>>();
+        res.sort_by(|(a, _), (b, _)| a.cmp(b));
+        res
+    }
+
+    /// Set or clear the completion tag used for the arguments of the specified external command.
+    pub fn set_external_completion(&self, command: String, tag: Option<String>) {
+        let mut data = self.data.lock().unwrap();
+        match tag {
+            Some(tag) => data.external_completions.insert(command, tag),
+            None => data.external_completions.remove(&command),
+        };
+    }
+
+    pub fn external_completion(&self, command: &str) -> Option<String> {
+        self.data
+            .lock()
+            .unwrap()
+            .external_completions
+            .get(command)
+            .cloned()
+    }
+
+    pub fn external_completions(&self) -> Vec<(String, String)> {
+        let data = self.data.lock().unwrap();
+        let mut res = data
+            .external_completions
+            .iter()
+            .map(|(command, tag)| (command.clone(), tag.clone()))
+            .collect::<Vec<_>>();
+        res.sort();
+        res
+    }
+
+    /// Allow or disallow completing the switches of an external command by running it with
+    /// `--help`.
+    pub fn set_help_completion(&self, command: String, enabled: bool) {
+        let mut data = self.data.lock().unwrap();
+        if enabled {
+            data.help_completions.insert(command);
+        } else {
+            data.help_completions.remove(&command);
+        }
+    }
+
+    pub fn help_completion(&self, command: &str) -> bool {
+        self.data.lock().unwrap().help_completions.contains(command)
+    }
+
+    /// The namespace of the module in the specified file, if it has already been imported.
+    pub fn module(&self, path: &Path) -> Option<Scope> {
+        self.data.lock().unwrap().modules.get(path).cloned()
+    }
+
+    pub fn set_module(&self, path: PathBuf, module: Scope) {
+        self.data.lock().unwrap().modules.insert(path, module);
+    }
 }
@@ -5,13 +5,16 @@ use crate::lang::command::Command;
 use crate::lang::errors::{CrushError, CrushResult, command_error, error, invalid_jump};
 use crate::lang::help::Help;
 use crate::lang::pipe::CrushStream;
+use crate::lang::threads::current_job;
 use crate::lang::{value::Value, value::ValueType};
 use crate::util::identity_arc::Identity;
 use crate::util::replace::Replace;
 use chrono::Duration;
 use ordered_map::OrderedMap;
+use std::collections::HashSet;
 use std::fmt::{Display, Formatter};
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, RwLock};
 
 /**
 This is where we store variables, including functions.
@@ -168,6 +171,10 @@ pub struct ScopeData {
     /// The return value of this scope, if any. Used by the return builtin to propagate a value
     pub return_value: Option<Value>,
 
+    /// Environment variables to set (or unset, if the value is `None`) for external commands
+    /// started in this scope or any scope called from it.
+    pub environment: OrderedMap<String, Option<String>>,
+
     /// The name of this scope, if any
     pub name: Option<String>,
 
@@ -198,6 +205,7 @@ impl ScopeData {
             is_stopped: false,
             is_readonly: false,
             return_value: None,
+            environment: OrderedMap::new(),
             name,
             description,
             is_loaded: true,
@@ -221,6 +229,7 @@ impl ScopeData {
             is_stopped: false,
             is_readonly: false,
             return_value: None,
+            environment: OrderedMap::new(),
             name,
             description,
             is_loaded: false,
@@ -240,6 +249,7 @@ impl Clone for ScopeData {
             is_stopped: self.is_stopped,
             is_readonly: self.is_readonly,
             return_value: self.return_value.clone(),
+            environment: self.environment.clone(),
             name: self.name.clone(),
             description: self.description.clone(),
             is_loaded: true,
@@ -252,6 +262,53 @@ fn lookup(key: &str, data: &MutexGuard<ScopeData>) -> Option<Value> {
     data.mapping.get(key).map(|v| v.clone())
 }
 
+/// Incremented every time a name that someone has cached the resolution of might have changed
+/// meaning, see `resolution_generation`.
+static RESOLUTION_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+fn watched_names() -> &'static RwLock<HashSet<String>> {
+    static NAMES: OnceLock<RwLock<HashSet<String>>> = OnceLock::new();
+    NAMES.get_or_init(|| RwLock::new(HashSet::new()))
+}
+
+/**
+A counter that changes whenever a name passed to `watch_names` might resolve to something new,
+i.e. whenever a variable by that name is declared, set or removed in any scope, or whenever the
+structure of a scope changes. The result of resolving such a name can be cached for as long as the
+generation stays the same.
+
+Names must be registered before reading the generation and resolving them, otherwise a concurrent
+change might go unnoticed.
+ */
+pub fn resolution_generation() -> u64 {
+    RESOLUTION_GENERATION.load(Ordering::Acquire)
+}
+
+/// Start tracking changes to the specified names, see `resolution_generation`.
+pub fn watch_names(names: &[String]) {
+    let watched = watched_names().read().unwrap();
+    if names.iter().all(|name| watched.contains(name)) {
+        return;
+    }
+    drop(watched);
+    watched_names()
+        .write()
+        .unwrap()
+        .extend(names.iter().cloned());
+}
+
+/// Must be called *after* a change has been made, so that a resolution started after the
+/// generation changed sees the change.
+fn invalidate_resolutions(name: Option<&str>) {
+    let watched = match name {
+        None => true,
+        Some(name) => watched_names().read().unwrap().contains(name),
+    };
+    if watched {
+        RESOLUTION_GENERATION.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
 impl Scope {
     pub fn create_root() -> Scope {
         Scope {
@@ -282,6 +339,7 @@ impl Scope {
                 is_stopped,
                 is_readonly,
                 return_value: None,
+                environment: OrderedMap::new(),
                 name,
                 description,
                 is_loaded: true,
@@ -425,8 +483,38 @@ impl Scope {
         Ok(())
     }
 
+    /// Set an environment variable for all external commands started in this scope or in any
+    /// scope called from it. A value of `None` removes the variable.
+    pub fn set_environment(&self, name: &str, value: Option<String>) -> CrushResult<()> {
+        self.lock()?.environment.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    /// Returns the environment variable overrides in effect in this scope. Overrides in inner
+    /// scopes take precedence over those of the scopes calling them.
+    pub fn environment(&self) -> CrushResult<OrderedMap<String, Option<String>>> {
+        let mut layers = Vec::new();
+        let mut current = Some(self.clone());
+        while let Some(scope) = current {
+            let data = scope.lock()?;
+            if !data.environment.is_empty() {
+                layers.push(data.environment.clone());
+            }
+            current = data.calling_scope.clone();
+        }
+        let mut res = OrderedMap::new();
+        for layer in layers.iter().rev() {
+            for (name, value) in layer.iter() {
+                res.insert(name.clone(), value.clone());
+            }
+        }
+        Ok(res)
+    }
+
+    /// True if execution in this scope should stop, either because of a jump like `break` or
+    /// `return`, or because the job running in the current thread has been cancelled.
     pub fn is_stopped(&self) -> bool {
-        self.lock().unwrap().is_stopped
+        self.lock().unwrap().is_stopped || current_job().is_some_and(|job| job.is_cancelled())
     }
 
     pub fn take_return_value(&self) -> Option<Value> {
@@ -464,6 +552,8 @@ impl Scope {
         let mut data = self.lock()?;
         data.mapping.clear();
         data.uses.clear();
+        drop(data);
+        invalidate_resolutions(None);
         Ok(())
     }
 
@@ -601,6 +691,8 @@ impl Scope {
             );
         }
         data.mapping.insert(name.to_string(), value);
+        drop(data);
+        invalidate_resolutions(Some(name));
         Ok(())
     }
 
@@ -626,6 +718,8 @@ impl Scope {
             return error("Scope is read only");
         }
         data.mapping.insert(name.to_string(), value);
+        drop(data);
+        invalidate_resolutions(Some(name));
         Ok(())
     }
 
@@ -658,6 +752,8 @@ impl Scope {
                 name).as_str())
         } else {
             data.mapping.insert(name.to_string(), value);
+            drop(data);
+            invalidate_resolutions(Some(name));
             Ok(())
         }
     }
@@ -706,7 +802,10 @@ impl Scope {
             if data.is_readonly {
                 return Ok(None);
             }
-            Ok(data.mapping.remove(key))
+            let res = data.mapping.remove(key);
+            drop(data);
+            invalidate_resolutions(Some(key));
+            Ok(res)
         }
     }
 
@@ -736,6 +835,23 @@ impl Scope {
         }
     }
 
+    /// Look up a variable declared directly in this scope. `slot` is a hint of where in the scope
+    /// to look for it, which is updated to where the variable was found. Passing the same slot
+    /// when looking up the same name in a similar scope, e.g. the scope of a later call to the
+    /// same closure, avoids hashing the name.
+    pub fn get_local_slot(&self, name: &str, slot: &AtomicUsize) -> CrushResult<Option<Value>> {
+        let data = self.lock()?;
+        if let Some((key, value)) = data.mapping.get_index(slot.load(Ordering::Relaxed))
+            && key == name
+        {
+            return Ok(Some(value.clone()));
+        }
+        Ok(data.mapping.get_index_of(name).and_then(|idx| {
+            slot.store(idx, Ordering::Relaxed);
+            data.mapping.get_index(idx).map(|(_, value)| value.clone())
+        }))
+    }
+
     pub fn get_local(&self, name: &str) -> CrushResult<Option<Value>> {
         let data = self.lock()?;
         match lookup(name, &data) {
@@ -753,6 +869,8 @@ impl Scope {
             .is_none()
         {
             inner.uses.push(other.clone());
+            drop(inner);
+            invalidate_resolutions(None);
         }
     }
 
@@ -764,6 +882,7 @@ impl Scope {
             .position(|s| s.id() == other.id())
             .map(|i| inner.uses.remove(i));
         drop(inner);
+        invalidate_resolutions(None);
         self.parent().map(|parent| parent.unuse(other));
     }
 
@@ -824,6 +943,7 @@ impl Scope {
 
     pub fn set_parent(&self, parent: Option<Scope>) {
         self.lock().unwrap().parent_scope = parent;
+        invalidate_resolutions(None);
     }
 
     pub fn parent(&self) -> Option<Scope> {
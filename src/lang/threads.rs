@@ -1,15 +1,49 @@
-use crate::lang::errors::CrushResult;
+use crate::lang::errors::{CrushResult, error, ignore_send_error};
 use crate::lang::printer::Printer;
-use crate::lang::state::global_state::JobId;
+use crate::lang::state::global_state::{JobControl, JobHandle, JobId};
 use chrono::{DateTime, Local};
 use crossbeam::channel::Receiver;
 use crossbeam::channel::Sender;
 use crossbeam::channel::unbounded;
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::thread::{JoinHandle, ThreadId};
+use std::thread::{JoinHandle, Thread, ThreadId};
 use std::time::Duration;
 
+thread_local! {
+    /// The control of the job that the current thread is running a part of, if any.
+    static CURRENT_JOB: RefCell<Option<Arc<JobControl>>> = const { RefCell::new(None) };
+}
+
+/// Returns the control of the job that the current thread is running a part of, if any.
+pub fn current_job() -> Option<Arc<JobControl>> {
+    CURRENT_JOB.with(|job| job.borrow().clone())
+}
+
+/// Set the job that the current thread is running a part of, and return the previous one.
+pub fn set_current_job(job: Option<Arc<JobControl>>) -> Option<Arc<JobControl>> {
+    CURRENT_JOB.with(|current| current.replace(job))
+}
+
+thread_local! {
+    /// The files of the modules that the current thread is being run to import, innermost last.
+    static IMPORTS: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Returns the files of the modules that the current thread is being run to import, innermost
+/// last.
+pub fn imports() -> Vec<PathBuf> {
+    IMPORTS.with(|imports| imports.borrow().clone())
+}
+
+/// Set the modules that the current thread is being run to import, and return the previous ones.
+pub fn set_imports(imports: Vec<PathBuf>) -> Vec<PathBuf> {
+    IMPORTS.with(|current| current.replace(imports))
+}
+
 /**
 A thread management utility. Spawn, track and join on threads.
 */
@@ -17,12 +51,14 @@ struct ThreadData {
     handle: JoinHandle<CrushResult<()>>,
     creation_time: DateTime<Local>,
     job_id: Option<JobId>,
+    finished: Arc<AtomicBool>,
 }
 
 struct ThreadStoreInternal {
     threads: Vec<ThreadData>,
     sender: Sender<ThreadId>,
     receiver: Receiver<ThreadId>,
+    waiter: Option<Thread>,
 }
 
 pub struct ThreadDescription {
@@ -31,10 +67,10 @@ pub struct ThreadDescription {
     pub job_id: Option<JobId>,
 }
 
-fn join_handle(handle: JoinHandle<CrushResult<()>>, printer: &Printer) {
+fn join_handle(handle: JoinHandle<CrushResult<()>>) -> CrushResult<()> {
     match handle.join() {
-        Ok(res) => printer.handle_error(res),
-        Err(_) => printer.error("Unknown error while waiting for command to exit"),
+        Ok(res) => ignore_send_error(res),
+        Err(_) => error("Unknown error while waiting for command to exit"),
     }
 }
 
@@ -52,6 +88,7 @@ impl ThreadStore {
                 threads: Vec::new(),
                 sender,
                 receiver,
+                waiter: None,
             })),
         }
     }
@@ -59,21 +96,43 @@ impl ThreadStore {
     fn exit(&self) {
         let data = self.data.lock().unwrap();
         let _ = data.sender.send(std::thread::current().id());
+        if let Some(waiter) = &data.waiter {
+            waiter.unpark();
+        }
+    }
+
+    /**
+    Wake up the thread waiting in `join_job`, if any, so that it can check if the job it is
+    waiting for has been suspended.
+    */
+    pub fn wake(&self) {
+        if let Some(waiter) = &self.data.lock().unwrap().waiter {
+            waiter.unpark();
+        }
     }
 
     /**
-    Spawn a new thread
+    Spawn a new thread. The thread runs as part of the specified job, or the job of the calling
+    thread if none is specified.
     */
-    pub fn spawn<F>(&self, name: &str, job_id: Option<JobId>, f: F) -> CrushResult<ThreadId>
+    pub fn spawn<F>(&self, name: &str, job: Option<&JobHandle>, f: F) -> CrushResult<ThreadId>
     where
         F: FnOnce() -> CrushResult<()>,
         F: Send + 'static,
     {
         let slef = self.clone();
+        let job_id = job.map(|h| h.id());
+        let job = job.map(|h| h.control()).or_else(current_job);
+        let imports = imports();
+        let finished = Arc::new(AtomicBool::new(false));
+        let thread_finished = finished.clone();
         let handle = thread::Builder::new()
             .name(name.to_string())
             .spawn(move || {
+                set_current_job(job);
+                set_imports(imports);
                 let res = f();
+                thread_finished.store(true, Ordering::Release);
                 slef.exit();
                 res
             })?;
@@ -83,6 +142,7 @@ impl ThreadStore {
             handle,
             creation_time: Local::now(),
             job_id,
+            finished,
         });
         Ok(id)
     }
@@ -97,7 +157,7 @@ impl ThreadStore {
                 None => break,
                 Some(h) => {
                     drop(data);
-                    join_handle(h.handle, printer);
+                    printer.handle_error(join_handle(h.handle));
                 }
             }
         }
@@ -114,14 +174,14 @@ impl ThreadStore {
         }
         drop(data);
         for id in kill_list {
-            self.join_one(id, printer);
+            printer.handle_error(self.join_one(id));
         }
     }
 
     /**
-    Block calling thread until specified thread has exited
+    Block calling thread until specified thread has exited, and return the result of the thread
     */
-    pub fn join_one(&self, id: ThreadId, printer: &Printer) {
+    pub fn join_one(&self, id: ThreadId) -> CrushResult<()> {
         let mut data = self.data.lock().unwrap();
         let mut kill_idx = None;
         for idx in 0..data.threads.len() {
@@ -133,7 +193,39 @@ impl ThreadStore {
         if let Some(idx) = kill_idx {
             let h = data.threads.remove(idx);
             drop(data);
-            join_handle(h.handle, printer);
+            join_handle(h.handle)
+        } else {
+            Ok(())
+        }
+    }
+
+    /**
+    Block calling thread until specified thread has exited, and return the result of the thread.
+    If the specified job is suspended before that, return early and leave the thread to be
+    reaped later.
+    */
+    pub fn join_job(&self, id: ThreadId, job: &JobControl) -> CrushResult<()> {
+        loop {
+            let mut data = self.data.lock().unwrap();
+            let idx = match data
+                .threads
+                .iter()
+                .position(|t| t.handle.thread().id() == id)
+            {
+                None => return Ok(()),
+                Some(idx) => idx,
+            };
+            if data.threads[idx].finished.load(Ordering::Acquire) {
+                let h = data.threads.remove(idx);
+                drop(data);
+                return join_handle(h.handle);
+            }
+            if job.is_suspended() {
+                return Ok(());
+            }
+            data.waiter = Some(thread::current());
+            drop(data);
+            thread::park_timeout(Duration::from_millis(100));
         }
     }
 
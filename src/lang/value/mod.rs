@@ -1,3 +1,4 @@
+mod pattern;
 /**
 The type representing any value in crush.
  */
@@ -16,8 +17,8 @@ use regex::Regex;
 use crate::lang::data::r#struct::Struct;
 use crate::lang::data::r#struct::StructReader;
 use crate::lang::data::{
-    binary::BinaryReader, dict::Dict, dict::DictReader, list::List, table::ColumnType,
-    table::TableReader,
+    binary::BinaryOutputStream, binary::BinaryReader, dict::Dict, dict::DictReader, list::List,
+    table::ColumnType, table::TableReader,
 };
 use crate::lang::errors::{CrushResult, command_error, data_error};
 use crate::lang::pipe::{Stream, TableInputStream, TableOutputStream};
@@ -41,6 +42,7 @@ use crate::util::identity_arc::Identity;
 use crate::util::integer_formater::format_integer;
 use crate::util::repr::Repr;
 use ordered_map::OrderedMap;
+pub use pattern::{MatchArm, Pattern};
 use std::fmt::{Display, Formatter};
 use std::io::Read;
 use std::ops::Add;
@@ -70,6 +72,7 @@ pub enum Value {
     Bool(bool),
     Float(f64),
     BinaryInputStream(BinaryInputStream),
+    BinaryOutputStream(BinaryOutputStream),
     Binary(Arc<[u8]>),
     Type(ValueType),
 }
@@ -113,6 +116,7 @@ impl DisplayNonRecursive for Value {
             | Value::TableOutputStream(_)
             | Value::Table(_)
             | Value::BinaryInputStream(_)
+            | Value::BinaryOutputStream(_)
             | Value::Empty => {
                 f.write_str("<")?;
                 std::fmt::Display::fmt(&self.value_type(), f)?;
@@ -156,7 +160,8 @@ impl Repr for Value {
             Value::TableInputStream(_)
             | Value::TableOutputStream(_)
             | Value::Table(_)
-            | Value::BinaryInputStream(_) => panic!(),
+            | Value::BinaryInputStream(_)
+            | Value::BinaryOutputStream(_) => panic!(),
             Value::Empty => panic!(),
         }
     }
@@ -448,6 +453,7 @@ impl Value {
             Value::Float(_) => ValueType::Float,
             Value::Empty => ValueType::Empty,
             Value::BinaryInputStream(_) => ValueType::BinaryInputStream,
+            Value::BinaryOutputStream(_) => ValueType::BinaryOutputStream,
             Value::Binary(_) => ValueType::Binary,
             Value::Type(_) => ValueType::Type,
         }
@@ -486,6 +492,9 @@ impl Value {
             Value::TableOutputStream(_) => {
                 return error("Value of type table_output_stream can't be materialized");
             }
+            Value::BinaryOutputStream(_) => {
+                return error("Value of type binary_output_stream can't be materialized");
+            }
             Value::Empty
             | Value::String(_)
             | Value::Integer(_)
@@ -551,6 +560,7 @@ impl Value {
             ValueType::Empty => error("Invalid convert"),
             ValueType::Any => error("Invalid convert"),
             ValueType::BinaryInputStream => error("invalid convert"),
+            ValueType::BinaryOutputStream => error("invalid convert"),
             ValueType::Type => error("invalid convert"),
             ValueType::OneOf(_) => error("Can't convert to multiple types"),
         }
@@ -677,6 +687,7 @@ impl Clone for Value {
             Value::Float(f) => Value::Float(*f),
             Value::Empty => Value::Empty,
             Value::BinaryInputStream(v) => Value::BinaryInputStream(v.as_ref().clone()),
+            Value::BinaryOutputStream(v) => Value::BinaryOutputStream(v.clone()),
             Value::Binary(v) => Value::Binary(v.clone()),
             Value::Type(t) => Value::Type(t.clone()),
         }
@@ -720,7 +731,8 @@ impl Hash for Value {
             | Value::List(_)
             | Value::TableInputStream(_)
             | Value::TableOutputStream(_)
-            | Value::BinaryInputStream(_) => panic!("Can't hash output"),
+            | Value::BinaryInputStream(_)
+            | Value::BinaryOutputStream(_) => panic!("Can't hash output"),
             Value::Float(v) => {
                 let (m, x, s) = integer_decode(*v);
                 m.hash(state);
@@ -0,0 +1,269 @@
+use crate::lang::argument::Argument;
+use crate::lang::ast::source::Source;
+use crate::lang::data::list::List;
+use crate::lang::errors::{CrushResult, command_error, data_error};
+use crate::lang::pipe::{empty_channel, pipe};
+use crate::lang::state::contexts::EvalContext;
+use crate::lang::state::scope::ScopeType;
+use crate::lang::value::{Value, ValueDefinition};
+use crate::util::repr::Repr;
+use std::fmt::{Display, Formatter};
+
+/**
+A compiled pattern of a match statement. Values embedded in the pattern, like `$integer` or
+`$(pwd)`, are evaluated every time the pattern is tried.
+ */
+#[derive(Clone)]
+pub enum Pattern {
+    /// `_`, matches anything.
+    Any,
+    /// Matches depending on the type of the value the definition evaluates to.
+    Value(ValueDefinition),
+    /// Binds the matched value to a name, if the inner pattern matches.
+    Bind(String, Box<Pattern>),
+    /// Matches a list element by element, with an optional pattern for the remaining elements.
+    List {
+        items: Vec<Pattern>,
+        rest: Option<Box<Pattern>>,
+    },
+    /// Matches the fields of a struct or the keys of a dict.
+    Record(Vec<(String, Pattern)>),
+}
+
+/**
+An arm of a match statement. The guard and the body are closure definitions, which are invoked
+with the names bound by the pattern as named arguments.
+ */
+#[derive(Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<ValueDefinition>,
+    pub body: ValueDefinition,
+}
+
+impl Pattern {
+    /// Match a value against this pattern, adding the names it binds to the specified list.
+    fn matches(
+        &self,
+        value: &Value,
+        bindings: &mut Vec<(String, Value)>,
+        context: &mut EvalContext,
+    ) -> CrushResult<bool> {
+        match self {
+            Pattern::Any => Ok(true),
+            Pattern::Value(definition) => {
+                matches_value(&definition.eval_and_bind(context)?, value, bindings)
+            }
+            Pattern::Bind(name, pattern) => {
+                if pattern.matches(value, bindings, context)? {
+                    bindings.push((name.clone(), value.clone()));
+                    Ok(true)
+                } else {
+                    Ok(false)
+                }
+            }
+            Pattern::List { items, rest } => match value {
+                Value::List(list) => matches_list(items, rest, list, bindings, context),
+                _ => Ok(false),
+            },
+            Pattern::Record(fields) => {
+                for (name, pattern) in fields {
+                    let field_value = match value {
+                        Value::Struct(s) => s.get(name),
+                        Value::Dict(d) => d.get(&Value::from(name.as_str())),
+                        _ => return Ok(false),
+                    };
+                    match field_value {
+                        Some(field_value) => {
+                            if !pattern.matches(&field_value, bindings, context)? {
+                                return Ok(false);
+                            }
+                        }
+                        None => return Ok(false),
+                    }
+                }
+                Ok(true)
+            }
+        }
+    }
+}
+
+/// Match a value against an evaluated value in a pattern, like `$integer` or `5`.
+fn matches_value(
+    pattern: &Value,
+    value: &Value,
+    bindings: &mut Vec<(String, Value)>,
+) -> CrushResult<bool> {
+    let text = match value {
+        Value::String(s) => Some(s.to_string()),
+        Value::File(f) => Some(f.to_string_lossy().to_string()),
+        _ => None,
+    };
+    Ok(match (pattern, text) {
+        (Value::Type(t), _) => t.is(value),
+        (Value::Glob(g), Some(text)) => g.matches(&text),
+        (Value::Regex(_, re), Some(text)) => match re.captures(&text) {
+            Some(captures) => {
+                for name in re.capture_names().flatten() {
+                    if let Some(m) = captures.name(name) {
+                        bindings.push((name.to_string(), Value::from(m.as_str())));
+                    }
+                }
+                true
+            }
+            None => false,
+        },
+        (Value::Glob(_) | Value::Regex(_, _), None) => false,
+        (pattern, _) => pattern == value,
+    })
+}
+
+fn matches_list(
+    items: &[Pattern],
+    rest: &Option<Box<Pattern>>,
+    list: &List,
+    bindings: &mut Vec<(String, Value)>,
+    context: &mut EvalContext,
+) -> CrushResult<bool> {
+    let count = items.len();
+    if list.len() < count || (rest.is_none() && list.len() != count) {
+        return Ok(false);
+    }
+    for (idx, item) in items.iter().enumerate() {
+        if !item.matches(&list.get(idx)?, bindings, context)? {
+            return Ok(false);
+        }
+    }
+    match rest {
+        Some(rest) => rest.matches(
+            &Value::List(list.slice(count, list.len())?),
+            bindings,
+            context,
+        ),
+        None => Ok(true),
+    }
+}
+
+/// Invoke the guard or the body of an arm in a new conditional scope, and return its output.
+fn invoke(
+    closure: &ValueDefinition,
+    arguments: Vec<Argument>,
+    source: &Source,
+    context: &mut EvalContext,
+) -> CrushResult<Value> {
+    let command = match closure.eval_and_bind(context)? {
+        Value::Command(command) => command,
+        v => {
+            return command_error(format!(
+                "Invalid match arm, expected a command, got a value of type `{}`.",
+                v.value_type()
+            ));
+        }
+    };
+    let env = context
+        .env
+        .create_child(&context.env, ScopeType::Conditional);
+    let (sender, receiver) = pipe();
+    command.eval(
+        context
+            .with_scope(&env)
+            .job_context(empty_channel(), sender)
+            .command_context(source, arguments, None),
+    )?;
+    receiver.recv()
+}
+
+/**
+Evaluate a match statement. The arms are tried in order, and the output of the body of the first
+arm whose pattern matches and whose guard, if any, returns true is returned. If no arm matches,
+the output is empty.
+ */
+pub fn eval_match(
+    value: &ValueDefinition,
+    arms: &[MatchArm],
+    source: &Source,
+    context: &mut EvalContext,
+) -> CrushResult<Value> {
+    let value = value.eval_and_bind(context)?;
+    for arm in arms {
+        let mut bindings = Vec::new();
+        if !arm.pattern.matches(&value, &mut bindings, context)? {
+            continue;
+        }
+        let arguments = bindings
+            .into_iter()
+            .map(|(name, value)| Argument::named(&name, value, source))
+            .collect::<Vec<_>>();
+
+        if let Some(guard) = &arm.guard {
+            match invoke(guard, arguments.clone(), source, context)? {
+                Value::Bool(true) => {}
+                Value::Bool(false) => continue,
+                v => {
+                    return data_error(format!(
+                        "Match guard must output value of boolean type, got a value of type `{}`.",
+                        v.value_type()
+                    ));
+                }
+            }
+        }
+
+        return invoke(&arm.body, arguments, source, context);
+    }
+    Ok(Value::Empty)
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pattern::Any => f.write_str("_"),
+            Pattern::Value(v) => v.repr(f),
+            Pattern::Bind(name, pattern) => match pattern.as_ref() {
+                Pattern::Any => f.write_str(name),
+                pattern => write!(f, "{}: {}", name, pattern),
+            },
+            Pattern::List { items, rest } => {
+                f.write_str("[")?;
+                let mut first = true;
+                for item in items {
+                    if !first {
+                        f.write_str(" ")?;
+                    }
+                    first = false;
+                    item.fmt(f)?;
+                }
+                if let Some(rest) = rest {
+                    if !first {
+                        f.write_str(" ")?;
+                    }
+                    write!(f, "@{}", rest)?;
+                }
+                f.write_str("]")
+            }
+            Pattern::Record(fields) => {
+                f.write_str("[")?;
+                let mut first = true;
+                for (name, pattern) in fields {
+                    if !first {
+                        f.write_str(" ")?;
+                    }
+                    first = false;
+                    write!(f, "{}={}", name, pattern)?;
+                }
+                f.write_str("]")
+            }
+        }
+    }
+}
+
+impl Display for MatchArm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.pattern.fmt(f)?;
+        if let Some(guard) = &self.guard {
+            f.write_str(" if ")?;
+            guard.fmt(f)?;
+        }
+        f.write_str(" ")?;
+        self.body.fmt(f)
+    }
+}
@@ -4,6 +4,7 @@ use crate::lang::ast::source::Source;
 use crate::lang::command::ParameterDefinition;
 use crate::lang::pipe::black_hole;
 use crate::lang::state::contexts::EvalContext;
+use crate::lang::value::pattern::{MatchArm, eval_match};
 use crate::lang::{command::CrushCommand, job::Job};
 use crate::util::repr::Repr;
 use crate::{
@@ -25,13 +26,20 @@ pub enum ValueDefinition {
     JobListDefinition(Vec<Job>),
     Identifier(Source),
     GetAttr(Box<ValueDefinition>, Source),
+    /// A match statement, with its arms compiled by the parser
+    Match {
+        value: Box<ValueDefinition>,
+        arms: Vec<MatchArm>,
+        source: Source,
+    },
 }
 
 impl ValueDefinition {
     pub fn location(&self) -> Location {
         match self {
             ValueDefinition::Value(_, l) => l.location(),
-            ValueDefinition::ClosureDefinition { source, .. } => source.location(),
+            ValueDefinition::ClosureDefinition { source, .. }
+            | ValueDefinition::Match { source, .. } => source.location(),
             ValueDefinition::JobDefinition(j) => j.location(),
             ValueDefinition::Identifier(l) => l.location(),
             ValueDefinition::GetAttr(p, a) => p.location().union(a.location()),
@@ -47,7 +55,8 @@ impl ValueDefinition {
             ValueDefinition::Identifier(source)
             | ValueDefinition::GetAttr(_, source)
             | ValueDefinition::Value(_, source)
-            | ValueDefinition::ClosureDefinition { source, .. } => source,
+            | ValueDefinition::ClosureDefinition { source, .. }
+            | ValueDefinition::Match { source, .. } => source,
             ValueDefinition::JobDefinition(j) => j.source(),
             ValueDefinition::JobListDefinition(j) => j.last().map(|j| j.source()).unwrap(),
         }
@@ -57,6 +66,7 @@ impl ValueDefinition {
         match self {
             ValueDefinition::JobDefinition(j) => j.can_block(context),
             ValueDefinition::GetAttr(_inner1, _inner2) => true,
+            ValueDefinition::Match { .. } => true,
             _ => false,
         }
     }
@@ -138,10 +148,31 @@ impl ValueDefinition {
                 ))?;
                 (Some(parent), val)
             }
+
+            ValueDefinition::Match {
+                value,
+                arms,
+                source,
+            } => (None, eval_match(value, arms, source, context)?),
         })
     }
 }
 
+fn fmt_match(
+    value: &ValueDefinition,
+    arms: &[MatchArm],
+    f: &mut Formatter<'_>,
+) -> std::fmt::Result {
+    f.write_str("match ")?;
+    value.repr(f)?;
+    f.write_str(" {")?;
+    for arm in arms {
+        f.write_str(" ")?;
+        arm.fmt(f)?;
+    }
+    f.write_str(" }")
+}
+
 impl Display for ValueDefinition {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match &self {
@@ -173,6 +204,7 @@ impl Display for ValueDefinition {
                 l.fmt(f)
             }
             ValueDefinition::JobListDefinition(jl) => jl.fmt(f),
+            ValueDefinition::Match { value, arms, .. } => fmt_match(value, arms, f),
         }
     }
 }
@@ -226,6 +258,11 @@ impl Repr for ValueDefinition {
                 }
                 f.write_str(")")
             }
+            ValueDefinition::Match { value, arms, .. } => {
+                f.write_str("(")?;
+                fmt_match(value, arms, f)?;
+                f.write_str(")")
+            }
         }
     }
 }
@@ -6,6 +6,7 @@ use crate::lang::errors::{CrushResult, command_error, error};
 use crate::lang::help::Help;
 use crate::lang::{data::table::ColumnType, value::Value};
 use crate::util::glob::Glob;
+use crate::util::time::parse_time;
 use itertools::Itertools;
 use ordered_map::OrderedMap;
 use regex::Regex;
@@ -34,6 +35,7 @@ pub enum ValueType {
     Empty,
     Any,
     BinaryInputStream,
+    BinaryOutputStream,
     Binary,
     Type,
     OneOf(Vec<ValueType>),
@@ -81,6 +83,7 @@ impl ValueType {
             ValueType::TableInputStream(_) => &types::table_input_stream::methods(),
             ValueType::TableOutputStream(_) => &types::table_output_stream::methods(),
             ValueType::Binary => &types::binary::methods(),
+            ValueType::BinaryInputStream => &types::binary_stream::methods(),
             ValueType::Scope => &types::scope::methods(),
             ValueType::Struct => &types::r#struct::methods(),
             ValueType::OneOf(_) => &types::one_of::methods(),
@@ -123,6 +126,9 @@ impl ValueType {
             ValueType::TableOutputStream(_) => {
                 return command_error("Can't materialize `$table_output_stream`");
             }
+            ValueType::BinaryOutputStream => {
+                return command_error("Can't materialize `$binary_output_stream`");
+            }
             ValueType::Table(r) => ValueType::Table(ColumnType::materialize(r)?),
             ValueType::List(l) => ValueType::List(Box::from(l.materialize()?)),
             ValueType::Dict(k, v) => {
@@ -145,6 +151,7 @@ impl ValueType {
             | ValueType::Dict(_, _)
             | ValueType::Command
             | ValueType::BinaryInputStream
+            | ValueType::BinaryOutputStream
             | ValueType::TableInputStream(_)
             | ValueType::Struct
             | ValueType::Table(_) => false,
@@ -169,6 +176,10 @@ impl ValueType {
             ValueType::File => Ok(Value::from(s)),
             ValueType::Float => Ok(Value::Float(s.parse::<f64>()?)),
             ValueType::Bool => Ok(Value::Bool(s.parse::<bool>()?)),
+            ValueType::Time => match parse_time(s) {
+                Some(t) => Ok(Value::Time(t)),
+                None => error(format!("Can't parse `{}` as a time", s)),
+            },
             _ => error(format!("Can't parse string into value of type `{}`", self)),
         }
     }
@@ -230,6 +241,7 @@ impl Help for ValueType {
             ValueType::Empty => "Nothing.",
             ValueType::Any => "Any type.",
             ValueType::BinaryInputStream => "A stream of binary data.",
+            ValueType::BinaryOutputStream => "An output stream of binary data.",
             ValueType::Binary => "Binary data.",
             ValueType::Type => "A type.",
             ValueType::OneOf(types) => {
@@ -361,6 +373,7 @@ impl Display for ValueType {
             ValueType::Empty => f.write_str("empty"),
             ValueType::Any => f.write_str("any"),
             ValueType::BinaryInputStream => f.write_str("binary_stream"),
+            ValueType::BinaryOutputStream => f.write_str("binary_output_stream"),
             ValueType::Binary => f.write_str("binary"),
             ValueType::Type => f.write_str("type"),
             ValueType::OneOf(types) => {
@@ -23,6 +23,8 @@ use std::path::PathBuf;
 enum Mode {
     Interactive,
     Pup,
+    PupStream,
+    Helper(PathBuf),
     File(PathBuf),
     Help,
 }
@@ -35,12 +37,18 @@ fn parse_args() -> CrushResult<Config> {
     let args = std::env::args().collect::<Vec<_>>();
     let mut mode = Mode::Interactive;
     let mut all_files = false;
-    for arg in &args[1..] {
+    let mut args = args[1..].iter();
+    while let Some(arg) = args.next() {
         if all_files {
             mode = Mode::File(PathBuf::from(arg))
         } else {
             match arg.as_str() {
                 "--pup" | "-p" => mode = Mode::Pup,
+                "--pup-stream" => mode = Mode::PupStream,
+                "--helper" => match args.next() {
+                    Some(socket) => mode = Mode::Helper(PathBuf::from(socket)),
+                    None => return command_error("Missing socket for --helper"),
+                },
                 "--interactive" | "-i" => mode = Mode::Interactive,
                 "--help" | "-h" => mode = Mode::Help,
                 "--" => all_files = true,
@@ -65,6 +73,12 @@ fn print_help(printer: &Printer) {
     printer.line("  -p --pup          Read a pup-serialized closure from standard input,");
     printer.line("                    execute it, serialize the output to pup-format,");
     printer.line("                    and write it to standard output");
+    printer.line("  --pup-stream      Like --pup, but write the output in the streaming");
+    printer.line("                    pup-format. Table streams are written one batch of");
+    printer.line("                    rows at a time as they are produced");
+    printer.line("  --helper SOCKET   Run as the privileged helper used by `user:do`. This is");
+    printer.line("                    started by Crush through sudo, and is not meant to be");
+    printer.line("                    run directly");
     printer.line("");
     printer.line("Crush can be run in three modes.");
     printer.line("");
@@ -82,9 +96,10 @@ fn print_help(printer: &Printer) {
     printer.line(
         "  standard input, and executed. The output of the closure will be written in pup-format",
     );
-    printer
-        .line("  to standard output. This third mode is used by e.g. sudo and remote:exec to run");
-    printer.line("  closures in a different process.");
+    printer.line(
+        "  to standard output. This third mode, and its streaming variant \"--pup-stream\", are",
+    );
+    printer.line("  used by e.g. remote:exec to run closures in a different process.");
 }
 
 fn run() -> CrushResult<i32> {
@@ -93,11 +108,12 @@ fn run() -> CrushResult<i32> {
     let root_scope = state::scope::Scope::create_root();
     let local_scope = root_scope.create_child(&root_scope, Namespace);
 
-    let (printer, print_handle) = if config.mode == Mode::Pup {
-        printer::noop()
-    } else {
-        printer::init(Some(root_scope.clone()))
-    };
+    let (printer, print_handle) =
+        if matches!(config.mode, Mode::Pup | Mode::PupStream | Mode::Helper(_)) {
+            printer::noop()
+        } else {
+            printer::init(Some(root_scope.clone()))
+        };
 
     let run_mode = match config.mode {
         Mode::Interactive => RunMode::Interactive,
@@ -121,12 +137,23 @@ fn run() -> CrushResult<i32> {
             execute::pup(local_scope, &buff, &global_state)?;
         }
 
-        Mode::File(f) => global_state.printer().handle_error(execute::file(
-            &local_scope,
-            f.as_path(),
-            &pretty_printer,
-            &global_state,
-        )),
+        Mode::PupStream => {
+            let mut buff = Vec::new();
+            std::io::stdin().read_to_end(&mut buff)?;
+            execute::pup_stream(local_scope, &buff, &global_state)?;
+        }
+
+        Mode::Helper(socket) => execute::helper(local_scope, &socket, &global_state)?,
+
+        Mode::File(f) => {
+            if let Err(e) = execute::file(&local_scope, f.as_path(), &pretty_printer, &global_state)
+            {
+                global_state.printer().crush_error(e);
+                if global_state.exit_status().is_none() {
+                    global_state.set_exit_status(1);
+                }
+            }
+        }
 
         Mode::Help => print_help(&global_state.printer()),
     }
@@ -198,7 +198,9 @@ fn get_color<'a>(
             }
         }
 
-        QuotedString(_, _) => colors.get("string_literal"),
+        QuotedString(_, _) | InterpolationStart(_) | StringFragment(_, _) | InterpolationEnd(_) => {
+            colors.get("string_literal")
+        }
         Flag(name, _) => match current_command {
             Some(cmd) => {
                 if name.len() > 2 && allowed_named_argument(cmd.completion_data(), &name[2..]) {
@@ -245,10 +247,10 @@ fn get_color<'a>(
             },
         },
         Background(_) => None,
+        RedirectOut(_) | RedirectAppend(_) | RedirectIn(_) => colors.get("operator"),
         Separator(_, _) => None,
-        For(_) | While(_) | Loop(_) | If(_) | Else(_) | Return(_) | Break(_) | Continue(_) => {
-            colors.get("keyword")
-        }
+        For(_) | While(_) | Loop(_) | If(_) | Else(_) | Try(_) | Catch(_) | Match(_)
+        | Return(_) | Break(_) | Continue(_) => colors.get("keyword"),
     }
 }
 
@@ -275,6 +277,9 @@ fn token_type(token: Token, scope: &Option<Scope>) -> Option<ValueType> {
         Token::Star(_) => None,
         Token::Slash(_) => None,
         Token::QuotedString(_, _) => Some(ValueType::String),
+        Token::InterpolationStart(_) => Some(ValueType::String),
+        Token::StringFragment(_, _) => None,
+        Token::InterpolationEnd(_) => None,
         Token::Comment(_, _) => None,
         Token::Identifier(id, _) => match scope {
             None => None,
@@ -296,6 +301,9 @@ fn token_type(token: Token, scope: &Option<Scope>) -> Option<ValueType> {
         Token::Declare(_) => None,
         Token::Separator(_, _) => None,
         Token::Background(_) => None,
+        Token::RedirectOut(_) => None,
+        Token::RedirectAppend(_) => None,
+        Token::RedirectIn(_) => None,
         Token::SubStart(_) => None,
         Token::SubEnd(_) => None,
         Token::BlockStart(_) => None,
@@ -311,6 +319,9 @@ fn token_type(token: Token, scope: &Option<Scope>) -> Option<ValueType> {
         Token::Loop(_) => None,
         Token::If(_) => None,
         Token::Else(_) => None,
+        Token::Try(_) => None,
+        Token::Catch(_) => None,
+        Token::Match(_) => None,
         Token::Return(_) => None,
         Token::Break(_) => None,
         Token::Continue(_) => None,
@@ -10,6 +10,7 @@ pub mod identity_arc;
 pub mod integer_formater;
 pub mod logins;
 pub mod md;
+pub mod protobuf;
 pub mod regex;
 pub mod replace;
 pub mod repr;
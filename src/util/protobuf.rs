@@ -0,0 +1,428 @@
+/// Conversion between protobuf messages and Crush values.
+///
+/// Message descriptors are loaded either from `.proto` files or from encoded file descriptors,
+/// e.g. as returned by gRPC server reflection. Messages become structs, repeated fields become
+/// lists, map fields become dicts and enums become the name of the enum value. The well known
+/// `google.protobuf.Timestamp` and `google.protobuf.Duration` types are mapped to the native
+/// Crush time and duration types.
+use crate::lang::data::dict::Dict;
+use crate::lang::data::list::List;
+use crate::lang::data::r#struct::Struct;
+use crate::lang::data::table::ColumnType;
+use crate::lang::errors::{CrushResult, data_error, serialization_error};
+use crate::lang::value::{Value, ValueType};
+use chrono::{DateTime, Duration, Local};
+use prost::Message;
+use prost_reflect::{
+    DescriptorPool, DynamicMessage, FieldDescriptor, Kind, MapKey, MessageDescriptor,
+    ReflectMessage,
+};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+const TIMESTAMP: &str = "google.protobuf.Timestamp";
+const DURATION: &str = "google.protobuf.Duration";
+
+/// Parse the specified `.proto` files, including any files they import. Imports are resolved
+/// relative to the directories of the input files.
+pub fn load(files: &[PathBuf]) -> CrushResult<DescriptorPool> {
+    let includes = files
+        .iter()
+        .map(|f| {
+            f.parent()
+                .map(|p| p.to_path_buf())
+                .unwrap_or_else(|| PathBuf::from("."))
+        })
+        .collect::<HashSet<_>>();
+    let parsed = match protobuf_parse::Parser::new()
+        .pure()
+        .includes(includes)
+        .inputs(files)
+        .parse_and_typecheck()
+    {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            return serialization_error(format!("Failed to parse protobuf definition: {}", e));
+        }
+    };
+    let mut encoded = Vec::new();
+    for file in parsed.file_descriptors {
+        match protobuf::Message::write_to_bytes(&file) {
+            Ok(bytes) => encoded.push(bytes),
+            Err(e) => return serialization_error(e.to_string()),
+        }
+    }
+    pool(encoded)
+}
+
+/// Create a descriptor pool from a list of encoded `FileDescriptorProto` messages. Files may be
+/// listed in any order, and duplicates are ignored.
+pub fn pool(files: Vec<Vec<u8>>) -> CrushResult<DescriptorPool> {
+    let mut seen = HashMap::new();
+    for file in files {
+        let file = prost_types::FileDescriptorProto::decode(file.as_slice())?;
+        seen.entry(file.name().to_string()).or_insert(file);
+    }
+    let mut pool = DescriptorPool::new();
+    pool.add_file_descriptor_protos(seen.into_values())?;
+    Ok(pool)
+}
+
+/// Create a descriptor pool from files containing serialized `FileDescriptorSet` messages, e.g. as
+/// produced by `protoc --descriptor_set_out`.
+pub fn load_descriptor_sets(files: &[PathBuf]) -> CrushResult<DescriptorPool> {
+    let mut pool = DescriptorPool::new();
+    for file in files {
+        pool.decode_file_descriptor_set(std::fs::read(file)?.as_slice())?;
+    }
+    Ok(pool)
+}
+
+/// Look up a message by its fully qualified name.
+pub fn message(pool: &DescriptorPool, name: &str) -> CrushResult<MessageDescriptor> {
+    match pool.get_message_by_name(name.trim_start_matches('.')) {
+        Some(desc) => Ok(desc),
+        None => data_error(format!(
+            "Unknown message `{}`. Known messages are {}.",
+            name,
+            pool.all_messages()
+                .filter(|m| !m.full_name().starts_with("google.protobuf."))
+                .map(|m| format!("`{}`", m.full_name()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// The Crush type that values of the specified kind are converted into.
+fn kind_type(kind: &Kind) -> ValueType {
+    match kind {
+        Kind::Double | Kind::Float => ValueType::Float,
+        Kind::Int32
+        | Kind::Int64
+        | Kind::Uint32
+        | Kind::Uint64
+        | Kind::Sint32
+        | Kind::Sint64
+        | Kind::Fixed32
+        | Kind::Fixed64
+        | Kind::Sfixed32
+        | Kind::Sfixed64 => ValueType::Integer,
+        Kind::Bool => ValueType::Bool,
+        Kind::String | Kind::Enum(_) => ValueType::String,
+        Kind::Bytes => ValueType::Binary,
+        Kind::Message(m) => match m.full_name() {
+            TIMESTAMP => ValueType::Time,
+            DURATION => ValueType::Duration,
+            _ => ValueType::Struct,
+        },
+    }
+}
+
+/// The Crush type that the specified field is converted into.
+pub fn field_type(field: &FieldDescriptor) -> ValueType {
+    match field.kind() {
+        Kind::Message(entry) if field.is_map() => ValueType::Dict(
+            Box::from(kind_type(&entry.map_entry_key_field().kind())),
+            Box::from(kind_type(&entry.map_entry_value_field().kind())),
+        ),
+        kind if field.is_list() => ValueType::List(Box::from(kind_type(&kind))),
+        kind => kind_type(&kind),
+    }
+}
+
+/// The columns of a table where every row is a message of the specified type. Fields that can be
+/// unset may also contain `$empty`.
+pub fn column_types(desc: &MessageDescriptor) -> Vec<ColumnType> {
+    desc.fields()
+        .map(|f| {
+            let cell_type = if f.supports_presence() {
+                ValueType::OneOf(vec![field_type(&f), ValueType::Empty])
+            } else {
+                field_type(&f)
+            };
+            ColumnType::new_from_string(f.name().to_string(), cell_type)
+        })
+        .collect()
+}
+
+/// A description of the fields of a message, suitable for use in a command signature.
+pub fn signature(desc: &MessageDescriptor) -> String {
+    desc.fields()
+        .map(|f| format!("{}={}", f.name(), field_type(&f)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn timestamp(message: &DynamicMessage) -> CrushResult<Value> {
+    let seconds = message
+        .get_field_by_name("seconds")
+        .and_then(|v| v.as_i64());
+    let nanos = message.get_field_by_name("nanos").and_then(|v| v.as_i32());
+    match DateTime::from_timestamp(seconds.unwrap_or(0), nanos.unwrap_or(0) as u32) {
+        Some(t) => Ok(Value::Time(t.with_timezone(&Local))),
+        None => data_error("Timestamp out of range."),
+    }
+}
+
+fn duration(message: &DynamicMessage) -> Value {
+    let seconds = message
+        .get_field_by_name("seconds")
+        .and_then(|v| v.as_i64());
+    let nanos = message.get_field_by_name("nanos").and_then(|v| v.as_i32());
+    Value::Duration(
+        Duration::seconds(seconds.unwrap_or(0)) + Duration::nanoseconds(nanos.unwrap_or(0) as i64),
+    )
+}
+
+fn map_key_to_value(key: &MapKey) -> Value {
+    match key {
+        MapKey::Bool(b) => Value::Bool(*b),
+        MapKey::I32(i) => Value::from(*i),
+        MapKey::I64(i) => Value::Integer(*i as i128),
+        MapKey::U32(i) => Value::from(*i),
+        MapKey::U64(i) => Value::from(*i),
+        MapKey::String(s) => Value::from(s),
+    }
+}
+
+fn to_value(value: &prost_reflect::Value, kind: &Kind) -> CrushResult<Value> {
+    use prost_reflect::Value as P;
+    Ok(match value {
+        P::Bool(b) => Value::Bool(*b),
+        P::I32(i) => Value::from(*i),
+        P::I64(i) => Value::Integer(*i as i128),
+        P::U32(i) => Value::from(*i),
+        P::U64(i) => Value::from(*i),
+        P::F32(f) => Value::Float(*f as f64),
+        P::F64(f) => Value::Float(*f),
+        P::String(s) => Value::from(s),
+        P::Bytes(b) => Value::from(b.as_ref()),
+        P::EnumNumber(n) => match kind {
+            Kind::Enum(e) => match e.get_value(*n) {
+                Some(v) => Value::from(v.name()),
+                None => Value::from(*n),
+            },
+            _ => Value::from(*n),
+        },
+        P::Message(m) => message_to_value(m)?,
+        P::List(items) => List::new(
+            kind_type(kind),
+            items
+                .iter()
+                .map(|i| to_value(i, kind))
+                .collect::<CrushResult<Vec<_>>>()?,
+        )
+        .into(),
+        P::Map(entries) => {
+            let (key_kind, value_kind) = match kind {
+                Kind::Message(entry) => (
+                    entry.map_entry_key_field().kind(),
+                    entry.map_entry_value_field().kind(),
+                ),
+                _ => return data_error("Invalid protobuf map."),
+            };
+            let dict = Dict::new(kind_type(&key_kind), kind_type(&value_kind))?;
+            for (k, v) in entries {
+                dict.insert(map_key_to_value(k), to_value(v, &value_kind)?)?;
+            }
+            Value::Dict(dict)
+        }
+    })
+}
+
+/// Convert a protobuf message into a Crush value. Most messages become structs, but timestamps
+/// and durations are converted into the corresponding native types.
+pub fn message_to_value(message: &DynamicMessage) -> CrushResult<Value> {
+    let desc = message.descriptor();
+    match desc.full_name() {
+        TIMESTAMP => timestamp(message),
+        DURATION => Ok(duration(message)),
+        _ => Ok(Value::Struct(message_to_struct(message)?)),
+    }
+}
+
+/// Convert a protobuf message into a struct with one member per field. Fields that support
+/// presence tracking and are not set, e.g. unset message fields, are set to `$empty`.
+pub fn message_to_struct(message: &DynamicMessage) -> CrushResult<Struct> {
+    let mut members = Vec::new();
+    for field in message.descriptor().fields() {
+        let value = if field.supports_presence() && !message.has_field(&field) {
+            Value::Empty
+        } else {
+            to_value(&message.get_field(&field), &field.kind())?
+        };
+        members.push((field.name().to_string(), value));
+    }
+    Ok(Struct::new(members, None))
+}
+
+fn type_error<T>(field: &FieldDescriptor, expected: &str, value: &Value) -> CrushResult<T> {
+    data_error(format!(
+        "Invalid value for field `{}`. Expected {}, got a value of type `{}`.",
+        field.full_name(),
+        expected,
+        value.value_type()
+    ))
+}
+
+fn integer<T: TryFrom<i128>>(field: &FieldDescriptor, value: &Value) -> CrushResult<T> {
+    match value {
+        Value::Integer(i) => T::try_from(*i).or_else(|_| {
+            data_error(format!(
+                "Value {} is out of range for field `{}`.",
+                i,
+                field.full_name()
+            ))
+        }),
+        _ => type_error(field, "an integer", value),
+    }
+}
+
+fn float(field: &FieldDescriptor, value: &Value) -> CrushResult<f64> {
+    match value {
+        Value::Float(f) => Ok(*f),
+        Value::Integer(i) => Ok(*i as f64),
+        _ => type_error(field, "a float", value),
+    }
+}
+
+fn seconds_and_nanos(desc: MessageDescriptor, seconds: i64, nanos: i32) -> DynamicMessage {
+    let mut message = DynamicMessage::new(desc);
+    message.set_field_by_name("seconds", prost_reflect::Value::I64(seconds));
+    message.set_field_by_name("nanos", prost_reflect::Value::I32(nanos));
+    message
+}
+
+fn to_message(
+    field: &FieldDescriptor,
+    desc: MessageDescriptor,
+    value: &Value,
+) -> CrushResult<DynamicMessage> {
+    match (desc.full_name(), value) {
+        (TIMESTAMP, Value::Time(t)) => Ok(seconds_and_nanos(
+            desc,
+            t.timestamp(),
+            t.timestamp_subsec_nanos() as i32,
+        )),
+        (DURATION, Value::Duration(d)) => {
+            Ok(seconds_and_nanos(desc, d.num_seconds(), d.subsec_nanos()))
+        }
+        (TIMESTAMP, _) => type_error(field, "a time", value),
+        (DURATION, _) => type_error(field, "a duration", value),
+        (_, Value::Struct(s)) => struct_to_message(s, &desc),
+        _ => type_error(field, "a struct", value),
+    }
+}
+
+fn to_proto(
+    field: &FieldDescriptor,
+    kind: &Kind,
+    value: &Value,
+) -> CrushResult<prost_reflect::Value> {
+    use prost_reflect::Value as P;
+    Ok(match kind {
+        Kind::Double => P::F64(float(field, value)?),
+        Kind::Float => P::F32(float(field, value)? as f32),
+        Kind::Int32 | Kind::Sint32 | Kind::Sfixed32 => P::I32(integer(field, value)?),
+        Kind::Int64 | Kind::Sint64 | Kind::Sfixed64 => P::I64(integer(field, value)?),
+        Kind::Uint32 | Kind::Fixed32 => P::U32(integer(field, value)?),
+        Kind::Uint64 | Kind::Fixed64 => P::U64(integer(field, value)?),
+        Kind::Bool => match value {
+            Value::Bool(b) => P::Bool(*b),
+            _ => return type_error(field, "a bool", value),
+        },
+        Kind::String => match value {
+            Value::String(s) => P::String(s.to_string()),
+            _ => return type_error(field, "a string", value),
+        },
+        Kind::Bytes => match value {
+            Value::Binary(b) => P::Bytes(b.to_vec().into()),
+            Value::String(s) => P::Bytes(s.as_bytes().to_vec().into()),
+            _ => return type_error(field, "binary data", value),
+        },
+        Kind::Enum(e) => match value {
+            Value::String(s) => match e.get_value_by_name(s) {
+                Some(v) => P::EnumNumber(v.number()),
+                None => {
+                    return data_error(format!(
+                        "Invalid value for field `{}`. Expected one of {}.",
+                        field.full_name(),
+                        e.values()
+                            .map(|v| format!("`{}`", v.name()))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ));
+                }
+            },
+            Value::Integer(_) => P::EnumNumber(integer(field, value)?),
+            _ => return type_error(field, "a string", value),
+        },
+        Kind::Message(desc) => P::Message(to_message(field, desc.clone(), value)?),
+    })
+}
+
+fn to_map_key(field: &FieldDescriptor, kind: &Kind, value: &Value) -> CrushResult<MapKey> {
+    Ok(match to_proto(field, kind, value)? {
+        prost_reflect::Value::Bool(b) => MapKey::Bool(b),
+        prost_reflect::Value::I32(i) => MapKey::I32(i),
+        prost_reflect::Value::I64(i) => MapKey::I64(i),
+        prost_reflect::Value::U32(i) => MapKey::U32(i),
+        prost_reflect::Value::U64(i) => MapKey::U64(i),
+        prost_reflect::Value::String(s) => MapKey::String(s),
+        _ => {
+            return data_error(format!(
+                "Invalid key type for field `{}`.",
+                field.full_name()
+            ));
+        }
+    })
+}
+
+fn field_to_proto(field: &FieldDescriptor, value: &Value) -> CrushResult<prost_reflect::Value> {
+    match (field.kind(), value) {
+        (Kind::Message(entry), Value::Dict(d)) if field.is_map() => {
+            let key_kind = entry.map_entry_key_field().kind();
+            let value_kind = entry.map_entry_value_field().kind();
+            let mut map = HashMap::new();
+            for (k, v) in d.elements() {
+                map.insert(
+                    to_map_key(field, &key_kind, &k)?,
+                    to_proto(field, &value_kind, &v)?,
+                );
+            }
+            Ok(prost_reflect::Value::Map(map))
+        }
+        (_, _) if field.is_map() => type_error(field, "a dict", value),
+        (kind, Value::List(l)) if field.is_list() => Ok(prost_reflect::Value::List(
+            l.iter()
+                .map(|v| to_proto(field, &kind, &v))
+                .collect::<CrushResult<Vec<_>>>()?,
+        )),
+        (_, _) if field.is_list() => type_error(field, "a list", value),
+        (kind, value) => to_proto(field, &kind, value),
+    }
+}
+
+/// Convert a struct into a protobuf message of the specified type. Members that are `$empty` are
+/// left unset, and it is an error for the struct to contain members that the message type lacks.
+pub fn struct_to_message(value: &Struct, desc: &MessageDescriptor) -> CrushResult<DynamicMessage> {
+    let mut message = DynamicMessage::new(desc.clone());
+    for (name, value) in value.local_elements() {
+        let field = match desc.get_field_by_name(&name) {
+            Some(field) => field,
+            None => {
+                return data_error(format!(
+                    "Message `{}` has no field named `{}`.",
+                    desc.full_name(),
+                    name
+                ));
+            }
+        };
+        if let Value::Empty = value {
+            continue;
+        }
+        message.set_field(&field, field_to_proto(&field, &value)?);
+    }
+    Ok(message)
+}
@@ -1,4 +1,13 @@
-use chrono::Duration;
+use chrono::{DateTime, Duration, Local};
+
+/// Parse a string as a point in time. Both the RFC 3339 format and the format Crush itself uses
+/// when displaying times are accepted.
+pub fn parse_time(s: &str) -> Option<DateTime<Local>> {
+    DateTime::parse_from_rfc3339(s)
+        .or_else(|_| DateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S %z"))
+        .ok()
+        .map(|t| t.with_timezone(&Local))
+}
 
 pub fn duration_format(d: &Duration) -> String {
     const MICROS_IN_SECOND: i128 = 1_000_000_000;
@@ -4,8 +4,10 @@ use std::process::Command;
 use test_finder::test_finder;
 
 fn run_system_test(name: &Path) {
+    // Times are rendered in the local time zone, so pin it to make the output reproducible.
     let output = Command::new("./target/debug/crush")
         .args(&[name.to_str().unwrap()])
+        .env("TZ", "UTC")
         .output()
         .expect("failed to execute process");
     let output_name = name.with_extension("crush.output");